@@ -0,0 +1,166 @@
+//! Parsing files without knowing their coordinate type
+//!
+//! Binary files store coordinates either as 16 bit integers ([`ShortCLI`]) or as 32 bit floats
+//! ([`LongCLI`]), and [`CLI`] has to be told which one to expect. [`AnyCLI`] looks at the first
+//! command of the geometry section instead and parses the file with the matching type.
+//!
+//! ```
+//! use colain::AnyCLI;
+//! # let buf = colain::CLIBuilder::new().short().layer(1).polyline(1, 1, vec![0, 0, 5, 5]).to_bytes().unwrap();
+//!
+//! match AnyCLI::new(&buf).unwrap() {
+//!     AnyCLI::Short(model) => println!("{} layers with 16 bit coordinates", model.iter().count()),
+//!     AnyCLI::Long(model) => println!("{} layers with 32 bit coordinates", model.iter().count()),
+//! }
+//! ```
+//!
+//! ASCII files and files without geometry are read as [`LongCLI`], which represents decimal
+//! coordinates.
+
+use crate::clitype::{CLIType, LongCLI, ShortCLI};
+use crate::{Error, Header, Parser, ParserOptions, CLI};
+use bytes::Buf;
+
+/// Coordinate type of a file, see [`clitype`](crate::clitype)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Variant {
+    /// 16 bit coordinates, see [`ShortCLI`]
+    Short,
+    /// 32 bit coordinates, see [`LongCLI`]
+    Long,
+}
+
+/// A model of either coordinate type, see the [module documentation](self)
+pub enum AnyCLI<'a> {
+    /// A file with 16 bit coordinates
+    Short(CLI<'a, ShortCLI>),
+    /// A file with 32 bit coordinates
+    Long(CLI<'a, LongCLI>),
+}
+
+impl<'a> AnyCLI<'a> {
+    /// Parse a file with the coordinate type it uses
+    pub fn new(raw: &'a [u8]) -> Result<Self, Error> {
+        Parser::new().parse_any(raw)
+    }
+
+    /// Find the coordinate type of a file from the first command of its geometry section.
+    ///
+    /// Fails with [`Error::InvalidGeometryCommand`] if that is not a command of either type.
+    pub fn detect(raw: &[u8], options: &ParserOptions) -> Result<Variant, Error> {
+        let (header, _, mut geom) = CLI::<LongCLI>::split_any(raw, options)?;
+        if !header.binary {
+            return Ok(Variant::Long);
+        }
+        let word = if header.aligned { 4 } else { 2 };
+        while geom.len() >= word {
+            let command = options.dialect.command(geom.get_u16_le());
+            geom.advance(word - 2);
+            return match command {
+                0 if options.dialect.zero_padding() => continue,
+                c if is_command::<ShortCLI>(c, options.extensions) => Ok(Variant::Short),
+                c if is_command::<LongCLI>(c, options.extensions) => Ok(Variant::Long),
+                c => Err(Error::InvalidGeometryCommand(c)),
+            };
+        }
+        Ok(Variant::Long)
+    }
+
+    /// Coordinate type of the model
+    pub fn variant(&self) -> Variant {
+        match self {
+            AnyCLI::Short(_) => Variant::Short,
+            AnyCLI::Long(_) => Variant::Long,
+        }
+    }
+
+    /// Get file metadata
+    pub fn header(&self) -> &Header {
+        match self {
+            AnyCLI::Short(model) => model.header(),
+            AnyCLI::Long(model) => model.header(),
+        }
+    }
+
+    /// The model if it has 16 bit coordinates
+    pub fn as_short(&self) -> Option<&CLI<'a, ShortCLI>> {
+        match self {
+            AnyCLI::Short(model) => Some(model),
+            AnyCLI::Long(_) => None,
+        }
+    }
+
+    /// The model if it has 32 bit coordinates
+    pub fn as_long(&self) -> Option<&CLI<'a, LongCLI>> {
+        match self {
+            AnyCLI::Short(_) => None,
+            AnyCLI::Long(model) => Some(model),
+        }
+    }
+
+    /// Serialize the model as a binary CLI file, see [`CLI::to_bytes`]
+    pub fn to_bytes(&self) -> Vec<u8> {
+        match self {
+            AnyCLI::Short(model) => model.to_bytes(),
+            AnyCLI::Long(model) => model.to_bytes(),
+        }
+    }
+}
+
+/// True if `command` is one of the commands of `T`, counting the records of the
+/// [`extension`](crate::extension) module only if `extensions` is set
+pub(crate) fn is_command<T: CLIType>(command: u16, extensions: bool) -> bool {
+    let extension = [T::CMD_POWER, T::CMD_SPEED, T::CMD_USERDATA];
+    [T::CMD_LAYER, T::CMD_PLINE, T::CMD_HATCH].contains(&command)
+        || extensions && extension.contains(&command)
+}
+
+impl<T: CLIType> Parser<T> {
+    /// Parse a file with the coordinate type it uses, see [`AnyCLI`]
+    ///
+    /// The coordinate type this parser was configured with is ignored.
+    pub fn parse_any<'a>(&self, raw: &'a [u8]) -> Result<AnyCLI<'a>, Error> {
+        Ok(match AnyCLI::detect(raw, &self.options)? {
+            Variant::Short => AnyCLI::Short(self.clone().short().parse(raw)?),
+            Variant::Long => AnyCLI::Long(self.clone().long().parse(raw)?),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{CLIBuilder, Dialect};
+
+    #[test]
+    fn detect() -> Result<(), Error> {
+        let short = CLIBuilder::new().short().layer(1).to_bytes()?;
+        let long = CLIBuilder::new().aligned(true).layer(1.0).to_bytes()?;
+        let options = ParserOptions::default();
+        assert_eq!(AnyCLI::detect(&short, &options)?, Variant::Short);
+        assert_eq!(AnyCLI::detect(&long, &options)?, Variant::Long);
+
+        assert_eq!(AnyCLI::new(&short)?.variant(), Variant::Short);
+        assert_eq!(AnyCLI::new(&short)?.to_bytes(), short);
+        let model = AnyCLI::new(&long)?;
+        assert!(model.header().aligned);
+        assert_eq!(model.as_long().unwrap().iter().count(), 1);
+        assert!(model.as_short().is_none());
+
+        // Padding before the first command
+        let (_, start) = crate::parse_header(&short)?;
+        let mut padded = short[..start].to_vec();
+        padded.extend([0, 0]);
+        padded.extend(&short[start..]);
+        let zero_padded = ParserOptions {
+            dialect: Dialect::ZeroPadded,
+            ..Default::default()
+        };
+        assert_eq!(AnyCLI::detect(&padded, &zero_padded)?, Variant::Short);
+        assert!(matches!(
+            AnyCLI::detect(&padded, &options),
+            Err(Error::InvalidGeometryCommand(0))
+        ));
+        Ok(())
+    }
+}
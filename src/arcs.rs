@@ -0,0 +1,200 @@
+//! Reconstruction of circular arcs from tessellated contours
+//!
+//! Slicers approximate curved contours by many short chords. Exporters to formats with native
+//! curves, such as DXF or G-code with `G2`/`G3`, can emit far more compact output from the arcs
+//! recovered by [`Loop::fit_arcs`].
+
+use crate::clitype::CLIType;
+use crate::geom;
+use crate::Loop;
+use std::f64::consts::PI;
+
+/// A circular arc, in coordinate units
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FittedArc {
+    /// First point of the arc
+    pub start: [f64; 2],
+    /// Last point of the arc, equal to `start` for a full circle
+    pub end: [f64; 2],
+    /// Center of the circle
+    pub center: [f64; 2],
+    /// Radius of the circle
+    pub radius: f64,
+    /// Angle swept from start to end in radians, positive when counter-clockwise
+    pub sweep: f64,
+}
+
+impl FittedArc {
+    /// True if the arc runs counter-clockwise (`G3`), false if clockwise (`G2`)
+    pub fn is_ccw(&self) -> bool {
+        self.sweep > 0.0
+    }
+}
+
+/// Part of a path reconstructed by [`Loop::fit_arcs`]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PathElement {
+    /// A straight segment from the first to the second point
+    Line([f64; 2], [f64; 2]),
+    /// A circular arc
+    Arc(FittedArc),
+}
+
+/// Fewest points of the original path an arc has to replace
+const MIN_ARC_POINTS: usize = 4;
+
+impl<'a, T: CLIType> Loop<'a, T> {
+    /// Replace runs of points lying on a circle by arcs.
+    ///
+    /// A run of at least four points becomes an arc when every point lies within `tolerance` of
+    /// the circle, the chords between them deviate from it by no more than `tolerance` and the path
+    /// turns in one direction only. Runs are grown greedily from the start of the path, points that
+    /// do not fit any arc are connected by lines. The elements are continuous and in path order.
+    pub fn fit_arcs(&self, tolerance: f64) -> Vec<PathElement> {
+        let points = geom::to_points(&self.points, |c| T::coord_to_f64(c));
+        let mut elements = Vec::new();
+        let mut i = 0;
+        while i + 1 < points.len() {
+            let mut best = None;
+            let mut j = i + MIN_ARC_POINTS - 1;
+            while j < points.len() {
+                match fit(&points[i..=j], tolerance) {
+                    Some(arc) => best = Some((j, arc)),
+                    None => break,
+                }
+                j += 1;
+            }
+            match best {
+                Some((j, arc)) => {
+                    elements.push(PathElement::Arc(arc));
+                    i = j;
+                }
+                None => {
+                    elements.push(PathElement::Line(points[i], points[i + 1]));
+                    i += 1;
+                }
+            }
+        }
+        elements
+    }
+}
+
+/// Circle through three points, `None` if they are collinear
+fn circumcircle(a: [f64; 2], b: [f64; 2], c: [f64; 2]) -> Option<([f64; 2], f64)> {
+    let d = 2.0 * (a[0] * (b[1] - c[1]) + b[0] * (c[1] - a[1]) + c[0] * (a[1] - b[1]));
+    if d.abs() < f64::EPSILON {
+        return None;
+    }
+    let sq = |p: [f64; 2]| p[0] * p[0] + p[1] * p[1];
+    let x = (sq(a) * (b[1] - c[1]) + sq(b) * (c[1] - a[1]) + sq(c) * (a[1] - b[1])) / d;
+    let y = (sq(a) * (c[0] - b[0]) + sq(b) * (a[0] - c[0]) + sq(c) * (b[0] - a[0])) / d;
+    Some(([x, y], (a[0] - x).hypot(a[1] - y)))
+}
+
+/// Fit an arc to a run of points
+fn fit(run: &[[f64; 2]], tolerance: f64) -> Option<FittedArc> {
+    let n = run.len() - 1;
+    // The first and last point coincide for a full circle, so sample the run at thirds
+    let (center, radius) = circumcircle(run[0], run[(n / 3).max(1)], run[(2 * n / 3).max(2)])?;
+
+    let mut sweep = 0.0;
+    for w in run.windows(2) {
+        let (p, q) = (w[0], w[1]);
+        if ((p[0] - center[0]).hypot(p[1] - center[1]) - radius).abs() > tolerance {
+            return None;
+        }
+        let chord = (q[0] - p[0]).hypot(q[1] - p[1]);
+        if chord == 0.0 || chord > 2.0 * radius {
+            return None;
+        }
+        let sagitta = radius - (radius * radius - chord * chord / 4.0).sqrt();
+        if sagitta > tolerance {
+            return None;
+        }
+        let turn = ((p[0] - center[0]) * (q[1] - center[1])
+            - (p[1] - center[1]) * (q[0] - center[0]))
+            .atan2(
+                (p[0] - center[0]) * (q[0] - center[0]) + (p[1] - center[1]) * (q[1] - center[1]),
+            );
+        if sweep != 0.0 && turn.signum() != f64::signum(sweep) {
+            return None;
+        }
+        sweep += turn;
+    }
+    let end = run[n];
+    if ((end[0] - center[0]).hypot(end[1] - center[1]) - radius).abs() > tolerance
+        || sweep.abs() > 2.0 * PI + 1e-9
+    {
+        return None;
+    }
+    Some(FittedArc {
+        start: run[0],
+        end,
+        center,
+        radius,
+        sweep,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clitype::LongCLI;
+    use std::borrow::Cow;
+
+    fn path(points: Vec<[f64; 2]>) -> Loop<'static, LongCLI> {
+        Loop {
+            id: 0,
+            dir: 1,
+            points: Cow::Owned(
+                points
+                    .iter()
+                    .flat_map(|p| [p[0] as f32, p[1] as f32])
+                    .collect(),
+            ),
+            params: Default::default(),
+        }
+    }
+
+    #[test]
+    fn circle() {
+        let points = (0..=64)
+            .map(|i| {
+                let a = i as f64 / 64.0 * 2.0 * PI;
+                [10.0 + 5.0 * a.cos(), 5.0 * a.sin()]
+            })
+            .collect();
+        let elements = path(points).fit_arcs(0.01);
+        assert_eq!(elements.len(), 1);
+        let arc = match elements[0] {
+            PathElement::Arc(arc) => arc,
+            _ => panic!("expected an arc"),
+        };
+        assert!(arc.is_ccw());
+        assert!((arc.radius - 5.0).abs() < 1e-4);
+        assert!((arc.center[0] - 10.0).abs() < 1e-4);
+        assert!((arc.sweep - 2.0 * PI).abs() < 1e-4);
+    }
+
+    #[test]
+    fn slot() {
+        // A line followed by a clockwise half circle and another line
+        let mut points = vec![[-10.0, 0.0], [0.0, 0.0]];
+        points.extend((1..=16).map(|i| {
+            let a = PI / 2.0 - i as f64 / 16.0 * PI;
+            [2.0 * a.cos(), -2.0 + 2.0 * a.sin()]
+        }));
+        points.push([-10.0, -4.0]);
+        let elements = path(points).fit_arcs(0.01);
+        assert_eq!(elements.len(), 3);
+        assert!(matches!(elements[0], PathElement::Line(_, [0.0, 0.0])));
+        match elements[1] {
+            PathElement::Arc(arc) => {
+                assert!(!arc.is_ccw());
+                assert!((arc.sweep + PI).abs() < 1e-4);
+            }
+            _ => panic!("expected an arc"),
+        }
+        assert!(matches!(elements[2], PathElement::Line(_, [-10.0, -4.0])));
+    }
+}
@@ -0,0 +1,275 @@
+//! Conversion into Arrow record batches
+//!
+//! Large build files are easiest to explore with a query engine such as DataFusion or Polars.
+//! Enable the `arrow` feature to convert a model into two [Arrow](https://arrow.apache.org)
+//! tables, and the `parquet` feature to write them as Parquet files:
+//!
+//! - [`layers`]: one row per layer with its height and element counts,
+//! - [`segments`]: one row per loop segment and hatch vector.
+//!
+//! The tables share the `layer` column, and all lengths are in millimeters.
+//!
+//! ```
+//! use colain::{CLI, arrow, clitype::LongCLI};
+//! # let buf = colain::BuildPlate::<LongCLI>::new(1.0).to_bytes();
+//!
+//! let model = CLI::<LongCLI>::new(&buf).unwrap();
+//! let layers = arrow::layers(&model).unwrap();
+//! let segments = arrow::segments(&model).unwrap();
+//! assert_eq!(layers.num_rows(), model.iter().count());
+//! ```
+
+use crate::clitype::CLIType;
+use crate::{LoopKind, CLI};
+use arrow_array::{ArrayRef, Float64Array, Int64Array, RecordBatch, StringArray, UInt32Array};
+use arrow_schema::{ArrowError, DataType, Field, Schema};
+use std::sync::Arc;
+
+/// Schema of the [`layers`] table
+///
+/// | column | type | |
+/// |---|---|---|
+/// | `layer` | `UInt32` | index of the layer |
+/// | `z` | `Float64` | height of the layer |
+/// | `loops` | `UInt32` | number of loops |
+/// | `loop_points` | `UInt32` | number of points of all loops |
+/// | `hatches` | `UInt32` | number of hatch vectors |
+pub fn layers_schema() -> Schema {
+    Schema::new(vec![
+        Field::new("layer", DataType::UInt32, false),
+        Field::new("z", DataType::Float64, false),
+        Field::new("loops", DataType::UInt32, false),
+        Field::new("loop_points", DataType::UInt32, false),
+        Field::new("hatches", DataType::UInt32, false),
+    ])
+}
+
+/// Schema of the [`segments`] table
+///
+/// | column | type | |
+/// |---|---|---|
+/// | `layer` | `UInt32` | index of the layer |
+/// | `z` | `Float64` | height of the layer |
+/// | `kind` | `Utf8` | `loop`, `polyline` or `hatch` |
+/// | `element` | `UInt32` | index of the loop or hatch block within the layer |
+/// | `id` | `Int64` | id of the loop or hatch block |
+/// | `index` | `UInt32` | index of the segment within the loop or hatch block |
+/// | `x0`, `y0` | `Float64` | start point |
+/// | `x1`, `y1` | `Float64` | end point |
+pub fn segments_schema() -> Schema {
+    let mut fields = vec![
+        Field::new("layer", DataType::UInt32, false),
+        Field::new("z", DataType::Float64, false),
+        Field::new("kind", DataType::Utf8, false),
+        Field::new("element", DataType::UInt32, false),
+        Field::new("id", DataType::Int64, false),
+        Field::new("index", DataType::UInt32, false),
+    ];
+    for name in ["x0", "y0", "x1", "y1"] {
+        fields.push(Field::new(name, DataType::Float64, false));
+    }
+    Schema::new(fields)
+}
+
+/// One row per layer, see [`layers_schema`]
+pub fn layers<T: CLIType>(model: &CLI<'_, T>) -> Result<RecordBatch, ArrowError> {
+    let units = model.header().units;
+    let mut layer = Vec::new();
+    let mut z = Vec::new();
+    let mut loops = Vec::new();
+    let mut loop_points = Vec::new();
+    let mut hatches = Vec::new();
+    for (i, l) in model.iter().enumerate() {
+        layer.push(i as u32);
+        z.push(T::coord_to_f64(l.height) * units);
+        loops.push(l.loops.len() as u32);
+        loop_points.push(l.loops.iter().map(|l| l.points.len() / 2).sum::<usize>() as u32);
+        hatches.push(l.hatches.iter().map(|h| h.points.len() / 4).sum::<usize>() as u32);
+    }
+    let columns: Vec<ArrayRef> = vec![
+        Arc::new(UInt32Array::from(layer)),
+        Arc::new(Float64Array::from(z)),
+        Arc::new(UInt32Array::from(loops)),
+        Arc::new(UInt32Array::from(loop_points)),
+        Arc::new(UInt32Array::from(hatches)),
+    ];
+    RecordBatch::try_new(Arc::new(layers_schema()), columns)
+}
+
+/// One row per loop segment and hatch vector, see [`segments_schema`]
+///
+/// Loops contribute a row for each pair of consecutive points, so a loop of `n` points gives
+/// `n - 1` rows. Closed loops are not closed implicitly.
+pub fn segments<T: CLIType>(model: &CLI<'_, T>) -> Result<RecordBatch, ArrowError> {
+    let units = model.header().units;
+    let f = |c| T::coord_to_f64(c) * units;
+
+    let mut layer = Vec::new();
+    let mut z = Vec::new();
+    let mut kind = Vec::new();
+    let mut element = Vec::new();
+    let mut id = Vec::new();
+    let mut index = Vec::new();
+    let mut coords: [Vec<f64>; 4] = Default::default();
+    let mut push = |row: (usize, f64, &'static str, usize, i64, usize), s: [f64; 4]| {
+        layer.push(row.0 as u32);
+        z.push(row.1);
+        kind.push(row.2);
+        element.push(row.3 as u32);
+        id.push(row.4);
+        index.push(row.5 as u32);
+        for (column, value) in coords.iter_mut().zip(s) {
+            column.push(value);
+        }
+    };
+
+    for (i, l) in model.iter().enumerate() {
+        let height = f(l.height);
+        for (e, lp) in l.loops.iter().enumerate() {
+            let k = if lp.kind() == LoopKind::Open {
+                "polyline"
+            } else {
+                "loop"
+            };
+            let points: Vec<&[<T as CLIType>::Coord]> = lp.points.chunks_exact(2).collect();
+            for (j, w) in points.windows(2).enumerate() {
+                push(
+                    (i, height, k, e, T::meta_to_i64(lp.id), j),
+                    [f(w[0][0]), f(w[0][1]), f(w[1][0]), f(w[1][1])],
+                );
+            }
+        }
+        for (e, h) in l.hatches.iter().enumerate() {
+            for (j, v) in h.iter().enumerate() {
+                push(
+                    (i, height, "hatch", e, T::meta_to_i64(h.id), j),
+                    [f(v[0]), f(v[1]), f(v[2]), f(v[3])],
+                );
+            }
+        }
+    }
+
+    let [x0, y0, x1, y1] = coords;
+    let columns: Vec<ArrayRef> = vec![
+        Arc::new(UInt32Array::from(layer)),
+        Arc::new(Float64Array::from(z)),
+        Arc::new(StringArray::from(kind)),
+        Arc::new(UInt32Array::from(element)),
+        Arc::new(Int64Array::from(id)),
+        Arc::new(UInt32Array::from(index)),
+        Arc::new(Float64Array::from(x0)),
+        Arc::new(Float64Array::from(y0)),
+        Arc::new(Float64Array::from(x1)),
+        Arc::new(Float64Array::from(y1)),
+    ];
+    RecordBatch::try_new(Arc::new(segments_schema()), columns)
+}
+
+/// Write the [`layers`] and [`segments`] tables as two Parquet files
+#[cfg(feature = "parquet")]
+pub fn write_parquet<T, L, S>(
+    model: &CLI<'_, T>,
+    layers_out: L,
+    segments_out: S,
+) -> Result<(), parquet::errors::ParquetError>
+where
+    T: CLIType,
+    L: std::io::Write + Send,
+    S: std::io::Write + Send,
+{
+    use parquet::arrow::ArrowWriter;
+
+    for (batch, out) in [
+        (
+            layers(model)?,
+            Box::new(layers_out) as Box<dyn std::io::Write + Send>,
+        ),
+        (segments(model)?, Box::new(segments_out)),
+    ] {
+        let mut writer = ArrowWriter::try_new(out, batch.schema(), None)?;
+        writer.write(&batch)?;
+        writer.close()?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clitype::ShortCLI;
+    use crate::{Hatches, Header, Layer, Loop};
+    use arrow_array::Array;
+    use std::borrow::Cow;
+
+    fn model() -> CLI<'static, ShortCLI> {
+        CLI {
+            header: Header {
+                units: 0.5,
+                ..Header::default()
+            },
+            layers: vec![
+                Layer {
+                    loops: vec![Loop {
+                        id: 7,
+                        dir: 1,
+                        points: Cow::Owned(vec![0, 0, 4, 0, 4, 4]),
+                        params: Default::default(),
+                    }],
+                    hatches: vec![Hatches {
+                        id: 9,
+                        points: Cow::Owned(vec![1, 1, 3, 1]),
+                        params: Default::default(),
+                    }],
+                    ..Layer::empty(1)
+                },
+                Layer::empty(2),
+            ],
+            verbatim: Default::default(),
+        }
+    }
+
+    #[test]
+    fn tables() {
+        let model = model();
+        let layers = layers(&model).unwrap();
+        assert_eq!(layers.num_rows(), 2);
+        let z = layers
+            .column(1)
+            .as_any()
+            .downcast_ref::<Float64Array>()
+            .unwrap();
+        assert_eq!(z.values(), &[0.5, 1.0]);
+        let points = layers
+            .column(3)
+            .as_any()
+            .downcast_ref::<UInt32Array>()
+            .unwrap();
+        assert_eq!(points.values(), &[3, 0]);
+
+        let segments = segments(&model).unwrap();
+        assert_eq!(segments.num_rows(), 3);
+        let kind = segments
+            .column(2)
+            .as_any()
+            .downcast_ref::<StringArray>()
+            .unwrap();
+        assert_eq!(kind.value(0), "loop");
+        assert_eq!(kind.value(2), "hatch");
+        let x1 = segments
+            .column(8)
+            .as_any()
+            .downcast_ref::<Float64Array>()
+            .unwrap();
+        assert_eq!(x1.values(), &[2.0, 2.0, 1.5]);
+        assert_eq!(x1.null_count(), 0);
+    }
+
+    #[cfg(feature = "parquet")]
+    #[test]
+    fn parquet() {
+        let (mut layers, mut segments) = (Vec::new(), Vec::new());
+        write_parquet(&model(), &mut layers, &mut segments).unwrap();
+        assert_eq!(&layers[..4], b"PAR1");
+        assert_eq!(&segments[segments.len() - 4..], b"PAR1");
+    }
+}
@@ -0,0 +1,212 @@
+//! Reading the ASCII geometry section
+//!
+//! Files declaring `$$ASCII` describe their geometry with the same commands as the header:
+//!
+//! ```text
+//! $$GEOMETRYSTART
+//! $$LAYER/0.1
+//! $$POLYLINE/1,1,4,0,0,10,0,10,10,0,0
+//! $$HATCHES/1,1,2,2,8,8
+//! $$GEOMETRYEND
+//! ```
+//!
+//! Parameters are separated by commas and may continue on the following lines, comments run from
+//! `//` to the next `//` or the end of the line. The records are turned into the same layers,
+//! loops and hatches as the binary encoding, see [`Parser::parse`](crate::Parser::parse).
+
+use crate::{Dialect, Error};
+
+/// A command of the geometry section
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Record {
+    /// Start of a layer at a height in coordinate units
+    Layer(f64),
+    /// A polyline with its id, direction and coordinates
+    Polyline { id: i64, dir: i64, points: Vec<f64> },
+    /// A set of hatches with its id and coordinates
+    Hatches { id: i64, points: Vec<f64> },
+    /// A command this crate does not know
+    Unknown(String),
+}
+
+/// Iterator over the records of an ASCII geometry section, with their line numbers
+///
+/// Iteration ends at `$$GEOMETRYEND` or after the first error.
+pub(crate) struct Records<'a> {
+    text: &'a str,
+    pos: usize,
+    line: usize,
+    dialect: Dialect,
+}
+
+impl<'a> Records<'a> {
+    /// Read a geometry section starting on line `line` of the file
+    pub fn new(geom: &'a [u8], line: usize, dialect: Dialect) -> Result<Self, Error> {
+        let text = std::str::from_utf8(geom).map_err(|e| {
+            let lines = geom[..e.valid_up_to()].iter().filter(|&&b| b == b'\n');
+            Error::InvalidAsciiGeometry(line + lines.count())
+        })?;
+        Ok(Records {
+            text,
+            pos: 0,
+            line,
+            dialect,
+        })
+    }
+
+    /// Text of the next command without comments, and the line it starts on
+    fn next_command(&mut self) -> Option<(usize, String)> {
+        let bytes = self.text.as_bytes();
+        let mut command: Option<(usize, String)> = None;
+        while self.pos < bytes.len() {
+            let rest = &self.text[self.pos..];
+            if rest.starts_with("//") {
+                let line = rest.find('\n').unwrap_or(rest.len());
+                let end = rest[2..line].find("//").map_or(line, |e| e + 4);
+                self.pos += end;
+                continue;
+            }
+            if rest.starts_with("$$") {
+                if command.is_some() {
+                    break;
+                }
+                command = Some((self.line, String::new()));
+            }
+            let c = rest.chars().next().unwrap();
+            if c == '\n' {
+                self.line += 1;
+            }
+            if let Some((_, text)) = command.as_mut() {
+                text.push(c);
+            }
+            self.pos += c.len_utf8();
+        }
+        command
+    }
+
+    fn parse(&self, line: usize, command: &str) -> Result<Option<Record>, Error> {
+        let invalid = || Error::InvalidAsciiGeometry(line);
+        let (name, params) = match command.find('/') {
+            Some(slash) => (&command[..slash], &command[slash + 1..]),
+            None => (command, ""),
+        };
+        let name = if self.dialect.relaxed_header() {
+            name.chars()
+                .filter(|c| !c.is_whitespace())
+                .collect::<String>()
+                .to_ascii_uppercase()
+        } else {
+            name.trim().to_string()
+        };
+        match &*name {
+            "$$GEOMETRYSTART" => return Ok(None),
+            "$$LAYER" | "$$POLYLINE" | "$$HATCHES" => {}
+            _ => return Ok(Some(Record::Unknown(name))),
+        }
+        let params = params
+            .split(',')
+            .map(str::trim)
+            .filter(|p| !p.is_empty())
+            .map(|p| p.parse::<f64>().map_err(|_| invalid()))
+            .collect::<Result<Vec<f64>, Error>>()?;
+        let integer = |v: f64| {
+            if v.fract() == 0.0 {
+                Ok(v as i64)
+            } else {
+                Err(invalid())
+            }
+        };
+
+        Ok(Some(match &*name {
+            "$$LAYER" => match params[..] {
+                [z] => Record::Layer(z),
+                _ => return Err(invalid()),
+            },
+            "$$POLYLINE" => match params[..] {
+                [id, dir, n, ref points @ ..] if points.len() as f64 == 2.0 * n => {
+                    Record::Polyline {
+                        id: integer(id)?,
+                        dir: integer(dir)?,
+                        points: points.to_vec(),
+                    }
+                }
+                _ => return Err(invalid()),
+            },
+            "$$HATCHES" => match params[..] {
+                [id, n, ref points @ ..] if points.len() as f64 == 4.0 * n => Record::Hatches {
+                    id: integer(id)?,
+                    points: points.to_vec(),
+                },
+                _ => return Err(invalid()),
+            },
+            _ => unreachable!(),
+        }))
+    }
+}
+
+impl<'a> Iterator for Records<'a> {
+    type Item = Result<(usize, Record), Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let (line, command) = self.next_command()?;
+            let command = command.trim();
+            if command == "$$GEOMETRYEND" {
+                self.pos = self.text.len();
+                return None;
+            }
+            match self.parse(line, command) {
+                Ok(Some(record)) => return Some(Ok((line, record))),
+                Ok(None) => continue,
+                Err(e) => {
+                    self.pos = self.text.len();
+                    return Some(Err(e));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn records() -> Result<(), Error> {
+        let text = "\n$$GEOMETRYSTART // start of the geometry //\n$$LAYER/0.1\n\
+            $$POLYLINE/1,1,3, // a triangle\n0,0, 1,0,\n0,1\n$$HATCHES/2,1,0,0,1,1\n\
+            $$LABEL/3,text\n$$GEOMETRYEND\n$$LAYER/0.2";
+        let records = Records::new(text.as_bytes(), 10, Dialect::Standard)?
+            .collect::<Result<Vec<_>, Error>>()?;
+        assert_eq!(
+            records,
+            [
+                (12, Record::Layer(0.1)),
+                (
+                    13,
+                    Record::Polyline {
+                        id: 1,
+                        dir: 1,
+                        points: vec![0.0, 0.0, 1.0, 0.0, 0.0, 1.0]
+                    }
+                ),
+                (
+                    16,
+                    Record::Hatches {
+                        id: 2,
+                        points: vec![0.0, 0.0, 1.0, 1.0]
+                    }
+                ),
+                (17, Record::Unknown("$$LABEL".into())),
+            ]
+        );
+
+        let mut bad = Records::new(b"$$LAYER/1\n$$POLYLINE/1,1,2,0,0,1", 1, Dialect::Standard)?;
+        assert!(matches!(
+            bad.nth(1),
+            Some(Err(Error::InvalidAsciiGeometry(2)))
+        ));
+        assert!(bad.next().is_none());
+        Ok(())
+    }
+}
@@ -0,0 +1,133 @@
+//! Axis aligned bounding boxes of layers and models
+//!
+//! Boxes are computed over the points of all loops and hatches, in coordinate units like the
+//! geometry itself. They are computed on every call, which takes a single pass over the points.
+//!
+//! ```
+//! use colain::CLIBuilder;
+//!
+//! let model = CLIBuilder::new()
+//!     .layer(0.5)
+//!     .polyline(1, 1, vec![0.0, 0.0, 10.0, 0.0, 10.0, 5.0, 0.0, 0.0])
+//!     .layer(1.0)
+//!     .hatches(1, vec![-2.0, 1.0, 3.0, 1.0])
+//!     .build()
+//!     .unwrap();
+//!
+//! let bb = model.bounding_box().unwrap();
+//! assert_eq!((bb.min, bb.max), ([-2.0, 0.0, 0.5], [10.0, 5.0, 1.0]));
+//! ```
+
+use crate::clitype::CLIType;
+use crate::{Layer, CLI};
+
+/// Axis aligned box from `min` to `max`, with `N` dimensions
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Bounds<const N: usize> {
+    /// Lowest coordinate along each axis
+    pub min: [f64; N],
+    /// Highest coordinate along each axis
+    pub max: [f64; N],
+}
+
+impl<const N: usize> Bounds<N> {
+    /// Box around a set of points, `None` if there are no points
+    pub fn of<I: IntoIterator<Item = [f64; N]>>(points: I) -> Option<Self> {
+        let mut points = points.into_iter();
+        let first = points.next()?;
+        let mut bb = Bounds {
+            min: first,
+            max: first,
+        };
+        for p in points {
+            bb.add(p);
+        }
+        Some(bb)
+    }
+
+    /// Grow the box to include a point
+    pub fn add(&mut self, p: [f64; N]) {
+        for (i, &c) in p.iter().enumerate() {
+            self.min[i] = self.min[i].min(c);
+            self.max[i] = self.max[i].max(c);
+        }
+    }
+
+    /// Extent of the box along each axis
+    pub fn size(&self) -> [f64; N] {
+        let mut size = self.max;
+        for (s, min) in size.iter_mut().zip(self.min) {
+            *s -= min;
+        }
+        size
+    }
+
+    /// Center of the box
+    pub fn center(&self) -> [f64; N] {
+        let mut center = self.max;
+        for (c, min) in center.iter_mut().zip(self.min) {
+            *c = (*c + min) / 2.0;
+        }
+        center
+    }
+}
+
+impl<'a, T: CLIType> Layer<'a, T> {
+    /// Box around all loops and hatches of the layer, `None` if the layer is empty
+    pub fn bounding_box(&self) -> Option<Bounds<2>> {
+        Bounds::of(self.all_points())
+    }
+}
+
+impl<'a, T: CLIType> CLI<'a, T> {
+    /// Box around all loops and hatches of the model, with the heights of the layers that hold
+    /// them as the third axis. `None` if the model has no geometry.
+    pub fn bounding_box(&self) -> Option<Bounds<3>> {
+        self.layers
+            .iter()
+            .filter_map(|layer| {
+                let bb = layer.bounding_box()?;
+                let z = T::coord_to_f64(layer.height);
+                Some(Bounds {
+                    min: [bb.min[0], bb.min[1], z],
+                    max: [bb.max[0], bb.max[1], z],
+                })
+            })
+            .reduce(|mut a, b| {
+                a.add(b.min);
+                a.add(b.max);
+                a
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clitype::ShortCLI;
+    use crate::CLIBuilder;
+
+    #[test]
+    fn bounding_box() {
+        let model = CLIBuilder::new()
+            .short()
+            .layer(1)
+            .layer(2)
+            .polyline(1, 1, vec![4, 4, 8, 4, 8, 9, 4, 4])
+            .layer(3)
+            .hatches(2, vec![2, 6, 5, 6])
+            .layer(4)
+            .build()
+            .unwrap();
+        let layers: Vec<&Layer<'_, ShortCLI>> = model.iter().collect();
+        assert_eq!(layers[0].bounding_box(), None);
+        let bb = layers[1].bounding_box().unwrap();
+        assert_eq!((bb.min, bb.max), ([4.0, 4.0], [8.0, 9.0]));
+        assert_eq!((bb.size(), bb.center()), ([4.0, 5.0], [6.0, 6.5]));
+
+        let bb = model.bounding_box().unwrap();
+        assert_eq!((bb.min, bb.max), ([2.0, 4.0, 2.0], [8.0, 9.0, 3.0]));
+        let empty = CLIBuilder::new().layer(1.0).build().unwrap();
+        assert_eq!(empty.bounding_box(), None);
+    }
+}
@@ -0,0 +1,269 @@
+//! Construction of models from scratch
+//!
+//! A [`CLIBuilder`] assembles the header and geometry of a new file, e.g. in a slicer, and
+//! produces a [`CLI`] that can be written with [`CLI::to_bytes`]. Elements are added to the last
+//! layer that was started, coordinates are given in coordinate units like everywhere else in this
+//! crate.
+//!
+//! ```
+//! use colain::{CLIBuilder, CLI, clitype::ShortCLI};
+//!
+//! let bytes = CLIBuilder::new()
+//!     .short()
+//!     .units(0.005)
+//!     .aligned(true)
+//!     .layer(10)
+//!     .polyline(1, 1, vec![0, 0, 100, 0, 100, 100, 0, 0])
+//!     .hatches(1, vec![10, 10, 90, 10, 10, 20, 90, 20])
+//!     .layer(20)
+//!     .polyline(1, 1, vec![0, 0, 100, 0, 100, 100, 0, 0])
+//!     .to_bytes()
+//!     .unwrap();
+//!
+//! let model = CLI::<ShortCLI>::new(&bytes).unwrap();
+//! assert_eq!(model.iter().count(), 2);
+//! ```
+
+use crate::clitype::{CLIType, LongCLI, ShortCLI};
+use crate::{Error, Hatches, Header, Layer, Loop, CLI};
+use std::borrow::Cow;
+
+/// Builder for a new model, see the [module documentation](self)
+///
+/// The header starts out as the [default header](Header::default): binary, in millimeters,
+/// version 2.0 and without alignment.
+#[derive(Debug, Clone)]
+pub struct CLIBuilder<T: CLIType = LongCLI> {
+    header: Header,
+    layers: Vec<Layer<'static, T>>,
+    /// An element was added before the first layer
+    outside: bool,
+}
+
+impl CLIBuilder<LongCLI> {
+    /// A builder for [`LongCLI`] files
+    pub fn new() -> Self {
+        CLIBuilder::default()
+    }
+}
+
+impl<T: CLIType> Default for CLIBuilder<T> {
+    fn default() -> Self {
+        CLIBuilder {
+            header: Header::default(),
+            layers: Vec::new(),
+            outside: false,
+        }
+    }
+}
+
+impl<T: CLIType> CLIBuilder<T> {
+    fn with_type<U: CLIType>(self) -> CLIBuilder<U> {
+        let coords = |c: &[T::Coord]| -> Cow<'static, [U::Coord]> {
+            Cow::Owned(
+                c.iter()
+                    .map(|&c| U::coord_from_f64(T::coord_to_f64(c)))
+                    .collect(),
+            )
+        };
+        let meta = |m: T::Meta| U::meta_from_i64(T::meta_to_i64(m));
+        let layers = self.layers.iter().map(|layer| Layer {
+            loops: layer
+                .loops
+                .iter()
+                .map(|l| Loop {
+                    id: meta(l.id),
+                    dir: meta(l.dir),
+                    points: coords(&l.points),
+                    params: Default::default(),
+                })
+                .collect(),
+            hatches: layer
+                .hatches
+                .iter()
+                .map(|h| Hatches {
+                    id: meta(h.id),
+                    points: coords(&h.points),
+                    params: Default::default(),
+                })
+                .collect(),
+            order: layer.order.clone(),
+            ..Layer::empty(U::coord_from_f64(T::coord_to_f64(layer.height)))
+        });
+        CLIBuilder {
+            header: self.header,
+            layers: layers.collect(),
+            outside: self.outside,
+        }
+    }
+
+    /// Build a file with 32 bit coordinates, see [`LongCLI`]
+    ///
+    /// Geometry added so far is converted.
+    pub fn long(self) -> CLIBuilder<LongCLI> {
+        self.with_type()
+    }
+
+    /// Build a file with 16 bit coordinates, see [`ShortCLI`]
+    ///
+    /// Geometry added so far is converted, coordinates out of the 16 bit range saturate.
+    pub fn short(self) -> CLIBuilder<ShortCLI> {
+        self.with_type()
+    }
+
+    /// Set how many millimeters each coordinate unit represents
+    pub fn units(mut self, units: f64) -> Self {
+        self.header.units = units;
+        self
+    }
+
+    /// Set the version of the spec the file follows
+    pub fn version(mut self, version: f32) -> Self {
+        self.header.version = version;
+        self
+    }
+
+    /// Pad every command word and value to 4 bytes, declaring `$$ALIGN` in the header
+    pub fn aligned(mut self, aligned: bool) -> Self {
+        self.header.aligned = aligned;
+        self
+    }
+
+    /// Start a new layer at `height`, later elements are added to it
+    pub fn layer(mut self, height: <T as CLIType>::Coord) -> Self {
+        self.layers.push(Layer::empty(height));
+        self
+    }
+
+    /// Add a polyline with interleaved x and y coordinates to the current layer.
+    ///
+    /// See [`LoopKind`](crate::LoopKind) for the meaning of `dir`.
+    ///
+    /// # Panics
+    ///
+    /// If the number of coordinates is odd.
+    pub fn polyline(
+        mut self,
+        id: <T as CLIType>::Meta,
+        dir: <T as CLIType>::Meta,
+        points: impl Into<Vec<<T as CLIType>::Coord>>,
+    ) -> Self {
+        let points = points.into();
+        assert!(points.len() % 2 == 0, "a polyline needs whole points");
+        match self.layers.last_mut() {
+            Some(layer) => layer.push_loop(Loop {
+                id,
+                dir,
+                points: Cow::Owned(points),
+                params: Default::default(),
+            }),
+            None => self.outside = true,
+        }
+        self
+    }
+
+    /// Add hatches, given as interleaved start and end points, to the current layer.
+    ///
+    /// # Panics
+    ///
+    /// If the number of coordinates is not a multiple of 4.
+    pub fn hatches(
+        mut self,
+        id: <T as CLIType>::Meta,
+        points: impl Into<Vec<<T as CLIType>::Coord>>,
+    ) -> Self {
+        let points = points.into();
+        assert!(points.len() % 4 == 0, "hatches need a start and end point");
+        match self.layers.last_mut() {
+            Some(layer) => layer.push_hatches(Hatches {
+                id,
+                points: Cow::Owned(points),
+                params: Default::default(),
+            }),
+            None => self.outside = true,
+        }
+        self
+    }
+
+    /// Finish the model.
+    ///
+    /// Fails with [`Error::ElementOutsideLayer`] if geometry was added before the first layer,
+    /// and with [`Error::LayerOrder`] if a layer is below the one before it.
+    pub fn build(self) -> Result<CLI<'static, T>, Error> {
+        if self.outside {
+            return Err(Error::ElementOutsideLayer);
+        }
+        let mut model = CLI {
+            header: self.header,
+            layers: Vec::new(),
+            verbatim: Default::default(),
+        };
+        model.try_extend(self.layers)?;
+        Ok(model)
+    }
+
+    /// Finish the model and serialize it as a binary file, see [`CLIBuilder::build`]
+    pub fn to_bytes(self) -> Result<Vec<u8>, Error> {
+        self.build().map(|model| model.to_bytes())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Parser;
+
+    #[test]
+    fn build() -> Result<(), Error> {
+        for aligned in [false, true] {
+            let bytes = CLIBuilder::new()
+                .units(0.01)
+                .aligned(aligned)
+                .layer(5.0)
+                .polyline(2, 0, [0.0, 0.0, 70_000.0, 0.0, 0.0, 0.0])
+                .hatches(3, [1.0, 1.0, 2.0, 2.5])
+                .layer(10.0)
+                .to_bytes()?;
+            let model = Parser::new().parse(&bytes)?;
+            assert_eq!(model.header().units, 0.01);
+            assert_eq!(model.header().aligned, aligned);
+            let layers = model.iter().collect::<Vec<_>>();
+            assert_eq!(layers.len(), 2);
+            assert_eq!(layers[0].height(), 5.0);
+            let l = layers[0].iter_loops().next().unwrap();
+            assert_eq!((l.id(), l.dir()), (2, 0));
+            assert_eq!(l.points(), [0.0, 0.0, 70_000.0, 0.0, 0.0, 0.0]);
+            assert_eq!(
+                layers[0].iter_hatches().next().unwrap().points(),
+                [1.0, 1.0, 2.0, 2.5]
+            );
+        }
+
+        let short = CLIBuilder::new()
+            .layer(1.0)
+            .polyline(1, 1, [0.0, 0.0, 70_000.0, 4.6])
+            .short()
+            .build()?;
+        assert_eq!(
+            short
+                .iter()
+                .next()
+                .unwrap()
+                .iter_loops()
+                .next()
+                .unwrap()
+                .points(),
+            [0, 0, 65535, 5]
+        );
+
+        assert!(matches!(
+            CLIBuilder::new().hatches(1, []).layer(1.0).build(),
+            Err(Error::ElementOutsideLayer)
+        ));
+        assert!(matches!(
+            CLIBuilder::new().layer(2.0).layer(1.0).build(),
+            Err(Error::LayerOrder)
+        ));
+        Ok(())
+    }
+}
@@ -0,0 +1,280 @@
+//! Checksum footer detecting corruption in transfer
+//!
+//! [`CLI::to_bytes_with_checksum`] appends a user data record to the end of the geometry section
+//! holding a digest of all bytes before it. [`Parser::verify_checksum`] checks the digest while
+//! parsing, failing with [`Error::ChecksumMismatch`] if the file was altered.
+//!
+//! The record carries the id `0` and its data is the tag `$$CHECKSUM/CRC32/` or
+//! `$$CHECKSUM/SHA256/` followed by the raw digest, CRC-32 being stored little endian. CRC-32 is
+//! enough to catch transfer errors, SHA-256 also guards against deliberate tampering when the
+//! digest is compared with one obtained separately.
+//!
+//! The user data command is defined by this crate and not by the spec, see
+//! [`extension`](crate::extension), so a checksummed file is only readable by this crate. Other
+//! readers reject the footer as an invalid command. The parser of this crate always removes it
+//! before decoding the geometry, whether or not [`Parser::extensions`] is set.
+//!
+//! ```
+//! use colain::checksum::Checksum;
+//! use colain::{clitype::LongCLI, Parser, CLI};
+//! # let buf = colain::BuildPlate::<LongCLI>::new(1.0).to_bytes();
+//! let model = CLI::<LongCLI>::new(&buf).unwrap();
+//!
+//! let sent = model.to_bytes_with_checksum(Checksum::Sha256);
+//! let received = Parser::new().verify_checksum().parse(&sent).unwrap();
+//! ```
+//!
+//! [`Parser::extensions`]: crate::Parser::extensions
+//! [`Parser::verify_checksum`]: crate::Parser::verify_checksum
+
+use crate::clitype::CLIType;
+use crate::{writer, Error, CLI};
+
+/// Digest algorithm of a checksum footer
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Checksum {
+    /// CRC-32 (IEEE 802.3), 4 bytes
+    Crc32,
+    /// SHA-256, 32 bytes
+    Sha256,
+}
+
+impl Checksum {
+    const ALL: [Checksum; 2] = [Checksum::Crc32, Checksum::Sha256];
+
+    fn tag(self) -> &'static [u8] {
+        match self {
+            Checksum::Crc32 => b"$$CHECKSUM/CRC32/",
+            Checksum::Sha256 => b"$$CHECKSUM/SHA256/",
+        }
+    }
+
+    /// Digest of `data` with this algorithm
+    pub fn digest(self, data: &[u8]) -> Vec<u8> {
+        match self {
+            Checksum::Crc32 => crc32(data).to_le_bytes().to_vec(),
+            Checksum::Sha256 => sha256(data).to_vec(),
+        }
+    }
+}
+
+/// The footer record for a file, with the digest of `data`
+fn footer<T: CLIType>(kind: Checksum, data: &[u8], aligned: bool) -> Vec<u8> {
+    let mut payload = kind.tag().to_vec();
+    payload.extend(kind.digest(data));
+    let mut out = Vec::new();
+    writer::write_user_data::<T>(&mut out, T::meta_from_i64(0), &payload, aligned);
+    out
+}
+
+/// Length and verification result of the footer at the end of a file, `None` if there is none
+pub(crate) fn find_footer<T: CLIType>(raw: &[u8], aligned: bool) -> Option<(usize, bool)> {
+    Checksum::ALL.iter().find_map(|&kind| {
+        let expected = footer::<T>(kind, &[], aligned);
+        let start = raw.len().checked_sub(expected.len())?;
+        // everything up to the digest is fixed
+        let digest = kind.digest(&[]).len();
+        let payload = kind.tag().len() + digest;
+        let padding = if aligned {
+            payload.next_multiple_of(4) - payload
+        } else {
+            0
+        };
+        let tagged = expected.len() - padding - digest;
+        if raw[start..start + tagged] != expected[..tagged] {
+            return None;
+        }
+        Some((
+            expected.len(),
+            footer::<T>(kind, &raw[..start], aligned) == raw[start..],
+        ))
+    })
+}
+
+impl<'a, T: CLIType> CLI<'a, T> {
+    /// Serialize the model like [`CLI::to_bytes`], followed by a checksum footer, see the
+    /// [module documentation](self). Only this crate can read the result.
+    pub fn to_bytes_with_checksum(&self, kind: Checksum) -> Vec<u8> {
+        let mut out = self.to_bytes();
+        let footer = footer::<T>(kind, &out, self.header.aligned);
+        out.extend(footer);
+        out
+    }
+}
+
+/// Check a parsed file against its footer, returning the geometry section without it
+pub(crate) fn strip_footer<'a, T: CLIType>(
+    raw: &[u8],
+    geom: &'a [u8],
+    aligned: bool,
+    verify: bool,
+) -> Result<&'a [u8], Error> {
+    match find_footer::<T>(raw, aligned) {
+        Some((len, valid)) if len <= geom.len() => {
+            if verify && !valid {
+                return Err(Error::ChecksumMismatch);
+            }
+            Ok(&geom[..geom.len() - len])
+        }
+        _ if verify => Err(Error::MissingChecksum),
+        _ => Ok(geom),
+    }
+}
+
+/// CRC-32 as used by zip (IEEE 802.3, reflected)
+pub(crate) fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xEDB8_8320 & mask);
+        }
+    }
+    !crc
+}
+
+/// SHA-256 (FIPS 180-4)
+fn sha256(data: &[u8]) -> [u8; 32] {
+    const K: [u32; 64] = [
+        0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4,
+        0xab1c5ed5, 0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe,
+        0x9bdc06a7, 0xc19bf174, 0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f,
+        0x4a7484aa, 0x5cb0a9dc, 0x76f988da, 0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7,
+        0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967, 0x27b70a85, 0x2e1b2138, 0x4d2c6dfc,
+        0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85, 0xa2bfe8a1, 0xa81a664b,
+        0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070, 0x19a4c116,
+        0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+        0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7,
+        0xc67178f2,
+    ];
+    let mut h: [u32; 8] = [
+        0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab,
+        0x5be0cd19,
+    ];
+
+    let mut message = data.to_vec();
+    message.push(0x80);
+    while message.len() % 64 != 56 {
+        message.push(0);
+    }
+    message.extend(((data.len() as u64) * 8).to_be_bytes());
+
+    for block in message.chunks_exact(64) {
+        let mut w = [0u32; 64];
+        for (i, word) in block.chunks_exact(4).enumerate() {
+            w[i] = u32::from_be_bytes([word[0], word[1], word[2], word[3]]);
+        }
+        for i in 16..64 {
+            let s0 = w[i - 15].rotate_right(7) ^ w[i - 15].rotate_right(18) ^ (w[i - 15] >> 3);
+            let s1 = w[i - 2].rotate_right(17) ^ w[i - 2].rotate_right(19) ^ (w[i - 2] >> 10);
+            w[i] = w[i - 16]
+                .wrapping_add(s0)
+                .wrapping_add(w[i - 7])
+                .wrapping_add(s1);
+        }
+
+        let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut hh] = h;
+        for i in 0..64 {
+            let s1 = e.rotate_right(6) ^ e.rotate_right(11) ^ e.rotate_right(25);
+            let ch = (e & f) ^ (!e & g);
+            let t1 = hh
+                .wrapping_add(s1)
+                .wrapping_add(ch)
+                .wrapping_add(K[i])
+                .wrapping_add(w[i]);
+            let s0 = a.rotate_right(2) ^ a.rotate_right(13) ^ a.rotate_right(22);
+            let maj = (a & b) ^ (a & c) ^ (b & c);
+            let t2 = s0.wrapping_add(maj);
+            hh = g;
+            g = f;
+            f = e;
+            e = d.wrapping_add(t1);
+            d = c;
+            c = b;
+            b = a;
+            a = t1.wrapping_add(t2);
+        }
+        for (h, v) in h.iter_mut().zip([a, b, c, d, e, f, g, hh]) {
+            *h = h.wrapping_add(v);
+        }
+    }
+
+    let mut out = [0u8; 32];
+    for (chunk, v) in out.chunks_exact_mut(4).zip(h) {
+        chunk.copy_from_slice(&v.to_be_bytes());
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clitype::ShortCLI;
+    use crate::{Header, Layer, Loop, Parser};
+    use std::borrow::Cow;
+
+    fn hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    #[test]
+    fn digests() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(
+            hex(&sha256(b"")),
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+        assert_eq!(
+            hex(&sha256(b"abc")),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        assert_eq!(
+            hex(&sha256(&[b'a'; 1000])),
+            "41edece42d63e8d9bf515a9ba6932e1c20cbc9f5a5d134645adb5db1b9737ea3"
+        );
+    }
+
+    #[test]
+    fn footer() -> Result<(), Error> {
+        for aligned in [false, true] {
+            let model = CLI::<ShortCLI> {
+                header: Header {
+                    aligned,
+                    ..Header::default()
+                },
+                layers: vec![Layer {
+                    loops: vec![Loop {
+                        id: 1,
+                        dir: 1,
+                        points: Cow::Owned(vec![0, 0, 1, 0, 0, 1]),
+                        params: Default::default(),
+                    }],
+                    ..Layer::empty(1)
+                }],
+                verbatim: Default::default(),
+            };
+            let plain = model.to_bytes();
+            assert!(matches!(
+                Parser::new().short().verify_checksum().parse(&plain),
+                Err(Error::MissingChecksum)
+            ));
+
+            for kind in Checksum::ALL {
+                let mut buf = model.to_bytes_with_checksum(kind);
+                let parsed = Parser::new().short().verify_checksum().parse(&buf)?;
+                assert_eq!(parsed.iter().next().unwrap().user_data().len(), 0);
+                assert_eq!(parsed.to_bytes(), plain);
+
+                // last coordinate of the loop
+                buf[plain.len() - 1] ^= 1;
+                assert!(matches!(
+                    Parser::new().short().verify_checksum().parse(&buf),
+                    Err(Error::ChecksumMismatch)
+                ));
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,113 @@
+//! Building models from iterators of layers
+//!
+//! Layers produced by a pipeline, e.g. a slicer or a chain of transformations, can be collected
+//! into a model and written out. Layers must be in ascending order of height: collecting into a
+//! `Result` reports [`Error::LayerOrder`] for the first layer below its predecessor, while
+//! [`CLI::try_extend`] and [`Extend`] add layers to an existing model.
+//!
+//! ```
+//! use colain::{CLI, Error, clitype::LongCLI};
+//! # let buf = colain::BuildPlate::<LongCLI>::new(1.0).to_bytes();
+//! let model = CLI::<LongCLI>::new(&buf).unwrap();
+//!
+//! let simplified: Result<CLI<LongCLI>, Error> = model.iter().map(|l| l.simplified(100)).collect();
+//! let bytes = simplified.unwrap().to_bytes();
+//! ```
+//!
+//! Collected models get a [default header](crate::Header::default): binary, in millimeters,
+//! without alignment.
+
+use crate::clitype::CLIType;
+use crate::{Error, Header, Layer, CLI};
+use std::iter::FromIterator;
+
+impl<'a, T: CLIType> CLI<'a, T> {
+    /// Append layers to the model.
+    ///
+    /// Fails with [`Error::LayerOrder`] if a layer is below the one before it, in which case the
+    /// model is left unchanged. Layers at the same height as their predecessor are accepted.
+    pub fn try_extend<I: IntoIterator<Item = Layer<'a, T>>>(
+        &mut self,
+        layers: I,
+    ) -> Result<(), Error> {
+        let len = self.layers.len();
+        let mut last = self.layers.last().map(|l| T::coord_to_f64(l.height));
+        for layer in layers {
+            let height = T::coord_to_f64(layer.height);
+            if matches!(last, Some(last) if height < last) {
+                self.layers.truncate(len);
+                return Err(Error::LayerOrder);
+            }
+            last = Some(height);
+            self.layers.push(layer);
+        }
+        Ok(())
+    }
+}
+
+impl<'a, T: CLIType> Extend<Layer<'a, T>> for CLI<'a, T> {
+    /// Append layers to the model, see [`CLI::try_extend`]
+    ///
+    /// # Panics
+    ///
+    /// If a layer is below the one before it.
+    fn extend<I: IntoIterator<Item = Layer<'a, T>>>(&mut self, layers: I) {
+        self.try_extend(layers)
+            .expect("layers must be added in ascending order of height");
+    }
+}
+
+impl<'a, T: CLIType> FromIterator<Layer<'a, T>> for Result<CLI<'a, T>, Error> {
+    fn from_iter<I: IntoIterator<Item = Layer<'a, T>>>(layers: I) -> Self {
+        let mut model = CLI {
+            header: Header::default(),
+            layers: Vec::new(),
+            verbatim: Default::default(),
+        };
+        model.try_extend(layers)?;
+        Ok(model)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clitype::ShortCLI;
+
+    fn layer(height: u16) -> Layer<'static, ShortCLI> {
+        Layer::empty(height)
+    }
+
+    #[test]
+    fn order() -> Result<(), Error> {
+        let mut model: CLI<ShortCLI> = [1, 2, 2]
+            .iter()
+            .map(|&h| layer(h))
+            .collect::<Result<_, _>>()?;
+        assert_eq!(model.iter().count(), 3);
+
+        assert!(matches!(
+            model.try_extend(vec![layer(3), layer(1)]),
+            Err(Error::LayerOrder)
+        ));
+        assert_eq!(model.iter().count(), 3);
+
+        model.extend(vec![layer(3), layer(4)]);
+        let heights: Vec<u16> = model.iter().map(|l| l.height()).collect();
+        assert_eq!(heights, [1, 2, 2, 3, 4]);
+
+        let bytes = model.to_bytes();
+        assert_eq!(CLI::<ShortCLI>::new(&bytes)?.iter().count(), 5);
+
+        let unordered: Result<CLI<ShortCLI>, _> = vec![layer(2), layer(1)].into_iter().collect();
+        assert!(matches!(unordered, Err(Error::LayerOrder)));
+        Ok(())
+    }
+
+    #[test]
+    #[should_panic]
+    fn extend_below() {
+        let mut model: CLI<ShortCLI> = std::iter::once(layer(2)).collect::<Result<_, _>>().unwrap();
+        model.extend(std::iter::once(layer(1)));
+    }
+}
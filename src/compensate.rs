@@ -0,0 +1,212 @@
+//! Shrinkage compensation
+//!
+//! Sintered and debound parts shrink, usually by a different amount along each axis and
+//! sometimes depending on the height in the part. Scaling the geometry up before building
+//! pre-compensates for this.
+//!
+//! ```
+//! use colain::{CLI, Compensation, clitype::LongCLI};
+//! # let buf = colain::BuildPlate::<LongCLI>::new(1.0).to_bytes();
+//! let model = CLI::<LongCLI>::new(&buf).unwrap();
+//!
+//! // 1.5 % in x and y, 2 % in z
+//! let scaled = model.compensate(1.015, 1.015, 1.02);
+//!
+//! // x shrinks more towards the top of a 50 mm part
+//! let scaled = model.compensate_with(&Compensation {
+//!     scale: [1.015, 1.015, 1.02],
+//!     curve_x: vec![(0.0, 1.0), (50.0, 1.005)],
+//!     ..Default::default()
+//! });
+//! ```
+
+use crate::clitype::CLIType;
+use crate::{Hatches, Layer, Loop, CLI};
+use std::borrow::Cow;
+
+/// Scale factors for [`CLI::compensate_with`]
+///
+/// Factors must be positive, mirroring is not supported.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Compensation {
+    /// Scale factor along x, y and z
+    pub scale: [f64; 3],
+    /// Additional factor along x as a function of the height in millimeters before scaling,
+    /// given as `(z, factor)` points sorted by `z`. Interpolated linearly between the points
+    /// and constant beyond the first and last one. Empty for no additional scaling.
+    pub curve_x: Vec<(f64, f64)>,
+    /// Additional factor along y, like [`Compensation::curve_x`]
+    pub curve_y: Vec<(f64, f64)>,
+    /// Point in millimeters that stays in place
+    pub origin: [f64; 3],
+}
+
+impl Default for Compensation {
+    fn default() -> Self {
+        Compensation {
+            scale: [1.0; 3],
+            curve_x: Vec::new(),
+            curve_y: Vec::new(),
+            origin: [0.0; 3],
+        }
+    }
+}
+
+/// Value of a piecewise linear curve, 1 if it is empty
+fn interpolate(curve: &[(f64, f64)], z: f64) -> f64 {
+    let (first, last) = match (curve.first(), curve.last()) {
+        (Some(first), Some(last)) => (first, last),
+        _ => return 1.0,
+    };
+    if z <= first.0 {
+        return first.1;
+    }
+    if z >= last.0 {
+        return last.1;
+    }
+    let i = curve.partition_point(|p| p.0 <= z);
+    let (a, b) = (curve[i - 1], curve[i]);
+    a.1 + (b.1 - a.1) * (z - a.0) / (b.0 - a.0)
+}
+
+impl<'a, T: CLIType> CLI<'a, T> {
+    /// Copy of the model scaled by a factor along each axis around the origin, see the
+    /// [module documentation](self)
+    pub fn compensate(&self, scale_x: f64, scale_y: f64, scale_z: f64) -> CLI<'static, T> {
+        self.compensate_with(&Compensation {
+            scale: [scale_x, scale_y, scale_z],
+            ..Default::default()
+        })
+    }
+
+    /// Copy of the model scaled according to a [`Compensation`].
+    ///
+    /// Every layer keeps its geometry, process parameters and user data, only the coordinates
+    /// and heights change.
+    pub fn compensate_with(&self, compensation: &Compensation) -> CLI<'static, T> {
+        let units = self.header.units;
+        let [ox, oy, oz] = compensation.origin;
+        let layers = self
+            .layers
+            .iter()
+            .map(|layer| {
+                let z = T::coord_to_f64(layer.height) * units;
+                let sx = compensation.scale[0] * interpolate(&compensation.curve_x, z);
+                let sy = compensation.scale[1] * interpolate(&compensation.curve_y, z);
+                let scale = |coords: &[<T as CLIType>::Coord]| -> Vec<<T as CLIType>::Coord> {
+                    coords
+                        .chunks_exact(2)
+                        .flat_map(|p| {
+                            let x = ox + (T::coord_to_f64(p[0]) * units - ox) * sx;
+                            let y = oy + (T::coord_to_f64(p[1]) * units - oy) * sy;
+                            [T::coord_from_f64(x / units), T::coord_from_f64(y / units)]
+                        })
+                        .collect()
+                };
+                Layer {
+                    loops: layer
+                        .loops
+                        .iter()
+                        .map(|l| Loop {
+                            id: l.id,
+                            dir: l.dir,
+                            points: Cow::Owned(scale(&l.points)),
+                            params: l.params.to_owned_params(),
+                        })
+                        .collect(),
+                    hatches: layer
+                        .hatches
+                        .iter()
+                        .map(|h| Hatches {
+                            id: h.id,
+                            points: Cow::Owned(scale(&h.points)),
+                            params: h.params.to_owned_params(),
+                        })
+                        .collect(),
+                    user_data: layer.user_data.iter().map(|u| u.to_owned_data()).collect(),
+                    order: layer.order.clone(),
+                    ..Layer::empty(T::coord_from_f64(
+                        (oz + (z - oz) * compensation.scale[2]) / units,
+                    ))
+                }
+            })
+            .collect();
+
+        CLI {
+            header: self.header.clone(),
+            layers,
+            verbatim: Default::default(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clitype::LongCLI;
+    use crate::Header;
+
+    fn model() -> CLI<'static, LongCLI> {
+        let layer = |height: f32| Layer {
+            loops: vec![Loop {
+                id: 1,
+                dir: 1,
+                points: Cow::Owned(vec![0.0, 0.0, 100.0, 0.0, 100.0, 50.0, 0.0, 0.0]),
+                params: Default::default(),
+            }],
+            hatches: vec![Hatches {
+                id: 2,
+                points: Cow::Owned(vec![10.0, 10.0, 90.0, 10.0]),
+                params: Default::default(),
+            }],
+            ..Layer::empty(height)
+        };
+        CLI {
+            header: Header {
+                units: 0.1,
+                ..Header::default()
+            },
+            layers: vec![layer(0.0), layer(100.0)],
+            verbatim: Default::default(),
+        }
+    }
+
+    #[test]
+    fn uniform() {
+        let scaled = model().compensate(1.1, 1.2, 1.5);
+        let top = scaled.iter().nth(1).unwrap();
+        assert_eq!(top.height(), 150.0);
+        let points = &top.iter_loops().next().unwrap().points;
+        assert!((points[2] - 110.0).abs() < 1e-4);
+        assert!((points[5] - 60.0).abs() < 1e-4);
+        let hatch = &top.iter_hatches().next().unwrap().points;
+        assert!((hatch[0] - 11.0).abs() < 1e-4 && (hatch[1] - 12.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn curve() {
+        let compensation = Compensation {
+            curve_x: vec![(0.0, 1.0), (20.0, 2.0)],
+            origin: [5.0, 0.0, 0.0],
+            ..Default::default()
+        };
+        assert_eq!(interpolate(&compensation.curve_x, 5.0), 1.25);
+        assert_eq!(interpolate(&compensation.curve_x, -1.0), 1.0);
+        assert_eq!(interpolate(&[], 3.0), 1.0);
+
+        let scaled = model().compensate_with(&compensation);
+        let x = |layer: usize| {
+            scaled
+                .iter()
+                .nth(layer)
+                .unwrap()
+                .iter_loops()
+                .next()
+                .unwrap()
+                .points[2]
+        };
+        assert_eq!(x(0), 100.0);
+        // the top is at z = 10 mm, x = 10 mm moves away from the origin at 5 mm
+        assert!((x(1) - 125.0).abs() < 1e-4);
+    }
+}
@@ -0,0 +1,216 @@
+//! Vertical connectivity of loops through the build
+//!
+//! Where [`CLI::loop_correspondence`] picks the single most likely continuation of a loop,
+//! [`CLI::connectivity_graph`] keeps every overlap between the loops of consecutive layers. The
+//! result is a directed acyclic graph from the build plate upwards, in which features can be
+//! followed through merges and splits, and parts that start in mid-air show up as loops with
+//! nothing below them.
+//!
+//! ```
+//! use colain::{CLI, clitype::LongCLI};
+//! # let buf = colain::BuildPlate::<LongCLI>::new(1.0).to_bytes();
+//! let model = CLI::<LongCLI>::new(&buf).unwrap();
+//!
+//! let graph = model.connectivity_graph();
+//! for node in graph.floating() {
+//!     let loop_ref = graph.nodes()[node];
+//!     println!("loop {} of layer {} starts in mid-air", loop_ref.index, loop_ref.layer);
+//! }
+//! ```
+
+use crate::clitype::CLIType;
+use crate::geom::{self, BoundingBox};
+use crate::{Layer, CLI};
+
+/// A loop of the model, identified by its position
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct LoopRef {
+    /// Index of the layer
+    pub layer: usize,
+    /// Index of the loop within the layer
+    pub index: usize,
+}
+
+/// Overlaps between the loops of consecutive layers, see the [module documentation](self)
+///
+/// Nodes are numbered layer by layer in the order of the loops, edges always point from a loop to
+/// a loop of the layer above.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ConnectivityGraph {
+    nodes: Vec<LoopRef>,
+    /// First node of every layer, plus the total number of nodes
+    offsets: Vec<usize>,
+    below: Vec<Vec<usize>>,
+    above: Vec<Vec<usize>>,
+}
+
+impl ConnectivityGraph {
+    /// Every loop of the model
+    pub fn nodes(&self) -> &[LoopRef] {
+        &self.nodes
+    }
+
+    /// Node of a loop, `None` if there is no such loop
+    pub fn node(&self, layer: usize, index: usize) -> Option<usize> {
+        let start = *self.offsets.get(layer)?;
+        let end = *self.offsets.get(layer + 1)?;
+        (index < end - start).then(|| start + index)
+    }
+
+    /// Nodes of the layer below overlapping a node
+    pub fn below(&self, node: usize) -> &[usize] {
+        &self.below[node]
+    }
+
+    /// Nodes of the layer above overlapping a node
+    pub fn above(&self, node: usize) -> &[usize] {
+        &self.above[node]
+    }
+
+    /// All edges as pairs of a node and a node of the layer above it
+    pub fn edges(&self) -> impl Iterator<Item = (usize, usize)> + '_ {
+        self.above
+            .iter()
+            .enumerate()
+            .flat_map(|(from, to)| to.iter().map(move |&to| (from, to)))
+    }
+
+    /// Nodes without any loop below them, the start of every feature
+    pub fn roots(&self) -> impl Iterator<Item = usize> + '_ {
+        (0..self.nodes.len()).filter(move |&n| self.below[n].is_empty())
+    }
+
+    /// Roots above the first layer, i.e. loops starting in mid-air
+    pub fn floating(&self) -> impl Iterator<Item = usize> + '_ {
+        self.roots().filter(move |&n| self.nodes[n].layer > 0)
+    }
+
+    /// Connected component of every node, numbered from zero in the order of their first node.
+    ///
+    /// Loops in the same component belong to the same part, or to parts merging at some height.
+    pub fn components(&self) -> Vec<usize> {
+        let mut parent: Vec<usize> = (0..self.nodes.len()).collect();
+        fn find(parent: &mut [usize], mut n: usize) -> usize {
+            while parent[n] != n {
+                parent[n] = parent[parent[n]];
+                n = parent[n];
+            }
+            n
+        }
+        for (a, b) in self.edges() {
+            let (a, b) = (find(&mut parent, a), find(&mut parent, b));
+            parent[a.max(b)] = a.min(b);
+        }
+        let mut ids = vec![usize::MAX; self.nodes.len()];
+        let mut count = 0;
+        (0..self.nodes.len())
+            .map(|n| {
+                let root = find(&mut parent, n);
+                if ids[root] == usize::MAX {
+                    ids[root] = count;
+                    count += 1;
+                }
+                ids[root]
+            })
+            .collect()
+    }
+}
+
+impl<'a, T: CLIType> Layer<'a, T> {
+    /// Bounding box of every loop, `None` for loops without points
+    fn loop_boxes(&self) -> Vec<Option<BoundingBox>> {
+        self.loops
+            .iter()
+            .map(|l| BoundingBox::of(geom::to_points(&l.points, |c| T::coord_to_f64(c))))
+            .collect()
+    }
+}
+
+impl<'a, T: CLIType> CLI<'a, T> {
+    /// Link every loop to the loops of the adjacent layers whose bounding boxes overlap it.
+    ///
+    /// Only loops of the same [`LoopKind`](crate::LoopKind) are linked, boxes that merely touch
+    /// count as overlapping.
+    pub fn connectivity_graph(&self) -> ConnectivityGraph {
+        let mut graph = ConnectivityGraph::default();
+        for (layer, l) in self.layers.iter().enumerate() {
+            graph.offsets.push(graph.nodes.len());
+            graph
+                .nodes
+                .extend((0..l.loops.len()).map(|index| LoopRef { layer, index }));
+        }
+        graph.offsets.push(graph.nodes.len());
+        graph.below = vec![Vec::new(); graph.nodes.len()];
+        graph.above = vec![Vec::new(); graph.nodes.len()];
+
+        let mut lower = self.layers.first().map(|l| l.loop_boxes());
+        for (n, pair) in self.layers.windows(2).enumerate() {
+            let upper = pair[1].loop_boxes();
+            for (i, (a, bb)) in pair[0].loops.iter().zip(lower.iter().flatten()).enumerate() {
+                for (j, (b, obb)) in pair[1].loops.iter().zip(upper.iter()).enumerate() {
+                    let overlaps = match (bb, obb) {
+                        (Some(bb), Some(obb)) => bb.intersection(obb).is_some(),
+                        _ => false,
+                    };
+                    if overlaps && a.kind() == b.kind() {
+                        let (from, to) = (graph.offsets[n] + i, graph.offsets[n + 1] + j);
+                        graph.above[from].push(to);
+                        graph.below[to].push(from);
+                    }
+                }
+            }
+            lower = Some(upper);
+        }
+        graph
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clitype::ShortCLI;
+    use crate::{Header, Loop};
+    use std::borrow::Cow;
+
+    fn square(x: u16, size: u16) -> Loop<'static, ShortCLI> {
+        Loop {
+            id: 0,
+            dir: 1,
+            points: Cow::Owned(vec![x, 0, x + size, 0, x + size, size, x, size, x, 0]),
+            params: Default::default(),
+        }
+    }
+
+    fn layer(height: u16, loops: Vec<Loop<'static, ShortCLI>>) -> Layer<'static, ShortCLI> {
+        Layer {
+            loops,
+            ..Layer::empty(height)
+        }
+    }
+
+    #[test]
+    fn merge_and_float() {
+        let model = CLI {
+            header: Header::default(),
+            layers: vec![
+                layer(1, vec![square(0, 10), square(20, 10)]),
+                // Both columns merge into one, and a new part starts far away
+                layer(2, vec![square(5, 20), square(100, 5)]),
+                layer(3, vec![square(5, 20)]),
+            ],
+            verbatim: Default::default(),
+        };
+        let graph = model.connectivity_graph();
+        assert_eq!(graph.nodes().len(), 5);
+        assert_eq!(graph.node(1, 1), Some(3));
+        assert_eq!(graph.node(2, 1), None);
+        assert_eq!(graph.node(3, 0), None);
+
+        assert_eq!(graph.below(2), [0, 1]);
+        assert_eq!(graph.above(2), [4]);
+        assert_eq!(graph.edges().collect::<Vec<_>>(), [(0, 2), (1, 2), (2, 4)]);
+        assert_eq!(graph.roots().collect::<Vec<_>>(), [0, 1, 3]);
+        assert_eq!(graph.floating().collect::<Vec<_>>(), [3]);
+        assert_eq!(graph.components(), [0, 0, 0, 1, 0]);
+    }
+}
@@ -0,0 +1,116 @@
+//! Standard conversion traits
+//!
+//! Parsing through [`TryFrom`] and [`FromStr`] lets generic code load models without knowing
+//! about this crate. Parsing a byte slice borrows from it like [`CLI::new`], while parsing a
+//! string or a file copies the geometry so the model does not outlive its source.
+//!
+//! Such models are [`OwnedCLI`]s: they can be stored anywhere and sent to other threads after the
+//! file is gone. [`CLI::to_owned_model`] turns a borrowing model into one.
+//!
+//! ```
+//! use colain::{CLI, OwnedCLI, clitype::LongCLI};
+//! # let buf = colain::BuildPlate::<LongCLI>::new(1.0).to_bytes();
+//!
+//! let model: OwnedCLI<LongCLI> = CLI::<LongCLI>::new(&buf).unwrap().to_owned_model();
+//! drop(buf);
+//! std::thread::spawn(move || println!("{} layers", model.iter().count()));
+//! ```
+
+use crate::clitype::{CLIType, LongCLI};
+use crate::{Error, CLI};
+use std::convert::TryFrom;
+use std::path::Path;
+use std::str::FromStr;
+
+/// A model that does not borrow from a file
+///
+/// It can be stored anywhere and sent to other threads after the file is gone. Parsing a string
+/// with [`FromStr`] or a file with [`TryFrom`] returns one, [`CLI::to_owned_model`] turns a
+/// borrowing model into one.
+pub type OwnedCLI<T = LongCLI> = CLI<'static, T>;
+
+impl<'a, T: CLIType> CLI<'a, T> {
+    /// Copy borrowed data so the model no longer references the file.
+    ///
+    /// The header text, unknown records and skipped records are copied as well. Layers are encoded
+    /// again when written, with their records in the order they were read, so writing the copy
+    /// gives the same records as writing the original. The bytes can still differ where a file
+    /// deviates from how this crate encodes records, such as padding of aligned records that is
+    /// not zero, or user data and skipped records following the same block in a different order.
+    pub fn to_owned_model(&self) -> OwnedCLI<T> {
+        CLI {
+            header: self.header.clone(),
+            layers: self.layers.iter().map(|l| l.to_owned_layer()).collect(),
+            verbatim: self.verbatim.to_owned_verbatim(),
+        }
+    }
+}
+
+impl<'a, T: CLIType> TryFrom<&'a [u8]> for CLI<'a, T> {
+    type Error = Error;
+
+    /// Same as [`CLI::new`]
+    fn try_from(raw: &'a [u8]) -> Result<Self, Error> {
+        CLI::new(raw)
+    }
+}
+
+impl<T: CLIType> FromStr for CLI<'static, T> {
+    type Err = Error;
+
+    /// Parse the text of a file, copying its geometry
+    fn from_str(s: &str) -> Result<Self, Error> {
+        Ok(CLI::<T>::new(s.as_bytes())?.to_owned_model())
+    }
+}
+
+impl<T: CLIType> TryFrom<&Path> for CLI<'static, T> {
+    type Error = Error;
+
+    /// Read and parse a file, failing with [`Error::Io`] if it cannot be read
+    fn try_from(path: &Path) -> Result<Self, Error> {
+        let raw = std::fs::read(path)?;
+        Ok(CLI::<T>::new(&raw)?.to_owned_model())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clitype::LongCLI;
+    use crate::{Header, Layer};
+
+    #[test]
+    fn conversions() -> Result<(), Error> {
+        let buf = CLI::<LongCLI> {
+            header: Header::default(),
+            layers: vec![Layer::empty(0.5)],
+            verbatim: Default::default(),
+        }
+        .to_bytes();
+
+        let borrowed = CLI::<LongCLI>::try_from(&buf[..])?;
+        assert_eq!(borrowed.to_bytes(), buf);
+
+        let path = std::env::temp_dir().join(format!("colain-convert-{}.cli", std::process::id()));
+        std::fs::write(&path, &buf)?;
+        let read = CLI::<LongCLI>::try_from(path.as_path());
+        std::fs::remove_file(&path)?;
+        assert_eq!(read?.to_bytes(), buf);
+        assert!(matches!(
+            CLI::<LongCLI>::try_from(path.as_path()),
+            Err(Error::Io(_))
+        ));
+
+        let owned: OwnedCLI<LongCLI> = borrowed.to_owned_model();
+        drop(borrowed);
+        assert_eq!(owned.to_bytes(), buf);
+
+        let text = std::str::from_utf8(&buf).unwrap();
+        assert_eq!(text.parse::<CLI<LongCLI>>()?.to_bytes(), buf);
+        let ascii = "$$HEADERSTART\n$$ASCII\n$$UNITS/1\n$$VERSION/200\n$$HEADEREND\n$$LAYER/0.5"
+            .parse::<CLI<LongCLI>>()?;
+        assert_eq!(ascii.iter().map(|l| l.height).collect::<Vec<_>>(), [0.5]);
+        Ok(())
+    }
+}
@@ -0,0 +1,152 @@
+//! Tracking contours from one layer to the next
+//!
+//! Lofting, morphing and tracking defects through a build all need to know which loop of a layer
+//! continues which loop of the layer below. [`Layer::continuations`] provides this for a pair of
+//! layers and [`CLI::loop_correspondence`] for a whole model.
+
+use crate::clitype::CLIType;
+use crate::geom::{self, BoundingBox};
+use crate::{Layer, CLI};
+
+/// Where a loop continues in the next layer, see [`Layer::continuations`]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Continuation {
+    /// Index of the loop in the next layer
+    pub index: usize,
+    /// Overlap of the bounding boxes relative to the smaller one, from 0 to 1
+    pub overlap: f64,
+    /// Distance between the centroids of the points of both loops, in coordinate units
+    pub distance: f64,
+}
+
+impl<'a, T: CLIType> Layer<'a, T> {
+    /// Find the most likely continuation of every loop of this layer in `next`.
+    ///
+    /// Candidates are loops of the same [`LoopKind`](crate::LoopKind) whose bounding box overlaps,
+    /// or whose centroid is at most `max_distance` coordinate units away. Of these, the one with
+    /// the largest overlap wins, ties are broken by centroid distance. Several loops may continue
+    /// into the same loop, e.g. where two regions merge.
+    pub fn continuations(
+        &self,
+        next: &Layer<'_, T>,
+        max_distance: f64,
+    ) -> Vec<Option<Continuation>> {
+        let describe = |layer: &Layer<'_, T>| -> Vec<Option<(BoundingBox, [f64; 2])>> {
+            layer
+                .loops
+                .iter()
+                .map(|l| {
+                    let points = geom::to_points(&l.points, |c| T::coord_to_f64(c));
+                    Some((
+                        BoundingBox::of(points.iter().copied())?,
+                        geom::centroid(&points)?,
+                    ))
+                })
+                .collect()
+        };
+        let (this, other) = (describe(self), describe(next));
+
+        self.loops
+            .iter()
+            .zip(this.iter())
+            .map(|(l, shape)| {
+                let (bb, c) = (*shape)?;
+                let mut best: Option<Continuation> = None;
+                for (index, (m, other)) in next.loops.iter().zip(other.iter()).enumerate() {
+                    let (obb, oc) = match other {
+                        Some(o) if m.kind() == l.kind() => *o,
+                        _ => continue,
+                    };
+                    let distance = (c[0] - oc[0]).hypot(c[1] - oc[1]);
+                    let overlap = bb.intersection(&obb).map_or(0.0, |i| {
+                        let area = |b: &BoundingBox| (b.max[0] - b.min[0]) * (b.max[1] - b.min[1]);
+                        let smaller = area(&bb).min(area(&obb));
+                        if smaller > 0.0 {
+                            area(&i) / smaller
+                        } else {
+                            1.0
+                        }
+                    });
+                    if overlap == 0.0 && distance > max_distance {
+                        continue;
+                    }
+                    let candidate = Continuation {
+                        index,
+                        overlap,
+                        distance,
+                    };
+                    let better = match best {
+                        None => true,
+                        Some(b) => {
+                            overlap > b.overlap || (overlap == b.overlap && distance < b.distance)
+                        }
+                    };
+                    if better {
+                        best = Some(candidate);
+                    }
+                }
+                best
+            })
+            .collect()
+    }
+}
+
+impl<'a, T: CLIType> CLI<'a, T> {
+    /// Continuations of every loop between each pair of consecutive layers.
+    ///
+    /// Entry `n` of the result maps the loops of layer `n` to those of layer `n + 1`, see
+    /// [`Layer::continuations`]. `max_distance` is in millimeters.
+    pub fn loop_correspondence(&self, max_distance: f64) -> Vec<Vec<Option<Continuation>>> {
+        let max_distance = max_distance / self.header.units;
+        self.layers
+            .windows(2)
+            .map(|pair| pair[0].continuations(&pair[1], max_distance))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::clitype::ShortCLI;
+    use crate::{Header, Layer, Loop, CLI};
+    use std::borrow::Cow;
+
+    fn square(x: u16, size: u16) -> Loop<'static, ShortCLI> {
+        Loop {
+            id: 0,
+            dir: 1,
+            points: Cow::Owned(vec![x, 0, x + size, 0, x + size, size, x, size, x, 0]),
+            params: Default::default(),
+        }
+    }
+
+    fn layer(height: u16, loops: Vec<Loop<'static, ShortCLI>>) -> Layer<'static, ShortCLI> {
+        Layer {
+            loops,
+            ..Layer::empty(height)
+        }
+    }
+
+    #[test]
+    fn tracking() {
+        let model = CLI {
+            header: Header {
+                units: 0.5,
+                ..Header::default()
+            },
+            layers: vec![
+                layer(1, vec![square(0, 10), square(40, 10), square(100, 2)]),
+                layer(2, vec![square(41, 10), square(1, 9), square(106, 2)]),
+            ],
+            verbatim: Default::default(),
+        };
+        let map = model.loop_correspondence(2.5);
+        assert_eq!(map.len(), 1);
+        let indices: Vec<_> = map[0].iter().map(|c| c.map(|c| c.index)).collect();
+        assert_eq!(indices, vec![Some(1), Some(0), None]);
+        assert_eq!(map[0][1].unwrap().overlap, 0.9);
+
+        let far = model.loop_correspondence(3.5);
+        assert_eq!(far[0][2].unwrap().index, 2);
+    }
+}
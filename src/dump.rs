@@ -0,0 +1,129 @@
+//! Flat CSV dump of all geometry
+//!
+//! For ad-hoc analysis in pandas or a spreadsheet, [`CLI::dump_csv`] writes every point of every
+//! loop and every hatch vector as its own row.
+//!
+//! ```no_run
+//! use colain::{CLI, clitype::LongCLI};
+//! # let bytes = Vec::new();
+//!
+//! let model = CLI::<LongCLI>::new(&bytes).unwrap();
+//! let file = std::fs::File::create("points.csv").unwrap();
+//! model.dump_csv(std::io::BufWriter::new(file)).unwrap();
+//! ```
+
+use crate::clitype::CLIType;
+use crate::{LoopKind, CLI};
+use std::io::{self, Write};
+
+impl<'a, T: CLIType> CLI<'a, T> {
+    /// Write all geometry as CSV with a header row, coordinates in millimeters.
+    ///
+    /// The columns are `layer,z,kind,element,id,index,x,y,x1,y1`:
+    ///
+    /// - `kind` is `loop` for closed loops, `polyline` for open ones and `hatch` for hatches,
+    /// - `element` is the index of the loop or hatch block within the layer and `id` its id,
+    /// - `index` is the index of the point within the loop or of the vector within the hatch block,
+    /// - `x,y` is the loop point or the start of the hatch vector, `x1,y1` the end of the hatch
+    ///   vector and empty for loop points.
+    pub fn dump_csv<W: Write>(&self, mut out: W) -> io::Result<()> {
+        let units = self.header.units;
+        let f = |c| T::coord_to_f64(c) * units;
+        writeln!(out, "layer,z,kind,element,id,index,x,y,x1,y1")?;
+        for (layer_index, layer) in self.layers.iter().enumerate() {
+            let z = f(layer.height);
+            for (element, l) in layer.loops.iter().enumerate() {
+                let kind = if l.kind() == LoopKind::Open {
+                    "polyline"
+                } else {
+                    "loop"
+                };
+                let id = T::meta_to_i64(l.id);
+                for (index, p) in l.points.chunks_exact(2).enumerate() {
+                    writeln!(
+                        out,
+                        "{},{},{},{},{},{},{},{},,",
+                        layer_index,
+                        z,
+                        kind,
+                        element,
+                        id,
+                        index,
+                        f(p[0]),
+                        f(p[1])
+                    )?;
+                }
+            }
+            for (element, h) in layer.hatches.iter().enumerate() {
+                let id = T::meta_to_i64(h.id);
+                for (index, v) in h.iter().enumerate() {
+                    writeln!(
+                        out,
+                        "{},{},hatch,{},{},{},{},{},{},{}",
+                        layer_index,
+                        z,
+                        element,
+                        id,
+                        index,
+                        f(v[0]),
+                        f(v[1]),
+                        f(v[2]),
+                        f(v[3])
+                    )?;
+                }
+            }
+        }
+        out.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::clitype::ShortCLI;
+    use crate::{Hatches, Header, Layer, Loop, CLI};
+    use std::borrow::Cow;
+
+    #[test]
+    fn csv() {
+        let model = CLI::<ShortCLI> {
+            header: Header {
+                units: 0.5,
+                ..Header::default()
+            },
+            layers: vec![Layer {
+                loops: vec![
+                    Loop {
+                        id: 7,
+                        dir: 1,
+                        points: Cow::Owned(vec![0, 0, 4, 0]),
+                        params: Default::default(),
+                    },
+                    Loop {
+                        id: 8,
+                        dir: 2,
+                        points: Cow::Owned(vec![1, 3]),
+                        params: Default::default(),
+                    },
+                ],
+                hatches: vec![Hatches {
+                    id: 9,
+                    points: Cow::Owned(vec![1, 1, 3, 1, 1, 2, 3, 2]),
+                    params: Default::default(),
+                }],
+                ..Layer::empty(1)
+            }],
+            verbatim: Default::default(),
+        };
+        let mut out = Vec::new();
+        model.dump_csv(&mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "layer,z,kind,element,id,index,x,y,x1,y1\n\
+             0,0.5,loop,0,7,0,0,0,,\n\
+             0,0.5,loop,0,7,1,2,0,,\n\
+             0,0.5,polyline,1,8,0,0.5,1.5,,\n\
+             0,0.5,hatch,0,9,0,0.5,0.5,1.5,0.5\n\
+             0,0.5,hatch,0,9,1,0.5,1,1.5,1\n"
+        );
+    }
+}
@@ -0,0 +1,158 @@
+//! DXF export of layers
+//!
+//! CAD tools are a convenient way to review and measure the geometry of a layer.
+//! [`CLI::write_dxf`] writes a layer as a DXF drawing in millimeters:
+//!
+//! - each loop becomes an `LWPOLYLINE` on the DXF layer `CONTOURS`, closed unless the loop is an
+//!   open polyline, at the elevation of the layer,
+//! - each hatch becomes a `LINE` on the DXF layer `HATCHES`.
+//!
+//! Only the header and the entities sections are written, a minimal form of DXF that most CAD
+//! tools accept.
+//!
+//! ```no_run
+//! use colain::{CLI, clitype::LongCLI};
+//! # let bytes = Vec::new();
+//!
+//! let model = CLI::<LongCLI>::new(&bytes).unwrap();
+//! let file = std::fs::File::create("layer_10.dxf").unwrap();
+//! model.write_dxf(10, std::io::BufWriter::new(file)).unwrap();
+//! ```
+
+use crate::clitype::CLIType;
+use crate::{LoopKind, CLI};
+use std::fmt::Display;
+use std::io::{self, Write};
+
+/// DXF layer of the loops
+const CONTOURS: &str = "CONTOURS";
+
+/// DXF layer of the hatches
+const HATCHES: &str = "HATCHES";
+
+/// Write a group code and its value
+fn pair<W: Write>(out: &mut W, code: u16, value: impl Display) -> io::Result<()> {
+    write!(out, "{}\n{}\n", code, value)
+}
+
+impl<'a, T: CLIType> CLI<'a, T> {
+    /// Write the layer with the given index as a DXF drawing, see the
+    /// [module documentation](self).
+    ///
+    /// A closed loop whose last point repeats the first one is written without the repeated
+    /// point, the polyline is closed by its flag instead.
+    ///
+    /// # Panics
+    /// Panics if `index` is out of bounds.
+    pub fn write_dxf<W: Write>(&self, index: usize, mut out: W) -> io::Result<()> {
+        let layer = &self.layers[index];
+        let f = |c| T::coord_to_f64(c) * self.header.units;
+        let z = f(layer.height);
+        let out = &mut out;
+
+        pair(out, 0, "SECTION")?;
+        pair(out, 2, "HEADER")?;
+        pair(out, 9, "$ACADVER")?;
+        pair(out, 1, "AC1015")?;
+        // Millimeters
+        pair(out, 9, "$INSUNITS")?;
+        pair(out, 70, 4)?;
+        pair(out, 0, "ENDSEC")?;
+
+        pair(out, 0, "SECTION")?;
+        pair(out, 2, "ENTITIES")?;
+        for l in &layer.loops {
+            let mut points: Vec<[f64; 2]> = l
+                .points
+                .chunks_exact(2)
+                .map(|p| [f(p[0]), f(p[1])])
+                .collect();
+            let closed = l.kind() != LoopKind::Open;
+            if closed && points.len() > 1 && points.first() == points.last() {
+                points.pop();
+            }
+            pair(out, 0, "LWPOLYLINE")?;
+            pair(out, 8, CONTOURS)?;
+            pair(out, 90, points.len())?;
+            pair(out, 70, closed as u8)?;
+            pair(out, 38, z)?;
+            for p in points {
+                pair(out, 10, p[0])?;
+                pair(out, 20, p[1])?;
+            }
+        }
+        for v in layer.hatches.iter().flat_map(|h| h.iter()) {
+            pair(out, 0, "LINE")?;
+            pair(out, 8, HATCHES)?;
+            for (code, value) in [(10, v[0]), (20, v[1])] {
+                pair(out, code, f(value))?;
+            }
+            pair(out, 30, z)?;
+            for (code, value) in [(11, v[2]), (21, v[3])] {
+                pair(out, code, f(value))?;
+            }
+            pair(out, 31, z)?;
+        }
+        pair(out, 0, "ENDSEC")?;
+        pair(out, 0, "EOF")?;
+        out.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::clitype::ShortCLI;
+    use crate::{CLIBuilder, CLI};
+
+    #[test]
+    fn layer() {
+        let model: CLI<'_, ShortCLI> = CLIBuilder::new()
+            .short()
+            .units(0.5)
+            .layer(4)
+            .polyline(1, 1, vec![0, 0, 4, 0, 4, 4, 0, 0])
+            .polyline(2, 2, vec![10, 0, 12, 2])
+            .hatches(3, vec![1, 1, 3, 1])
+            .build()
+            .unwrap();
+        let mut out = Vec::new();
+        model.write_dxf(0, &mut out).unwrap();
+        let dxf = String::from_utf8(out).unwrap();
+        let lines: Vec<&str> = dxf.lines().collect();
+        let pairs: Vec<(&str, &str)> = lines.chunks(2).map(|p| (p[0], p[1])).collect();
+
+        let entities = pairs.iter().position(|&p| p == ("2", "ENTITIES")).unwrap();
+        let contour = &pairs[entities + 1..entities + 12];
+        assert_eq!(
+            contour,
+            [
+                ("0", "LWPOLYLINE"),
+                ("8", "CONTOURS"),
+                ("90", "3"),
+                ("70", "1"),
+                ("38", "2"),
+                ("10", "0"),
+                ("20", "0"),
+                ("10", "2"),
+                ("20", "0"),
+                ("10", "2"),
+                ("20", "2"),
+            ]
+        );
+        assert_eq!(pairs[entities + 15], ("70", "0"));
+        let line = pairs.iter().position(|&p| p == ("0", "LINE")).unwrap();
+        assert_eq!(
+            pairs[line + 1..line + 8],
+            [
+                ("8", "HATCHES"),
+                ("10", "0.5"),
+                ("20", "0.5"),
+                ("30", "2"),
+                ("11", "1.5"),
+                ("21", "0.5"),
+                ("31", "2"),
+            ]
+        );
+        assert_eq!(pairs.last(), Some(&("0", "EOF")));
+    }
+}
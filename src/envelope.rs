@@ -0,0 +1,379 @@
+//! Checking geometry against the build volume of a machine
+//!
+//! Machines reject, or worse, silently misbehave on geometry outside of their build volume.
+//! [`CLI::check_envelope`] reports every such element before a file is sent to the machine and
+//! [`CLI::clip_to_envelope`] removes it.
+//!
+//! ```no_run
+//! use colain::{CLI, Envelope, clitype::LongCLI};
+//! # let bytes = Vec::new();
+//!
+//! let model = CLI::<LongCLI>::new(&bytes).unwrap();
+//! let envelope = Envelope::new(250.0, 250.0, 300.0);
+//! for v in model.check_envelope(&envelope) {
+//!     eprintln!("layer {} at {}mm exceeds the build volume", v.layer, v.height);
+//! }
+//! ```
+
+use crate::clitype::CLIType;
+use crate::geom::{self, BoundingBox};
+use crate::{Hatches, Header, Layer, Loop, LoopKind, VectorParams, CLI};
+use std::borrow::Cow;
+
+/// An axis aligned build volume in millimeters
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Envelope {
+    /// Lower corner
+    pub min: [f64; 3],
+    /// Upper corner
+    pub max: [f64; 3],
+}
+
+impl Envelope {
+    /// Build volume of the given size with its lower corner at the origin
+    pub fn new(width: f64, depth: f64, height: f64) -> Self {
+        Envelope {
+            min: [0.0; 3],
+            max: [width, depth, height],
+        }
+    }
+
+    fn contains_z(&self, z: f64) -> bool {
+        (self.min[2]..=self.max[2]).contains(&z)
+    }
+
+    fn contains(&self, p: [f64; 2]) -> bool {
+        (self.min[0]..=self.max[0]).contains(&p[0]) && (self.min[1]..=self.max[1]).contains(&p[1])
+    }
+}
+
+/// Geometry of a layer outside of an [`Envelope`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct EnvelopeViolation {
+    /// Index of the layer
+    pub layer: usize,
+    /// Height of the layer in millimeters
+    pub height: f64,
+    /// True if the whole layer is above or below the envelope
+    pub outside_z: bool,
+    /// Indices of loops with at least one point outside
+    pub loops: Vec<usize>,
+    /// (hatch block, hatch index) of hatches with an end point outside
+    pub hatches: Vec<(usize, usize)>,
+}
+
+impl<'a, T: CLIType> CLI<'a, T> {
+    /// Report the layers with geometry outside of the envelope
+    pub fn check_envelope(&self, envelope: &Envelope) -> Vec<EnvelopeViolation> {
+        let units = self.header.units;
+        let point = |x, y| [T::coord_to_f64(x) * units, T::coord_to_f64(y) * units];
+        let mut found = Vec::new();
+        for (index, layer) in self.layers.iter().enumerate() {
+            let height = T::coord_to_f64(layer.height) * units;
+            let loops: Vec<usize> = layer
+                .loops
+                .iter()
+                .enumerate()
+                .filter(|(_, l)| {
+                    l.points
+                        .chunks_exact(2)
+                        .any(|p| !envelope.contains(point(p[0], p[1])))
+                })
+                .map(|(i, _)| i)
+                .collect();
+            let hatches: Vec<(usize, usize)> = layer
+                .hatches
+                .iter()
+                .enumerate()
+                .flat_map(|(b, h)| h.iter().enumerate().map(move |(i, v)| (b, i, v)))
+                .filter(|(_, _, v)| {
+                    !envelope.contains(point(v[0], v[1])) || !envelope.contains(point(v[2], v[3]))
+                })
+                .map(|(b, i, _)| (b, i))
+                .collect();
+            let outside_z = !envelope.contains_z(height);
+            if outside_z || !loops.is_empty() || !hatches.is_empty() {
+                found.push(EnvelopeViolation {
+                    layer: index,
+                    height,
+                    outside_z,
+                    loops,
+                    hatches,
+                });
+            }
+        }
+        found
+    }
+
+    /// Copy of the model with everything outside of the envelope removed.
+    ///
+    /// Layers above or below the envelope are dropped. Closed loops are clipped to the envelope and
+    /// stay closed, open polylines and hatches are cut at its boundary. Process parameters of
+    /// hatches are kept for the remaining vectors, those of clipped loops are dropped.
+    pub fn clip_to_envelope(&self, envelope: &Envelope) -> CLI<'static, T> {
+        let units = self.header.units;
+        let to_f = |c| T::coord_to_f64(c) * units;
+        let from_f = |v: f64| T::coord_from_f64(v / units);
+        let coords = |points: &[[f64; 2]]| -> Vec<<T as CLIType>::Coord> {
+            points
+                .iter()
+                .flat_map(|p| [from_f(p[0]), from_f(p[1])])
+                .collect()
+        };
+
+        let layers: Vec<Layer<'static, T>> = self
+            .layers
+            .iter()
+            .filter(|l| envelope.contains_z(to_f(l.height)))
+            .map(|layer| {
+                let mut loops = Vec::new();
+                for l in layer.loops.iter() {
+                    let points: Vec<[f64; 2]> = l
+                        .points
+                        .chunks_exact(2)
+                        .map(|p| [to_f(p[0]), to_f(p[1])])
+                        .collect();
+                    if points.iter().all(|&p| envelope.contains(p)) {
+                        loops.push(Loop {
+                            id: l.id,
+                            dir: l.dir,
+                            points: Cow::Owned(l.points.to_vec()),
+                            params: l.params.to_owned_params(),
+                        });
+                        continue;
+                    }
+                    let pieces = if l.kind() == LoopKind::Open {
+                        clip_polyline(&points, envelope)
+                    } else {
+                        clip_polygon(&points, envelope).into_iter().collect()
+                    };
+                    loops.extend(pieces.iter().map(|piece| Loop {
+                        id: l.id,
+                        dir: l.dir,
+                        points: Cow::Owned(coords(piece)),
+                        params: VectorParams::default(),
+                    }));
+                }
+
+                let mut hatches = Vec::new();
+                for h in layer.hatches.iter() {
+                    let mut kept = Vec::new();
+                    let mut points = Vec::new();
+                    for (i, v) in h.iter().enumerate() {
+                        if let Some([a, b]) = clip_segment(
+                            [to_f(v[0]), to_f(v[1])],
+                            [to_f(v[2]), to_f(v[3])],
+                            envelope,
+                        ) {
+                            kept.push(i);
+                            points.extend(coords(&[a, b]));
+                        }
+                    }
+                    if kept.is_empty() {
+                        continue;
+                    }
+                    let select = |values: &Option<Cow<'_, [<T as CLIType>::Coord]>>| {
+                        values.as_ref().map(|v| {
+                            Cow::Owned(kept.iter().filter_map(|&i| v.get(i).copied()).collect())
+                        })
+                    };
+                    hatches.push(Hatches {
+                        id: h.id,
+                        points: Cow::Owned(points),
+                        params: VectorParams {
+                            power: select(&h.params.power),
+                            speed: select(&h.params.speed),
+                        },
+                    });
+                }
+
+                let end = loops.len() + hatches.len();
+                Layer {
+                    user_data: layer
+                        .user_data
+                        .iter()
+                        .map(|u| crate::UserData {
+                            position: u.position.min(end),
+                            ..u.to_owned_data()
+                        })
+                        .collect(),
+                    loops,
+                    hatches,
+                    ..Layer::empty(layer.height)
+                }
+            })
+            .collect();
+
+        CLI {
+            header: Header {
+                layers: Some(layers.len()),
+                ..self.header.clone()
+            },
+            layers,
+            verbatim: Default::default(),
+        }
+    }
+}
+
+/// Clip a segment to the XY extent of the envelope
+fn clip_segment(a: [f64; 2], b: [f64; 2], envelope: &Envelope) -> Option<[[f64; 2]; 2]> {
+    let bb = BoundingBox {
+        min: [envelope.min[0], envelope.min[1]],
+        max: [envelope.max[0], envelope.max[1]],
+    };
+    geom::clip_segment(a, b, &bb)
+}
+
+/// Cut an open polyline into the pieces inside the envelope
+fn clip_polyline(points: &[[f64; 2]], envelope: &Envelope) -> Vec<Vec<[f64; 2]>> {
+    let mut pieces: Vec<Vec<[f64; 2]>> = Vec::new();
+    let mut current: Vec<[f64; 2]> = Vec::new();
+    for w in points.windows(2) {
+        match clip_segment(w[0], w[1], envelope) {
+            Some([a, b]) => {
+                if current.last() != Some(&a) {
+                    if current.len() > 1 {
+                        pieces.push(std::mem::take(&mut current));
+                    }
+                    current.clear();
+                    current.push(a);
+                }
+                current.push(b);
+            }
+            None => {
+                if current.len() > 1 {
+                    pieces.push(std::mem::take(&mut current));
+                }
+                current.clear();
+            }
+        }
+    }
+    if current.len() > 1 {
+        pieces.push(current);
+    }
+    pieces
+}
+
+/// Sutherland-Hodgman clipping of a closed polygon to the XY extent of the envelope
+fn clip_polygon(points: &[[f64; 2]], envelope: &Envelope) -> Option<Vec<[f64; 2]>> {
+    let mut poly: Vec<[f64; 2]> = points.to_vec();
+    if poly.len() > 1 && poly.first() == poly.last() {
+        poly.pop();
+    }
+    for axis in 0..2 {
+        for (limit, keep_above) in [(envelope.min[axis], true), (envelope.max[axis], false)] {
+            let inside = |p: &[f64; 2]| {
+                if keep_above {
+                    p[axis] >= limit
+                } else {
+                    p[axis] <= limit
+                }
+            };
+            let mut out = Vec::with_capacity(poly.len() + 2);
+            for (i, &a) in poly.iter().enumerate() {
+                let b = poly[(i + 1) % poly.len()];
+                if inside(&a) {
+                    out.push(a);
+                }
+                if inside(&a) != inside(&b) {
+                    let t = (limit - a[axis]) / (b[axis] - a[axis]);
+                    out.push([a[0] + t * (b[0] - a[0]), a[1] + t * (b[1] - a[1])]);
+                }
+            }
+            poly = out;
+            if poly.is_empty() {
+                return None;
+            }
+        }
+    }
+    if poly.len() < 3 {
+        return None;
+    }
+    poly.push(poly[0]);
+    Some(poly)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clitype::LongCLI;
+
+    fn model() -> CLI<'static, LongCLI> {
+        let layer = |height| Layer {
+            loops: vec![
+                Loop {
+                    id: 0,
+                    dir: 1,
+                    points: Cow::Owned(vec![0.0, 0.0, 10.0, 0.0, 10.0, 10.0, 0.0, 10.0, 0.0, 0.0]),
+                    params: Default::default(),
+                },
+                Loop {
+                    id: 0,
+                    dir: 1,
+                    points: Cow::Owned(vec![
+                        80.0, 0.0, 120.0, 0.0, 120.0, 10.0, 80.0, 10.0, 80.0, 0.0,
+                    ]),
+                    params: Default::default(),
+                },
+                Loop {
+                    id: 0,
+                    dir: 2,
+                    points: Cow::Owned(vec![90.0, 20.0, 110.0, 20.0, 110.0, 30.0, 90.0, 30.0]),
+                    params: Default::default(),
+                },
+            ],
+            hatches: vec![Hatches {
+                id: 0,
+                points: Cow::Owned(vec![
+                    1.0, 1.0, 9.0, 1.0, 95.0, 5.0, 105.0, 5.0, 101.0, 5.0, 105.0, 5.0,
+                ]),
+                params: VectorParams {
+                    power: Some(Cow::Owned(vec![100.0, 200.0, 300.0])),
+                    speed: None,
+                },
+            }],
+            ..Layer::empty(height)
+        };
+        CLI {
+            header: Header::default(),
+            layers: vec![layer(1.0), layer(60.0)],
+            verbatim: Default::default(),
+        }
+    }
+
+    #[test]
+    fn check() {
+        let violations = model().check_envelope(&Envelope::new(100.0, 100.0, 50.0));
+        assert_eq!(violations.len(), 2);
+        assert!(!violations[0].outside_z);
+        assert_eq!(violations[0].loops, vec![1, 2]);
+        assert_eq!(violations[0].hatches, vec![(0, 1), (0, 2)]);
+        assert!(violations[1].outside_z);
+
+        assert!(model()
+            .check_envelope(&Envelope::new(200.0, 200.0, 100.0))
+            .is_empty());
+    }
+
+    #[test]
+    fn clip() {
+        let clipped = model().clip_to_envelope(&Envelope::new(100.0, 100.0, 50.0));
+        assert_eq!(clipped.layers.len(), 1);
+        let layer = &clipped.layers[0];
+        assert_eq!(layer.loops.len(), 4);
+        let area = geom::signed_area(&geom::to_points(layer.loops[1].points(), |c| c as f64));
+        assert_eq!(area, 200.0);
+        // the open path leaves the envelope and comes back
+        assert_eq!(layer.loops[2].points(), &[90.0, 20.0, 100.0, 20.0]);
+        assert_eq!(layer.loops[3].points(), &[100.0, 30.0, 90.0, 30.0]);
+        let hatches = &layer.hatches[0];
+        assert_eq!(
+            hatches.points(),
+            &[1.0, 1.0, 9.0, 1.0, 95.0, 5.0, 100.0, 5.0]
+        );
+        assert_eq!(hatches.params().power(), Some(&[100.0f32, 200.0][..]));
+        assert!(clipped
+            .check_envelope(&Envelope::new(100.0, 100.0, 50.0))
+            .is_empty());
+    }
+}
@@ -0,0 +1,161 @@
+//! Scan length per id
+//!
+//! Machines commonly map the id of a loop or hatch block to a parameter set. Summing the scan
+//! length per id lets a toolchain verify how much of each layer every parameter set exposes.
+
+use crate::clitype::CLIType;
+use crate::geom;
+use crate::{Layer, CLI};
+use std::collections::BTreeMap;
+
+/// Scan length of one id within a layer, see [`Layer::exposure_by_id`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct Exposure {
+    /// The id of the loops and hatch blocks
+    pub id: i64,
+    /// Total length of the loops with this id
+    pub contour_length: f64,
+    /// Total length of the hatch vectors with this id
+    pub hatch_length: f64,
+    /// Share of the total scan length of the layer, from 0 to 1
+    pub fraction: f64,
+}
+
+impl Exposure {
+    /// Combined length of contours and hatches
+    pub fn total(&self) -> f64 {
+        self.contour_length + self.hatch_length
+    }
+}
+
+impl<'a, T: CLIType> Layer<'a, T> {
+    /// Scan length of every id used in the layer, ordered by id, in coordinate units.
+    ///
+    /// Jumps between vectors are not counted.
+    pub fn exposure_by_id(&self) -> Vec<Exposure> {
+        let f = |c| T::coord_to_f64(c);
+        let mut by_id: BTreeMap<i64, (f64, f64)> = BTreeMap::new();
+        for l in self.loops.iter() {
+            let length = geom::path_length(&geom::to_points(&l.points, f));
+            by_id.entry(T::meta_to_i64(l.id)).or_default().0 += length;
+        }
+        for h in self.hatches.iter() {
+            let length: f64 = h
+                .iter()
+                .map(|v| (f(v[2]) - f(v[0])).hypot(f(v[3]) - f(v[1])))
+                .sum();
+            by_id.entry(T::meta_to_i64(h.id)).or_default().1 += length;
+        }
+
+        let total: f64 = by_id.values().map(|(c, h)| c + h).sum();
+        by_id
+            .into_iter()
+            .map(|(id, (contour_length, hatch_length))| Exposure {
+                id,
+                contour_length,
+                hatch_length,
+                fraction: if total > 0.0 {
+                    (contour_length + hatch_length) / total
+                } else {
+                    0.0
+                },
+            })
+            .collect()
+    }
+}
+
+impl<'a, T: CLIType> CLI<'a, T> {
+    /// [`Layer::exposure_by_id`] of every layer, with lengths in millimeters
+    pub fn exposure_report(&self) -> Vec<Vec<Exposure>> {
+        let units = self.header.units;
+        self.layers
+            .iter()
+            .map(|l| {
+                let mut exposure = l.exposure_by_id();
+                for e in exposure.iter_mut() {
+                    e.contour_length *= units;
+                    e.hatch_length *= units;
+                }
+                exposure
+            })
+            .collect()
+    }
+
+    /// Scan length of every id summed over all layers, in millimeters.
+    ///
+    /// The fraction is relative to the scan length of the whole model.
+    pub fn exposure_totals(&self) -> Vec<Exposure> {
+        let mut by_id: BTreeMap<i64, Exposure> = BTreeMap::new();
+        for e in self.exposure_report().into_iter().flatten() {
+            let sum = by_id.entry(e.id).or_insert(Exposure {
+                id: e.id,
+                contour_length: 0.0,
+                hatch_length: 0.0,
+                fraction: 0.0,
+            });
+            sum.contour_length += e.contour_length;
+            sum.hatch_length += e.hatch_length;
+        }
+        let total: f64 = by_id.values().map(Exposure::total).sum();
+        by_id
+            .into_values()
+            .map(|mut e| {
+                e.fraction = if total > 0.0 { e.total() / total } else { 0.0 };
+                e
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::clitype::ShortCLI;
+    use crate::{Hatches, Header, Layer, Loop, CLI};
+    use std::borrow::Cow;
+
+    #[test]
+    fn by_id() {
+        let layer = Layer::<ShortCLI> {
+            loops: vec![Loop {
+                id: 1,
+                dir: 1,
+                points: Cow::Owned(vec![0, 0, 10, 0, 10, 10, 0, 10, 0, 0]),
+                params: Default::default(),
+            }],
+            hatches: vec![
+                Hatches {
+                    id: 2,
+                    points: Cow::Owned(vec![1, 1, 9, 1, 1, 2, 9, 2]),
+                    params: Default::default(),
+                },
+                Hatches {
+                    id: 1,
+                    points: Cow::Owned(vec![1, 5, 5, 5]),
+                    params: Default::default(),
+                },
+            ],
+            ..Layer::empty(1)
+        };
+        let model = CLI {
+            header: Header {
+                units: 0.5,
+                ..Header::default()
+            },
+            layers: vec![layer.clone(), layer],
+            verbatim: Default::default(),
+        };
+
+        let layer = &model.exposure_report()[0];
+        assert_eq!(layer.len(), 2);
+        assert_eq!(
+            (layer[0].id, layer[0].contour_length, layer[0].hatch_length),
+            (1, 20.0, 2.0)
+        );
+        assert_eq!((layer[1].id, layer[1].hatch_length), (2, 8.0));
+        assert!((layer[0].fraction - 22.0 / 30.0).abs() < 1e-12);
+
+        let totals = model.exposure_totals();
+        assert_eq!(totals[1].total(), 16.0);
+        assert!((totals[1].fraction - 8.0 / 30.0).abs() < 1e-12);
+    }
+}
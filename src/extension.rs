@@ -0,0 +1,270 @@
+//! Extension records in the binary geometry section
+//!
+//! The CLI spec only defines commands for layers, polylines and hatches. Machine toolchains
+//! for metal additive manufacturing add records of their own, with codes that differ between
+//! vendors. The codes below are defined by this crate, continuing after those of the spec, and
+//! are not a standard. Since a vendor may use the same codes for records of its own, they are
+//! only decoded with [`Parser::extensions`], otherwise they are unknown commands like any other,
+//! see [`Parser::skip_unknown`] and [`Parser::vendor_commands`].
+//!
+//! | Record      | Short | Long | Parameters                                        |
+//! |-------------|-------|------|---------------------------------------------------|
+//! | Laser power | 133   | 134  | number of values `n`, `n` values                  |
+//! | Scan speed  | 135   | 136  | number of values `n`, `n` values                  |
+//! | User data   | 137   | 138  | id, number of bytes `n`, `n` bytes                |
+//!
+//! ```
+//! use colain::{clitype::LongCLI, Parser};
+//! # let buf = colain::BuildPlate::<LongCLI>::new(1.0).to_bytes();
+//!
+//! let model = Parser::new().extensions().parse(&buf).unwrap();
+//! for layer in model.iter() {
+//!     for hatches in layer.iter_hatches() {
+//!         println!("{:?}", hatches.params().power());
+//!     }
+//! }
+//! ```
+//!
+//! ## Process parameters
+//!
+//! Power and speed records follow a geometry block and are attached to the preceding
+//! [`Loop`](crate::Loop) or [`Hatches`](crate::Hatches) as [`VectorParams`].
+//! The count is stored like any other metadata (and is therefore affected by `$$ALIGN`), the values
+//! are tightly packed and use the coordinate type of the file. A polyline of `k` points has `k - 1`
+//! vectors while a hatch block has one vector per hatch. The number of values is not validated
+//! against the block since toolchains disagree on whether the closing vector of a loop is included.
+//!
+//! Units of the values are machine specific and are passed through unchanged.
+//!
+//! ## User data
+//!
+//! User data records hold an opaque payload, vendors typically store per-layer process notes in them.
+//! They are attached to the current [`Layer`](crate::Layer) as [`UserData`] together with their
+//! position among the geometry blocks of that layer. When the file is aligned the payload is padded
+//! with zeros to a multiple of 4 bytes.
+//!
+//! ## Writing
+//!
+//! Models holding parameters or user data are written with these records. Readers other than this
+//! crate reject such files, and this crate only reads them back with [`Parser::extensions`].
+//!
+//! [`Parser::extensions`]: crate::Parser::extensions
+//! [`Parser::skip_unknown`]: crate::Parser::skip_unknown
+//! [`Parser::vendor_commands`]: crate::Parser::vendor_commands
+
+use crate::clitype::CLIType;
+use std::borrow::Cow;
+
+/// Per-vector process parameters attached to a geometry block
+#[derive(Debug, Clone)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(bound(
+        serialize = "<T as CLIType>::Coord: serde::Serialize, <T as CLIType>::Meta: serde::Serialize",
+        deserialize = "<T as CLIType>::Coord: serde::Deserialize<'de>, <T as CLIType>::Meta: serde::Deserialize<'de>"
+    ))
+)]
+pub struct VectorParams<'a, T: CLIType> {
+    pub(crate) power: Option<Cow<'a, [<T as CLIType>::Coord]>>,
+    pub(crate) speed: Option<Cow<'a, [<T as CLIType>::Coord]>>,
+}
+
+impl<'a, T: CLIType> VectorParams<'a, T> {
+    /// Laser power of each vector, if present in the file
+    pub fn power(&self) -> Option<&[<T as CLIType>::Coord]> {
+        self.power.as_deref()
+    }
+    /// Scan speed of each vector, if present in the file
+    pub fn speed(&self) -> Option<&[<T as CLIType>::Coord]> {
+        self.speed.as_deref()
+    }
+    /// True if no parameters were attached to the block
+    pub fn is_empty(&self) -> bool {
+        self.power.is_none() && self.speed.is_none()
+    }
+
+    /// Copy borrowed values so the parameters no longer reference the file
+    pub(crate) fn to_owned_params(&self) -> VectorParams<'static, T> {
+        VectorParams {
+            power: self.power.as_ref().map(|p| Cow::Owned(p.to_vec())),
+            speed: self.speed.as_ref().map(|s| Cow::Owned(s.to_vec())),
+        }
+    }
+}
+
+/// Opaque user data stored in a layer
+#[derive(Debug, Clone)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(bound(
+        serialize = "<T as CLIType>::Coord: serde::Serialize, <T as CLIType>::Meta: serde::Serialize",
+        deserialize = "<T as CLIType>::Coord: serde::Deserialize<'de>, <T as CLIType>::Meta: serde::Deserialize<'de>"
+    ))
+)]
+pub struct UserData<'a, T: CLIType> {
+    pub(crate) id: <T as CLIType>::Meta,
+    pub(crate) position: usize,
+    pub(crate) data: Cow<'a, [u8]>,
+}
+
+impl<'a, T: CLIType> UserData<'a, T> {
+    /// Identifier of the record, its meaning is vendor specific
+    pub fn id(&self) -> <T as CLIType>::Meta {
+        self.id
+    }
+    /// Number of geometry blocks of the layer preceding this record.
+    ///
+    /// Blocks are counted in the order they were read, which is also the order in which they are
+    /// written. Loops and hatches of a layer not read from a file count loops first.
+    pub fn position(&self) -> usize {
+        self.position
+    }
+    /// The payload
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Copy a borrowed payload so the record no longer references the file
+    pub(crate) fn to_owned_data(&self) -> UserData<'static, T> {
+        UserData {
+            id: self.id,
+            position: self.position,
+            data: Cow::Owned(self.data.to_vec()),
+        }
+    }
+}
+
+impl<T: CLIType> Default for VectorParams<'_, T> {
+    fn default() -> Self {
+        VectorParams {
+            power: None,
+            speed: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clitype::ShortCLI;
+    use crate::{writer, Error, Hatches, Header, Layer, Loop, Parser, CLI};
+
+    fn header() -> Header {
+        Header {
+            aligned: true,
+            ..Header::default()
+        }
+    }
+
+    #[test]
+    fn round_trip() -> Result<(), Error> {
+        let layer = Layer::<ShortCLI> {
+            hatches: vec![Hatches {
+                id: 3,
+                points: Cow::Owned(vec![0, 0, 10, 0, 0, 5, 10, 5]),
+                params: VectorParams {
+                    power: Some(Cow::Owned(vec![200, 180])),
+                    speed: None,
+                },
+            }],
+            ..Layer::empty(10)
+        };
+        let bytes = writer::encode(&header(), &[layer]);
+        // Not records of the spec
+        assert!(matches!(
+            CLI::<ShortCLI>::new(&bytes).map_err(Error::into_inner),
+            Err(Error::InvalidGeometryCommand(133))
+        ));
+
+        let model = Parser::new().short().extensions().parse(&bytes)?;
+        let hatches = model.iter().next().unwrap().iter_hatches().next().unwrap();
+        assert_eq!(hatches.params().power(), Some(&[200u16, 180][..]));
+        assert_eq!(hatches.params().speed(), None);
+        Ok(())
+    }
+
+    #[test]
+    fn user_data() -> Result<(), Error> {
+        let square = || Loop {
+            id: 1,
+            dir: 1,
+            points: Cow::Owned(vec![0, 0, 1, 0, 1, 1, 0, 0]),
+            params: VectorParams::default(),
+        };
+        let layer = Layer::<ShortCLI> {
+            loops: vec![square(), square()],
+            user_data: vec![
+                UserData {
+                    id: 7,
+                    position: 1,
+                    data: Cow::Borrowed(b"recoat slowly"),
+                },
+                UserData {
+                    id: 8,
+                    position: 2,
+                    data: Cow::Borrowed(b"done"),
+                },
+            ],
+            ..Layer::empty(10)
+        };
+        let bytes = writer::encode(&header(), &[layer]);
+
+        let model = Parser::new().short().extensions().parse(&bytes)?;
+        let layer = model.iter().next().unwrap();
+        assert_eq!(layer.iter_loops().count(), 2);
+        let data = layer.user_data();
+        assert_eq!(data.len(), 2);
+        assert_eq!((data[0].id(), data[0].position()), (7, 1));
+        assert_eq!(data[0].data(), b"recoat slowly");
+        assert_eq!((data[1].id(), data[1].position()), (8, 2));
+        assert_eq!(data[1].data(), b"done");
+        Ok(())
+    }
+
+    #[test]
+    fn block_order() -> Result<(), Error> {
+        let mut layer = Layer::<ShortCLI> {
+            user_data: vec![UserData {
+                id: 7,
+                position: 1,
+                data: Cow::Borrowed(b"between"),
+            }],
+            ..Layer::empty(10)
+        };
+        layer.push_hatches(Hatches {
+            id: 3,
+            points: Cow::Owned(vec![0, 0, 10, 0]),
+            params: VectorParams::default(),
+        });
+        layer.push_loop(Loop {
+            id: 1,
+            dir: 2,
+            points: Cow::Owned(vec![0, 0, 1, 1]),
+            params: VectorParams::default(),
+        });
+        let bytes = writer::encode(&header(), &[layer]);
+
+        let model = Parser::new().short().extensions().parse(&bytes)?;
+        let layer = model.iter().next().unwrap();
+        assert_eq!(layer.user_data()[0].position(), 1);
+        // Re-encoded rather than copied from the file
+        assert_eq!(model.to_owned_model().to_bytes(), bytes);
+        Ok(())
+    }
+
+    #[test]
+    fn unattached() {
+        let mut bytes = writer::encode::<ShortCLI>(&header(), &[Layer::empty(10)]);
+        bytes.extend_from_slice(&[135, 0, 0, 0, 1, 0, 0, 0, 100, 0]);
+        assert!(matches!(
+            Parser::new()
+                .short()
+                .extensions()
+                .parse(&bytes)
+                .map_err(Error::into_inner),
+            Err(Error::UnattachedExtension(135))
+        ));
+    }
+}
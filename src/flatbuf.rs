@@ -0,0 +1,777 @@
+//! FlatBuffers interchange of parsed models
+//!
+//! A parsing service can hand models to rendering or analysis processes as FlatBuffers, which the
+//! consumer reads in place without deserializing. Enable the `flatbuffers` feature to use this
+//! module. The schema is `proto/colain.fbs` in the repository, the tables in [`fb`] are
+//! implemented by hand following it so no code generation step is needed.
+//!
+//! [`root`] verifies a buffer and returns a view of the [`fb::Model`] borrowing from it. Converting
+//! to an owned [`CLI`] with [`CLI::from_flatbuffer`] is only needed to use the rest of this crate.
+//! Like the [`protobuf`](crate::protobuf) interchange, coordinates are stored in coordinate units
+//! as doubles, so the header and every layer survive a round trip. The text the model was parsed
+//! from, that is [`CLI::raw_header`] and [`CLI::unknown_records`], is not carried.
+//!
+//! ```
+//! use colain::{CLI, clitype::LongCLI, flatbuf};
+//! # let buf = colain::BuildPlate::<LongCLI>::new(1.0).to_bytes();
+//!
+//! let model = CLI::<LongCLI>::new(&buf).unwrap();
+//! let bytes = model.to_flatbuffer();
+//!
+//! // On the consumer side, nothing is copied
+//! let view = flatbuf::root(&bytes).unwrap();
+//! for layer in view.layers().into_iter().flatten() {
+//!     let points = layer.loops().into_iter().flatten().map(|l| l.points().map_or(0, |p| p.len()));
+//!     println!("z = {}, {} coordinates", layer.height(), points.sum::<usize>());
+//! }
+//! ```
+
+use crate::clitype::CLIType;
+use crate::{
+    Block, Date, Hatches, Header, HeaderUserData, Layer, Loop, UserData, VectorParams, CLI,
+};
+use flatbuffers::{FlatBufferBuilder, InvalidFlatbuffer, WIPOffset};
+use std::borrow::Cow;
+
+/// File identifier of the buffers
+pub const IDENTIFIER: &str = "COLN";
+
+/// Tables of `proto/colain.fbs`, as views into a buffer
+pub mod fb {
+    use flatbuffers::{
+        Follow, ForwardsUOffset, InvalidFlatbuffer, Table, VOffsetT, Vector, Verifiable, Verifier,
+    };
+
+    /// Vector of tables
+    pub type Tables<'a, T> = Vector<'a, ForwardsUOffset<T>>;
+
+    macro_rules! table {
+        ($(#[$doc:meta])* $name:ident { $($field:ident: $ty:ty = $slot:expr,)* }) => {
+            $(#[$doc])*
+            #[derive(Clone, Copy, PartialEq)]
+            pub struct $name<'a> {
+                table: Table<'a>,
+            }
+
+            impl<'a> Follow<'a> for $name<'a> {
+                type Inner = $name<'a>;
+                unsafe fn follow(buf: &'a [u8], loc: usize) -> Self::Inner {
+                    $name {
+                        table: Table::new(buf, loc),
+                    }
+                }
+            }
+
+            impl $name<'_> {
+                $(pub(crate) const $field: VOffsetT = $slot;)*
+            }
+
+            impl Verifiable for $name<'_> {
+                fn run_verifier(v: &mut Verifier, pos: usize) -> Result<(), InvalidFlatbuffer> {
+                    v.visit_table(pos)?
+                        $(.visit_field::<$ty>(stringify!($field), Self::$field, false)?)*
+                        .finish();
+                    Ok(())
+                }
+            }
+        };
+    }
+
+    /// Slot of the field with the given index
+    const fn slot(index: VOffsetT) -> VOffsetT {
+        4 + 2 * index
+    }
+
+    table! {
+        /// Header of a model, see [`crate::Header`]
+        Header {
+            BINARY: bool = slot(0),
+            UNITS: f64 = slot(1),
+            VERSION: f32 = slot(2),
+            ALIGNED: bool = slot(3),
+            LAYERS: u64 = slot(4),
+            DATE: ForwardsUOffset<&'_ str> = slot(5),
+            DIMENSION: ForwardsUOffset<Vector<'_, f64>> = slot(6),
+            LABELS: ForwardsUOffset<Tables<'_, Label<'_>>> = slot(7),
+            USER_DATA: ForwardsUOffset<Tables<'_, HeaderUserData<'_>>> = slot(8),
+            EXTRA: ForwardsUOffset<Tables<'_, Extra<'_>>> = slot(9),
+        }
+    }
+
+    table! {
+        /// A name given to an id, see [`crate::Header::labels`]
+        Label {
+            ID: i64 = slot(0),
+            TEXT: ForwardsUOffset<&'_ str> = slot(1),
+        }
+    }
+
+    table! {
+        /// A vendor specific header entry, see [`crate::HeaderUserData`]
+        HeaderUserData {
+            UID: ForwardsUOffset<&'_ str> = slot(0),
+            DATA: ForwardsUOffset<Vector<'_, u8>> = slot(1),
+        }
+    }
+
+    table! {
+        /// A header command not known to the crate, see [`crate::Header::extra`]
+        Extra {
+            NAME: ForwardsUOffset<&'_ str> = slot(0),
+            VALUE: ForwardsUOffset<&'_ str> = slot(1),
+        }
+    }
+
+    table! {
+        /// A loop, see [`crate::Loop`]
+        Loop {
+            ID: i64 = slot(0),
+            DIR: i64 = slot(1),
+            POINTS: ForwardsUOffset<Vector<'_, f64>> = slot(2),
+            POWER: ForwardsUOffset<Vector<'_, f64>> = slot(3),
+            SPEED: ForwardsUOffset<Vector<'_, f64>> = slot(4),
+        }
+    }
+
+    table! {
+        /// A hatch block, see [`crate::Hatches`]
+        Hatches {
+            ID: i64 = slot(0),
+            POINTS: ForwardsUOffset<Vector<'_, f64>> = slot(1),
+            POWER: ForwardsUOffset<Vector<'_, f64>> = slot(2),
+            SPEED: ForwardsUOffset<Vector<'_, f64>> = slot(3),
+        }
+    }
+
+    table! {
+        /// A user data record, see [`crate::UserData`]
+        UserData {
+            ID: i64 = slot(0),
+            POSITION: u64 = slot(1),
+            DATA: ForwardsUOffset<Vector<'_, u8>> = slot(2),
+        }
+    }
+
+    table! {
+        /// A layer, see [`crate::Layer`]
+        Layer {
+            HEIGHT: f64 = slot(0),
+            LOOPS: ForwardsUOffset<Tables<'_, Loop<'_>>> = slot(1),
+            HATCHES: ForwardsUOffset<Tables<'_, Hatches<'_>>> = slot(2),
+            USER_DATA: ForwardsUOffset<Tables<'_, UserData<'_>>> = slot(3),
+            BLOCKS: ForwardsUOffset<Vector<'_, bool>> = slot(4),
+        }
+    }
+
+    table! {
+        /// A complete model
+        Model {
+            HEADER: ForwardsUOffset<Header<'_>> = slot(0),
+            LAYERS: ForwardsUOffset<Tables<'_, Layer<'_>>> = slot(1),
+        }
+    }
+
+    // Safety of the accessors: the views are only handed out by `root` after verification, which
+    // checks that every present field has the type read here.
+    macro_rules! scalar {
+        ($(#[$doc:meta])* $name:ident: $ty:ty = $slot:ident) => {
+            $(#[$doc])*
+            pub fn $name(&self) -> $ty {
+                unsafe { self.table.get::<$ty>(Self::$slot, Some(Default::default())) }.unwrap_or_default()
+            }
+        };
+    }
+    macro_rules! vector {
+        ($(#[$doc:meta])* $name:ident: $ty:ty = $slot:ident) => {
+            $(#[$doc])*
+            pub fn $name(&self) -> Option<$ty> {
+                unsafe { self.table.get::<ForwardsUOffset<$ty>>(Self::$slot, None) }
+            }
+        };
+    }
+
+    impl<'a> Header<'a> {
+        scalar! {
+            /// True for binary files
+            binary: bool = BINARY
+        }
+        scalar! {
+            /// Millimeters per coordinate unit
+            units: f64 = UNITS
+        }
+        scalar! {
+            /// CLI version
+            version: f32 = VERSION
+        }
+        scalar! {
+            /// True if the binary file is aligned
+            aligned: bool = ALIGNED
+        }
+        /// Number of layers declared by the header
+        pub fn layers(&self) -> Option<u64> {
+            unsafe { self.table.get::<u64>(Self::LAYERS, None) }
+        }
+        vector! {
+            /// Build date as written in the header
+            date: &'a str = DATE
+        }
+        vector! {
+            /// Box around the part as x1, y1, z1, x2, y2, z2 in millimeters
+            dimension: Vector<'a, f64> = DIMENSION
+        }
+        vector! {
+            /// Names given to the ids of loops and hatches, sorted by id
+            labels: Tables<'a, Label<'a>> = LABELS
+        }
+        vector! {
+            /// Vendor specific entries in the order they appear
+            user_data: Tables<'a, HeaderUserData<'a>> = USER_DATA
+        }
+        vector! {
+            /// Commands not known to the crate in the order they appear
+            extra: Tables<'a, Extra<'a>> = EXTRA
+        }
+    }
+
+    impl<'a> Label<'a> {
+        scalar! {
+            /// Identifier of loops and hatches
+            id: i64 = ID
+        }
+        vector! {
+            /// The name
+            text: &'a str = TEXT
+        }
+    }
+
+    impl<'a> HeaderUserData<'a> {
+        vector! {
+            /// Identifier of the vendor or application
+            uid: &'a str = UID
+        }
+        vector! {
+            /// The payload
+            data: Vector<'a, u8> = DATA
+        }
+    }
+
+    impl<'a> Extra<'a> {
+        vector! {
+            /// Name of the command
+            name: &'a str = NAME
+        }
+        vector! {
+            /// Its value, absent if it has none
+            value: &'a str = VALUE
+        }
+    }
+
+    impl<'a> Loop<'a> {
+        scalar! {
+            /// Identifier
+            id: i64 = ID
+        }
+        scalar! {
+            /// Direction, 2 for open lines
+            dir: i64 = DIR
+        }
+        vector! {
+            /// Interleaved x and y coordinates
+            points: Vector<'a, f64> = POINTS
+        }
+        vector! {
+            /// Laser power of each vector
+            power: Vector<'a, f64> = POWER
+        }
+        vector! {
+            /// Scan speed of each vector
+            speed: Vector<'a, f64> = SPEED
+        }
+    }
+
+    impl<'a> Hatches<'a> {
+        scalar! {
+            /// Identifier
+            id: i64 = ID
+        }
+        vector! {
+            /// Interleaved start and end points
+            points: Vector<'a, f64> = POINTS
+        }
+        vector! {
+            /// Laser power of each vector
+            power: Vector<'a, f64> = POWER
+        }
+        vector! {
+            /// Scan speed of each vector
+            speed: Vector<'a, f64> = SPEED
+        }
+    }
+
+    impl<'a> UserData<'a> {
+        scalar! {
+            /// Identifier
+            id: i64 = ID
+        }
+        scalar! {
+            /// Number of geometry blocks preceding the record
+            position: u64 = POSITION
+        }
+        vector! {
+            /// The payload
+            data: Vector<'a, u8> = DATA
+        }
+    }
+
+    impl<'a> Layer<'a> {
+        scalar! {
+            /// Height in coordinate units
+            height: f64 = HEIGHT
+        }
+        vector! {
+            /// Loops in file order
+            loops: Tables<'a, Loop<'a>> = LOOPS
+        }
+        vector! {
+            /// Hatch blocks in file order
+            hatches: Tables<'a, Hatches<'a>> = HATCHES
+        }
+        vector! {
+            /// User data records
+            user_data: Tables<'a, UserData<'a>> = USER_DATA
+        }
+        vector! {
+            /// Kind of each geometry block in file order, true for hatches
+            blocks: Vector<'a, bool> = BLOCKS
+        }
+    }
+
+    impl<'a> Model<'a> {
+        vector! {
+            /// The layers in order
+            layers: Tables<'a, Layer<'a>> = LAYERS
+        }
+        /// The header
+        pub fn header(&self) -> Option<Header<'a>> {
+            unsafe {
+                self.table
+                    .get::<ForwardsUOffset<Header<'a>>>(Self::HEADER, None)
+            }
+        }
+    }
+}
+
+/// Verify a buffer and return a view of the model it contains
+///
+/// Fails if the buffer does not carry the [`IDENTIFIER`] or its contents are inconsistent.
+pub fn root(buf: &[u8]) -> Result<fb::Model<'_>, InvalidFlatbuffer> {
+    if !flatbuffers::buffer_has_identifier(buf, IDENTIFIER, false) {
+        return Err(InvalidFlatbuffer::MissingRequiredField {
+            required: "file identifier".into(),
+            error_trace: Default::default(),
+        });
+    }
+    flatbuffers::root::<fb::Model>(buf)
+}
+
+impl<'a, T: CLIType> CLI<'a, T> {
+    /// Serialize the model as a FlatBuffer, see [`flatbuf`](crate::flatbuf)
+    pub fn to_flatbuffer(&self) -> Vec<u8> {
+        let mut fbb = FlatBufferBuilder::new();
+        let mut layers = Vec::with_capacity(self.layers.len());
+        for layer in &self.layers {
+            let mut loops = Vec::with_capacity(layer.loops.len());
+            for l in &layer.loops {
+                let points = values::<T>(&mut fbb, &l.points);
+                let (power, speed) = params::<T>(&mut fbb, &l.params);
+                let start = fbb.start_table();
+                fbb.push_slot::<i64>(fb::Loop::ID, T::meta_to_i64(l.id), 0);
+                fbb.push_slot::<i64>(fb::Loop::DIR, T::meta_to_i64(l.dir), 0);
+                fbb.push_slot_always(fb::Loop::POINTS, points);
+                push_optional(&mut fbb, fb::Loop::POWER, power);
+                push_optional(&mut fbb, fb::Loop::SPEED, speed);
+                loops.push(finished::<fb::Loop>(fbb.end_table(start).value()));
+            }
+
+            let mut hatches = Vec::with_capacity(layer.hatches.len());
+            for h in &layer.hatches {
+                let points = values::<T>(&mut fbb, &h.points);
+                let (power, speed) = params::<T>(&mut fbb, &h.params);
+                let start = fbb.start_table();
+                fbb.push_slot::<i64>(fb::Hatches::ID, T::meta_to_i64(h.id), 0);
+                fbb.push_slot_always(fb::Hatches::POINTS, points);
+                push_optional(&mut fbb, fb::Hatches::POWER, power);
+                push_optional(&mut fbb, fb::Hatches::SPEED, speed);
+                hatches.push(finished::<fb::Hatches>(fbb.end_table(start).value()));
+            }
+
+            let mut user_data = Vec::with_capacity(layer.user_data.len());
+            for u in &layer.user_data {
+                let data = fbb.create_vector(&u.data[..]);
+                let start = fbb.start_table();
+                fbb.push_slot::<i64>(fb::UserData::ID, T::meta_to_i64(u.id), 0);
+                fbb.push_slot::<u64>(fb::UserData::POSITION, u.position as u64, 0);
+                fbb.push_slot_always(fb::UserData::DATA, data);
+                user_data.push(finished::<fb::UserData>(fbb.end_table(start).value()));
+            }
+
+            let loops = fbb.create_vector(&loops);
+            let hatches = fbb.create_vector(&hatches);
+            let user_data = fbb.create_vector(&user_data);
+            let blocks: Vec<_> = layer.order.iter().map(|&b| b == Block::Hatches).collect();
+            let blocks = fbb.create_vector(&blocks);
+            let start = fbb.start_table();
+            fbb.push_slot::<f64>(fb::Layer::HEIGHT, T::coord_to_f64(layer.height), 0.0);
+            fbb.push_slot_always(fb::Layer::LOOPS, loops);
+            fbb.push_slot_always(fb::Layer::HATCHES, hatches);
+            fbb.push_slot_always(fb::Layer::USER_DATA, user_data);
+            fbb.push_slot_always(fb::Layer::BLOCKS, blocks);
+            layers.push(finished::<fb::Layer>(fbb.end_table(start).value()));
+        }
+        let layers = fbb.create_vector(&layers);
+        let date = self.header.date.as_ref().map(|d| fbb.create_string(&d.raw));
+        let dimension = self.header.dimension.map(|d| fbb.create_vector(&d));
+        let mut labels = Vec::with_capacity(self.header.labels.len());
+        for (&id, text) in &self.header.labels {
+            let text = fbb.create_string(text);
+            let start = fbb.start_table();
+            fbb.push_slot::<i64>(fb::Label::ID, id, 0);
+            fbb.push_slot_always(fb::Label::TEXT, text);
+            labels.push(finished::<fb::Label>(fbb.end_table(start).value()));
+        }
+        let labels = fbb.create_vector(&labels);
+        let mut user_data = Vec::with_capacity(self.header.user_data.len());
+        for u in &self.header.user_data {
+            let uid = fbb.create_string(&u.uid);
+            let data = fbb.create_vector(&u.data);
+            let start = fbb.start_table();
+            fbb.push_slot_always(fb::HeaderUserData::UID, uid);
+            fbb.push_slot_always(fb::HeaderUserData::DATA, data);
+            user_data.push(finished::<fb::HeaderUserData>(fbb.end_table(start).value()));
+        }
+        let user_data = fbb.create_vector(&user_data);
+        let mut extra = Vec::with_capacity(self.header.extra.len());
+        for (name, value) in &self.header.extra {
+            let name = fbb.create_string(name);
+            let value = fbb.create_string(value);
+            let start = fbb.start_table();
+            fbb.push_slot_always(fb::Extra::NAME, name);
+            fbb.push_slot_always(fb::Extra::VALUE, value);
+            extra.push(finished::<fb::Extra>(fbb.end_table(start).value()));
+        }
+        let extra = fbb.create_vector(&extra);
+
+        let start = fbb.start_table();
+        fbb.push_slot::<bool>(fb::Header::BINARY, self.header.binary, false);
+        fbb.push_slot::<f64>(fb::Header::UNITS, self.header.units, 0.0);
+        fbb.push_slot::<f32>(fb::Header::VERSION, self.header.version, 0.0);
+        fbb.push_slot::<bool>(fb::Header::ALIGNED, self.header.aligned, false);
+        if let Some(n) = self.header.layers {
+            fbb.push_slot_always::<u64>(fb::Header::LAYERS, n as u64);
+        }
+        push_optional(&mut fbb, fb::Header::DATE, date);
+        push_optional(&mut fbb, fb::Header::DIMENSION, dimension);
+        fbb.push_slot_always(fb::Header::LABELS, labels);
+        fbb.push_slot_always(fb::Header::USER_DATA, user_data);
+        fbb.push_slot_always(fb::Header::EXTRA, extra);
+        let header = finished::<fb::Header>(fbb.end_table(start).value());
+
+        let start = fbb.start_table();
+        fbb.push_slot_always(fb::Model::HEADER, header);
+        fbb.push_slot_always(fb::Model::LAYERS, layers);
+        let model = fbb.end_table(start);
+        fbb.finish(model, Some(IDENTIFIER));
+        fbb.finished_data().to_vec()
+    }
+
+    /// Verify a FlatBuffer and copy the model it contains, see [`flatbuf`](crate::flatbuf)
+    ///
+    /// Also fails if a loop has an odd number of coordinates or hatches a number of coordinates
+    /// that is not a multiple of 4, which could not be written as a CLI file, or if the dimension
+    /// does not have 6 values.
+    pub fn from_flatbuffer(buf: &[u8]) -> Result<CLI<'static, T>, InvalidFlatbuffer> {
+        let model = root(buf)?;
+        let coords = |values: Option<flatbuffers::Vector<'_, f64>>| -> Vec<<T as CLIType>::Coord> {
+            values
+                .iter()
+                .flatten()
+                .map(|v| T::coord_from_f64(v))
+                .collect()
+        };
+        // Coordinates of whole points, `stride` being 2 for loops and 4 for hatches
+        let points = |values: Option<flatbuffers::Vector<'_, f64>>, stride: usize, field: &str| {
+            if values.map_or(0, |v| v.len()) % stride != 0 {
+                return Err(InvalidFlatbuffer::MissingRequiredField {
+                    required: format!("whole points in {}", field).into(),
+                    error_trace: Default::default(),
+                });
+            }
+            Ok(Cow::Owned(coords(values)))
+        };
+        let vector_params =
+            |power: Option<flatbuffers::Vector<'_, f64>>,
+             speed: Option<flatbuffers::Vector<'_, f64>>| VectorParams {
+                power: power.map(|p| Cow::Owned(coords(Some(p)))),
+                speed: speed.map(|s| Cow::Owned(coords(Some(s)))),
+            };
+
+        // The header is required, so verification guarantees it is present
+        let header = model.header().expect("verified");
+        let dimension = match header.dimension() {
+            None => None,
+            Some(d) if d.len() == 6 => Some([0, 1, 2, 3, 4, 5].map(|i| d.get(i))),
+            Some(_) => {
+                return Err(InvalidFlatbuffer::MissingRequiredField {
+                    required: "6 values in Header.dimension".into(),
+                    error_trace: Default::default(),
+                })
+            }
+        };
+        let layers = model
+            .layers()
+            .iter()
+            .flatten()
+            .map(|layer| {
+                Ok(Layer {
+                    loops: layer
+                        .loops()
+                        .iter()
+                        .flatten()
+                        .map(|l| {
+                            Ok(Loop {
+                                id: T::meta_from_i64(l.id()),
+                                dir: T::meta_from_i64(l.dir()),
+                                points: points(l.points(), 2, "Loop.points")?,
+                                params: vector_params(l.power(), l.speed()),
+                            })
+                        })
+                        .collect::<Result<_, InvalidFlatbuffer>>()?,
+                    hatches: layer
+                        .hatches()
+                        .iter()
+                        .flatten()
+                        .map(|h| {
+                            Ok(Hatches {
+                                id: T::meta_from_i64(h.id()),
+                                points: points(h.points(), 4, "Hatches.points")?,
+                                params: vector_params(h.power(), h.speed()),
+                            })
+                        })
+                        .collect::<Result<_, InvalidFlatbuffer>>()?,
+                    user_data: layer
+                        .user_data()
+                        .iter()
+                        .flatten()
+                        .map(|u| UserData {
+                            id: T::meta_from_i64(u.id()),
+                            position: u.position() as usize,
+                            data: Cow::Owned(
+                                u.data().map(|d| d.bytes().to_vec()).unwrap_or_default(),
+                            ),
+                        })
+                        .collect(),
+                    order: layer
+                        .blocks()
+                        .iter()
+                        .flatten()
+                        .map(|h| if h { Block::Hatches } else { Block::Loop })
+                        .collect(),
+                    ..Layer::empty(T::coord_from_f64(layer.height()))
+                })
+            })
+            .collect::<Result<_, InvalidFlatbuffer>>()?;
+
+        Ok(CLI {
+            header: Header {
+                binary: header.binary(),
+                units: header.units(),
+                version: header.version(),
+                aligned: header.aligned(),
+                layers: header.layers().map(|n| n as usize),
+                date: header.date().map(Date::new),
+                dimension,
+                labels: header
+                    .labels()
+                    .iter()
+                    .flatten()
+                    .map(|l| (l.id(), l.text().unwrap_or_default().to_string()))
+                    .collect(),
+                user_data: header
+                    .user_data()
+                    .iter()
+                    .flatten()
+                    .map(|u| HeaderUserData {
+                        uid: u.uid().unwrap_or_default().to_string(),
+                        data: u.data().map(|d| d.bytes().to_vec()).unwrap_or_default(),
+                    })
+                    .collect(),
+                extra: header
+                    .extra()
+                    .iter()
+                    .flatten()
+                    .map(|e| {
+                        let name = e.name().unwrap_or_default().to_string();
+                        (name, e.value().unwrap_or_default().to_string())
+                    })
+                    .collect(),
+            },
+            layers,
+            verbatim: Default::default(),
+        })
+    }
+}
+
+type Values<'fbb> = WIPOffset<flatbuffers::Vector<'fbb, f64>>;
+
+fn values<'fbb, T: CLIType>(
+    fbb: &mut FlatBufferBuilder<'fbb>,
+    coords: &[<T as CLIType>::Coord],
+) -> Values<'fbb> {
+    let values: Vec<f64> = coords.iter().map(|&c| T::coord_to_f64(c)).collect();
+    fbb.create_vector(&values)
+}
+
+fn params<'fbb, T: CLIType>(
+    fbb: &mut FlatBufferBuilder<'fbb>,
+    params: &VectorParams<'_, T>,
+) -> (Option<Values<'fbb>>, Option<Values<'fbb>>) {
+    (
+        params.power().map(|p| values::<T>(fbb, p)),
+        params.speed().map(|s| values::<T>(fbb, s)),
+    )
+}
+
+/// Typed offset of a finished table
+fn finished<'fbb, V: 'fbb>(offset: u32) -> WIPOffset<V> {
+    WIPOffset::new(offset)
+}
+
+fn push_optional<X: flatbuffers::Push>(
+    fbb: &mut FlatBufferBuilder,
+    slot: flatbuffers::VOffsetT,
+    value: Option<X>,
+) {
+    if let Some(value) = value {
+        fbb.push_slot_always(slot, value);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clitype::ShortCLI;
+
+    #[test]
+    fn round_trip() {
+        let model = CLI::<ShortCLI> {
+            header: Header {
+                units: 0.005,
+                aligned: true,
+                layers: Some(1),
+                date: Some(Date::new("070493")),
+                dimension: Some([0.0, 0.0, 0.0, 50.0, 25.0, 0.1]),
+                labels: vec![(4, "contour".to_string())].into_iter().collect(),
+                user_data: vec![HeaderUserData {
+                    uid: "VENDOR".to_string(),
+                    data: b"\x00,\n".to_vec(),
+                }],
+                extra: vec![("VENDOR".to_string(), "x=1".to_string())],
+                ..Header::default()
+            },
+            layers: vec![Layer {
+                loops: vec![Loop {
+                    id: 4,
+                    dir: 2,
+                    points: Cow::Owned(vec![0, 0, 10, 0, 65535, 10]),
+                    params: VectorParams {
+                        power: Some(Cow::Owned(vec![100, 200])),
+                        speed: None,
+                    },
+                }],
+                hatches: vec![Hatches {
+                    id: 5,
+                    points: Cow::Owned(vec![0, 2, 10, 2]),
+                    params: Default::default(),
+                }],
+                user_data: vec![UserData {
+                    id: 9,
+                    position: 1,
+                    data: Cow::Owned(b"data".to_vec()),
+                }],
+                order: vec![Block::Hatches, Block::Loop],
+                ..Layer::empty(20)
+            }],
+            verbatim: Default::default(),
+        };
+
+        let bytes = model.to_flatbuffer();
+        let view = root(&bytes).unwrap();
+        let layer = view.layers().unwrap().get(0);
+        assert_eq!(layer.height(), 20.0);
+        let l = layer.loops().unwrap().get(0);
+        assert_eq!(
+            l.points().unwrap().iter().collect::<Vec<_>>(),
+            vec![0.0, 0.0, 10.0, 0.0, 65535.0, 10.0]
+        );
+        assert!(l.speed().is_none());
+        assert_eq!(view.header().unwrap().layers(), Some(1));
+
+        let back = CLI::<ShortCLI>::from_flatbuffer(&bytes).unwrap();
+        assert_eq!(back.to_bytes(), model.to_bytes());
+        assert_eq!(
+            back.layers[0].loops[0].params.power(),
+            Some(&[100, 200][..])
+        );
+        assert!(back.layers[0].loops[0].params.speed().is_none());
+        assert_eq!(back.header.layers, Some(1));
+        assert_eq!(back.header.date, model.header.date);
+        assert_eq!(back.header.dimension, model.header.dimension);
+        assert_eq!(back.header.label(4), Some("contour"));
+        assert_eq!(back.header.user_data, model.header.user_data);
+        assert_eq!(back.header.extra, model.header.extra);
+    }
+
+    #[test]
+    fn invalid() {
+        let model = CLI::<ShortCLI> {
+            header: Header::default(),
+            layers: vec![],
+            verbatim: Default::default(),
+        };
+        let bytes = model.to_flatbuffer();
+        assert!(root(&bytes).unwrap().header().unwrap().layers().is_none());
+        assert!(root(&bytes[..bytes.len() - 4]).is_err());
+        assert!(root(b"not a flatbuffer").is_err());
+    }
+
+    #[test]
+    fn incomplete_points() {
+        let model = |loop_points: Vec<u16>, hatch_points: Vec<u16>| CLI::<ShortCLI> {
+            header: Header::default(),
+            layers: vec![Layer {
+                loops: vec![Loop {
+                    id: 1,
+                    dir: 1,
+                    points: Cow::Owned(loop_points),
+                    params: Default::default(),
+                }],
+                hatches: vec![Hatches {
+                    id: 2,
+                    points: Cow::Owned(hatch_points),
+                    params: Default::default(),
+                }],
+                ..Layer::empty(1)
+            }],
+            verbatim: Default::default(),
+        };
+        let whole = model(vec![0, 0, 1, 1], vec![0, 0, 1, 1]).to_flatbuffer();
+        assert!(CLI::<ShortCLI>::from_flatbuffer(&whole).is_ok());
+        let odd = model(vec![0, 0, 1], vec![0, 0, 1, 1]).to_flatbuffer();
+        assert!(matches!(
+            CLI::<ShortCLI>::from_flatbuffer(&odd),
+            Err(InvalidFlatbuffer::MissingRequiredField { .. })
+        ));
+        let partial = model(vec![0, 0, 1, 1], vec![0, 0]).to_flatbuffer();
+        assert!(CLI::<ShortCLI>::from_flatbuffer(&partial).is_err());
+    }
+}
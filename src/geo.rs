@@ -0,0 +1,130 @@
+//! Conversions into the types of the [geo](https://docs.rs/geo) crate
+//!
+//! The geo ecosystem offers area and length measures, boolean operations, simplification and
+//! much more. Loops, hatches and layers convert into its [`geo_types`], with coordinates in
+//! coordinate units like the model itself:
+//!
+//! - a [`Loop`] into a `LineString`, or a `Polygon` without holes,
+//! - [`Hatches`] into a `MultiLineString` or a `Vec` of `Line`s, one per hatch,
+//! - a [`Layer`] into a `MultiPolygon` of its closed loops, nested as by [`Layer::regions`].
+//!
+//! ```
+//! use colain::CLIBuilder;
+//! use geo_types::{MultiPolygon, Polygon};
+//!
+//! let model = CLIBuilder::new()
+//!     .layer(1.0)
+//!     .polyline(1, 1, vec![0.0, 0.0, 4.0, 0.0, 4.0, 4.0, 0.0, 4.0, 0.0, 0.0])
+//!     .build()
+//!     .unwrap();
+//! let layer = model.iter().next().unwrap();
+//! let polygon = Polygon::from(layer.iter_loops().next().unwrap());
+//! assert_eq!(polygon.exterior().0.len(), 5);
+//! assert_eq!(MultiPolygon::from(layer).0.len(), 1);
+//! ```
+
+use crate::clitype::CLIType;
+use crate::islands::Region;
+use crate::{Hatches, Layer, Loop};
+use geo_types::{Coord, Line, LineString, MultiLineString, MultiPolygon, Polygon};
+
+impl<'a, T: CLIType> From<&Loop<'a, T>> for LineString<f64> {
+    fn from(l: &Loop<'a, T>) -> Self {
+        l.points
+            .chunks_exact(2)
+            .map(|p| Coord {
+                x: T::coord_to_f64(p[0]),
+                y: T::coord_to_f64(p[1]),
+            })
+            .collect()
+    }
+}
+
+impl<'a, T: CLIType> From<&Loop<'a, T>> for Polygon<f64> {
+    /// The area enclosed by the loop, closed whether or not its last point repeats the first one
+    fn from(l: &Loop<'a, T>) -> Self {
+        Polygon::new(l.into(), Vec::new())
+    }
+}
+
+impl<'a, T: CLIType> From<&Hatches<'a, T>> for Vec<Line<f64>> {
+    fn from(h: &Hatches<'a, T>) -> Self {
+        let f = |c| T::coord_to_f64(c);
+        h.iter()
+            .map(|v| Line::new((f(v[0]), f(v[1])), (f(v[2]), f(v[3]))))
+            .collect()
+    }
+}
+
+impl<'a, T: CLIType> From<&Hatches<'a, T>> for MultiLineString<f64> {
+    fn from(h: &Hatches<'a, T>) -> Self {
+        let lines: Vec<Line<f64>> = h.into();
+        lines.into_iter().map(LineString::from).collect()
+    }
+}
+
+impl<'a, T: CLIType> From<&Layer<'a, T>> for MultiPolygon<f64> {
+    /// One polygon per outer boundary of [`Layer::regions`] with the holes directly inside it.
+    /// Regions standing inside holes become polygons of their own. Open polylines are left out.
+    fn from(layer: &Layer<'a, T>) -> Self {
+        fn add<T: CLIType>(layer: &Layer<'_, T>, region: &Region, out: &mut Vec<Polygon<f64>>) {
+            let holes = region.holes.iter();
+            let interiors = holes.map(|h| (&layer.loops[h.index]).into()).collect();
+            out.push(Polygon::new((&layer.loops[region.outer]).into(), interiors));
+            for hole in &region.holes {
+                for inner in &hole.regions {
+                    add(layer, inner, out);
+                }
+            }
+        }
+        let mut polygons = Vec::new();
+        for region in layer.regions() {
+            add(layer, &region, &mut polygons);
+        }
+        MultiPolygon(polygons)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clitype::ShortCLI;
+    use crate::{CLIBuilder, CLI};
+
+    #[test]
+    fn conversions() {
+        let square = |x: u16, size: u16| vec![x, x, x + size, x, x + size, x + size, x, x + size];
+        let model: CLI<'_, ShortCLI> = CLIBuilder::new()
+            .short()
+            .layer(1)
+            .polyline(1, 1, square(0, 10))
+            .polyline(2, 0, square(2, 6))
+            .polyline(3, 1, square(4, 2))
+            .polyline(4, 2, vec![20, 0, 22, 2])
+            .hatches(5, vec![1, 1, 9, 1, 1, 9, 9, 9])
+            .build()
+            .unwrap();
+        let layer = model.iter().next().unwrap();
+        let loops: Vec<_> = layer.iter_loops().collect();
+
+        let line = LineString::from(loops[3]);
+        assert_eq!(
+            line.0,
+            [Coord { x: 20.0, y: 0.0 }, Coord { x: 22.0, y: 2.0 }]
+        );
+        let polygon = Polygon::from(loops[0]);
+        assert!(polygon.exterior().is_closed());
+        assert_eq!(polygon.exterior().0.len(), 5);
+
+        let hatches = layer.iter_hatches().next().unwrap();
+        let lines: Vec<Line<f64>> = hatches.into();
+        assert_eq!(lines[1], Line::new((1.0, 9.0), (9.0, 9.0)));
+        assert_eq!(MultiLineString::from(hatches).0.len(), 2);
+
+        // The innermost square stands inside the hole of the ring
+        let polygons = MultiPolygon::from(layer);
+        assert_eq!(polygons.0.len(), 2);
+        assert_eq!(polygons.0[0].interiors().len(), 1);
+        assert_eq!(polygons.0[1].exterior().0[0], Coord { x: 4.0, y: 4.0 });
+    }
+}
@@ -0,0 +1,233 @@
+//! GeoJSON export
+//!
+//! GIS tools and web map renderers read GeoJSON ([RFC 7946](https://tools.ietf.org/html/rfc7946)),
+//! which makes it a convenient way to inspect slice geometry outside of AM specific software.
+//! [`CLI::write_geojson`] writes every layer as a `FeatureCollection`:
+//!
+//! - each island (see [`Layer::islands`](crate::Layer::islands)) becomes a `Polygon` with its holes,
+//! - open polylines become a `LineString`,
+//! - each hatch block becomes a `MultiLineString`.
+//!
+//! Every feature carries the layer index, the height `z` and the id of the loop or hatch block as
+//! properties, along with a `kind` of `"contour"`, `"polyline"` or `"hatches"`. Coordinates are in
+//! millimeters.
+//!
+//! [`Layer::to_geojson`] returns a single layer as a string, in the coordinate units of its file.
+//!
+//! ```no_run
+//! use colain::{CLI, clitype::LongCLI};
+//! # let bytes = Vec::new();
+//!
+//! let model = CLI::<LongCLI>::new(&bytes).unwrap();
+//! let file = std::fs::File::create("layers.geojsonl").unwrap();
+//! model.write_geojson(std::io::BufWriter::new(file)).unwrap();
+//! ```
+
+use crate::clitype::CLIType;
+use crate::geom;
+use crate::{Layer, LoopKind, CLI};
+use std::io::{self, Write};
+
+impl<'a, T: CLIType> CLI<'a, T> {
+    /// Write every layer as a GeoJSON `FeatureCollection`, one per line.
+    ///
+    /// The output is newline delimited GeoJSON, which most GIS tools accept directly. Use
+    /// [`CLI::write_layer_geojson`] to get a single layer as a regular GeoJSON document.
+    pub fn write_geojson<W: Write>(&self, mut out: W) -> io::Result<()> {
+        for index in 0..self.layers.len() {
+            self.write_layer_geojson(index, &mut out)?;
+            writeln!(out)?;
+        }
+        out.flush()
+    }
+
+    /// Write the layer with the given index as a GeoJSON `FeatureCollection`.
+    ///
+    /// # Panics
+    /// Panics if `index` is out of bounds.
+    pub fn write_layer_geojson<W: Write>(&self, index: usize, mut out: W) -> io::Result<()> {
+        write_layer(
+            &mut out,
+            &self.layers[index],
+            Some(index),
+            self.header.units,
+        )
+    }
+}
+
+impl<'a, T: CLIType> Layer<'a, T> {
+    /// The layer as a GeoJSON `FeatureCollection`, see the [module documentation](self).
+    ///
+    /// Unlike [`CLI::write_layer_geojson`] the coordinates and the height are given in
+    /// coordinate units, since a layer does not know the units of its file, and the features
+    /// have no `layer` property.
+    pub fn to_geojson(&self) -> String {
+        let mut out = Vec::new();
+        write_layer(&mut out, self, None, 1.0).expect("writing to a Vec does not fail");
+        String::from_utf8(out).expect("GeoJSON is written as UTF-8")
+    }
+}
+
+fn write_layer<T: CLIType, W: Write>(
+    out: &mut W,
+    layer: &Layer<'_, T>,
+    index: Option<usize>,
+    units: f64,
+) -> io::Result<()> {
+    let f = |c| T::coord_to_f64(c) * units;
+    let z = f(layer.height);
+    let ring = |i: usize, ccw: bool| {
+        let mut points = geom::to_points(&layer.loops[i].points, f);
+        if (geom::signed_area(&points) > 0.0) != ccw {
+            points.reverse();
+        }
+        if points.first() != points.last() {
+            points.push(points[0]);
+        }
+        points
+    };
+
+    let mut features = Vec::new();
+    for island in layer.islands() {
+        let mut rings = vec![ring(island.outer, true)];
+        rings.extend(island.holes.iter().map(|&h| ring(h, false)));
+        features.push((
+            "contour",
+            T::meta_to_i64(layer.loops[island.outer].id),
+            "Polygon",
+            rings,
+        ));
+    }
+    for l in layer.loops.iter().filter(|l| l.kind() == LoopKind::Open) {
+        features.push((
+            "polyline",
+            T::meta_to_i64(l.id),
+            "LineString",
+            vec![geom::to_points(&l.points, f)],
+        ));
+    }
+    for h in &layer.hatches {
+        let lines = h
+            .iter()
+            .map(|v| vec![[f(v[0]), f(v[1])], [f(v[2]), f(v[3])]])
+            .collect();
+        features.push(("hatches", T::meta_to_i64(h.id), "MultiLineString", lines));
+    }
+
+    write!(out, r#"{{"type":"FeatureCollection","features":["#)?;
+    for (i, (kind, id, geometry, lines)) in features.iter().enumerate() {
+        if i > 0 {
+            write!(out, ",")?;
+        }
+        write!(out, r#"{{"type":"Feature","properties":{{"#)?;
+        if let Some(index) = index {
+            write!(out, r#""layer":{},"#, index)?;
+        }
+        write!(
+            out,
+            r#""z":{},"kind":"{}","id":{}}},"geometry":{{"type":"{}","coordinates":"#,
+            z, kind, id, geometry
+        )?;
+        if *geometry == "LineString" {
+            write_points(out, &lines[0])?;
+        } else {
+            write!(out, "[")?;
+            for (j, line) in lines.iter().enumerate() {
+                if j > 0 {
+                    write!(out, ",")?;
+                }
+                write_points(out, line)?;
+            }
+            write!(out, "]")?;
+        }
+        write!(out, "}}}}")?;
+    }
+    write!(out, "]}}")
+}
+
+fn write_points<W: Write>(out: &mut W, points: &[[f64; 2]]) -> io::Result<()> {
+    write!(out, "[")?;
+    for (i, p) in points.iter().enumerate() {
+        if i > 0 {
+            write!(out, ",")?;
+        }
+        write!(out, "[{},{}]", p[0], p[1])?;
+    }
+    write!(out, "]")
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::clitype::ShortCLI;
+    use crate::{Hatches, Header, Layer, Loop, CLI};
+    use std::borrow::Cow;
+
+    #[test]
+    fn layers() {
+        let model = CLI::<ShortCLI> {
+            header: Header {
+                units: 0.5,
+                ..Header::default()
+            },
+            layers: vec![
+                Layer {
+                    loops: vec![
+                        Loop {
+                            id: 3,
+                            dir: 0,
+                            points: Cow::Owned(vec![0, 0, 0, 8, 8, 8, 8, 0, 0, 0]),
+                            params: Default::default(),
+                        },
+                        Loop {
+                            id: 3,
+                            dir: 0,
+                            points: Cow::Owned(vec![2, 2, 4, 2, 4, 4, 2, 4]),
+                            params: Default::default(),
+                        },
+                        Loop {
+                            id: 4,
+                            dir: 2,
+                            points: Cow::Owned(vec![10, 0, 12, 2]),
+                            params: Default::default(),
+                        },
+                    ],
+                    hatches: vec![Hatches {
+                        id: 5,
+                        points: Cow::Owned(vec![1, 1, 3, 1, 1, 2, 3, 2]),
+                        params: Default::default(),
+                    }],
+                    ..Layer::empty(1)
+                },
+                Layer::empty(3),
+            ],
+            verbatim: Default::default(),
+        };
+        let mut out = Vec::new();
+        model.write_geojson(&mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        let lines: Vec<&str> = out.lines().collect();
+        assert_eq!(
+            lines[0],
+            concat!(
+                r#"{"type":"FeatureCollection","features":["#,
+                r#"{"type":"Feature","properties":{"layer":0,"z":0.5,"kind":"contour","id":3},"geometry":{"type":"Polygon","coordinates":"#,
+                r#"[[[0,0],[4,0],[4,4],[0,4],[0,0]],[[1,2],[2,2],[2,1],[1,1],[1,2]]]}},"#,
+                r#"{"type":"Feature","properties":{"layer":0,"z":0.5,"kind":"polyline","id":4},"geometry":{"type":"LineString","coordinates":"#,
+                r#"[[5,0],[6,1]]}},"#,
+                r#"{"type":"Feature","properties":{"layer":0,"z":0.5,"kind":"hatches","id":5},"geometry":{"type":"MultiLineString","coordinates":"#,
+                r#"[[[0.5,0.5],[1.5,0.5]],[[0.5,1],[1.5,1]]]}}]}"#
+            )
+        );
+        assert_eq!(lines[1], r#"{"type":"FeatureCollection","features":[]}"#);
+        assert_eq!(lines.len(), 2);
+
+        let layer = model.iter().next().unwrap().to_geojson();
+        assert!(layer.starts_with(concat!(
+            r#"{"type":"FeatureCollection","features":["#,
+            r#"{"type":"Feature","properties":{"z":1,"kind":"contour","id":3},"geometry":{"type":"Polygon","coordinates":"#,
+            r#"[[[0,0],[8,0],[8,8],[0,8],[0,0]],[[2,4],[4,4],[4,2],[2,2],[2,4]]]}},"#
+        )));
+        let json: serde_json::Value = serde_json::from_str(&layer).unwrap();
+        assert_eq!(json["features"].as_array().unwrap().len(), 3);
+    }
+}
@@ -0,0 +1,514 @@
+//! 2D geometry helpers shared by the analysis functions
+//!
+//! Everything in here works on `f64` points in whatever space the caller chooses (usually
+//! millimeters), independent of the [`CLIType`](crate::clitype::CLIType) of the model.
+//!
+//! The predicates the crate relies on are public so that downstream code can make the same
+//! decisions. [`orient2d`] computes the sign of the orientation determinant exactly, falling
+//! back to exact arithmetic only when the floating point result is too close to zero to trust.
+//! The other predicates are built on top of it, so collinear and touching cases are decided
+//! consistently.
+//!
+//! ```
+//! use colain::geom::{self, Orientation};
+//!
+//! assert_eq!(geom::orientation([0.0, 0.0], [1.0, 0.0], [0.0, 1.0]), Orientation::CounterClockwise);
+//! assert!(geom::on_segment([0.5, 0.5], [0.0, 0.0], [1.0, 1.0]));
+//! assert!(geom::segments_intersect([[0.0, 0.0], [1.0, 1.0]], [[0.0, 1.0], [1.0, 0.0]]));
+//!
+//! let square = [[0.0, 0.0], [1.0, 0.0], [1.0, 1.0], [0.0, 1.0]];
+//! assert_eq!(geom::winding_number(&square, [0.5, 0.5]), 1);
+//! assert_eq!(geom::polygon_orientation(&square), Orientation::CounterClockwise);
+//! ```
+
+/// Orientation of three points, or of the vertices of a polygon
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Orientation {
+    /// Turning left
+    CounterClockwise,
+    /// Turning right
+    Clockwise,
+    /// On a line, or a polygon without area
+    Collinear,
+}
+
+impl Orientation {
+    fn of(v: f64) -> Self {
+        if v > 0.0 {
+            Orientation::CounterClockwise
+        } else if v < 0.0 {
+            Orientation::Clockwise
+        } else {
+            Orientation::Collinear
+        }
+    }
+}
+
+/// Sum of two floats as a value and its rounding error
+#[inline]
+fn two_sum(a: f64, b: f64) -> (f64, f64) {
+    let x = a + b;
+    let bv = x - a;
+    let av = x - bv;
+    (x, (a - av) + (b - bv))
+}
+
+/// Product of two floats as a value and its rounding error
+#[inline]
+fn two_product(a: f64, b: f64) -> (f64, f64) {
+    let x = a * b;
+    (x, a.mul_add(b, -x))
+}
+
+/// Twice the signed area of the triangle `a`, `b`, `c`.
+///
+/// Positive when the points are counter-clockwise, negative when clockwise and zero when
+/// collinear. The sign is always exact, the magnitude is only approximate when it is tiny
+/// compared to the coordinates.
+pub fn orient2d(a: [f64; 2], b: [f64; 2], c: [f64; 2]) -> f64 {
+    let left = (a[0] - c[0]) * (b[1] - c[1]);
+    let right = (a[1] - c[1]) * (b[0] - c[0]);
+    let det = left - right;
+    // Error bound from Shewchuk, "Adaptive Precision Floating-Point Arithmetic and Fast Robust
+    // Geometric Predicates"
+    const EPSILON: f64 = f64::EPSILON / 2.0;
+    const BOUND: f64 = (3.0 + 16.0 * EPSILON) * EPSILON;
+    if det.abs() >= BOUND * (left.abs() + right.abs()) {
+        return det;
+    }
+
+    // Expand the determinant into its six products and sum them without rounding
+    let products = [
+        two_product(a[0], b[1]),
+        two_product(-a[1], b[0]),
+        two_product(b[0], c[1]),
+        two_product(-b[1], c[0]),
+        two_product(c[0], a[1]),
+        two_product(-c[1], a[0]),
+    ];
+    let mut expansion: Vec<f64> = Vec::with_capacity(12);
+    for (hi, lo) in products {
+        for term in [lo, hi] {
+            let mut q = term;
+            for e in expansion.iter_mut() {
+                let (sum, err) = two_sum(q, *e);
+                *e = err;
+                q = sum;
+            }
+            expansion.retain(|e| *e != 0.0);
+            if q != 0.0 {
+                expansion.push(q);
+            }
+        }
+    }
+    // The components are non-overlapping and increasing, the largest one carries the sign
+    expansion.last().copied().unwrap_or(0.0)
+}
+
+/// Orientation of the points `a`, `b`, `c`, see [`orient2d`]
+pub fn orientation(a: [f64; 2], b: [f64; 2], c: [f64; 2]) -> Orientation {
+    Orientation::of(orient2d(a, b, c))
+}
+
+/// Whether `p` lies on the closed segment `a`-`b`
+pub fn on_segment(p: [f64; 2], a: [f64; 2], b: [f64; 2]) -> bool {
+    orient2d(a, b, p) == 0.0 && within(p, a, b)
+}
+
+/// Whether `p` lies in the bounding box of `a` and `b`
+fn within(p: [f64; 2], a: [f64; 2], b: [f64; 2]) -> bool {
+    (0..2).all(|i| a[i].min(b[i]) <= p[i] && p[i] <= a[i].max(b[i]))
+}
+
+/// Whether two closed segments share at least one point, touching and overlapping included
+pub fn segments_intersect(s: [[f64; 2]; 2], t: [[f64; 2]; 2]) -> bool {
+    let (d1, d2) = (orient2d(t[0], t[1], s[0]), orient2d(t[0], t[1], s[1]));
+    let (d3, d4) = (orient2d(s[0], s[1], t[0]), orient2d(s[0], s[1], t[1]));
+    if d1 * d2 < 0.0 && d3 * d4 < 0.0 {
+        return true;
+    }
+    (d1 == 0.0 && within(s[0], t[0], t[1]))
+        || (d2 == 0.0 && within(s[1], t[0], t[1]))
+        || (d3 == 0.0 && within(t[0], s[0], s[1]))
+        || (d4 == 0.0 && within(t[1], s[0], s[1]))
+}
+
+/// Shared part of two segments
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SegmentIntersection {
+    /// The segments cross or touch in a single point
+    Point([f64; 2]),
+    /// The segments are collinear and overlap along a segment
+    Overlap([[f64; 2]; 2]),
+}
+
+/// Shared part of two closed segments, `None` if they are disjoint.
+///
+/// Whether the segments meet is decided exactly by [`segments_intersect`], the intersection
+/// point itself is subject to rounding.
+pub fn segment_intersection(s: [[f64; 2]; 2], t: [[f64; 2]; 2]) -> Option<SegmentIntersection> {
+    if !segments_intersect(s, t) {
+        return None;
+    }
+    let d = [s[1][0] - s[0][0], s[1][1] - s[0][1]];
+    let e = [t[1][0] - t[0][0], t[1][1] - t[0][1]];
+    let collinear = orient2d(s[0], s[1], t[0]) == 0.0 && orient2d(s[0], s[1], t[1]) == 0.0;
+    if !collinear {
+        // Exactly one of the segments may be degenerate
+        if d == [0.0, 0.0] {
+            return Some(SegmentIntersection::Point(s[0]));
+        }
+        if e == [0.0, 0.0] {
+            return Some(SegmentIntersection::Point(t[0]));
+        }
+        let f = [t[0][0] - s[0][0], t[0][1] - s[0][1]];
+        let u = ((f[0] * e[1] - f[1] * e[0]) / (d[0] * e[1] - d[1] * e[0])).clamp(0.0, 1.0);
+        return Some(SegmentIntersection::Point([
+            s[0][0] + u * d[0],
+            s[0][1] + u * d[1],
+        ]));
+    }
+    // Order the shared endpoints along the dominant axis of the union
+    let axis = if (d[0].abs() + e[0].abs()) >= (d[1].abs() + e[1].abs()) {
+        0
+    } else {
+        1
+    };
+    let mut shared: Vec<[f64; 2]> = [s[0], s[1], t[0], t[1]]
+        .iter()
+        .copied()
+        .filter(|&p| within(p, s[0], s[1]) && within(p, t[0], t[1]))
+        .collect();
+    shared.sort_by(|a, b| a[axis].total_cmp(&b[axis]));
+    let (first, last) = (shared[0], shared[shared.len() - 1]);
+    Some(if first == last {
+        SegmentIntersection::Point(first)
+    } else {
+        SegmentIntersection::Overlap([first, last])
+    })
+}
+
+/// Winding number of a closed polygon around a point.
+///
+/// Counter-clockwise polygons wind positively. Points on the boundary are counted as inside of
+/// the edge they lie on, so the result on the boundary depends on the edge.
+pub fn winding_number(polygon: &[[f64; 2]], p: [f64; 2]) -> i32 {
+    let mut winding = 0;
+    for (i, &a) in polygon.iter().enumerate() {
+        let b = polygon[(i + 1) % polygon.len()];
+        if a[1] <= p[1] {
+            if b[1] > p[1] && orient2d(a, b, p) > 0.0 {
+                winding += 1;
+            }
+        } else if b[1] <= p[1] && orient2d(a, b, p) < 0.0 {
+            winding -= 1;
+        }
+    }
+    winding
+}
+
+/// Winding direction of a closed polygon, the sign of its [`signed_area`]
+pub fn polygon_orientation(polygon: &[[f64; 2]]) -> Orientation {
+    Orientation::of(signed_area(polygon))
+}
+
+/// Axis aligned bounding box
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct BoundingBox {
+    pub min: [f64; 2],
+    pub max: [f64; 2],
+}
+
+impl BoundingBox {
+    /// Bounding box of a set of points, `None` if there are no points
+    pub fn of<I: IntoIterator<Item = [f64; 2]>>(points: I) -> Option<Self> {
+        let mut points = points.into_iter();
+        let first = points.next()?;
+        let mut bb = BoundingBox {
+            min: first,
+            max: first,
+        };
+        for p in points {
+            bb.add(p);
+        }
+        Some(bb)
+    }
+
+    /// Grow the box to include a point
+    pub fn add(&mut self, p: [f64; 2]) {
+        self.min = [self.min[0].min(p[0]), self.min[1].min(p[1])];
+        self.max = [self.max[0].max(p[0]), self.max[1].max(p[1])];
+    }
+
+    /// Overlapping part of both boxes, `None` if they are disjoint
+    pub fn intersection(&self, other: &Self) -> Option<Self> {
+        let min = [self.min[0].max(other.min[0]), self.min[1].max(other.min[1])];
+        let max = [self.max[0].min(other.max[0]), self.max[1].min(other.max[1])];
+        if min[0] <= max[0] && min[1] <= max[1] {
+            Some(BoundingBox { min, max })
+        } else {
+            None
+        }
+    }
+}
+
+/// Signed area of a closed polygon, positive when counter-clockwise
+pub fn signed_area(points: &[[f64; 2]]) -> f64 {
+    let mut sum = 0.0;
+    for (i, a) in points.iter().enumerate() {
+        let b = points[(i + 1) % points.len()];
+        sum += a[0] * b[1] - b[0] * a[1];
+    }
+    sum / 2.0
+}
+
+/// Convert a flat coordinate array into points
+pub(crate) fn to_points<C: Copy>(coords: &[C], f: impl Fn(C) -> f64) -> Vec<[f64; 2]> {
+    coords.chunks_exact(2).map(|p| [f(p[0]), f(p[1])]).collect()
+}
+
+/// Length of a polyline
+pub(crate) fn path_length(points: &[[f64; 2]]) -> f64 {
+    points
+        .windows(2)
+        .map(|w| (w[1][0] - w[0][0]).hypot(w[1][1] - w[0][1]))
+        .sum()
+}
+
+/// Resample a polyline to `n` points evenly spaced along its length.
+///
+/// The first and last points are kept, so closed polylines stay closed.
+pub(crate) fn resample(points: &[[f64; 2]], n: usize) -> Vec<[f64; 2]> {
+    if points.len() < 2 || n < 2 {
+        return points.iter().copied().take(n).collect();
+    }
+    let total = path_length(points);
+    let mut out = Vec::with_capacity(n);
+    let mut segment = 0;
+    let mut walked = 0.0;
+    for i in 0..n {
+        let target = total * i as f64 / (n - 1) as f64;
+        loop {
+            let (a, b) = (points[segment], points[segment + 1]);
+            let len = (b[0] - a[0]).hypot(b[1] - a[1]);
+            if walked + len >= target || segment + 2 == points.len() {
+                let f = if len > 0.0 {
+                    ((target - walked) / len).clamp(0.0, 1.0)
+                } else {
+                    0.0
+                };
+                out.push([a[0] + f * (b[0] - a[0]), a[1] + f * (b[1] - a[1])]);
+                break;
+            }
+            walked += len;
+            segment += 1;
+        }
+    }
+    out
+}
+
+/// Mean of a set of points
+pub(crate) fn centroid(points: &[[f64; 2]]) -> Option<[f64; 2]> {
+    if points.is_empty() {
+        return None;
+    }
+    let n = points.len() as f64;
+    let sum = points
+        .iter()
+        .fold([0.0, 0.0], |s, p| [s[0] + p[0], s[1] + p[1]]);
+    Some([sum[0] / n, sum[1] / n])
+}
+
+/// Point of the segment `a`-`b` closest to `p`
+pub(crate) fn closest_point(p: [f64; 2], a: [f64; 2], b: [f64; 2]) -> [f64; 2] {
+    let d = [b[0] - a[0], b[1] - a[1]];
+    let len2 = d[0] * d[0] + d[1] * d[1];
+    let t = if len2 > 0.0 {
+        (((p[0] - a[0]) * d[0] + (p[1] - a[1]) * d[1]) / len2).clamp(0.0, 1.0)
+    } else {
+        0.0
+    };
+    [a[0] + t * d[0], a[1] + t * d[1]]
+}
+
+/// Distance from a point to the segment `a`-`b`
+pub(crate) fn point_segment_distance(p: [f64; 2], a: [f64; 2], b: [f64; 2]) -> f64 {
+    let c = closest_point(p, a, b);
+    (p[0] - c[0]).hypot(p[1] - c[1])
+}
+
+/// Shortest distance between two segments, zero if they intersect
+pub(crate) fn segment_distance(s: [[f64; 2]; 2], t: [[f64; 2]; 2]) -> f64 {
+    if segments_intersect(s, t) {
+        return 0.0;
+    }
+    point_segment_distance(s[0], t[0], t[1])
+        .min(point_segment_distance(s[1], t[0], t[1]))
+        .min(point_segment_distance(t[0], s[0], s[1]))
+        .min(point_segment_distance(t[1], s[0], s[1]))
+}
+
+/// Centroid of the area enclosed by a polygon, `None` if it has no area
+pub(crate) fn area_centroid(points: &[[f64; 2]]) -> Option<[f64; 2]> {
+    let area = signed_area(points);
+    if area == 0.0 {
+        return None;
+    }
+    let (mut x, mut y) = (0.0, 0.0);
+    for (i, a) in points.iter().enumerate() {
+        let b = points[(i + 1) % points.len()];
+        let cross = a[0] * b[1] - b[0] * a[1];
+        x += (a[0] + b[0]) * cross;
+        y += (a[1] + b[1]) * cross;
+    }
+    Some([x / (6.0 * area), y / (6.0 * area)])
+}
+
+/// Convex hull of a set of points, counter-clockwise without repeating the first point
+pub(crate) fn convex_hull(mut points: Vec<[f64; 2]>) -> Vec<[f64; 2]> {
+    points.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+    points.dedup();
+    if points.len() < 3 {
+        return points;
+    }
+    let cross = orient2d;
+    let mut hull: Vec<[f64; 2]> = Vec::with_capacity(points.len() + 1);
+    for &p in points.iter() {
+        while hull.len() >= 2 && cross(hull[hull.len() - 2], hull[hull.len() - 1], p) <= 0.0 {
+            hull.pop();
+        }
+        hull.push(p);
+    }
+    let lower = hull.len() + 1;
+    for &p in points.iter().rev().skip(1) {
+        while hull.len() >= lower && cross(hull[hull.len() - 2], hull[hull.len() - 1], p) <= 0.0 {
+            hull.pop();
+        }
+        hull.push(p);
+    }
+    // the last point is the first one again
+    hull.pop();
+    hull
+}
+
+/// Minimum area rectangle enclosing a convex polygon as (center, size, angle)
+///
+/// One side of the optimal rectangle is collinear with an edge of the hull, so every edge
+/// direction is tried. The angle is that of the first side, in `[0, pi/2)`.
+pub(crate) fn min_area_rect(hull: &[[f64; 2]]) -> Option<([f64; 2], [f64; 2], f64)> {
+    let mut best: Option<(f64, [f64; 2], [f64; 2], f64)> = None;
+    for (i, a) in hull.iter().enumerate() {
+        let b = hull[(i + 1) % hull.len()];
+        let angle = (b[1] - a[1])
+            .atan2(b[0] - a[0])
+            .rem_euclid(std::f64::consts::FRAC_PI_2);
+        let (s, c) = angle.sin_cos();
+        // Bounds in the frame rotated by -angle
+        let bb = BoundingBox::of(
+            hull.iter()
+                .map(|p| [c * p[0] + s * p[1], -s * p[0] + c * p[1]]),
+        )?;
+        let size = [bb.max[0] - bb.min[0], bb.max[1] - bb.min[1]];
+        let area = size[0] * size[1];
+        if !matches!(best, Some(b) if b.0 <= area) {
+            let m = [(bb.min[0] + bb.max[0]) / 2.0, (bb.min[1] + bb.max[1]) / 2.0];
+            let center = [c * m[0] - s * m[1], s * m[0] + c * m[1]];
+            best = Some((area, center, size, angle));
+        }
+    }
+    best.map(|(_, center, size, angle)| (center, size, angle))
+}
+
+/// Liang-Barsky clipping of a segment to a box, `None` if it lies outside
+pub(crate) fn clip_segment(a: [f64; 2], b: [f64; 2], bb: &BoundingBox) -> Option<[[f64; 2]; 2]> {
+    let d = [b[0] - a[0], b[1] - a[1]];
+    let (mut t0, mut t1) = (0.0f64, 1.0f64);
+    for axis in 0..2 {
+        for (p, q) in [
+            (-d[axis], a[axis] - bb.min[axis]),
+            (d[axis], bb.max[axis] - a[axis]),
+        ] {
+            if p == 0.0 {
+                if q < 0.0 {
+                    return None;
+                }
+            } else {
+                let t = q / p;
+                if p < 0.0 {
+                    t0 = t0.max(t);
+                } else {
+                    t1 = t1.min(t);
+                }
+            }
+        }
+    }
+    if t0 > t1 {
+        return None;
+    }
+    let at = |t: f64| [a[0] + t * d[0], a[1] + t * d[1]];
+    Some([at(t0), at(t1)])
+}
+
+/// Distance from a point to a box, zero inside of it
+pub(crate) fn box_distance(p: [f64; 2], bb: &BoundingBox) -> f64 {
+    let dx = (bb.min[0] - p[0]).max(p[0] - bb.max[0]).max(0.0);
+    let dy = (bb.min[1] - p[1]).max(p[1] - bb.max[1]).max(0.0);
+    dx.hypot(dy)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn near_collinear() {
+        // Points that are collinear in exact arithmetic but not in naive floating point
+        let a = [0.1, 0.1];
+        let b = [0.3, 0.3];
+        for i in 0..100 {
+            let c = [0.5 + i as f64 * f64::EPSILON, 0.5];
+            let expected = if i == 0 {
+                Orientation::Collinear
+            } else {
+                Orientation::Clockwise
+            };
+            assert_eq!(orientation(a, b, c), expected, "{}", i);
+            assert_eq!(orientation(b, a, c), orientation(a, c, b));
+        }
+        assert!(on_segment([2.0, 2.0], [1.0, 1.0], [3.0, 3.0]));
+        assert!(!on_segment([4.0, 4.0], [1.0, 1.0], [3.0, 3.0]));
+    }
+
+    #[test]
+    fn intersections() {
+        let s = [[0.0, 0.0], [2.0, 2.0]];
+        assert_eq!(
+            segment_intersection(s, [[0.0, 2.0], [2.0, 0.0]]),
+            Some(SegmentIntersection::Point([1.0, 1.0]))
+        );
+        assert_eq!(
+            segment_intersection(s, [[2.0, 2.0], [3.0, 0.0]]),
+            Some(SegmentIntersection::Point([2.0, 2.0]))
+        );
+        assert_eq!(
+            segment_intersection(s, [[1.0, 1.0], [3.0, 3.0]]),
+            Some(SegmentIntersection::Overlap([[1.0, 1.0], [2.0, 2.0]]))
+        );
+        assert_eq!(segment_intersection(s, [[3.0, 3.0], [4.0, 4.0]]), None);
+        assert_eq!(segment_intersection(s, [[1.0, 0.0], [3.0, 2.0]]), None);
+    }
+
+    #[test]
+    fn winding() {
+        let square = [[0.0, 0.0], [2.0, 0.0], [2.0, 2.0], [0.0, 2.0]];
+        let reversed: Vec<_> = square.iter().rev().copied().collect();
+        assert_eq!(winding_number(&square, [1.0, 1.0]), 1);
+        assert_eq!(winding_number(&reversed, [1.0, 1.0]), -1);
+        assert_eq!(winding_number(&square, [3.0, 1.0]), 0);
+        assert_eq!(polygon_orientation(&reversed), Orientation::Clockwise);
+        assert_eq!(
+            polygon_orientation(&[[0.0, 0.0], [1.0, 1.0]]),
+            Orientation::Collinear
+        );
+    }
+}
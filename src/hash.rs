@@ -0,0 +1,159 @@
+//! Content hashing of layers
+//!
+//! The hash covers the canonical values of the geometry rather than its encoding: coordinates are
+//! hashed as `f64` and metadata as `i64`, so the hash does not depend on `$$ALIGN`, on whether the
+//! layer borrows or owns its data, or on the byte order of the host. It uses 64 bit FNV-1a, which is
+//! stable across platforms and releases of this crate, so hashes may be stored and compared later.
+
+use crate::clitype::CLIType;
+use crate::{Layer, VectorParams};
+
+/// 64 bit FNV-1a
+struct Fnv(u64);
+
+impl Fnv {
+    const OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+    const PRIME: u64 = 0x0000_0100_0000_01b3;
+
+    fn bytes(&mut self, bytes: &[u8]) {
+        for &b in bytes {
+            self.0 ^= b as u64;
+            self.0 = self.0.wrapping_mul(Fnv::PRIME);
+        }
+    }
+
+    fn u64(&mut self, v: u64) {
+        self.bytes(&v.to_le_bytes())
+    }
+
+    fn i64(&mut self, v: i64) {
+        self.bytes(&v.to_le_bytes())
+    }
+
+    fn f64(&mut self, v: f64) {
+        // -0.0 and 0.0 describe the same position
+        self.u64((v + 0.0).to_bits())
+    }
+
+    fn coords<T: CLIType>(&mut self, coords: &[<T as CLIType>::Coord]) {
+        self.u64(coords.len() as u64);
+        for &c in coords {
+            self.f64(T::coord_to_f64(c));
+        }
+    }
+
+    fn params<T: CLIType>(&mut self, params: &VectorParams<'_, T>) {
+        for values in [params.power(), params.speed()] {
+            match values {
+                Some(values) => {
+                    self.bytes(&[1]);
+                    self.coords::<T>(values);
+                }
+                None => self.bytes(&[0]),
+            }
+        }
+    }
+}
+
+impl<'a, T: CLIType> Layer<'a, T> {
+    /// Hash of the content of the layer, for detecting which layers changed between two
+    /// revisions of a build file.
+    ///
+    /// Height, loops, hatches, their process parameters and user data all contribute to the
+    /// hash, in file order. Two layers with the same content have the same hash, independent of
+    /// how the files were encoded. The hash is not cryptographic.
+    ///
+    /// ```no_run
+    /// # use colain::{CLI, clitype::LongCLI};
+    /// # let (old, new) = (Vec::new(), Vec::new());
+    /// let old = CLI::<LongCLI>::new(&old).unwrap();
+    /// let new = CLI::<LongCLI>::new(&new).unwrap();
+    /// let changed = old
+    ///     .iter()
+    ///     .zip(new.iter())
+    ///     .filter(|(a, b)| a.content_hash() != b.content_hash())
+    ///     .count();
+    /// ```
+    pub fn content_hash(&self) -> u64 {
+        let mut h = Fnv(Fnv::OFFSET);
+        h.f64(T::coord_to_f64(self.height));
+
+        h.u64(self.loops.len() as u64);
+        for l in &self.loops {
+            h.i64(T::meta_to_i64(l.id));
+            h.i64(T::meta_to_i64(l.dir));
+            h.coords::<T>(&l.points);
+            h.params(&l.params);
+        }
+
+        h.u64(self.hatches.len() as u64);
+        for hatches in &self.hatches {
+            h.i64(T::meta_to_i64(hatches.id));
+            h.coords::<T>(&hatches.points);
+            h.params(&hatches.params);
+        }
+
+        h.u64(self.user_data.len() as u64);
+        for d in &self.user_data {
+            h.i64(T::meta_to_i64(d.id));
+            h.u64(d.position as u64);
+            h.u64(d.data.len() as u64);
+            h.bytes(&d.data);
+        }
+        h.0
+    }
+}
+
+/// Combined hash of a sequence of hashes, e.g. of all layers of a model
+pub(crate) fn combine<I: IntoIterator<Item = u64>>(hashes: I) -> u64 {
+    let mut h = Fnv(Fnv::OFFSET);
+    for hash in hashes {
+        h.u64(hash);
+    }
+    h.0
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::clitype::ShortCLI;
+    use crate::{Error, Header, Layer, Loop, CLI};
+    use std::borrow::Cow;
+
+    fn model(aligned: bool, x: u16) -> CLI<'static, ShortCLI> {
+        let layer = |height| Layer {
+            loops: vec![Loop {
+                id: 1,
+                dir: 1,
+                points: Cow::Owned(vec![0, 0, x, 0, x, x, 0, 0]),
+                params: Default::default(),
+            }],
+            ..Layer::empty(height)
+        };
+        CLI {
+            header: Header {
+                aligned,
+                ..Header::default()
+            },
+            layers: vec![layer(10), layer(20)],
+            verbatim: Default::default(),
+        }
+    }
+
+    #[test]
+    fn change_detection() -> Result<(), Error> {
+        let a = model(false, 5).to_bytes();
+        let b = model(true, 5).to_bytes();
+        let c = model(false, 6).to_bytes();
+        let (a, b, c) = (
+            CLI::<ShortCLI>::new(&a)?,
+            CLI::<ShortCLI>::new(&b)?,
+            CLI::<ShortCLI>::new(&c)?,
+        );
+        let hashes = |m: &CLI<ShortCLI>| m.iter().map(|l| l.content_hash()).collect::<Vec<_>>();
+
+        assert_eq!(hashes(&a), hashes(&b));
+        assert_ne!(hashes(&a)[0], hashes(&a)[1]);
+        assert_ne!(hashes(&a)[0], hashes(&c)[0]);
+        Ok(())
+    }
+}
@@ -0,0 +1,310 @@
+//! Optional header commands
+//!
+//! Besides the format, units and version every file must declare, the spec defines a number of
+//! optional header commands. They are kept in the [`Header`](crate::Header) as written, with
+//! structured accessors where the spec prescribes a format.
+//!
+//! `$$USERDATA` entries carry a payload of a declared length, which may hold arbitrary bytes
+//! including line breaks. Only the rest of the header has to be valid UTF-8.
+
+use crate::Error;
+
+/// A line of the header section
+pub(crate) enum Line<'a> {
+    /// Any line except user data, including its line break
+    Text(&'a str),
+    /// A `$$USERDATA` entry
+    UserData {
+        /// The entry as written, including its line break
+        line: &'a [u8],
+        uid: &'a str,
+        data: &'a [u8],
+    },
+}
+
+const USERDATA: &[u8] = b"$$USERDATA/";
+
+/// Split a header section into lines, reading the payload of user data entries by their length.
+///
+/// Fails with [`Error::HeaderInvalidUTF8`] if a line other than a user data payload is not valid
+/// UTF-8, and with [`Error::InvalidHeaderValue`] if a user data entry is malformed.
+pub(crate) fn lines(raw: &[u8]) -> impl Iterator<Item = Result<Line<'_>, Error>> {
+    let mut rest = raw;
+    std::iter::from_fn(move || {
+        if rest.is_empty() {
+            return None;
+        }
+        let line = next_line(rest);
+        match &line {
+            Ok(Line::Text(text)) => rest = &rest[text.len()..],
+            Ok(Line::UserData { line, .. }) => rest = &rest[line.len()..],
+            Err(_) => rest = &[],
+        }
+        Some(line)
+    })
+}
+
+fn next_line(rest: &[u8]) -> Result<Line<'_>, Error> {
+    let line_end = |from: usize| {
+        rest[from..]
+            .iter()
+            .position(|&b| b == b'\n')
+            .map_or(rest.len(), |n| from + n + 1)
+    };
+    let indent = rest
+        .iter()
+        .take_while(|&&b| b == b' ' || b == b'\t')
+        .count();
+    if !rest[indent..].starts_with(USERDATA) {
+        let text = &rest[..line_end(0)];
+        return std::str::from_utf8(text)
+            .map(Line::Text)
+            .map_err(|_| Error::HeaderInvalidUTF8);
+    }
+
+    // $$USERDATA/uid,len,data
+    let fields = indent + USERDATA.len();
+    let mut commas = rest[fields..]
+        .iter()
+        .enumerate()
+        .filter(|(_, &b)| b == b',')
+        .map(|(n, _)| fields + n);
+    let (uid_end, len_end) = match (commas.next(), commas.next()) {
+        (Some(uid), Some(len)) => (uid, len),
+        _ => return Err(Error::InvalidHeaderValue),
+    };
+    let uid = std::str::from_utf8(&rest[fields..uid_end]).map_err(|_| Error::HeaderInvalidUTF8)?;
+    let len: usize = std::str::from_utf8(&rest[uid_end + 1..len_end])
+        .ok()
+        .and_then(|len| len.trim().parse().ok())
+        .ok_or(Error::InvalidHeaderValue)?;
+    let data_end = (len_end + 1)
+        .checked_add(len)
+        .filter(|&end| end <= rest.len())
+        .ok_or(Error::InvalidHeaderValue)?;
+    Ok(Line::UserData {
+        line: &rest[..line_end(data_end)],
+        uid: uid.trim(),
+        data: &rest[len_end + 1..data_end],
+    })
+}
+
+/// A `$$USERDATA` entry of the header
+///
+/// The spec leaves the meaning of the payload to the vendor identified by `uid`, it is passed
+/// through unchanged. Its length is the length of `data`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct HeaderUserData {
+    /// Identifier of the vendor or application the data is meant for
+    pub uid: String,
+    /// The payload
+    pub data: Vec<u8>,
+}
+
+impl HeaderUserData {
+    /// The entry as written in the header, without a line break
+    pub(crate) fn to_line(&self) -> Vec<u8> {
+        let mut line = format!("$$USERDATA/{},{},", self.uid, self.data.len()).into_bytes();
+        line.extend_from_slice(&self.data);
+        line
+    }
+}
+
+/// Name of a command that is not otherwise read, without `$$` and the trailing slash
+///
+/// `None` if the line is not a command or delimits the header.
+pub(crate) fn extra_command(command: &str) -> Option<&str> {
+    let name = command.strip_prefix("$$")?;
+    let name = name.strip_suffix('/').unwrap_or(name);
+    let delimiter = matches!(name, "HEADERSTART" | "HEADEREND");
+    (!name.is_empty() && !delimiter).then_some(name)
+}
+
+/// Read the value of `$$DIMENSION`, six comma separated numbers `x1,y1,z1,x2,y2,z2`
+pub(crate) fn parse_dimension(value: &str) -> Option<[f64; 6]> {
+    let mut dimension = [0.0; 6];
+    let mut values = value.split(',');
+    for d in dimension.iter_mut() {
+        *d = values.next()?.trim().parse().ok()?;
+    }
+    values.next().is_none().then_some(dimension)
+}
+
+/// Read the value of `$$LABEL`, an id and the name given to it separated by a comma
+pub(crate) fn parse_label(value: &str) -> Option<(i64, &str)> {
+    let (id, text) = value.split_once(',')?;
+    Some((id.trim().parse().ok()?, text.trim()))
+}
+
+/// Build date declared by `$$DATE`
+///
+/// The spec gives the date as `ddmmyy`, e.g. `$$DATE/070493` for the 7th of April 1993. Files
+/// written by other tools do not always follow that format, so the value is kept as written and
+/// [`Date::calendar`] is only set when it can be read.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Date {
+    /// The value as written in the header
+    pub raw: String,
+    /// Day, month and year, `None` if the value is not a valid date
+    pub calendar: Option<CalendarDate>,
+}
+
+/// Day, month and year of a [`Date`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CalendarDate {
+    /// Full year, two digit years from 70 on are taken as 19xx and all others as 20xx
+    pub year: u16,
+    /// Month, from 1 to 12
+    pub month: u8,
+    /// Day of the month, from 1
+    pub day: u8,
+}
+
+impl Date {
+    /// Read a date from the value of `$$DATE`, in the `ddmmyy` format of the spec or with a
+    /// four digit year
+    pub fn new(raw: &str) -> Self {
+        let raw = raw.trim();
+        Date {
+            raw: raw.to_string(),
+            calendar: CalendarDate::parse(raw),
+        }
+    }
+}
+
+impl From<CalendarDate> for Date {
+    /// A date written in the `ddmmyy` format of the spec
+    fn from(calendar: CalendarDate) -> Self {
+        Date {
+            raw: format!(
+                "{:02}{:02}{:02}",
+                calendar.day,
+                calendar.month,
+                calendar.year % 100
+            ),
+            calendar: Some(calendar),
+        }
+    }
+}
+
+impl CalendarDate {
+    fn parse(raw: &str) -> Option<Self> {
+        if !matches!(raw.len(), 6 | 8) || !raw.bytes().all(|b| b.is_ascii_digit()) {
+            return None;
+        }
+        let day: u8 = raw[0..2].parse().ok()?;
+        let month: u8 = raw[2..4].parse().ok()?;
+        let year: u16 = raw[4..].parse().ok()?;
+        let year = match (raw.len(), year) {
+            (6, 70..) => 1900 + year,
+            (6, _) => 2000 + year,
+            _ => year,
+        };
+        let leap = year % 4 == 0 && (year % 100 != 0 || year % 400 == 0);
+        let days = match month {
+            2 if leap => 29,
+            2 => 28,
+            4 | 6 | 9 | 11 => 30,
+            1..=12 => 31,
+            _ => return None,
+        };
+        (1..=days)
+            .contains(&day)
+            .then_some(CalendarDate { year, month, day })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn user_data() -> Result<(), Error> {
+        let raw = b"$$UNITS/1\n  $$USERDATA/acme, 4,a\n\xff\n // note\r\n$$LAYERS/2";
+        let split = lines(raw).collect::<Result<Vec<_>, _>>()?;
+        assert_eq!(split.len(), 3);
+        assert!(matches!(split[0], Line::Text("$$UNITS/1\n")));
+        match &split[1] {
+            Line::UserData { line, uid, data } => {
+                assert_eq!(*uid, "acme");
+                assert_eq!(*data, b"a\n\xff\n");
+                assert!(line.ends_with(b" // note\r\n"));
+            }
+            Line::Text(_) => panic!("user data read as text"),
+        }
+        assert!(matches!(split[2], Line::Text("$$LAYERS/2")));
+
+        let entry = HeaderUserData {
+            uid: "acme".into(),
+            data: b"a\n\xff\n".to_vec(),
+        };
+        assert_eq!(entry.to_line(), b"$$USERDATA/acme,4,a\n\xff\n");
+
+        for invalid in [
+            &b"$$USERDATA/acme,9,abc"[..],
+            b"$$USERDATA/acme",
+            b"$$USERDATA/a,x,",
+        ] {
+            assert!(matches!(
+                lines(invalid).last(),
+                Some(Err(Error::InvalidHeaderValue))
+            ));
+        }
+        assert!(matches!(
+            lines(b"$$UNITS/\xff").next(),
+            Some(Err(Error::HeaderInvalidUTF8))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn dimension() {
+        assert_eq!(
+            parse_dimension("-1.5,0,0, 20,30.25,4"),
+            Some([-1.5, 0.0, 0.0, 20.0, 30.25, 4.0])
+        );
+        assert_eq!(parse_dimension("0,0,0,1,1"), None);
+        assert_eq!(parse_dimension("0,0,0,1,1,1,1"), None);
+        assert_eq!(parse_dimension("0,0,0,1,1,x"), None);
+    }
+
+    #[test]
+    fn extra() {
+        assert_eq!(extra_command("$$VENDOR/"), Some("VENDOR"));
+        assert_eq!(extra_command("$$PREVIEW"), Some("PREVIEW"));
+        assert_eq!(extra_command("$$HEADERSTART"), None);
+        assert_eq!(extra_command("text"), None);
+    }
+
+    #[test]
+    fn label() {
+        assert_eq!(parse_label("1, left bracket "), Some((1, "left bracket")));
+        assert_eq!(parse_label("2,a,b"), Some((2, "a,b")));
+        assert_eq!(parse_label("part"), None);
+        assert_eq!(parse_label("x,part"), None);
+    }
+
+    #[test]
+    fn date() {
+        let date = Date::new(" 070493");
+        assert_eq!(date.raw, "070493");
+        assert_eq!(
+            date.calendar,
+            Some(CalendarDate {
+                year: 1993,
+                month: 4,
+                day: 7
+            })
+        );
+        assert_eq!(Date::new("29022024").calendar.unwrap().year, 2024);
+        assert_eq!(Date::new("010105").calendar.unwrap().year, 2005);
+        for invalid in ["290223", "320193", "011393", "1993-04-07", ""] {
+            assert_eq!(Date::new(invalid).calendar, None, "{}", invalid);
+        }
+        let written = Date::from(Date::new("070493").calendar.unwrap());
+        assert_eq!(written, Date::new("070493"));
+    }
+}
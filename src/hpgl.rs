@@ -0,0 +1,161 @@
+//! HPGL export for pen plotters
+//!
+//! Plotting a layer on paper at full scale and laying the printed part on top is a quick way to
+//! check the dimensions of large parts. [`CLI::write_hpgl`] writes a layer as HPGL commands,
+//! drawing the contours with one pen and the hatches with another.
+//!
+//! ```no_run
+//! use colain::{CLI, HpglOptions, clitype::LongCLI};
+//! # let bytes = Vec::new();
+//!
+//! let model = CLI::<LongCLI>::new(&bytes).unwrap();
+//! let file = std::fs::File::create("layer_10.plt").unwrap();
+//! model.write_hpgl(10, std::io::BufWriter::new(file), &HpglOptions::default()).unwrap();
+//! ```
+
+use crate::clitype::CLIType;
+use crate::{LoopKind, CLI};
+use std::io::{self, Write};
+
+/// Plotter units per millimeter
+const UNITS_PER_MM: f64 = 40.0;
+
+/// Options for [`CLI::write_hpgl`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct HpglOptions {
+    /// Millimeters on paper per millimeter of the model, `1.0` plots at full scale
+    pub scale: f64,
+    /// Position of the model origin on paper in millimeters
+    pub offset: [f64; 2],
+    /// Pen for contours and open polylines
+    pub contour_pen: u8,
+    /// Pen for hatches, `None` leaves the hatches out
+    pub hatch_pen: Option<u8>,
+}
+
+impl Default for HpglOptions {
+    fn default() -> Self {
+        HpglOptions {
+            scale: 1.0,
+            offset: [0.0, 0.0],
+            contour_pen: 1,
+            hatch_pen: Some(2),
+        }
+    }
+}
+
+impl<'a, T: CLIType> CLI<'a, T> {
+    /// Write the layer with the given index as HPGL commands.
+    ///
+    /// The output initializes the plotter, draws every loop with [`HpglOptions::contour_pen`],
+    /// closing closed loops that do not end on their first point, then every hatch vector with
+    /// [`HpglOptions::hatch_pen`], and finally puts the pen away. Coordinates are converted to
+    /// plotter units of 0.025 mm.
+    ///
+    /// # Panics
+    /// Panics if `index` is out of bounds.
+    pub fn write_hpgl<W: Write>(
+        &self,
+        index: usize,
+        mut out: W,
+        options: &HpglOptions,
+    ) -> io::Result<()> {
+        let layer = &self.layers[index];
+        let scale = self.header.units * options.scale * UNITS_PER_MM;
+        let x = |c| (T::coord_to_f64(c) * scale + options.offset[0] * UNITS_PER_MM).round() as i64;
+        let y = |c| (T::coord_to_f64(c) * scale + options.offset[1] * UNITS_PER_MM).round() as i64;
+
+        write!(out, "IN;SP{};", options.contour_pen)?;
+        for l in &layer.loops {
+            let mut points: Vec<[i64; 2]> = l
+                .points
+                .chunks_exact(2)
+                .map(|p| [x(p[0]), y(p[1])])
+                .collect();
+            if l.kind() != LoopKind::Open && points.len() > 2 && points.first() != points.last() {
+                points.push(points[0]);
+            }
+            if let Some((first, rest)) = points.split_first() {
+                write!(out, "PU{},{};", first[0], first[1])?;
+                if !rest.is_empty() {
+                    write!(out, "PD")?;
+                    for (i, p) in rest.iter().enumerate() {
+                        let sep = if i + 1 < rest.len() { "," } else { ";" };
+                        write!(out, "{},{}{}", p[0], p[1], sep)?;
+                    }
+                }
+            }
+        }
+        if let Some(pen) = options.hatch_pen {
+            write!(out, "SP{};", pen)?;
+            for v in layer.hatches.iter().flat_map(|h| h.iter()) {
+                write!(out, "PU{},{};PD{},{};", x(v[0]), y(v[1]), x(v[2]), y(v[3]))?;
+            }
+        }
+        write!(out, "PU;SP0;")?;
+        out.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clitype::ShortCLI;
+    use crate::{Hatches, Header, Layer, Loop};
+    use std::borrow::Cow;
+
+    #[test]
+    fn layer() {
+        let model = CLI::<ShortCLI> {
+            header: Header {
+                units: 0.5,
+                ..Header::default()
+            },
+            layers: vec![Layer {
+                loops: vec![
+                    Loop {
+                        id: 0,
+                        dir: 1,
+                        points: Cow::Owned(vec![0, 0, 4, 0, 4, 4]),
+                        params: Default::default(),
+                    },
+                    Loop {
+                        id: 0,
+                        dir: 2,
+                        points: Cow::Owned(vec![10, 0, 12, 2]),
+                        params: Default::default(),
+                    },
+                ],
+                hatches: vec![Hatches {
+                    id: 0,
+                    points: Cow::Owned(vec![1, 1, 3, 1]),
+                    params: Default::default(),
+                }],
+                ..Layer::empty(1)
+            }],
+            verbatim: Default::default(),
+        };
+
+        let mut out = Vec::new();
+        model
+            .write_hpgl(0, &mut out, &HpglOptions::default())
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "IN;SP1;PU0,0;PD80,0,80,80,0,0;PU200,0;PD240,40;SP2;PU20,20;PD60,20;PU;SP0;"
+        );
+
+        let options = HpglOptions {
+            scale: 0.5,
+            offset: [10.0, 0.0],
+            hatch_pen: None,
+            ..Default::default()
+        };
+        let mut out = Vec::new();
+        model.write_hpgl(0, &mut out, &options).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "IN;SP1;PU400,0;PD440,0,440,40,400,0;PU500,0;PD520,20;PU;SP0;"
+        );
+    }
+}
@@ -0,0 +1,164 @@
+//! Convex envelopes of layers and parts
+//!
+//! The convex hull of a layer is a cheap, conservative stand-in for its geometry, e.g. for quick
+//! collision checks against the recoater or between parts. The minimum area rectangle around it
+//! tells how to rotate a part so it takes the least space on a build plate.
+
+use crate::clitype::CLIType;
+use crate::geom;
+use crate::{Layer, CLI};
+
+/// A rectangle rotated about its center
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct OrientedRect {
+    /// Center of the rectangle
+    pub center: [f64; 2],
+    /// Length of the sides, the first one running along `angle`
+    pub size: [f64; 2],
+    /// Counter-clockwise angle between the x axis and the first side in radians, in `[0, pi/2)`
+    pub angle: f64,
+}
+
+impl OrientedRect {
+    /// Area of the rectangle
+    pub fn area(&self) -> f64 {
+        self.size[0] * self.size[1]
+    }
+
+    /// Corners of the rectangle, counter-clockwise
+    pub fn corners(&self) -> [[f64; 2]; 4] {
+        let (s, c) = self.angle.sin_cos();
+        let [w, h] = [self.size[0] / 2.0, self.size[1] / 2.0];
+        [[-w, -h], [w, -h], [w, h], [-w, h]].map(|[x, y]| {
+            [
+                self.center[0] + c * x - s * y,
+                self.center[1] + s * x + c * y,
+            ]
+        })
+    }
+
+    fn enclosing(hull: &[[f64; 2]]) -> Option<Self> {
+        geom::min_area_rect(hull).map(|(center, size, angle)| OrientedRect {
+            center,
+            size,
+            angle,
+        })
+    }
+}
+
+impl<'a, T: CLIType> Layer<'a, T> {
+    /// Convex hull of all loops and hatches of the layer.
+    ///
+    /// The hull is returned counter-clockwise in coordinate units, without repeating the first
+    /// point. Layers with fewer than three distinct points return just those points.
+    pub fn convex_hull(&self) -> Vec<[f64; 2]> {
+        geom::convex_hull(self.all_points().collect())
+    }
+
+    /// Smallest rectangle of any orientation enclosing all geometry of the layer, in coordinate
+    /// units. `None` if the layer is empty.
+    pub fn min_area_rect(&self) -> Option<OrientedRect> {
+        OrientedRect::enclosing(&self.convex_hull())
+    }
+
+    /// Every point of the layer's loops and hatches in coordinate units
+    pub(crate) fn all_points(&self) -> impl Iterator<Item = [f64; 2]> + '_ {
+        self.loops
+            .iter()
+            .flat_map(|l| l.points.chunks_exact(2))
+            .chain(self.hatches.iter().flat_map(|h| h.points.chunks_exact(2)))
+            .map(|p| [T::coord_to_f64(p[0]), T::coord_to_f64(p[1])])
+    }
+}
+
+impl<'a, T: CLIType> CLI<'a, T> {
+    /// Smallest rectangle of any orientation enclosing the footprint of the whole part, i.e. all
+    /// geometry of every layer, in millimeters. `None` if the model has no geometry.
+    ///
+    /// Rotating the part by `-angle` aligns the rectangle with the axes, which is what automatic
+    /// packing of build plates wants.
+    pub fn min_area_rect(&self) -> Option<OrientedRect> {
+        let units = self.header.units;
+        let hulls = self.layers.iter().flat_map(|l| l.convex_hull());
+        let hull = geom::convex_hull(hulls.map(|p| [p[0] * units, p[1] * units]).collect());
+        OrientedRect::enclosing(&hull)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clitype::{LongCLI, ShortCLI};
+    use crate::{geom, Hatches, Header, Layer, Loop, CLI};
+    use std::borrow::Cow;
+
+    #[test]
+    fn hull() {
+        let layer = Layer::<ShortCLI> {
+            loops: vec![Loop {
+                id: 0,
+                dir: 1,
+                // L shape
+                points: Cow::Owned(vec![0, 0, 4, 0, 4, 1, 1, 1, 1, 4, 0, 4, 0, 0]),
+                params: Default::default(),
+            }],
+            hatches: vec![Hatches {
+                id: 0,
+                points: Cow::Owned(vec![2, 2, 6, 2]),
+                params: Default::default(),
+            }],
+            ..Layer::empty(1)
+        };
+        let hull = layer.convex_hull();
+        assert_eq!(
+            hull,
+            vec![[0.0, 0.0], [4.0, 0.0], [6.0, 2.0], [1.0, 4.0], [0.0, 4.0]]
+        );
+        assert!(geom::signed_area(&hull) > 0.0);
+    }
+
+    #[test]
+    fn rotated_rect() {
+        // A 4 x 2 rectangle rotated by 30 degrees
+        let rect = OrientedRect {
+            center: [10.0, 5.0],
+            size: [4.0, 2.0],
+            angle: std::f64::consts::FRAC_PI_6,
+        };
+        let corners = rect.corners();
+        let mut points: Vec<f32> = corners
+            .iter()
+            .flat_map(|p| [p[0] as f32, p[1] as f32])
+            .collect();
+        let first = [points[0], points[1]];
+        points.extend_from_slice(&first);
+        let model = CLI::<LongCLI> {
+            header: Header {
+                units: 2.0,
+                ..Header::default()
+            },
+            layers: vec![Layer {
+                loops: vec![Loop {
+                    id: 0,
+                    dir: 1,
+                    points: Cow::Owned(points),
+                    params: Default::default(),
+                }],
+                ..Layer::empty(1.0)
+            }],
+            verbatim: Default::default(),
+        };
+
+        let found = model.layers[0].min_area_rect().unwrap();
+        assert!((found.area() - 8.0).abs() < 1e-4);
+        assert!(
+            (found.angle - rect.angle).abs() < 1e-6
+                || (found.angle - rect.angle - std::f64::consts::FRAC_PI_2).abs() < 1e-6
+        );
+        assert!((found.center[0] - 10.0).abs() < 1e-4);
+
+        let part = model.min_area_rect().unwrap();
+        assert!((part.area() - 32.0).abs() < 1e-3);
+        assert!((part.center[1] - 10.0).abs() < 1e-4);
+    }
+}
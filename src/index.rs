@@ -0,0 +1,220 @@
+//! Locating layers without decoding them
+//!
+//! Indexing walks the record structure of the geometry section and notes where each layer starts
+//! and ends, without building any loops or hatches. Layers can then be decoded individually and in
+//! any order, e.g. starting with the ones a viewer needs first, see [`CLI::decode_nearest`].
+
+use crate::clitype::CLIType;
+use crate::token::Tokens;
+use crate::trace;
+use crate::{Cursor, Error, Header, Layer, Parser, ParserOptions, CLI};
+use std::marker::PhantomData;
+use std::ops::Range;
+
+/// Position of a layer within the geometry section
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct LayerSpan {
+    /// Height of the layer in coordinate units
+    pub height: f64,
+    /// Bytes of the geometry section belonging to the layer, starting with its layer command
+    pub range: Range<usize>,
+}
+
+/// Find the position of every layer in a geometry section
+pub(crate) fn layer_spans<T: CLIType>(
+    geom: &[u8],
+    aligned: bool,
+    options: &ParserOptions,
+) -> Result<Vec<LayerSpan>, Error> {
+    let _span = trace::index_span(geom.len());
+    let mut spans: Vec<LayerSpan> = Vec::new();
+    let mut end = 0;
+    for token in Tokens::<T>::with_options(geom, aligned, options) {
+        let token = token?;
+        end = token.payload.as_ptr() as usize - geom.as_ptr() as usize + token.payload.len();
+        if token.command == T::CMD_LAYER {
+            if let Some(last) = spans.last_mut() {
+                last.range.end = token.offset;
+            }
+            let mut payload = token.payload;
+            spans.push(LayerSpan {
+                height: T::coord_to_f64(T::get_coord(&mut payload, aligned)),
+                range: token.offset..end,
+            });
+        } else if spans.is_empty() {
+            return Err(Error::ElementOutsideLayer);
+        }
+    }
+    if let Some(last) = spans.last_mut() {
+        last.range.end = end;
+    }
+    Ok(spans)
+}
+
+impl<'a, T: CLIType> CLI<'a, T> {
+    /// Decode a single layer located by [`layer_spans`]
+    pub(crate) fn decode_span(
+        header: &Header,
+        geom: &'a [u8],
+        span: &LayerSpan,
+        options: &ParserOptions,
+    ) -> Result<Layer<'a, T>, Error> {
+        let _span = trace::layer_span(span.range.len());
+        let mut scratch = CLI {
+            header: header.clone(),
+            layers: Vec::with_capacity(1),
+            verbatim: Default::default(),
+        };
+        let mut cursor = Cursor::default();
+        for token in Tokens::<T>::with_options(&geom[span.range.clone()], header.aligned, options) {
+            scratch.decode(&mut cursor, token?)?;
+        }
+        scratch.layers.pop().ok_or(Error::UnexpectedEOF)
+    }
+
+    /// Decode the layers of a file in order of their distance to height `z`.
+    ///
+    /// The geometry section is indexed first, which is much cheaper than decoding it, so the layer
+    /// closest to `z` is available right away even for very large files. The remaining layers
+    /// follow spiraling outward, alternating between layers above and below `z` as their distance
+    /// dictates. Each item carries the index of the layer in the file.
+    ///
+    /// `z` is given in coordinate units, like [`Layer::height`].
+    ///
+    /// ```
+    /// # use colain::{CLI, ParserOptions, clitype::LongCLI};
+    /// # let buf = colain::BuildPlate::<LongCLI>::new(1.0).to_bytes();
+    /// for layer in CLI::<LongCLI>::decode_nearest(&buf, 12.5, &ParserOptions::default()).unwrap() {
+    ///     let (index, layer) = layer.unwrap();
+    ///     // show the layer
+    /// }
+    /// ```
+    pub fn decode_nearest(
+        raw: &'a [u8],
+        z: <T as CLIType>::Coord,
+        options: &ParserOptions,
+    ) -> Result<NearestLayers<'a, T>, Error> {
+        let (header, _, geom) = Parser::<T>::default().options(options).split(raw)?;
+        let spans = layer_spans::<T>(geom, header.aligned, options)?;
+
+        let z = T::coord_to_f64(z);
+        let mut order: Vec<usize> = (0..spans.len()).collect();
+        order.sort_by(|&a, &b| {
+            let da = (spans[a].height - z).abs();
+            let db = (spans[b].height - z).abs();
+            da.partial_cmp(&db)
+                .unwrap_or(std::cmp::Ordering::Equal)
+                .then(a.cmp(&b))
+        });
+
+        Ok(NearestLayers {
+            header,
+            geom,
+            spans,
+            order: order.into_iter(),
+            options: options.clone(),
+            _type: PhantomData,
+        })
+    }
+}
+
+/// Layers decoded in order of their distance to a height, see [`CLI::decode_nearest`]
+pub struct NearestLayers<'a, T: CLIType> {
+    header: Header,
+    geom: &'a [u8],
+    spans: Vec<LayerSpan>,
+    order: std::vec::IntoIter<usize>,
+    options: ParserOptions,
+    _type: PhantomData<T>,
+}
+
+impl<T: CLIType> NearestLayers<'_, T> {
+    /// Header of the file
+    pub fn header(&self) -> &Header {
+        &self.header
+    }
+    /// Total number of layers in the file
+    pub fn layer_count(&self) -> usize {
+        self.spans.len()
+    }
+}
+
+impl<'a, T: CLIType> Iterator for NearestLayers<'a, T> {
+    type Item = Result<(usize, Layer<'a, T>), Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let index = self.order.next()?;
+        Some(
+            CLI::decode_span(&self.header, self.geom, &self.spans[index], &self.options)
+                .map(|layer| (index, layer)),
+        )
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.order.size_hint()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clitype::ShortCLI;
+    use crate::{Hatches, Loop};
+    use std::borrow::Cow;
+
+    fn file(aligned: bool) -> Vec<u8> {
+        let layer = |height| Layer::<ShortCLI> {
+            loops: vec![Loop {
+                id: height,
+                dir: 1,
+                points: Cow::Owned(vec![0, 0, 1, 0, 1, 1, 0, 0]),
+                params: Default::default(),
+            }],
+            hatches: vec![Hatches {
+                id: height,
+                points: Cow::Owned(vec![0, 0, 1, 1]),
+                params: Default::default(),
+            }],
+            ..Layer::empty(height)
+        };
+        CLI {
+            header: Header {
+                aligned,
+                ..Header::default()
+            },
+            layers: (1..=5).map(|h| layer(h * 10)).collect(),
+            verbatim: Default::default(),
+        }
+        .to_bytes()
+    }
+
+    #[test]
+    fn spiral() -> Result<(), Error> {
+        for aligned in [false, true] {
+            let bytes = file(aligned);
+            let nearest = CLI::<ShortCLI>::decode_nearest(&bytes, 32, &Default::default())?;
+            assert_eq!(nearest.layer_count(), 5);
+            let layers = nearest.collect::<Result<Vec<_>, _>>()?;
+            let order: Vec<usize> = layers.iter().map(|(i, _)| *i).collect();
+            assert_eq!(order, vec![2, 3, 1, 4, 0]);
+            for (i, layer) in layers {
+                assert_eq!(layer.height(), (i as u16 + 1) * 10);
+                assert_eq!(layer.iter_loops().next().unwrap().id(), layer.height());
+                assert_eq!(layer.iter_hatches().count(), 1);
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn checksum() -> Result<(), Error> {
+        let buf = file(false);
+        let model = CLI::<ShortCLI>::new(&buf)?;
+        let bytes = model.to_bytes_with_checksum(crate::checksum::Checksum::Sha256);
+        let mut nearest = CLI::<ShortCLI>::decode_nearest(&bytes, 50, &Default::default())?;
+        let (index, layer) = nearest.next().unwrap()?;
+        assert_eq!(index, 4);
+        assert!(layer.user_data.is_empty());
+        Ok(())
+    }
+}
@@ -0,0 +1,198 @@
+//! Decoding straight into user storage
+//!
+//! Implement [`GeometrySink`] to receive the geometry of a file record by record, without
+//! building a [`CLI`] model with its [`Loop`]s and [`Hatches`], for example to fill GPU staging
+//! buffers. The coordinates are borrowed from the file buffer whenever its alignment allows it.
+//!
+//! ```
+//! use colain::clitype::{CLIType, LongCLI};
+//! use colain::{GeometrySink, Parser};
+//! # let buf = colain::BuildPlate::<LongCLI>::new(1.0).to_bytes();
+//!
+//! /// Counts the points of the file
+//! #[derive(Default)]
+//! struct Count(usize);
+//!
+//! impl GeometrySink<LongCLI> for Count {
+//!     fn layer(&mut self, _height: f32) {}
+//!     fn contour(&mut self, _id: i32, _dir: i32, points: &[f32]) {
+//!         self.0 += points.len() / 2;
+//!     }
+//!     fn hatches(&mut self, _id: i32, points: &[f32]) {
+//!         self.0 += points.len() / 2;
+//!     }
+//! }
+//!
+//! let mut count = Count::default();
+//! let header = Parser::new().parse_into(&buf, &mut count).unwrap();
+//! ```
+//!
+//! [`Loop`]: crate::Loop
+//! [`Hatches`]: crate::Hatches
+
+use crate::clitype::CLIType;
+use crate::{trace, Error, Header, Parser, CLI};
+
+/// Receiver of the records of a geometry section, see the [module documentation](self)
+///
+/// Coordinates are in the units of the file, like in the model.
+pub trait GeometrySink<T: CLIType> {
+    /// Start of a new layer, all following records belong to it
+    fn layer(&mut self, height: T::Coord);
+
+    /// A polyline, `points` holds alternating x and y coordinates
+    fn contour(&mut self, id: T::Meta, dir: T::Meta, points: &[T::Coord]);
+
+    /// A block of hatches, `points` holds the start and end x and y of each hatch
+    fn hatches(&mut self, id: T::Meta, points: &[T::Coord]);
+
+    /// Per-vector laser power of the previous polyline or hatch block
+    fn power(&mut self, values: &[T::Coord]) {
+        let _ = values;
+    }
+
+    /// Per-vector speed of the previous polyline or hatch block
+    fn speed(&mut self, values: &[T::Coord]) {
+        let _ = values;
+    }
+
+    /// User data attached to the current layer
+    fn user_data(&mut self, id: T::Meta, data: &[u8]) {
+        let _ = (id, data);
+    }
+}
+
+impl<T: CLIType> Parser<T> {
+    /// Parse a file into a [`GeometrySink`] instead of a model, returning the header.
+    ///
+    /// The records are checked the same way as by [`Parser::parse`] and the filters, limits and
+    /// strictness of the parser apply. In strict mode the sink may already have received part
+    /// of the geometry when an error is returned.
+    pub fn parse_into<S: GeometrySink<T>>(
+        &self,
+        raw: &[u8],
+        sink: &mut S,
+    ) -> Result<Header, Error> {
+        let _span = trace::parse_span(raw.len());
+        let (header, _, geom) = self.split(raw)?;
+        let aligned = header.aligned;
+
+        let _span = trace::geometry_span(geom.len());
+        // whether a layer was started and whether an extension has something to attach to
+        let (mut in_layer, mut attached) = (false, false);
+        let base = geom.as_ptr() as usize - raw.as_ptr() as usize;
+        self.walk(geom, base, aligned, header.units, |token| {
+            let buf = &mut &token.payload[..];
+            let c = token.command;
+            if c != T::CMD_LAYER && !in_layer {
+                return Err(Error::ElementOutsideLayer);
+            }
+            if c == T::CMD_LAYER {
+                sink.layer(T::get_coord(buf, aligned));
+                in_layer = true;
+                attached = false;
+            } else if c == T::CMD_PLINE {
+                let id = T::get_meta(buf, aligned);
+                let dir = T::get_meta(buf, aligned);
+                let n = T::get_usize(buf, aligned);
+                sink.contour(id, dir, &CLI::<T>::cast_slice(n * 2, buf));
+                attached = true;
+                return Ok(n);
+            } else if c == T::CMD_HATCH {
+                let id = T::get_meta(buf, aligned);
+                let n = T::get_usize(buf, aligned);
+                sink.hatches(id, &CLI::<T>::cast_slice(n * 4, buf));
+                attached = true;
+                return Ok(n * 2);
+            } else if c == T::CMD_POWER || c == T::CMD_SPEED {
+                if !attached {
+                    return Err(Error::UnattachedExtension(c));
+                }
+                let n = T::get_usize(buf, aligned);
+                let values = CLI::<T>::cast_slice(n, buf);
+                if c == T::CMD_POWER {
+                    sink.power(&values);
+                } else {
+                    sink.speed(&values);
+                }
+            } else if c == T::CMD_USERDATA {
+                let id = T::get_meta(buf, aligned);
+                let n = T::get_usize(buf, aligned);
+                sink.user_data(id, &buf[..n]);
+            }
+            Ok(0)
+        })?;
+        Ok(header)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clitype::ShortCLI;
+    use crate::{Hatches, Layer, Loop};
+    use std::borrow::Cow;
+
+    #[derive(Default)]
+    struct Flat {
+        heights: Vec<u16>,
+        vertices: Vec<u16>,
+        hatches: usize,
+    }
+
+    impl GeometrySink<ShortCLI> for Flat {
+        fn layer(&mut self, height: u16) {
+            self.heights.push(height);
+        }
+        fn contour(&mut self, _id: u16, _dir: u16, points: &[u16]) {
+            self.vertices.extend_from_slice(points);
+        }
+        fn hatches(&mut self, _id: u16, points: &[u16]) {
+            self.hatches += points.len() / 4;
+        }
+    }
+
+    #[test]
+    fn flat() -> Result<(), Error> {
+        let layer = |height| Layer::<ShortCLI> {
+            loops: vec![Loop {
+                id: 1,
+                dir: 1,
+                points: Cow::Owned(vec![0, 0, height, 0, 0, 0]),
+                params: Default::default(),
+            }],
+            hatches: vec![Hatches {
+                id: 1,
+                points: Cow::Owned(vec![0, 0, 1, 1, 2, 2, 3, 3]),
+                params: Default::default(),
+            }],
+            ..Layer::empty(height)
+        };
+        let buf = CLI {
+            header: Header::default(),
+            layers: (1..=3).map(layer).collect(),
+            verbatim: Default::default(),
+        }
+        .to_bytes();
+
+        let mut flat = Flat::default();
+        let header = Parser::new()
+            .short()
+            .z_range(2.0..)
+            .parse_into(&buf, &mut flat)?;
+        assert_eq!(header.units, 1.0);
+        assert_eq!(flat.heights, vec![2, 3]);
+        assert_eq!(flat.vertices, vec![0, 0, 2, 0, 0, 0, 0, 0, 3, 0, 0, 0]);
+        assert_eq!(flat.hatches, 4);
+
+        let limited = Parser::new()
+            .short()
+            .limit_points(10)
+            .parse_into(&buf, &mut Flat::default());
+        assert!(matches!(
+            limited.map_err(Error::into_inner),
+            Err(Error::LimitExceeded)
+        ));
+        Ok(())
+    }
+}
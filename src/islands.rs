@@ -0,0 +1,252 @@
+//! Disjoint solid regions of a layer
+//!
+//! Loops are nested: the outer boundary of a region contains its holes, which may in turn contain
+//! further regions. [`Layer::islands`] resolves this nesting from the geometry itself rather than
+//! from the loop directions, which slicers do not set consistently. [`Layer::regions`] keeps the
+//! whole hierarchy as a tree instead.
+
+use crate::clitype::CLIType;
+use crate::geom;
+use crate::Layer;
+
+/// A connected solid region of a layer: an outer loop and the holes directly inside it
+///
+/// All values are in coordinate units.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Island {
+    /// Index of the outer loop within the layer
+    pub outer: usize,
+    /// Indices of the loops cutting holes into the region
+    pub holes: Vec<usize>,
+    /// Solid area, i.e. the area of the outer loop minus its holes
+    pub area: f64,
+    /// Centroid of the solid area
+    pub centroid: [f64; 2],
+}
+
+/// An outer boundary in the nesting of the loops of a layer, see [`Layer::regions`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Region {
+    /// Index of the outer loop within the layer
+    pub outer: usize,
+    /// Holes directly inside the outer loop
+    pub holes: Vec<Hole>,
+}
+
+/// A hole in a [`Region`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Hole {
+    /// Index of the loop within the layer
+    pub index: usize,
+    /// Regions inside the hole, e.g. a core standing in a ring
+    pub regions: Vec<Region>,
+}
+
+/// Index of a loop within its layer and its points
+type Polygon = (usize, Vec<[f64; 2]>);
+
+impl<'a, T: CLIType> Layer<'a, T> {
+    /// Closed loops of the layer as polygons, coordinates multiplied by `scale`
+    pub(crate) fn solid(&self, scale: f64) -> Vec<Vec<[f64; 2]>> {
+        self.loops
+            .iter()
+            .filter(|l| l.is_solid())
+            .map(|l| geom::to_points(&l.points, |c| T::coord_to_f64(c) * scale))
+            .collect()
+    }
+
+    /// Split the closed loops of the layer into disjoint solid regions.
+    ///
+    /// A loop is a hole if it is nested inside an odd number of other loops, and belongs to the
+    /// innermost loop containing it. Everything else is the outer boundary of an island.
+    /// Open polylines and degenerate loops are ignored.
+    ///
+    /// Comparing the islands of a layer with the solid region of the layer below reveals islands
+    /// that start floating in mid-air and need supports.
+    pub fn islands(&self) -> Vec<Island> {
+        let (polygons, parents) = self.nesting();
+        let area = |i: usize| geom::signed_area(&polygons[i].1).abs();
+
+        let mut islands: Vec<(usize, Island)> = Vec::new();
+        for (i, (index, p)) in polygons.iter().enumerate() {
+            if parents[i].len() % 2 != 1 {
+                let c = geom::area_centroid(p).unwrap_or(p[0]);
+                let a = area(i);
+                islands.push((
+                    i,
+                    Island {
+                        outer: *index,
+                        holes: Vec::new(),
+                        area: a,
+                        centroid: [c[0] * a, c[1] * a],
+                    },
+                ));
+            }
+        }
+        for (i, (index, p)) in polygons.iter().enumerate() {
+            if parents[i].len() % 2 == 1 {
+                // The innermost container is the one nested deepest
+                let parent = parents[i].iter().copied().max_by_key(|&j| parents[j].len());
+                if let Some(island) = islands.iter_mut().find(|(o, _)| Some(*o) == parent) {
+                    let island = &mut island.1;
+                    let c = geom::area_centroid(p).unwrap_or(p[0]);
+                    let a = area(i);
+                    island.holes.push(*index);
+                    island.area -= a;
+                    island.centroid =
+                        [island.centroid[0] - c[0] * a, island.centroid[1] - c[1] * a];
+                }
+            }
+        }
+
+        islands
+            .into_iter()
+            .map(|(_, mut island)| {
+                if island.area > 0.0 {
+                    island.centroid = [
+                        island.centroid[0] / island.area,
+                        island.centroid[1] / island.area,
+                    ];
+                }
+                island
+            })
+            .collect()
+    }
+
+    /// Nest the closed loops of the layer into a tree of outer boundaries and their holes.
+    ///
+    /// Nesting is decided like for [`Layer::islands`]: from the geometry, with a loop belonging
+    /// to the innermost loop containing it. Outer boundaries at the top of the tree are in the
+    /// order of their loops, as are the children of every node. Open polylines and degenerate
+    /// loops are left out.
+    pub fn regions(&self) -> Vec<Region> {
+        let (polygons, parents) = self.nesting();
+        let innermost = |i: usize| parents[i].iter().copied().max_by_key(|&j| parents[j].len());
+        let children = |parent: Option<usize>| -> Vec<usize> {
+            (0..polygons.len())
+                .filter(|&i| innermost(i) == parent)
+                .collect()
+        };
+        fn region(
+            i: usize,
+            indices: &[usize],
+            children: &dyn Fn(Option<usize>) -> Vec<usize>,
+        ) -> Region {
+            Region {
+                outer: indices[i],
+                holes: children(Some(i))
+                    .into_iter()
+                    .map(|h| Hole {
+                        index: indices[h],
+                        regions: children(Some(h))
+                            .into_iter()
+                            .map(|r| region(r, indices, children))
+                            .collect(),
+                    })
+                    .collect(),
+            }
+        }
+        let indices: Vec<usize> = polygons.iter().map(|(index, _)| *index).collect();
+        children(None)
+            .into_iter()
+            .map(|i| region(i, &indices, &children))
+            .collect()
+    }
+
+    /// Closed loops with area as pairs of loop index and points, and for each the positions of
+    /// the loops containing it, tested with its first point
+    fn nesting(&self) -> (Vec<Polygon>, Vec<Vec<usize>>) {
+        let polygons: Vec<Polygon> = self
+            .loops
+            .iter()
+            .enumerate()
+            .filter(|(_, l)| l.is_solid())
+            .map(|(i, l)| (i, geom::to_points(&l.points, |c| T::coord_to_f64(c))))
+            .filter(|(_, p)| p.len() >= 3 && geom::signed_area(p) != 0.0)
+            .collect();
+        let parents = polygons
+            .iter()
+            .enumerate()
+            .map(|(i, (_, p))| {
+                (0..polygons.len())
+                    .filter(|&j| j != i && geom::winding_number(&polygons[j].1, p[0]) != 0)
+                    .collect()
+            })
+            .collect();
+        (polygons, parents)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clitype::LongCLI;
+    use crate::Loop;
+    use std::borrow::Cow;
+
+    fn square(x: f32, y: f32, size: f32, dir: i32) -> Loop<'static, LongCLI> {
+        Loop {
+            id: 0,
+            dir,
+            points: Cow::Owned(vec![
+                x,
+                y,
+                x + size,
+                y,
+                x + size,
+                y + size,
+                x,
+                y + size,
+                x,
+                y,
+            ]),
+            params: Default::default(),
+        }
+    }
+
+    #[test]
+    fn nested() {
+        let layer = Layer::<LongCLI> {
+            loops: vec![
+                square(2.0, 2.0, 6.0, 0),
+                square(0.0, 0.0, 10.0, 1),
+                square(4.0, 4.0, 2.0, 1),
+                square(20.0, 0.0, 4.0, 1),
+                square(30.0, 0.0, 4.0, 2),
+            ],
+            ..Layer::empty(1.0)
+        };
+        let islands = layer.islands();
+        assert_eq!(islands.len(), 3);
+
+        let frame = islands.iter().find(|i| i.outer == 1).unwrap();
+        assert_eq!(frame.holes, vec![0]);
+        assert!((frame.area - 64.0).abs() < 1e-9);
+        assert!((frame.centroid[0] - 5.0).abs() < 1e-9);
+
+        let core = islands.iter().find(|i| i.outer == 2).unwrap();
+        assert!(core.holes.is_empty());
+        assert!((core.area - 4.0).abs() < 1e-9);
+
+        let separate = islands.iter().find(|i| i.outer == 3).unwrap();
+        assert_eq!(separate.centroid, [22.0, 2.0]);
+
+        let regions = layer.regions();
+        let core = Region {
+            outer: 2,
+            holes: vec![],
+        };
+        let frame = Region {
+            outer: 1,
+            holes: vec![Hole {
+                index: 0,
+                regions: vec![core],
+            }],
+        };
+        let separate = Region {
+            outer: 3,
+            holes: vec![],
+        };
+        assert_eq!(regions, [frame, separate]);
+    }
+}
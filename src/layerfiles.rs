@@ -0,0 +1,280 @@
+//! Models stored as one file per layer
+//!
+//! Some slicers write every layer into its own small CLI file, and some DLP and inkjet machines
+//! expect their jobs in this form. [`CLI::from_layer_files`] reads a directory of such files back
+//! into a single model, [`CLI::write_layer_files`] splits a model up.
+//!
+//! ```no_run
+//! use colain::{CLI, LayerFileNaming, clitype::LongCLI};
+//!
+//! let model = CLI::<LongCLI>::from_layer_files("slices").unwrap();
+//! println!("{} layers", model.iter().count());
+//! model.write_layer_files("job", LayerFileNaming::Height).unwrap();
+//! ```
+
+use crate::clitype::CLIType;
+use crate::{writer, Error, Header, CLI};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Errors when reading a directory of layer files
+#[derive(Debug)]
+pub enum LayerFilesError {
+    /// Reading the directory or a file failed
+    Io(io::Error),
+    /// A file could not be parsed
+    Parse(PathBuf, Error),
+    /// The header of a file does not agree with the first file on units or version
+    InconsistentHeader(PathBuf),
+    /// Two files contain a layer at the same height, given in millimeters
+    DuplicateHeight(f64),
+    /// The directory contains no CLI files
+    NoFiles,
+}
+
+impl std::fmt::Display for LayerFilesError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+impl std::error::Error for LayerFilesError {}
+
+impl From<io::Error> for LayerFilesError {
+    fn from(e: io::Error) -> Self {
+        LayerFilesError::Io(e)
+    }
+}
+
+/// How [`CLI::write_layer_files`] names the files
+#[derive(Debug, Clone, Copy, Default)]
+pub enum LayerFileNaming {
+    /// `layer_0000.cli`, `layer_0001.cli`, ... numbered in model order, with at least four digits
+    #[default]
+    Index,
+    /// `z_0.050.cli`, the height of the layer in millimeters with three decimals
+    Height,
+    /// Name returned by the function, given the index and the height in millimeters
+    Custom(fn(usize, f64) -> String),
+}
+
+impl LayerFileNaming {
+    fn name(&self, index: usize, z: f64, layers: usize) -> String {
+        match self {
+            LayerFileNaming::Index => {
+                let digits = layers.saturating_sub(1).to_string().len().max(4);
+                format!("layer_{:0digits$}.cli", index, digits = digits)
+            }
+            LayerFileNaming::Height => format!("z_{:.3}.cli", z),
+            LayerFileNaming::Custom(f) => f(index, z),
+        }
+    }
+}
+
+impl<'a, T: CLIType> CLI<'a, T> {
+    /// Write each layer into its own file in `dir`, returning the paths in model order.
+    ///
+    /// The directory is created if needed. Every file gets a copy of the header of the model
+    /// declaring a single layer. Fails with [`io::ErrorKind::InvalidInput`] if two layers would
+    /// be written to the same file, for example two layers at the same height with
+    /// [`LayerFileNaming::Height`].
+    pub fn write_layer_files<P: AsRef<Path>>(
+        &self,
+        dir: P,
+        naming: LayerFileNaming,
+    ) -> io::Result<Vec<PathBuf>> {
+        let dir = dir.as_ref();
+        let paths: Vec<PathBuf> = self
+            .layers
+            .iter()
+            .enumerate()
+            .map(|(i, l)| {
+                let z = T::coord_to_f64(l.height) * self.header.units;
+                dir.join(naming.name(i, z, self.layers.len()))
+            })
+            .collect();
+        let mut unique: Vec<&PathBuf> = paths.iter().collect();
+        unique.sort();
+        if let Some(w) = unique.windows(2).find(|w| w[0] == w[1]) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("two layers would be written to {}", w[0].display()),
+            ));
+        }
+
+        fs::create_dir_all(dir)?;
+        for (layer, path) in self.layers.iter().zip(paths.iter()) {
+            let mut out = Vec::new();
+            self.write_header(&mut out, 1);
+            writer::write_layer(&mut out, layer, self.header.aligned);
+            fs::write(path, out)?;
+        }
+        Ok(paths)
+    }
+}
+
+impl<T: CLIType> CLI<'static, T> {
+    /// Read every `.cli` file in a directory and combine their layers into one model, ordered by
+    /// height.
+    ///
+    /// Each file may hold any number of layers. All files must use the same units and version, the
+    /// header of the first file in name order is used for the model. Subdirectories are not read.
+    pub fn from_layer_files<P: AsRef<Path>>(dir: P) -> Result<Self, LayerFilesError> {
+        let mut paths = Vec::new();
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            let is_cli = path
+                .extension()
+                .is_some_and(|e| e.eq_ignore_ascii_case("cli"));
+            if is_cli && path.is_file() {
+                paths.push(path);
+            }
+        }
+        paths.sort();
+
+        let mut header: Option<Header> = None;
+        let mut layers = Vec::new();
+        for path in paths {
+            let buf = fs::read(&path)?;
+            let part = match CLI::<T>::new(&buf) {
+                Ok(part) => part,
+                Err(e) => return Err(LayerFilesError::Parse(path, e)),
+            };
+            match &header {
+                Some(h) if h.units != part.header.units || h.version != part.header.version => {
+                    return Err(LayerFilesError::InconsistentHeader(path));
+                }
+                Some(_) => {}
+                None => header = Some(part.header.clone()),
+            }
+            layers.extend(part.layers.iter().map(|l| l.to_owned_layer()));
+        }
+        let mut header = header.ok_or(LayerFilesError::NoFiles)?;
+
+        let z = |h: T::Coord| T::coord_to_f64(h) * header.units;
+        layers.sort_by(|a, b| z(a.height).total_cmp(&z(b.height)));
+        if let Some(w) = layers.windows(2).find(|w| z(w[0].height) == z(w[1].height)) {
+            return Err(LayerFilesError::DuplicateHeight(z(w[0].height)));
+        }
+        header.layers = Some(layers.len());
+        Ok(CLI {
+            header,
+            layers,
+            verbatim: Default::default(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clitype::ShortCLI;
+    use crate::{Layer, Loop};
+    use std::borrow::Cow;
+
+    fn file(units: f64, heights: &[u16]) -> Vec<u8> {
+        CLI::<ShortCLI> {
+            header: Header {
+                units,
+                ..Header::default()
+            },
+            layers: heights
+                .iter()
+                .map(|&height| Layer {
+                    loops: vec![Loop {
+                        id: 1,
+                        dir: 1,
+                        points: Cow::Owned(vec![0, 0, height, 0, 0, height]),
+                        params: Default::default(),
+                    }],
+                    ..Layer::empty(height)
+                })
+                .collect(),
+            verbatim: Default::default(),
+        }
+        .to_bytes()
+    }
+
+    fn dir(name: &str, files: &[(&str, Vec<u8>)]) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("colain-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        for (name, data) in files {
+            fs::write(dir.join(name), data).unwrap();
+        }
+        dir
+    }
+
+    #[test]
+    fn stitch() -> Result<(), LayerFilesError> {
+        let d = dir(
+            "stitch",
+            &[
+                ("b.cli", file(0.5, &[3])),
+                ("a.CLI", file(0.5, &[2, 4])),
+                ("c.cli", file(0.5, &[1])),
+                ("notes.txt", b"not a cli file".to_vec()),
+            ],
+        );
+        let model = CLI::<ShortCLI>::from_layer_files(&d)?;
+        let heights: Vec<u16> = model.iter().map(|l| l.height()).collect();
+        assert_eq!(heights, vec![1, 2, 3, 4]);
+        assert_eq!(model.header().layers, Some(4));
+        assert_eq!(model.iter().nth(2).unwrap().iter_loops().count(), 1);
+        fs::remove_dir_all(d)?;
+        Ok(())
+    }
+
+    #[test]
+    fn split() -> Result<(), LayerFilesError> {
+        let d = dir("split", &[("model.cli", file(0.5, &[1, 2, 3]))]);
+        let model = CLI::<ShortCLI>::from_layer_files(&d)?;
+        let out = d.join("layers");
+
+        let paths = model.write_layer_files(&out, LayerFileNaming::Index)?;
+        assert_eq!(paths[2], out.join("layer_0002.cli"));
+        let last = fs::read(&paths[2])?;
+        let last = CLI::<ShortCLI>::new(&last).unwrap();
+        assert_eq!(last.header().layers, Some(1));
+        assert_eq!(last.iter().next().unwrap().height(), 3);
+        let back = CLI::<ShortCLI>::from_layer_files(&out)?;
+        assert_eq!(back.to_bytes(), model.to_bytes());
+        fs::remove_dir_all(&out)?;
+
+        let paths = model.write_layer_files(&out, LayerFileNaming::Height)?;
+        assert_eq!(paths[0], out.join("z_0.500.cli"));
+        let custom = LayerFileNaming::Custom(|i, _| format!("{}.cli", i % 2));
+        assert_eq!(
+            model.write_layer_files(&out, custom).unwrap_err().kind(),
+            io::ErrorKind::InvalidInput
+        );
+        fs::remove_dir_all(d)?;
+        Ok(())
+    }
+
+    #[test]
+    fn inconsistent() {
+        let d = dir(
+            "units",
+            &[("a.cli", file(0.5, &[1])), ("b.cli", file(0.25, &[2]))],
+        );
+        assert!(matches!(
+            CLI::<ShortCLI>::from_layer_files(&d),
+            Err(LayerFilesError::InconsistentHeader(p)) if p.ends_with("b.cli")
+        ));
+        let d = dir(
+            "duplicate",
+            &[("a.cli", file(0.5, &[1])), ("b.cli", file(0.5, &[1]))],
+        );
+        assert!(matches!(
+            CLI::<ShortCLI>::from_layer_files(&d),
+            Err(LayerFilesError::DuplicateHeight(z)) if z == 0.5
+        ));
+        let d = dir("empty", &[]);
+        assert!(matches!(
+            CLI::<ShortCLI>::from_layer_files(&d),
+            Err(LayerFilesError::NoFiles)
+        ));
+    }
+}
@@ -0,0 +1,175 @@
+//! Decoding layers on demand
+//!
+//! [`CLI::new`] decodes every layer of a file before returning. For builds with tens of thousands
+//! of layers, of which only a few are looked at, [`LazyCLI`] instead indexes where each layer
+//! starts, see [`index`](crate::index), and decodes the loops and hatches of a layer only when it
+//! is requested. Nothing is cached: visiting a layer twice decodes it twice.
+//!
+//! ```
+//! use colain::{LazyCLI, clitype::LongCLI};
+//! # let buf = colain::BuildPlate::<LongCLI>::new(1.0).to_bytes();
+//!
+//! let model = LazyCLI::<LongCLI>::new(&buf).unwrap();
+//! println!("{} layers", model.len());
+//! for layer in model.iter().step_by(100) {
+//!     let layer = layer.unwrap();
+//!     println!("{} loops at {}", layer.iter_loops().count(), layer.height());
+//! }
+//! ```
+
+use crate::clitype::{CLIType, LongCLI};
+use crate::index::{layer_spans, LayerSpan};
+use crate::{Error, Header, Layer, Parser, ParserOptions, CLI};
+use std::marker::PhantomData;
+use std::ops::Range;
+
+/// A file whose layers are decoded when visited, see the [module documentation](self)
+pub struct LazyCLI<'a, T: CLIType = LongCLI> {
+    header: Header,
+    geom: &'a [u8],
+    spans: Vec<LayerSpan>,
+    options: ParserOptions,
+    _type: PhantomData<T>,
+}
+
+impl<'a, T: CLIType> LazyCLI<'a, T> {
+    /// Parse the header and index the layers of a file.
+    ///
+    /// Fails if the structure of the geometry section is invalid, errors within the records of a
+    /// layer only show up when it is decoded.
+    pub fn new(raw: &'a [u8]) -> Result<Self, Error> {
+        LazyCLI::new_with_options(raw, &ParserOptions::default())
+    }
+
+    /// Same as [`LazyCLI::new`] but with control over which deviations from the spec are
+    /// tolerated, see [`ParserOptions`]
+    pub fn new_with_options(raw: &'a [u8], options: &ParserOptions) -> Result<Self, Error> {
+        let (header, _, geom) = Parser::<T>::default().options(options).split(raw)?;
+        let spans = layer_spans::<T>(geom, header.aligned, options)?;
+        Ok(LazyCLI {
+            header,
+            geom,
+            spans,
+            options: options.clone(),
+            _type: PhantomData,
+        })
+    }
+
+    /// Get file metadata
+    pub fn header(&self) -> &Header {
+        &self.header
+    }
+
+    /// Number of layers in the file
+    pub fn len(&self) -> usize {
+        self.spans.len()
+    }
+
+    /// True if the file has no layers
+    pub fn is_empty(&self) -> bool {
+        self.spans.is_empty()
+    }
+
+    /// Height of a layer without decoding it, `None` if there is no such layer
+    pub fn height(&self, index: usize) -> Option<<T as CLIType>::Coord> {
+        self.spans.get(index).map(|s| T::coord_from_f64(s.height))
+    }
+
+    /// Decode a single layer, `None` if there is no such layer
+    pub fn layer(&self, index: usize) -> Option<Result<Layer<'a, T>, Error>> {
+        let span = self.spans.get(index)?;
+        Some(CLI::decode_span(
+            &self.header,
+            self.geom,
+            span,
+            &self.options,
+        ))
+    }
+
+    /// Iterate over the layers, decoding each one as it is reached
+    pub fn iter(&self) -> LazyLayers<'_, 'a, T> {
+        LazyLayers {
+            model: self,
+            range: 0..self.spans.len(),
+        }
+    }
+}
+
+/// Layers of a [`LazyCLI`], decoded one at a time
+///
+/// Skipping layers with [`Iterator::nth`] or [`Iterator::step_by`] does not decode them.
+pub struct LazyLayers<'l, 'a, T: CLIType> {
+    model: &'l LazyCLI<'a, T>,
+    range: Range<usize>,
+}
+
+impl<'a, T: CLIType> Iterator for LazyLayers<'_, 'a, T> {
+    type Item = Result<Layer<'a, T>, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let index = self.range.next()?;
+        self.model.layer(index)
+    }
+
+    fn nth(&mut self, n: usize) -> Option<Self::Item> {
+        let index = self.range.nth(n)?;
+        self.model.layer(index)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.range.size_hint()
+    }
+}
+
+impl<T: CLIType> DoubleEndedIterator for LazyLayers<'_, '_, T> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        let index = self.range.next_back()?;
+        self.model.layer(index)
+    }
+}
+
+impl<T: CLIType> ExactSizeIterator for LazyLayers<'_, '_, T> {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::checksum::Checksum;
+    use crate::clitype::ShortCLI;
+    use crate::CLIBuilder;
+
+    #[test]
+    fn on_demand() -> Result<(), Error> {
+        let mut builder = CLIBuilder::new().short();
+        for z in 1..=10 {
+            builder = builder.layer(z).polyline(z, 1, vec![0, 0, z, z]);
+        }
+        let buf = builder.to_bytes()?;
+
+        let lazy = LazyCLI::<ShortCLI>::new(&buf)?;
+        assert_eq!(lazy.len(), 10);
+        assert_eq!(lazy.height(3), Some(4));
+        assert_eq!(lazy.height(10), None);
+        let layer = lazy.layer(4).unwrap()?;
+        assert_eq!(layer.iter_loops().next().unwrap().points(), [0, 0, 5, 5]);
+        assert!(lazy.layer(10).is_none());
+
+        let heights = lazy.iter().rev().step_by(3).map(|l| l.map(|l| l.height()));
+        assert_eq!(heights.collect::<Result<Vec<_>, _>>()?, [10, 7, 4, 1]);
+        assert_eq!(lazy.iter().len(), 10);
+        assert_eq!(lazy.iter().nth(8).unwrap()?.height(), 9);
+        Ok(())
+    }
+
+    #[test]
+    fn checksum() -> Result<(), Error> {
+        let mut builder = CLIBuilder::new().short();
+        for z in 1..=3 {
+            builder = builder.layer(z).polyline(z, 1, vec![0, 0, z, z]);
+        }
+        let buf = builder.build()?.to_bytes_with_checksum(Checksum::Crc32);
+        let lazy = LazyCLI::<ShortCLI>::new(&buf)?;
+        assert_eq!(lazy.len(), 3);
+        assert!(lazy.layer(2).unwrap()?.user_data.is_empty());
+        Ok(())
+    }
+}
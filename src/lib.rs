@@ -6,9 +6,17 @@
 //! a [`Vec`] of loops and hatches respectively.
 //!
 //! **Note:** In keeping with the performance oriented nature of the library, conversions to real units using the UNITS portion of the header file is not done automatically.
-//! Remember to perform the conversion if necessary.
+//! Use [`CLI::scaled`] for lengths in millimeters, see the [`units`] module.
 //!
-//! **Note:** This library does not yet support parsing of ASCII formated files. Nor has it been tested extensively since .cli files are hard to come by.
+//! With the `safe` feature the crate contains no unsafe code: coordinates are always copied out of
+//! the file instead of being borrowed in place, which is slower and uses more memory. The crate is
+//! then `#![forbid(unsafe_code)]`, unless the `flatbuffers` feature is enabled as well, whose
+//! generated accessors need unsafe code.
+//!
+//! Files with an ASCII geometry section are parsed into the same structures as binary files, see
+//! [`Parser::parse`]. Writing always produces a binary file.
+//!
+//! **Note:** This library has not been tested extensively since .cli files are hard to come by.
 //! Please feel free to submit bug reports or .cli files for testing.
 //!
 //! [`here.`]: https://www.hmilch.net/downloads/cli_format.html
@@ -20,12 +28,15 @@
 //! use std::fs::File;
 //! use std::io::prelude::*;
 //! use colain::{
-//!		CLI,
-//!		clitype::{LongCLI, ShortCLI}
-//!	};
+//!     CLI,
+//!     clitype::{LongCLI, ShortCLI}
+//! };
 //!
 //! let mut buf: Vec<u8> = Vec::new();
+//! # /*
 //! File::open("example.cli").unwrap().read_to_end(&mut buf).unwrap();
+//! # */
+//! # let buf = colain::CLIBuilder::new().layer(1.0).polyline(1, 1, vec![0.0, 0.0, 1.0, 1.0]).to_bytes().unwrap();
 //!
 //! let model = CLI::<LongCLI>::new(&buf).unwrap();
 //!
@@ -35,6 +46,9 @@
 //! ## Iterating on each point of each loop in each layer
 //! See above for how to initialize model
 //!```
+//! # use colain::{CLI, clitype::LongCLI};
+//! # let buf = colain::CLIBuilder::new().layer(1.0).polyline(1, 1, vec![0.0, 0.0, 1.0, 1.0]).to_bytes().unwrap();
+//! # let model = CLI::<LongCLI>::new(&buf).unwrap();
 //! use colain::Point; // import the Point trait to provide access via .x() and .y()
 //! for layer in model.iter() {
 //!     for a_loop in layer.iter_loops() {
@@ -46,13 +60,152 @@
 //! }
 //!```
 
-use bytes::Buf;
+#![cfg_attr(
+    all(feature = "safe", not(feature = "flatbuffers")),
+    forbid(unsafe_code)
+)]
+#![cfg_attr(all(feature = "safe", feature = "flatbuffers"), deny(unsafe_code))]
+
+use bytes::{Buf, BufMut};
 use clitype::*;
+use std::borrow::Cow;
+use std::collections::BTreeMap;
 use std::fmt::Debug;
-use std::mem::size_of;
+use std::ops::{Bound, RangeBounds};
 
+pub mod any;
+pub mod arcs;
+#[cfg(feature = "arrow")]
+pub mod arrow;
+pub mod bounds;
+pub mod builder;
+pub mod checksum;
+pub mod collect;
+pub mod compensate;
+pub mod connectivity;
+pub mod correspondence;
+pub mod dump;
+#[cfg(feature = "dxf")]
+pub mod dxf;
+pub mod envelope;
+pub mod exposure;
+pub mod extension;
+#[cfg(feature = "flatbuffers")]
+#[cfg_attr(feature = "safe", allow(unsafe_code))]
+pub mod flatbuf;
+#[cfg(feature = "geo")]
+pub mod geo;
+pub mod geojson;
+pub mod geom;
+pub mod header;
+pub mod hpgl;
+pub mod hull;
+pub mod index;
+pub mod ingest;
+pub mod islands;
+pub mod layerfiles;
+pub mod lazy;
+pub mod lod;
+#[cfg(feature = "lyon")]
+pub mod lyon;
+pub mod mixed;
+pub mod morph;
+pub mod nesting;
+pub mod npz;
+pub mod options;
+pub mod overhang;
+#[cfg(feature = "ovf")]
+pub mod ovf;
+#[cfg(feature = "parallel")]
+pub mod parallel;
+pub mod parser;
+pub mod patch;
+pub mod plate;
+pub mod polyline;
+pub mod porosity;
+#[cfg(feature = "protobuf")]
+pub mod protobuf;
+pub mod scan;
+pub mod sdf;
+pub mod section;
+pub mod shared;
+pub mod sink;
+pub mod skeleton;
+pub mod skin;
+pub mod smooth;
+pub mod spatial;
+pub mod stats;
+pub mod support;
+pub mod tags;
+pub mod thickness;
+pub mod token;
+pub mod units;
+pub mod unknown;
 pub mod util;
+pub mod warning;
+pub mod winding;
+pub mod wkt;
+pub use any::{AnyCLI, Variant};
+pub use arcs::{FittedArc, PathElement};
+pub use bounds::Bounds;
+pub use builder::CLIBuilder;
+pub use compensate::Compensation;
+pub use connectivity::{ConnectivityGraph, LoopRef};
+pub use convert::OwnedCLI;
+pub use correspondence::Continuation;
+pub use envelope::{Envelope, EnvelopeViolation};
+pub use exposure::Exposure;
+pub use extension::{UserData, VectorParams};
+pub use header::{CalendarDate, Date, HeaderUserData};
+pub use hpgl::HpglOptions;
+pub use hull::OrientedRect;
+pub use index::NearestLayers;
+pub use ingest::GeometrySink;
+pub use islands::{Hole, Island, Region};
+pub use layerfiles::{LayerFileNaming, LayerFilesError};
+pub use lazy::{LazyCLI, LazyLayers};
+pub use lod::{LevelOfDetail, LodLevel};
+pub use mixed::MixedCLI;
+pub use morph::{LayerMatch, Morph};
+pub use options::*;
+pub use overhang::Overhang;
+pub use parser::{ParseMetrics, Parser};
+pub use patch::PatchError;
+pub use plate::*;
+pub use polyline::*;
+pub use porosity::HatchDensity;
+pub use scan::HatchCluster;
+pub use sdf::DistanceField;
+pub use section::SectionInterval;
+pub use shared::SharedCLI;
+pub use sink::*;
+pub use skin::SkinCore;
+pub use smooth::Smoothing;
+pub use spatial::{NearestLoop, SegmentHit, SegmentRef, SpatialIndex};
+pub use stats::LayerStats;
+pub use support::{SupportClassification, SupportHeuristics, SupportReason};
+pub use tags::{Tag, TagTarget};
+pub use thickness::{BandKind, ThicknessBand, ThicknessBin};
+pub use unknown::{UnknownCommand, UnknownSummary};
 pub use util::*;
+pub use warning::Warning;
+pub use winding::WindingConvention;
+pub use wkt::WktError;
+
+mod ascii;
+mod convert;
+mod hash;
+mod points;
+mod raster;
+#[cfg(feature = "serde")]
+mod serialize;
+mod similarity;
+mod trace;
+mod verbatim;
+mod writer;
+
+use token::Token;
+use verbatim::{LayerSource, RawRecord, Verbatim};
 
 /// A [`CLIType`] must be specified when creating a [`CLI`] object.
 ///
@@ -63,15 +216,16 @@ pub use util::*;
 /// While it is not explicitly required that a file consists of only one type of geometry,
 /// this library requires that the entire file consists of just one variation. If the variation
 /// that was specified does not match the file an [`Err`] containing the signal [`Error::TypeMismatch`]
-/// will be returned.
+/// will be returned. Use [`AnyCLI`] to pick the variation from the file, and [`MixedCLI`] for files
+/// that do mix both.
 pub mod clitype {
     use super::*;
 
     /// A type of CLI file
     pub trait CLIType
     where
-        Self::Meta: Debug + Copy,
-        Self::Coord: Debug + Copy,
+        Self::Meta: Debug + Copy + 'static,
+        Self::Coord: Debug + Copy + 'static,
     {
         /// Primitive type used to store metadata such as id, direction, etc.
         ///
@@ -93,6 +247,15 @@ pub mod clitype {
         // Command used to indicate a new set of hatches
         #[doc(hidden)]
         const CMD_HATCH: u16;
+        // Extension command carrying per-vector laser power
+        #[doc(hidden)]
+        const CMD_POWER: u16;
+        // Extension command carrying per-vector scan speed
+        #[doc(hidden)]
+        const CMD_SPEED: u16;
+        // Extension command carrying opaque user data
+        #[doc(hidden)]
+        const CMD_USERDATA: u16;
 
         // Pop a metadata from the buffer
         #[doc(hidden)]
@@ -103,19 +266,42 @@ pub mod clitype {
         // Pop a metadata from the buffer and cast to a usize
         #[doc(hidden)]
         fn get_usize(buf: &mut &[u8], aligned: bool) -> usize;
+
+        // Push a metadata onto the buffer
+        #[doc(hidden)]
+        fn put_meta(buf: &mut Vec<u8>, v: Self::Meta, aligned: bool);
+        // Push a coordinate onto the buffer
+        #[doc(hidden)]
+        fn put_coord(buf: &mut Vec<u8>, v: Self::Coord, aligned: bool);
+        // Push a usize onto the buffer as a metadata
+        #[doc(hidden)]
+        fn put_usize(buf: &mut Vec<u8>, v: usize, aligned: bool);
+
+        // Widen a coordinate for geometric calculations
+        #[doc(hidden)]
+        fn coord_to_f64(c: Self::Coord) -> f64;
+        // Narrow a calculated value back into a coordinate, saturating if necessary
+        #[doc(hidden)]
+        fn coord_from_f64(v: f64) -> Self::Coord;
+        // Widen a metadata for use outside of the file format
+        #[doc(hidden)]
+        fn meta_to_i64(m: Self::Meta) -> i64;
+        // Narrow a value back into a metadata, saturating if necessary
+        #[doc(hidden)]
+        fn meta_from_i64(v: i64) -> Self::Meta;
     }
 
     /// Configures the parser to use the short version of the CLI spec.
     ///
     /// In this version coordinates are stored as [`u16`] and metadata
     /// (ID, direction, etc.) are stored as [`u16`].
-    #[derive(Debug)]
+    #[derive(Debug, Clone, Copy, PartialEq)]
     pub struct ShortCLI();
     /// Configures the parser to use the long version of the CLI spec.
     ///
     /// In this version coordinates are stored as [`f32`] and metadata
     /// (ID, direction, etc.) are stored as [`i32`].
-    #[derive(Debug)]
+    #[derive(Debug, Clone, Copy, PartialEq)]
     pub struct LongCLI();
 
     impl CLIType for ShortCLI {
@@ -124,26 +310,57 @@ pub mod clitype {
         const CMD_LAYER: u16 = 128;
         const CMD_PLINE: u16 = 129;
         const CMD_HATCH: u16 = 131;
+        const CMD_POWER: u16 = 133;
+        const CMD_SPEED: u16 = 135;
+        const CMD_USERDATA: u16 = 137;
         fn get_meta(buf: &mut &[u8], aligned: bool) -> Self::Meta {
             let t = buf.get_u16_le();
             if aligned {
                 buf.advance(2)
             };
-            return t;
+            t
         }
         fn get_coord(buf: &mut &[u8], aligned: bool) -> Self::Coord {
             let t = buf.get_u16_le();
             if aligned {
                 buf.advance(2)
             };
-            return t;
+            t
         }
         fn get_usize(buf: &mut &[u8], aligned: bool) -> usize {
             let t = buf.get_u16_le() as usize;
             if aligned {
                 buf.advance(2)
             };
-            return t;
+            t
+        }
+        fn put_meta(buf: &mut Vec<u8>, v: Self::Meta, aligned: bool) {
+            buf.put_u16_le(v);
+            if aligned {
+                buf.put_u16_le(0)
+            };
+        }
+        fn put_coord(buf: &mut Vec<u8>, v: Self::Coord, aligned: bool) {
+            buf.put_u16_le(v);
+            if aligned {
+                buf.put_u16_le(0)
+            };
+        }
+        fn put_usize(buf: &mut Vec<u8>, v: usize, aligned: bool) {
+            ShortCLI::put_meta(buf, v as u16, aligned)
+        }
+        fn coord_to_f64(c: Self::Coord) -> f64 {
+            c as f64
+        }
+        fn coord_from_f64(v: f64) -> Self::Coord {
+            // `as` saturates, negative values clamp to 0
+            v.round() as u16
+        }
+        fn meta_to_i64(m: Self::Meta) -> i64 {
+            m as i64
+        }
+        fn meta_from_i64(v: i64) -> Self::Meta {
+            v.clamp(0, u16::MAX as i64) as u16
         }
     }
 
@@ -153,6 +370,9 @@ pub mod clitype {
         const CMD_LAYER: u16 = 127;
         const CMD_PLINE: u16 = 130;
         const CMD_HATCH: u16 = 132;
+        const CMD_POWER: u16 = 134;
+        const CMD_SPEED: u16 = 136;
+        const CMD_USERDATA: u16 = 138;
         fn get_meta(buf: &mut &[u8], _aligned: bool) -> Self::Meta {
             buf.get_i32_le()
         }
@@ -162,15 +382,55 @@ pub mod clitype {
         fn get_usize(buf: &mut &[u8], _aligned: bool) -> usize {
             buf.get_i32_le() as usize
         }
+        fn put_meta(buf: &mut Vec<u8>, v: Self::Meta, _aligned: bool) {
+            buf.put_i32_le(v)
+        }
+        fn put_coord(buf: &mut Vec<u8>, v: Self::Coord, _aligned: bool) {
+            buf.put_f32_le(v)
+        }
+        fn put_usize(buf: &mut Vec<u8>, v: usize, _aligned: bool) {
+            buf.put_i32_le(v as i32)
+        }
+        fn coord_to_f64(c: Self::Coord) -> f64 {
+            c as f64
+        }
+        fn coord_from_f64(v: f64) -> Self::Coord {
+            v as f32
+        }
+        fn meta_to_i64(m: Self::Meta) -> i64 {
+            m as i64
+        }
+        fn meta_from_i64(v: i64) -> Self::Meta {
+            v.clamp(i32::MIN as i64, i32::MAX as i64) as i32
+        }
     }
 }
 
 /// Reinterpret [T; 2] as a point
+///
+/// With the `glam` and `nalgebra` features the point converts into the vector types of these
+/// crates, in coordinate units.
 pub trait Point<T: Copy> {
     /// Get the x component of the point
     fn x(&self) -> T;
     /// Get the y component of the point
     fn y(&self) -> T;
+    /// The point as a glam vector
+    #[cfg(feature = "glam")]
+    fn to_glam(&self) -> glam::Vec2
+    where
+        T: Into<f32>,
+    {
+        glam::Vec2::new(self.x().into(), self.y().into())
+    }
+    /// The point as a nalgebra point
+    #[cfg(feature = "nalgebra")]
+    fn to_nalgebra(&self) -> nalgebra::Point2<f32>
+    where
+        T: Into<f32>,
+    {
+        nalgebra::Point2::new(self.x().into(), self.y().into())
+    }
 }
 impl<T: Copy> Point<T> for [T; 2] {
     #[inline]
@@ -188,37 +448,57 @@ impl<T: Copy> Point<T> for [T; 2] {
 /// use std::fs::File;
 /// use std::io::prelude::*;
 /// use colain::{
-///		CLI, Segment, Point,
-///		clitype::*
-///	};
+///     CLI, Segment, Point,
+///     clitype::*
+/// };
 ///
 /// let mut buf: Vec<u8> = Vec::new();
+/// # /*
 /// File::open("example.cli").unwrap().read_to_end(&mut buf).unwrap();
+/// # */
+/// # let buf = colain::CLIBuilder::new().layer(1.0).hatches(1, vec![0.0, 0.0, 1.0, 1.0]).to_bytes().unwrap();
 ///
 /// let model = CLI::<LongCLI>::new(&buf).unwrap();
-///	let x: f32 = model.iter().next().unwrap() // first layer
-///			.iter_hatches().next().unwrap() // first set of hatches in layer
-///			.iter().next().unwrap() // first segment in hatches
-///			.start() // first point in segment
-///			.x(); // x value of first point in segment
+/// let x: f32 = model.iter().next().unwrap() // first layer
+///         .iter_hatches().next().unwrap() // first set of hatches in layer
+///         .iter().next().unwrap() // first segment in hatches
+///         .start() // first point in segment
+///         .x(); // x value of first point in segment
 ///
 /// ```
+///
+/// Like [`Point`], segments convert into glam and nalgebra types with the `glam` and `nalgebra`
+/// features.
 pub trait Segment<T: Copy> {
     /// Get the first point
     fn start(&self) -> [T; 2];
     /// Get the second point
     fn end(&self) -> [T; 2];
+    /// The start and end point as glam vectors
+    #[cfg(feature = "glam")]
+    fn to_glam(&self) -> [glam::Vec2; 2]
+    where
+        T: Into<f32>,
+    {
+        [self.start().to_glam(), self.end().to_glam()]
+    }
+    /// The start and end point as nalgebra points
+    #[cfg(feature = "nalgebra")]
+    fn to_nalgebra(&self) -> [nalgebra::Point2<f32>; 2]
+    where
+        T: Into<f32>,
+    {
+        [self.start().to_nalgebra(), self.end().to_nalgebra()]
+    }
 }
 impl<T: Copy> Segment<T> for [T; 4] {
     #[inline]
     fn start(&self) -> [T; 2] {
-        // SAFETY: By nature of impl constrained to arrays of len 4
-        unsafe { *(&self[0..=1] as *const [T] as *const [T; 2]) }
+        [self[0], self[1]]
     }
     #[inline]
     fn end(&self) -> [T; 2] {
-        // SAFETY: By nature of impl constrained to arrays of len 4
-        unsafe { *(&self[2..=3] as *const [T] as *const [T; 2]) }
+        [self[2], self[3]]
     }
 }
 
@@ -233,21 +513,36 @@ impl<T: Copy> Segment<T> for [T; 4] {
 /// - 1 : counter-clockwise (external)
 /// - 2 : open line (no solid)
 ///
+/// [`Loop::direction`] gives these values as a [`Direction`], and [`Loop::kind`] classifies a
+/// loop according to them.
+///
 /// Each point is stored as an array of length two of the [`CLIType`]'s associated Coord type.
 /// The [`Point`] trait is provided as a more elegant way to access the data.
+///
+/// Loops parsed from a binary file borrow the geometry data in place. Loops produced by
+/// transformations (e.g. a [`BuildPlate`]) own their points instead.
 #[derive(Debug, Clone)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(bound(
+        serialize = "<T as CLIType>::Coord: serde::Serialize, <T as CLIType>::Meta: serde::Serialize",
+        deserialize = "<T as CLIType>::Coord: serde::Deserialize<'de>, <T as CLIType>::Meta: serde::Deserialize<'de>"
+    ))
+)]
 pub struct Loop<'a, T: CLIType> {
     id: <T as CLIType>::Meta,
     dir: <T as CLIType>::Meta,
-    points: &'a [<T as CLIType>::Coord],
+    points: Cow<'a, [<T as CLIType>::Coord]>,
+    params: VectorParams<'a, T>,
 }
 
 impl<'a, T: CLIType> Loop<'a, T> {
     /// Iterate over each point in the loop as [T; 2]
     ///
     /// Note availability of [`Point`] trait for a cleaner interface
-    pub fn iter(&'a self) -> ArrayChunksCopy<'a, <T as CLIType>::Coord, 2> {
-        ArrayChunksCopy::<'_, <T as CLIType>::Coord, 2>::new(self.points)
+    pub fn iter(&self) -> ArrayChunksCopy<'_, <T as CLIType>::Coord, 2> {
+        ArrayChunksCopy::<'_, <T as CLIType>::Coord, 2>::new(&self.points)
     }
     /// Get the CLI ID of this primitive
     pub fn id(&self) -> <T as CLIType>::Meta {
@@ -258,8 +553,16 @@ impl<'a, T: CLIType> Loop<'a, T> {
         self.dir
     }
     /// Pointer into the segment of the file that contains this geometry
-    pub fn points(&'a self) -> &'a [<T as CLIType>::Coord] {
-        self.points
+    pub fn points(&self) -> &[<T as CLIType>::Coord] {
+        &self.points
+    }
+    /// Per-vector process parameters that followed this loop in the file
+    pub fn params(&self) -> &VectorParams<'a, T> {
+        &self.params
+    }
+    /// Name of the part this loop belongs to, if the header labels its id
+    pub fn label<'h>(&self, header: &'h Header) -> Option<&'h str> {
+        header.label(T::meta_to_i64(self.id))
     }
 }
 
@@ -269,17 +572,26 @@ impl<'a, T: CLIType> Loop<'a, T> {
 /// the [`Segment`] trait is provided as an abstraction layer over the
 /// internal storage of each segment which is [T; 4]
 #[derive(Debug, Clone)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(bound(
+        serialize = "<T as CLIType>::Coord: serde::Serialize, <T as CLIType>::Meta: serde::Serialize",
+        deserialize = "<T as CLIType>::Coord: serde::Deserialize<'de>, <T as CLIType>::Meta: serde::Deserialize<'de>"
+    ))
+)]
 pub struct Hatches<'a, T: CLIType> {
     id: <T as CLIType>::Meta,
-    points: &'a [<T as CLIType>::Coord],
+    points: Cow<'a, [<T as CLIType>::Coord]>,
+    params: VectorParams<'a, T>,
 }
 
 impl<'a, T: CLIType> Hatches<'a, T> {
     /// Iterate over hatches as segments
     ///
     /// Note availability of [`Segment`] trait for a cleaner interface
-    pub fn iter(&'a self) -> ArrayChunks<'a, <T as CLIType>::Coord, 4> {
-        ArrayChunks::<'_, <T as CLIType>::Coord, 4>::new(self.points)
+    pub fn iter(&self) -> ArrayChunks<'_, <T as CLIType>::Coord, 4> {
+        ArrayChunks::<'_, <T as CLIType>::Coord, 4>::new(&self.points)
     }
     /// Get the CLI ID of this primitive
     pub fn id(&self) -> <T as CLIType>::Meta {
@@ -288,8 +600,16 @@ impl<'a, T: CLIType> Hatches<'a, T> {
     /// Pointer into the segment of the file that contains this geometry.
     /// The array should consist of sets of 2 points where each point
     /// consists of an X element then a Y element.
-    pub fn points(&'a self) -> &'a [<T as CLIType>::Coord] {
-        self.points
+    pub fn points(&self) -> &[<T as CLIType>::Coord] {
+        &self.points
+    }
+    /// Per-vector process parameters that followed these hatches in the file
+    pub fn params(&self) -> &VectorParams<'a, T> {
+        &self.params
+    }
+    /// Name of the part these hatches belong to, if the header labels their id
+    pub fn label<'h>(&self, header: &'h Header) -> Option<&'h str> {
+        header.label(T::meta_to_i64(self.id))
     }
 }
 
@@ -297,18 +617,79 @@ impl<'a, T: CLIType> Hatches<'a, T> {
 ///
 ///
 #[derive(Debug, Clone)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(bound(
+        serialize = "<T as CLIType>::Coord: serde::Serialize, <T as CLIType>::Meta: serde::Serialize",
+        deserialize = "<T as CLIType>::Coord: serde::Deserialize<'de>, <T as CLIType>::Meta: serde::Deserialize<'de>"
+    ))
+)]
 pub struct Layer<'a, T: CLIType> {
     height: <T as CLIType>::Coord,
     loops: Vec<Loop<'a, T>>,
     hatches: Vec<Hatches<'a, T>>,
+    user_data: Vec<UserData<'a, T>>,
+    /// Kind of each geometry block in file order, blocks missing from it follow loops first
+    #[cfg_attr(feature = "serde", serde(default))]
+    order: Vec<Block>,
+    /// Records skipped by the parser, see [`Parser::skip_unknown`]
+    #[cfg_attr(feature = "serde", serde(skip))]
+    raw: Vec<RawRecord<'a>>,
+    /// Records the layer was decoded from, until it is modified
+    #[cfg_attr(feature = "serde", serde(skip))]
+    source: Option<LayerSource<'a>>,
 }
 impl<'a, T: CLIType> Layer<'a, T> {
+    /// A layer at `height` without any geometry
+    pub(crate) fn empty(height: <T as CLIType>::Coord) -> Self {
+        Layer {
+            height,
+            loops: vec![],
+            hatches: vec![],
+            user_data: vec![],
+            order: vec![],
+            raw: vec![],
+            source: None,
+        }
+    }
+    /// Append a loop as the next block of the layer
+    pub(crate) fn push_loop(&mut self, l: Loop<'a, T>) {
+        self.loops.push(l);
+        self.order.push(Block::Loop);
+    }
+    /// Append hatches as the next block of the layer
+    pub(crate) fn push_hatches(&mut self, h: Hatches<'a, T>) {
+        self.hatches.push(h);
+        self.order.push(Block::Hatches);
+    }
+    /// Geometry blocks in file order, each as its kind and index into the loops or hatches
+    pub(crate) fn blocks(&self) -> Vec<(Block, usize)> {
+        let (mut loops, mut hatches) = (0, 0);
+        let mut blocks = Vec::with_capacity(self.loops.len() + self.hatches.len());
+        for &block in self.order.iter() {
+            match block {
+                Block::Loop if loops < self.loops.len() => {
+                    blocks.push((block, loops));
+                    loops += 1;
+                }
+                Block::Hatches if hatches < self.hatches.len() => {
+                    blocks.push((block, hatches));
+                    hatches += 1;
+                }
+                _ => {}
+            }
+        }
+        blocks.extend((loops..self.loops.len()).map(|i| (Block::Loop, i)));
+        blocks.extend((hatches..self.hatches.len()).map(|i| (Block::Hatches, i)));
+        blocks
+    }
     /// Iterator over each loop in the layer
-    pub fn iter_loops(&'a self) -> std::slice::Iter<'a, Loop<'a, T>> {
+    pub fn iter_loops(&self) -> std::slice::Iter<'_, Loop<'a, T>> {
         self.loops.iter()
     }
     /// Iterator over each set of hatches in the layer
-    pub fn iter_hatches(&'a self) -> std::slice::Iter<'a, Hatches<'a, T>> {
+    pub fn iter_hatches(&self) -> std::slice::Iter<'_, Hatches<'a, T>> {
         self.hatches.iter()
     }
     /// Get the height of the layer relative to the bottom of the part.
@@ -317,10 +698,65 @@ impl<'a, T: CLIType> Layer<'a, T> {
     pub fn height(&self) -> <T as CLIType>::Coord {
         self.height
     }
+    /// Heights spanned by the layer, from its own height up to the height of the `next` layer,
+    /// i.e. its height plus the thickness derived from the following layer.
+    ///
+    /// `None` for the last layer, which has no following layer to derive a thickness from. The
+    /// span is empty if both layers are at the same height, and reversed if `next` is below this
+    /// layer.
+    ///
+    /// ```
+    /// # use colain::{CLI, clitype::LongCLI};
+    /// # let buf = colain::BuildPlate::<LongCLI>::new(1.0).to_bytes();
+    /// # let model = CLI::<LongCLI>::new(&buf).unwrap();
+    /// let mut layers = model.iter().peekable();
+    /// while let Some(layer) = layers.next() {
+    ///     if let Some([bottom, top]) = layer.z_span(layers.peek().copied()) {
+    ///         println!("{} to {}", bottom, top);
+    ///     }
+    /// }
+    /// ```
+    pub fn z_span(&self, next: Option<&Layer<'_, T>>) -> Option<[<T as CLIType>::Coord; 2]> {
+        next.map(|next| [self.height, next.height])
+    }
+    /// User data records found in the layer, see [`extension`]
+    pub fn user_data(&self) -> &[UserData<'a, T>] {
+        &self.user_data
+    }
+
+    /// Copy borrowed data so the layer no longer references the file
+    pub fn to_owned_layer(&self) -> Layer<'static, T> {
+        Layer {
+            loops: self
+                .loops
+                .iter()
+                .map(|l| Loop {
+                    id: l.id,
+                    dir: l.dir,
+                    points: Cow::Owned(l.points.to_vec()),
+                    params: l.params.to_owned_params(),
+                })
+                .collect(),
+            hatches: self
+                .hatches
+                .iter()
+                .map(|h| Hatches {
+                    id: h.id,
+                    points: Cow::Owned(h.points.to_vec()),
+                    params: h.params.to_owned_params(),
+                })
+                .collect(),
+            user_data: self.user_data.iter().map(|u| u.to_owned_data()).collect(),
+            order: self.order.clone(),
+            raw: self.raw.iter().map(|r| r.to_owned_record()).collect(),
+            ..Layer::empty(self.height)
+        }
+    }
 }
 
 /// Contains all available CLI header information
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Header {
     /// True if the CLI file stores data in a binary format
     pub binary: bool,
@@ -332,6 +768,45 @@ pub struct Header {
     pub aligned: bool,
     /// The header can optionally declare the number of layers in the file`
     pub layers: Option<usize>,
+    /// Build date declared by `$$DATE`, see [`Date`]
+    pub date: Option<Date>,
+    /// Box around the part declared by `$$DIMENSION`, as `[x1, y1, z1, x2, y2, z2]` in
+    /// millimeters. It is not checked against the geometry.
+    pub dimension: Option<[f64; 6]>,
+    /// Names given to the ids of loops and hatches by `$$LABEL`, see [`Loop::label`]
+    pub labels: BTreeMap<i64, String>,
+    /// Vendor specific entries declared by `$$USERDATA`, in the order they appear
+    pub user_data: Vec<HeaderUserData>,
+    /// Commands not known to this crate as pairs of name and value, in the order they appear.
+    ///
+    /// `$$VENDOR/x=1` is kept as `("VENDOR", "x=1")`, a command without a value such as
+    /// `$$PREVIEW` has an empty value.
+    pub extra: Vec<(String, String)>,
+}
+
+impl Header {
+    /// Name given to an id by `$$LABEL`
+    pub fn label(&self, id: i64) -> Option<&str> {
+        self.labels.get(&id).map(String::as_str)
+    }
+}
+
+impl Default for Header {
+    /// Header of a binary file in millimeters, following version 2.0 of the spec
+    fn default() -> Self {
+        Header {
+            binary: true,
+            units: 1.0,
+            version: 2.0,
+            aligned: false,
+            layers: None,
+            date: None,
+            dimension: None,
+            labels: Default::default(),
+            user_data: Vec::new(),
+            extra: Vec::new(),
+        }
+    }
 }
 
 /// Errors encountered when parsing a CLI file
@@ -341,10 +816,10 @@ pub enum Error {
     EmptyFile,
     /// File does not contain a header section.
     NoHeader,
-    /// Header does not contain valid UTF-8.
+    /// Header does not contain valid UTF-8, apart from the payload of `$$USERDATA` entries.
     HeaderInvalidUTF8,
-    /// The header indicates that this file contains an ASCII encoded geometry section.
-    /// This library does not support his format at this time.
+    /// The header indicates that this file contains an ASCII encoded geometry section, which
+    /// is only understood by [`Parser::parse`] and the functions built on it.
     UnsupportedGeometryFormat,
     /// Header is missing a required element:
     /// - 0: Indication of binary or ASCII geometry section
@@ -353,32 +828,175 @@ pub enum Error {
     HeaderIncomplete(u8),
     /// A numeric header value could not be parsed.
     InvalidHeaderValue,
-    /// One of 6 binary commands was expected in the next two bytes, instead, this value was found.
+    /// One of the 6 binary commands (or an [`extension`] record, if enabled) was expected in the next two bytes, instead, this value was found.
     /// Most likely the file is corrupted. It is possible the file contains commands not included in the CLI spec.
     ///
     /// A bug in this library may also be present. Please consider submitting the .cli file in a PR. Thank you.
     InvalidGeometryCommand(u16),
     /// The file in invalid because it has geometry elements in the geometry section before specifying the first layer.
     ElementOutsideLayer,
+    /// An extension record (see [`extension`]) with this command was found before any polyline or hatches in its layer.
+    UnattachedExtension(u16),
     /// An element in the geometry section indicated that more data was present but the EOF has been reached.
     UnexpectedEOF,
     /// The [`CLIType`] specified when declaring the [`CLI`] parser does not match the data in the geometry section of the file.
     TypeMismatch,
+    /// A limit set on the [`Parser`] was exceeded.
+    LimitExceeded,
+    /// The checksum footer does not match the content of the file, see [`checksum`].
+    ChecksumMismatch,
+    /// [`Parser::verify_checksum`] was set but the file has no checksum footer.
+    MissingChecksum,
+    /// A layer was added below the last layer of the model, see [`CLI::try_extend`], or a parsed
+    /// layer is below the one before it while [`ParserOptions::require_ordered_layers`] is set.
+    LayerOrder,
+    /// Reading the file failed.
+    Io(std::io::Error),
+    /// A command of an ASCII geometry section on this line of the file is malformed.
+    InvalidAsciiGeometry(usize),
+    /// A [tag](tags) key contains a NUL character, which separates the parts of a tag record.
+    InvalidTagKey,
+    /// A part placed on a [`BuildPlate`] would have coordinates or heights its coordinate type
+    /// cannot hold.
+    CoordinateOutOfRange,
+    /// A loop converted from another format has an odd number of coordinates, or hatches a number
+    /// of coordinates that is not a multiple of 4.
+    IncompletePoints,
+    /// A record of a binary geometry section could not be read, with where it was found.
+    ///
+    /// The offset is enough to cut a corrupted file short before the record. Use
+    /// [`Error::into_inner`] to get to the error itself.
+    Located {
+        /// Offset of the record from the start of the file
+        offset: usize,
+        /// Index of the layer the record belongs to, counting every layer of the file. `None` if
+        /// the record is before the first layer.
+        layer: Option<usize>,
+        /// Index of the record within its layer, counting every record after the layer command.
+        /// `None` for the layer command itself.
+        element: Option<usize>,
+        /// What is wrong with the record
+        error: Box<Error>,
+    },
+}
+
+impl Error {
+    /// The error without the position it was found at, see [`Error::Located`]
+    pub fn into_inner(self) -> Error {
+        match self {
+            Error::Located { error, .. } => *error,
+            e => e,
+        }
+    }
+
+    /// Attach the position of the record the error was found at
+    pub(crate) fn located(
+        self,
+        offset: usize,
+        layer: Option<usize>,
+        element: Option<usize>,
+    ) -> Self {
+        Error::Located {
+            offset,
+            layer,
+            element,
+            error: Box::new(self),
+        }
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(e: std::io::Error) -> Self {
+        Error::Io(e)
+    }
 }
 
 impl std::fmt::Display for Error {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{:?}", self)
+        match self {
+            Error::Located {
+                offset,
+                layer,
+                element,
+                error,
+            } => {
+                write!(f, "{} at offset {}", error, offset)?;
+                if let Some(layer) = layer {
+                    write!(f, " in layer {}", layer)?;
+                }
+                match element {
+                    Some(element) => write!(f, ", record {}", element),
+                    None if layer.is_some() => write!(f, ", layer command"),
+                    None => Ok(()),
+                }
+            }
+            e => write!(f, "{:?}", e),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Located { error, .. } => Some(error.as_ref()),
+            _ => None,
+        }
     }
 }
 
-impl std::error::Error for Error {}
+/// Kind of geometry block
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub(crate) enum Block {
+    Loop,
+    Hatches,
+}
+
+/// Position of the parser within the geometry section
+#[derive(Debug, Default)]
+struct Cursor {
+    /// Index of the layer currently being filled
+    layer: Option<usize>,
+    /// Last geometry block of the current layer, extension records attach to it
+    last: Option<Block>,
+}
+
+/// Parse only the header of a file.
+///
+/// Returns the header and the offset at which the geometry section starts, including the padding
+/// of aligned files. Only the bytes up to `$$HEADEREND` are read, which makes this cheap enough to
+/// list the units, version and layer count of many files without decoding their geometry.
+/// Unlike [`CLI::new`], files with an ASCII geometry section are accepted.
+///
+/// ```no_run
+/// let buf = std::fs::read("example.cli").unwrap();
+/// let (header, _) = colain::parse_header(&buf).unwrap();
+/// println!("{} layers in units of {} mm", header.layers.unwrap_or(0), header.units);
+/// ```
+pub fn parse_header(raw: &[u8]) -> Result<(Header, usize), Error> {
+    parse_header_with_options(raw, &ParserOptions::default())
+}
+
+/// Same as [`parse_header`] but with control over which deviations from the spec are tolerated
+pub fn parse_header_with_options(
+    raw: &[u8],
+    options: &ParserOptions,
+) -> Result<(Header, usize), Error> {
+    let (end, header) = CLI::<LongCLI>::parse_header(raw, options.dialect)?;
+    let start = if header.aligned {
+        end.next_multiple_of(4)
+    } else {
+        end
+    };
+    Ok((header, start))
+}
 
 /// Light abstraction over a CLI file
 pub struct CLI<'a, T: CLIType> {
     // raw: &'a Vec<u8>,
     header: Header,
     layers: Vec<Layer<'a, T>>,
+    verbatim: Verbatim<'a>,
 }
 
 impl<'a, T: CLIType> CLI<'a, T> {
@@ -387,24 +1005,58 @@ impl<'a, T: CLIType> CLI<'a, T> {
     ///
     /// See crate level documentation for usage.
     pub fn new(raw: &'a [u8]) -> Result<Self, Error> {
-        let (mut gstart, header) = CLI::<T>::parse_header(&raw)?;
-        if !header.binary {
-            Err(Error::UnsupportedGeometryFormat)?;
-        }
+        CLI::new_with_options(raw, &ParserOptions::default())
+    }
 
-        if header.aligned {
-            gstart = 4 * ((gstart - 1) / 4) + 4;
+    /// Same as [`CLI::new`] but with control over which deviations from the spec are tolerated.
+    ///
+    /// See [`ParserOptions`], and [`Parser`] for further control over parsing.
+    pub fn new_with_options(raw: &'a [u8], options: &ParserOptions) -> Result<Self, Error> {
+        Parser::default().options(options).parse(raw)
+    }
+
+    /// Parse a buffer with several complete files concatenated into one, see
+    /// [`Parser::parse_all`]
+    pub fn parse_all(raw: &'a [u8]) -> Result<Vec<Self>, Error> {
+        Parser::default().parse_all(raw)
+    }
+
+    /// Parse the header and locate the binary geometry section
+    fn split(
+        raw: &'a [u8],
+        options: &ParserOptions,
+    ) -> Result<(Header, Option<&'a str>, &'a [u8]), Error> {
+        let (header, text, geom) = CLI::<T>::split_any(raw, options)?;
+        if header.binary {
+            Ok((header, text, geom))
+        } else {
+            trace::header_rejected(&Error::UnsupportedGeometryFormat);
+            Err(Error::UnsupportedGeometryFormat)
         }
-        let mut geom = &raw[gstart..];
+    }
 
-        let mut this = CLI {
-            header,
-            layers: Vec::new(),
+    /// Parse the header and locate the geometry section, which may be binary or ASCII
+    fn split_any(
+        raw: &'a [u8],
+        options: &ParserOptions,
+    ) -> Result<(Header, Option<&'a str>, &'a [u8]), Error> {
+        let _span = trace::header_span();
+        let timer = trace::Timer::start();
+        let (mut gstart, header) = match CLI::<T>::parse_header(raw, options.dialect) {
+            Ok(parsed) => parsed,
+            Err(e) => {
+                trace::header_rejected(&e);
+                return Err(e);
+            }
         };
+        trace::header_parsed(&header, gstart, &timer);
+        // Not UTF-8 if a user data entry holds binary data
+        let text = std::str::from_utf8(&raw[..gstart]).ok();
 
-        let mut current_layer = None;
-        while this.next_element(&mut current_layer, &mut geom)? {}
-        Ok(this)
+        if header.binary && header.aligned {
+            gstart = 4 * ((gstart - 1) / 4) + 4;
+        }
+        Ok((header, text, raw.get(gstart..).unwrap_or_default()))
     }
 
     /// Get file metadata
@@ -412,8 +1064,26 @@ impl<'a, T: CLIType> CLI<'a, T> {
         &self.header
     }
 
+    /// Serialize the model as a binary CLI file.
+    ///
+    /// The layer count in the written header always matches the number of layers in the model.
+    /// For a parsed model the original header text and any [unknown records](CLI::unknown_records)
+    /// are written back unchanged, see [`CLI::raw_header`]. Layers that were not modified since
+    /// parsing are copied from the file rather than encoded again, so saving a large file after a
+    /// small edit costs little more than copying it.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        self.write_header(&mut out, self.layers.len());
+        self.write_leading(&mut out);
+        for layer in &self.layers {
+            writer::write_layer(&mut out, layer, self.header.aligned);
+        }
+        out.extend_from_slice(&self.verbatim.unknown);
+        out
+    }
+
     #[inline]
-    fn parse_header(raw: &[u8]) -> Result<(usize, Header), Error> {
+    fn parse_header(raw: &[u8], dialect: Dialect) -> Result<(usize, Header), Error> {
         // TODO: UTF-8 aware audit
         let pattern: &[u8] = b"$$HEADEREND";
         if raw.len() <= pattern.len() {
@@ -437,30 +1107,61 @@ impl<'a, T: CLIType> CLI<'a, T> {
             Err(Error::NoHeader)?;
         }
 
-        let header =
-            std::str::from_utf8(&raw[0..search_index]).map_err(|_| Error::HeaderInvalidUTF8)?;
-
         // Format(binary, ascii), units, version, date, dimension, layers, align
         let mut items: [Option<&str>; 7] = [None, None, None, None, None, None, None];
-        for l in header.lines() {
+        let mut labels = BTreeMap::new();
+        let mut user_data = Vec::new();
+        let mut extra = Vec::new();
+        for line in header::lines(&raw[0..search_index]) {
+            let l = match line? {
+                header::Line::Text(l) => l,
+                header::Line::UserData { uid, data, .. } => {
+                    user_data.push(HeaderUserData {
+                        uid: uid.to_string(),
+                        data: data.to_vec(),
+                    });
+                    continue;
+                }
+            };
             let mut cleaned = l.trim();
             if cleaned.starts_with("//") {
                 continue;
             } // its a commented line
             if let Some(com) = cleaned.find("//") {
                 // remove comment after line
-                cleaned = &cleaned[0..com].trim();
+                cleaned = cleaned[0..com].trim();
             }
-            let (command, _value) =
-                cleaned.split_at(cleaned.find("/").map(|x| x + 1).unwrap_or(cleaned.len()));
-            match command {
+            let (command, value) =
+                cleaned.split_at(cleaned.find('/').map(|x| x + 1).unwrap_or(cleaned.len()));
+            let command = if dialect.relaxed_header() {
+                Cow::Owned(
+                    command
+                        .chars()
+                        .filter(|c| !c.is_whitespace())
+                        .collect::<String>()
+                        .to_ascii_uppercase(),
+                )
+            } else {
+                Cow::Borrowed(command)
+            };
+            match &*command {
                 "$$BINARY" => items[0] = Some("0"),
                 "$$ASCII" => items[0] = Some("1"),
-                "$$UNITS/" => items[1] = Some(&cleaned["$$UNITS/".len()..]),
-                "$$VERSION/" => items[2] = Some(&cleaned["$$VERSION/".len()..]),
-                "$$LAYERS/" => items[5] = Some(&cleaned["$$LAYERS/".len()..]),
+                "$$UNITS/" => items[1] = Some(value.trim()),
+                "$$VERSION/" => items[2] = Some(value.trim()),
+                "$$DATE/" => items[3] = Some(value.trim()),
+                "$$DIMENSION/" => items[4] = Some(value.trim()),
+                "$$LABEL/" => {
+                    let (id, text) = header::parse_label(value).ok_or(Error::InvalidHeaderValue)?;
+                    labels.insert(id, text.to_string());
+                }
+                "$$LAYERS/" => items[5] = Some(value.trim()),
                 "$$ALIGN" => items[6] = Some(""),
-                _ => {}
+                command => {
+                    if let Some(name) = header::extra_command(command) {
+                        extra.push((name.to_string(), value.trim().to_string()));
+                    }
+                }
             }
         }
 
@@ -490,115 +1191,204 @@ impl<'a, T: CLIType> CLI<'a, T> {
                 } else {
                     None
                 },
+                date: items[3].map(Date::new),
+                dimension: if let Some(d) = items[4] {
+                    Some(header::parse_dimension(d).ok_or(Error::InvalidHeaderValue)?)
+                } else {
+                    None
+                },
+                labels,
+                user_data,
+                extra,
             },
         ))
     }
 
-    fn next_element(
-        &mut self,
-        current_layer: &mut Option<usize>,
-        buf: &mut &'a [u8],
-    ) -> Result<bool, Error> {
-        // TODO: Should be some way to do this at compile time
+    /// Add a single record of the geometry section to the model
+    fn decode(&mut self, cursor: &mut Cursor, token: Token<'a>) -> Result<(), Error> {
         let aligned = self.header.aligned;
-        let coord_size: usize = size_of::<<T as CLIType>::Coord>();
-        let meta_size: usize = size_of::<<T as CLIType>::Meta>();
-        // Implementation notes:
-        // the CLI spec does not actually make clear what should happen to the last element in a 32bit aligned
-        // data section. You could technically leave the last two empty bytes off of the end of the file and still have valid data.
-        // Its also unlikely that this would ever matter since the last element of a data section is likely to be a hatches or polyline
-        // command which would not end with a half word element. The only reason to leave the aggressive EOF check in is that without it,
-        // get_meta could panic when advancing
-
-        let cmd = buf.get_u16_le();
-        if aligned {
-            buf.advance(2)
-        };
-
-        match cmd {
-            // Start layer long
-            127 | 128 => {
-                if cmd != T::CMD_LAYER {
-                    Err(Error::TypeMismatch)?;
-                }
+        let mut payload = token.payload;
+        let buf = &mut payload;
 
-                CLI::<T>::expect_eof(buf, coord_size + aligned as usize * 2)?;
-                let l = Layer {
-                    height: <T as CLIType>::get_coord(buf, aligned),
-                    loops: vec![],
-                    hatches: vec![],
-                };
-                // println!("New layer at: {:?}mm", l.height);
+        match token.command {
+            c if c == T::CMD_LAYER => {
+                let l = Layer::empty(<T as CLIType>::get_coord(buf, aligned));
                 self.layers.push(l);
-                if let Some(layer) = current_layer {
-                    *current_layer = Some(*layer + 1);
+                if let Some(layer) = cursor.layer {
+                    cursor.layer = Some(layer + 1);
                 } else {
-                    *current_layer = Some(0);
+                    cursor.layer = Some(0);
                 }
+                cursor.last = None;
             }
-            129 | 130 => {
-                if cmd != T::CMD_PLINE {
-                    Err(Error::TypeMismatch)?;
-                }
-
-                CLI::<T>::expect_eof(buf, 3 * (meta_size + aligned as usize * 2))?;
+            c if c == T::CMD_PLINE => {
                 let id = T::get_meta(buf, aligned);
                 let dir = T::get_meta(buf, aligned);
                 let n_pts = T::get_usize(buf, aligned) * 2; // num_pts * floats in point
-
-                // $$ ALIGN not a factor here since the spec says should be tightly packed
-                CLI::<T>::expect_eof(buf, coord_size * n_pts)?;
                 let points = CLI::<T>::cast_slice(n_pts, buf);
-                buf.advance(coord_size * n_pts);
 
-                if let Some(l) = current_layer {
-                    self.layers[*l].loops.push(Loop { id, dir, points });
+                if let Some(l) = cursor.layer {
+                    self.layers[l].push_loop(Loop {
+                        id,
+                        dir,
+                        points,
+                        params: VectorParams::default(),
+                    });
+                    cursor.last = Some(Block::Loop);
                 } else {
                     Err(Error::ElementOutsideLayer)?;
                 }
             }
-            // hatches short
-            131 | 132 => {
-                if cmd != T::CMD_HATCH {
-                    Err(Error::TypeMismatch)?;
-                }
-
-                CLI::<T>::expect_eof(buf, 2 * (meta_size + aligned as usize * 2))?;
+            c if c == T::CMD_HATCH => {
                 let id = T::get_meta(buf, aligned);
                 let n_pts = T::get_usize(buf, aligned) * 4; // num_pts * floats in point
-
-                // $$ ALIGN not a factor here since the spec says should be tightly packed
-                CLI::<T>::expect_eof(buf, coord_size * n_pts)?;
                 let points = CLI::<T>::cast_slice(n_pts, buf);
-                buf.advance(coord_size * n_pts);
 
-                if let Some(l) = current_layer {
-                    self.layers[*l].hatches.push(Hatches { id, points });
+                if let Some(l) = cursor.layer {
+                    self.layers[l].push_hatches(Hatches {
+                        id,
+                        points,
+                        params: VectorParams::default(),
+                    });
+                    cursor.last = Some(Block::Hatches);
                 } else {
                     Err(Error::ElementOutsideLayer)?;
                 }
             }
-            _ => return Err(Error::InvalidGeometryCommand(cmd)),
-        }
-        return Ok(buf.len() > 0);
-    }
+            // per-vector parameter extensions, see the extension module
+            c if c == T::CMD_POWER || c == T::CMD_SPEED => {
+                let n = T::get_usize(buf, aligned);
+                let values = CLI::<T>::cast_slice(n, buf);
+
+                let layer = match cursor.layer {
+                    Some(l) => &mut self.layers[l],
+                    None => return Err(Error::ElementOutsideLayer),
+                };
+                let params = match cursor.last {
+                    Some(Block::Loop) => &mut layer.loops.last_mut().unwrap().params,
+                    Some(Block::Hatches) => &mut layer.hatches.last_mut().unwrap().params,
+                    None => return Err(Error::UnattachedExtension(c)),
+                };
+                if c == T::CMD_POWER {
+                    params.power = Some(values);
+                } else {
+                    params.speed = Some(values);
+                }
+            }
+            c if c == T::CMD_USERDATA => {
+                let id = T::get_meta(buf, aligned);
+                let n = T::get_usize(buf, aligned);
+                let data = Cow::Borrowed(&buf[..n]);
 
-    fn cast_slice<A>(count: usize, floats: &'a [u8]) -> &'a [A] {
-        unsafe { std::slice::from_raw_parts(floats.as_ptr() as *const _, count) }
+                if let Some(l) = cursor.layer {
+                    let layer = &mut self.layers[l];
+                    layer.user_data.push(UserData {
+                        id,
+                        position: layer.loops.len() + layer.hatches.len(),
+                        data,
+                    });
+                } else {
+                    Err(Error::ElementOutsideLayer)?;
+                }
+            }
+            // The tokenizer only produces commands known for this type
+            c => return Err(Error::InvalidGeometryCommand(c)),
+        }
+        Ok(())
     }
 
-    fn expect_eof(buf: &[u8], req_bytes: usize) -> Result<(), Error> {
-        if buf.len() < req_bytes {
-            Err(Error::UnexpectedEOF)
+    /// Reinterpret the start of the buffer as `count` coordinates.
+    ///
+    /// The data is borrowed in place when it is suitably aligned for the coordinate type and the
+    /// host is little endian, otherwise it is decoded into an owned copy.
+    #[cfg(not(feature = "safe"))]
+    fn cast_slice(count: usize, floats: &'a [u8]) -> Cow<'a, [<T as CLIType>::Coord]> {
+        let ptr = floats.as_ptr();
+        if cfg!(target_endian = "little")
+            && ptr.align_offset(std::mem::align_of::<<T as CLIType>::Coord>()) == 0
+        {
+            // SAFETY: the caller checked that the buffer holds `count` coordinates, the pointer is
+            // aligned and both coordinate types are valid for any bit pattern
+            Cow::Borrowed(unsafe { std::slice::from_raw_parts(ptr as *const _, count) })
         } else {
-            Ok(())
+            let mut buf = floats;
+            Cow::Owned((0..count).map(|_| T::get_coord(&mut buf, false)).collect())
         }
     }
 
+    /// Decode `count` coordinates from the start of the buffer into an owned copy
+    #[cfg(feature = "safe")]
+    fn cast_slice(count: usize, floats: &'a [u8]) -> Cow<'a, [<T as CLIType>::Coord]> {
+        let mut buf = floats;
+        Cow::Owned((0..count).map(|_| T::get_coord(&mut buf, false)).collect())
+    }
+
     /// Iterate over each layer in the file
-    pub fn iter(&'a self) -> std::slice::Iter<'a, Layer<'a, T>> {
+    pub fn iter(&self) -> std::slice::Iter<'_, Layer<'a, T>> {
         self.layers.iter()
     }
+
+    /// Iterate mutably over each layer in the file, e.g. to [tag](tags) it
+    pub fn iter_mut(&mut self) -> std::slice::IterMut<'_, Layer<'a, T>> {
+        self.layers.iter_mut()
+    }
+
+    /// Height of the lowest layer, `None` if there are no layers
+    pub fn z_min(&self) -> Option<<T as CLIType>::Coord> {
+        self.layers
+            .iter()
+            .map(|l| l.height)
+            .min_by(|a, b| T::coord_to_f64(*a).total_cmp(&T::coord_to_f64(*b)))
+    }
+
+    /// Height of the highest layer, `None` if there are no layers
+    pub fn z_max(&self) -> Option<<T as CLIType>::Coord> {
+        self.layers
+            .iter()
+            .map(|l| l.height)
+            .max_by(|a, b| T::coord_to_f64(*a).total_cmp(&T::coord_to_f64(*b)))
+    }
+
+    /// The layer covering height `z`, `None` if `z` is above the highest layer.
+    ///
+    /// The height of a layer is that of its upper surface, so a layer covers the heights above
+    /// the layer below it up to and including its own. Layers are found by binary search and
+    /// have to be in ascending order, as the spec requires.
+    pub fn layer_at_height(&self, z: <T as CLIType>::Coord) -> Option<&Layer<'a, T>> {
+        let z = T::coord_to_f64(z);
+        let i = self
+            .layers
+            .partition_point(|l| T::coord_to_f64(l.height) < z);
+        self.layers.get(i)
+    }
+
+    /// The layers with a height within `range`, found by binary search like
+    /// [`CLI::layer_at_height`]
+    pub fn layers_in_range<R: RangeBounds<<T as CLIType>::Coord>>(
+        &self,
+        range: R,
+    ) -> &[Layer<'a, T>] {
+        let height = |l: &Layer<'a, T>| T::coord_to_f64(l.height);
+        let start = match range.start_bound() {
+            Bound::Included(&z) => self
+                .layers
+                .partition_point(|l| height(l) < T::coord_to_f64(z)),
+            Bound::Excluded(&z) => self
+                .layers
+                .partition_point(|l| height(l) <= T::coord_to_f64(z)),
+            Bound::Unbounded => 0,
+        };
+        let end = match range.end_bound() {
+            Bound::Included(&z) => self
+                .layers
+                .partition_point(|l| height(l) <= T::coord_to_f64(z)),
+            Bound::Excluded(&z) => self
+                .layers
+                .partition_point(|l| height(l) < T::coord_to_f64(z)),
+            Bound::Unbounded => self.layers.len(),
+        };
+        self.layers.get(start..end).unwrap_or_default()
+    }
 }
 
 #[cfg(test)]
@@ -616,15 +1406,50 @@ $$VERSION/105
 $$UNITS/1              // all coordinates are given in mm  // 
 // $$UNITS/0.01     all coordinates are given in units 0.01 mm //      
 $$DATE/070493                       // 7. April 1993 //
+$$DIMENSION/0,0,0,10.5,20,30
+$$LABEL/1, part1
+$$LABEL/2,part2                     //  second part //
+$$VENDOR/mode=fast                  //  unknown to the parser //
+$$PREVIEW
 $$LAYERS/100                        //  100 layers //
 $$HEADEREND                               
 
 $$GEOMETRYSTART          // start of GEOMETRY-section//
 "#;
 
-        let (_, header) = CLI::<LongCLI>::parse_header(data.as_bytes())?;
+        let (_, header) = CLI::<LongCLI>::parse_header(data.as_bytes(), Dialect::Standard)?;
         assert_eq!(header.units, 1.0);
         assert_eq!(header.version, 1.05);
+
+        let (header, start) = parse_header(data.as_bytes())?;
+        assert_eq!((header.binary, header.layers), (false, Some(100)));
+        let date = header.date.as_ref().unwrap();
+        assert_eq!(date.raw, "070493");
+        assert_eq!(
+            date.calendar.map(|c| (c.day, c.month, c.year)),
+            Some((7, 4, 1993))
+        );
+        assert_eq!(header.dimension, Some([0.0, 0.0, 0.0, 10.5, 20.0, 30.0]));
+        assert_eq!(
+            (header.label(1), header.label(2)),
+            (Some("part1"), Some("part2"))
+        );
+        assert_eq!(header.label(3), None);
+        assert_eq!(
+            header.extra,
+            [
+                ("VENDOR".to_string(), "mode=fast".to_string()),
+                ("PREVIEW".to_string(), String::new())
+            ]
+        );
+        assert_eq!(&data[start - 11..start], "$$HEADEREND");
+        let invalid = data.replace("10.5,20,30", "10.5,20");
+        assert!(matches!(
+            parse_header(invalid.as_bytes()),
+            Err(Error::InvalidHeaderValue)
+        ));
+        let aligned = "$$HEADERSTART\n$$BINARY\n$$UNITS/1\n$$VERSION/200\n$$ALIGN\n$$HEADEREND";
+        assert_eq!(parse_header(aligned.as_bytes())?.1, 68);
         Ok(())
     }
     #[test]
@@ -632,4 +1457,91 @@ $$GEOMETRYSTART          // start of GEOMETRY-section//
         assert_eq!("NoHeader", &format!("{}", Error::NoHeader));
         assert_eq!("NoHeader", &format!("{:?}", Error::NoHeader));
     }
+
+    #[test]
+    fn coordinates() -> Result<(), Error> {
+        let header = Header {
+            aligned: true,
+            labels: BTreeMap::from([(1, "part".to_string())]),
+            ..Header::default()
+        };
+        let layer = Layer::<LongCLI> {
+            loops: vec![Loop {
+                id: 1,
+                dir: 1,
+                points: Cow::Owned(vec![0.0, 0.0, 2.0, 0.0, 2.0, 1.0, 0.0, 0.0]),
+                params: VectorParams::default(),
+            }],
+            ..Layer::empty(0.5)
+        };
+        let bytes = writer::encode(&header, &[layer]);
+
+        let model = CLI::<LongCLI>::new(&bytes)?;
+        let points = &model.layers[0].loops[0].points;
+        assert_eq!(&points[..], &[0.0, 0.0, 2.0, 0.0, 2.0, 1.0, 0.0, 0.0]);
+        if cfg!(feature = "safe") {
+            assert!(matches!(points, Cow::Owned(_)));
+        }
+        assert_eq!(model.layers[0].loops[0].label(model.header()), Some("part"));
+        Ok(())
+    }
+
+    #[test]
+    fn z_extents() {
+        let layer = |height| Layer::<ShortCLI>::empty(height);
+        let header = Header {
+            units: 0.005,
+            ..Header::default()
+        };
+        let mut model = CLI {
+            header,
+            layers: vec![],
+            verbatim: Default::default(),
+        };
+        assert_eq!((model.z_min(), model.z_max()), (None, None));
+
+        model.layers = vec![layer(6), layer(12), layer(20)];
+        assert_eq!((model.z_min(), model.z_max()), (Some(6), Some(20)));
+        let spans: Vec<_> = (0..3)
+            .map(|i| model.layers[i].z_span(model.layers.get(i + 1)))
+            .collect();
+        assert_eq!(spans, [Some([6, 12]), Some([12, 20]), None]);
+    }
+
+    #[test]
+    fn by_height() -> Result<(), Error> {
+        let mut builder = CLIBuilder::new();
+        for z in [0.5, 1.0, 1.5, 2.0] {
+            builder = builder.layer(z);
+        }
+        let model = builder.build()?;
+        let at = |z| model.layer_at_height(z).map(|l| l.height());
+        assert_eq!(
+            (at(0.0), at(1.0), at(1.2), at(2.5)),
+            (Some(0.5), Some(1.0), Some(1.5), None)
+        );
+
+        let heights =
+            |layers: &[Layer<'_, LongCLI>]| layers.iter().map(|l| l.height()).collect::<Vec<_>>();
+        assert_eq!(heights(model.layers_in_range(1.0..2.0)), [1.0, 1.5]);
+        assert_eq!(heights(model.layers_in_range(1.0..=2.0)), [1.0, 1.5, 2.0]);
+        assert_eq!(heights(model.layers_in_range(..0.75)), [0.5]);
+        assert_eq!(heights(model.layers_in_range(1.1..1.2)), [] as [f32; 0]);
+        assert_eq!(model.layers_in_range(2.0..1.0).len(), 0);
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(all(feature = "glam", feature = "nalgebra"))]
+    fn math_types() {
+        let point: [u16; 2] = [3, 4];
+        assert_eq!(point.to_glam(), glam::Vec2::new(3.0, 4.0));
+        assert_eq!(point.to_nalgebra(), nalgebra::Point2::new(3.0, 4.0));
+        let segment: [f32; 4] = [0.0, 1.0, 2.5, 3.0];
+        assert_eq!(
+            segment.to_glam(),
+            [glam::Vec2::new(0.0, 1.0), glam::Vec2::new(2.5, 3.0)]
+        );
+        assert_eq!(segment.to_nalgebra()[1], nalgebra::Point2::new(2.5, 3.0));
+    }
 }
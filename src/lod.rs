@@ -0,0 +1,287 @@
+//! Simplified copies of the layers for viewers
+//!
+//! Drawing every point of a build with tens of thousands of layers is too slow for an overview.
+//! [`CLI::level_of_detail`] prepares progressively simplified copies of every layer, one level per
+//! point budget, so a viewer can draw a coarse level first and switch to finer ones, and finally
+//! the model itself, as the user zooms in.
+//!
+//! Contours are simplified with the Visvalingam-Whyatt algorithm, which removes the points
+//! spanning the smallest triangles with their neighbours first, so the overall shape survives
+//! even at small budgets. Hatches are thinned by keeping evenly spaced vectors.
+//!
+//! ```
+//! use colain::{CLI, clitype::LongCLI};
+//! # let buf = colain::BuildPlate::<LongCLI>::new(1.0).to_bytes();
+//! let model = CLI::<LongCLI>::new(&buf).unwrap();
+//!
+//! let lod = model.level_of_detail(&[100, 1_000, 10_000]);
+//! // Finest level that fits a budget of 500 points per layer
+//! let level = lod.select(500).unwrap();
+//! for layer in level.layers() {
+//!     // draw the layer
+//! }
+//! ```
+
+use crate::clitype::CLIType;
+use crate::geom;
+use crate::{Hatches, Layer, Loop, VectorParams, CLI};
+use std::borrow::Cow;
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+
+/// Simplified copies of the layers of a model, see the [module documentation](self)
+#[derive(Debug, Clone)]
+pub struct LevelOfDetail<T: CLIType> {
+    levels: Vec<LodLevel<T>>,
+}
+
+/// Copies of all layers simplified to the same point budget
+#[derive(Debug, Clone)]
+pub struct LodLevel<T: CLIType> {
+    budget: usize,
+    layers: Vec<Layer<'static, T>>,
+}
+
+impl<T: CLIType> LevelOfDetail<T> {
+    /// All levels, from the coarsest to the finest
+    pub fn levels(&self) -> &[LodLevel<T>] {
+        &self.levels
+    }
+
+    /// The finest level whose budget does not exceed `budget` points per layer, or the coarsest
+    /// level if they all do. `None` if there are no levels.
+    pub fn select(&self, budget: usize) -> Option<&LodLevel<T>> {
+        self.levels
+            .iter()
+            .rev()
+            .find(|l| l.budget <= budget)
+            .or_else(|| self.levels.first())
+    }
+}
+
+impl<T: CLIType> LodLevel<T> {
+    /// Maximum number of points per layer
+    pub fn budget(&self) -> usize {
+        self.budget
+    }
+
+    /// The simplified layers, in the order of the model
+    pub fn layers(&self) -> &[Layer<'static, T>] {
+        &self.layers
+    }
+}
+
+impl<'a, T: CLIType> Layer<'a, T> {
+    /// Number of points in the layer, counting both ends of every hatch vector
+    pub(crate) fn point_count(&self) -> usize {
+        let loops: usize = self.loops.iter().map(|l| l.points.len() / 2).sum();
+        let hatches: usize = self.hatches.iter().map(|h| h.points.len() / 2).sum();
+        loops + hatches
+    }
+
+    /// Copy of the layer simplified to about `budget` points.
+    ///
+    /// Every contour and hatch block keeps its share of the budget in proportion to its number of
+    /// points. Contours are never reduced below a triangle (or a single segment for open paths)
+    /// and every hatch block keeps at least one vector, so very small budgets may be exceeded.
+    /// Layers within budget are copied unchanged.
+    ///
+    /// Process parameters and user data are dropped since the vectors no longer match the
+    /// original ones.
+    pub fn simplified(&self, budget: usize) -> Layer<'static, T> {
+        let total = self.point_count();
+        let share = |points: usize| {
+            if total <= budget {
+                points
+            } else {
+                (points as f64 * budget as f64 / total as f64).round() as usize
+            }
+        };
+        let loops = self
+            .loops
+            .iter()
+            .map(|l| {
+                let points = geom::to_points(&l.points, |c| T::coord_to_f64(c));
+                let closed = points.len() > 3 && points.first() == points.last();
+                let keep = share(points.len()).max(if closed { 4 } else { 2 });
+                Loop {
+                    id: l.id,
+                    dir: l.dir,
+                    points: Cow::Owned(
+                        simplify(&points, keep)
+                            .into_iter()
+                            .flat_map(|i| [l.points[2 * i], l.points[2 * i + 1]])
+                            .collect(),
+                    ),
+                    params: VectorParams::default(),
+                }
+            })
+            .collect();
+        let hatches = self
+            .hatches
+            .iter()
+            .map(|h| {
+                let vectors = h.points.len() / 4;
+                let keep = (share(2 * vectors) / 2).clamp(vectors.min(1), vectors);
+                Hatches {
+                    id: h.id,
+                    points: Cow::Owned(
+                        (0..keep)
+                            .flat_map(|i| {
+                                let v = i * vectors / keep;
+                                h.points[4 * v..4 * v + 4].iter().copied()
+                            })
+                            .collect(),
+                    ),
+                    params: VectorParams::default(),
+                }
+            })
+            .collect();
+        Layer {
+            loops,
+            hatches,
+            ..Layer::empty(self.height)
+        }
+    }
+}
+
+impl<'a, T: CLIType> CLI<'a, T> {
+    /// Simplify every layer once per point budget, see [`Layer::simplified`].
+    ///
+    /// The levels are sorted from the smallest to the largest budget, duplicate budgets are only
+    /// built once.
+    pub fn level_of_detail(&self, budgets: &[usize]) -> LevelOfDetail<T> {
+        let mut budgets = budgets.to_vec();
+        budgets.sort_unstable();
+        budgets.dedup();
+        LevelOfDetail {
+            levels: budgets
+                .into_iter()
+                .map(|budget| LodLevel {
+                    budget,
+                    layers: self.layers.iter().map(|l| l.simplified(budget)).collect(),
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Indices of the `keep` points of a polyline that remain after Visvalingam-Whyatt
+/// simplification, in order. The end points are always kept.
+fn simplify(points: &[[f64; 2]], keep: usize) -> Vec<usize> {
+    let n = points.len();
+    if n <= keep.max(2) {
+        return (0..n).collect();
+    }
+    let mut prev: Vec<usize> = (0..n).map(|i| i.wrapping_sub(1)).collect();
+    let mut next: Vec<usize> = (1..=n).collect();
+    let mut removed = vec![false; n];
+    let area = |p: usize, i: usize, q: usize| geom::orient2d(points[p], points[i], points[q]).abs();
+    let mut areas: Vec<f64> = (0..n)
+        .map(|i| {
+            if i == 0 || i == n - 1 {
+                f64::INFINITY
+            } else {
+                area(i - 1, i, i + 1)
+            }
+        })
+        .collect();
+    // Non-negative floats order like their bit patterns
+    let mut heap: BinaryHeap<_> = (1..n - 1)
+        .map(|i| Reverse((areas[i].to_bits(), i)))
+        .collect();
+
+    let mut remaining = n;
+    while remaining > keep {
+        let Reverse((bits, i)) = match heap.pop() {
+            Some(entry) => entry,
+            None => break,
+        };
+        if removed[i] || bits != areas[i].to_bits() {
+            continue;
+        }
+        removed[i] = true;
+        remaining -= 1;
+        let (p, q) = (prev[i], next[i]);
+        next[p] = q;
+        prev[q] = p;
+        // A neighbour never becomes less significant than the point removed next to it
+        for j in [p, q] {
+            if j != 0 && j != n - 1 {
+                areas[j] = area(prev[j], j, next[j]).max(areas[i]);
+                heap.push(Reverse((areas[j].to_bits(), j)));
+            }
+        }
+    }
+    (0..n).filter(|&i| !removed[i]).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clitype::LongCLI;
+    use crate::Header;
+
+    fn circle(n: usize) -> Loop<'static, LongCLI> {
+        let mut points: Vec<f32> = (0..n)
+            .flat_map(|i| {
+                let a = i as f32 / n as f32 * std::f32::consts::TAU;
+                [a.cos(), a.sin()]
+            })
+            .collect();
+        points.extend_from_slice(&[1.0, 0.0]);
+        Loop {
+            id: 0,
+            dir: 1,
+            points: Cow::Owned(points),
+            params: Default::default(),
+        }
+    }
+
+    #[test]
+    fn simplify_keeps_corners() {
+        // Square with extra points along its sides
+        let points = [
+            [0.0, 0.0],
+            [1.0, 0.0],
+            [2.0, 0.0],
+            [2.0, 1.0],
+            [2.0, 2.0],
+            [1.0, 2.001],
+            [0.0, 2.0],
+            [0.0, 0.0],
+        ];
+        assert_eq!(simplify(&points, 5), [0, 2, 4, 6, 7]);
+        assert_eq!(simplify(&points, 100).len(), 8);
+    }
+
+    #[test]
+    fn levels() {
+        let layer = Layer::<LongCLI> {
+            loops: vec![circle(1000)],
+            hatches: vec![Hatches {
+                id: 0,
+                points: Cow::Owned((0..400).map(|i| i as f32).collect()),
+                params: Default::default(),
+            }],
+            ..Layer::empty(1.0)
+        };
+        let model = CLI {
+            header: Header::default(),
+            layers: vec![layer],
+            verbatim: Default::default(),
+        };
+        let lod = model.level_of_detail(&[1000, 100, 100]);
+        assert_eq!(lod.levels().len(), 2);
+
+        let coarse = &lod.levels()[0].layers()[0];
+        assert!((95..=100).contains(&coarse.point_count()));
+        let ring = coarse.loops[0].points();
+        assert_eq!(ring[..2], ring[ring.len() - 2..]);
+        assert_eq!(coarse.hatches[0].points()[..4], [0.0, 1.0, 2.0, 3.0]);
+
+        assert_eq!(lod.select(500).unwrap().budget(), 100);
+        assert_eq!(lod.select(50).unwrap().budget(), 100);
+        assert_eq!(lod.select(5000).unwrap().budget(), 1000);
+    }
+}
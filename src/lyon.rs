@@ -0,0 +1,124 @@
+//! Conversion into [lyon](https://docs.rs/lyon) paths
+//!
+//! lyon tessellates paths into triangles for rendering with wgpu or other GPU APIs, which makes it
+//! a good fit for slice viewers. A [`Loop`] converts into a [`Path`] with a single sub-path, and
+//! [`Layer::to_lyon_path`] builds one path of all loops of a layer, so that the holes of a region
+//! are cut out when the path is filled with the even-odd or non-zero fill rule. Coordinates are in
+//! coordinate units.
+//!
+//! ```
+//! use colain::CLIBuilder;
+//! use lyon_path::Path;
+//!
+//! let model = CLIBuilder::new()
+//!     .layer(1.0)
+//!     .polyline(1, 1, vec![0.0, 0.0, 4.0, 0.0, 4.0, 4.0, 0.0, 0.0])
+//!     .build()
+//!     .unwrap();
+//! let layer = model.iter().next().unwrap();
+//! let path = Path::from(layer.iter_loops().next().unwrap());
+//! assert_eq!(path.iter().count(), 4);
+//! ```
+
+use crate::clitype::CLIType;
+use crate::{Layer, Loop, LoopKind};
+use lyon_path::math::point;
+use lyon_path::path::Builder;
+use lyon_path::Path;
+
+/// Add a loop as a sub-path, closed unless it is an open polyline
+fn add_loop<T: CLIType>(builder: &mut Builder, l: &Loop<'_, T>) {
+    let f = |c| T::coord_to_f64(c) as f32;
+    let mut points: Vec<_> = l
+        .points
+        .chunks_exact(2)
+        .map(|p| point(f(p[0]), f(p[1])))
+        .collect();
+    let closed = l.kind() != LoopKind::Open;
+    // Closing the sub-path draws the last segment
+    if closed && points.len() > 1 && points.first() == points.last() {
+        points.pop();
+    }
+    if let Some((&first, rest)) = points.split_first() {
+        builder.begin(first);
+        for &p in rest {
+            builder.line_to(p);
+        }
+        builder.end(closed);
+    }
+}
+
+impl<'a, T: CLIType> From<&Loop<'a, T>> for Path {
+    fn from(l: &Loop<'a, T>) -> Self {
+        let mut builder = Path::builder();
+        add_loop(&mut builder, l);
+        builder.build()
+    }
+}
+
+impl<'a, T: CLIType> Layer<'a, T> {
+    /// All loops of the layer as sub-paths of one path, see the [module documentation](self)
+    pub fn to_lyon_path(&self) -> Path {
+        let mut builder = Path::builder();
+        for l in &self.loops {
+            add_loop(&mut builder, l);
+        }
+        builder.build()
+    }
+
+    /// All hatches of the layer as open sub-paths of a single segment, to be stroked
+    pub fn hatches_to_lyon_path(&self) -> Path {
+        let f = |c| T::coord_to_f64(c) as f32;
+        let mut builder = Path::builder();
+        for v in self.hatches.iter().flat_map(|h| h.iter()) {
+            builder.begin(point(f(v[0]), f(v[1])));
+            builder.line_to(point(f(v[2]), f(v[3])));
+            builder.end(false);
+        }
+        builder.build()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clitype::ShortCLI;
+    use crate::{CLIBuilder, CLI};
+    use lyon_path::PathEvent;
+
+    #[test]
+    fn paths() {
+        let model: CLI<'_, ShortCLI> = CLIBuilder::new()
+            .short()
+            .layer(1)
+            .polyline(1, 1, vec![0, 0, 4, 0, 4, 4, 0, 0])
+            .polyline(2, 2, vec![10, 0, 12, 2])
+            .hatches(3, vec![1, 1, 3, 1, 1, 2, 3, 2])
+            .build()
+            .unwrap();
+        let layer = model.iter().next().unwrap();
+
+        let contour = Path::from(layer.iter_loops().next().unwrap());
+        let events: Vec<_> = contour.iter().collect();
+        assert_eq!(events.len(), 4);
+        assert!(matches!(events[0], PathEvent::Begin { at } if at == point(0.0, 0.0)));
+        assert!(matches!(
+            events[3],
+            PathEvent::End { last, first, close: true }
+                if last == point(4.0, 4.0) && first == point(0.0, 0.0)
+        ));
+
+        let path = layer.to_lyon_path();
+        let ends: Vec<bool> = path
+            .iter()
+            .filter_map(|e| match e {
+                PathEvent::End { close, .. } => Some(close),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(ends, [true, false]);
+
+        let hatches = layer.hatches_to_lyon_path();
+        assert_eq!(hatches.iter().count(), 6);
+    }
+}
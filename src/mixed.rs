@@ -0,0 +1,319 @@
+//! Files mixing short and long commands
+//!
+//! The spec allows a geometry section to use [`ShortCLI`] and [`LongCLI`] commands side by side,
+//! while [`CLI`] expects a single type and fails with [`Error::TypeMismatch`] otherwise.
+//! [`MixedCLI`] reads both: every record is decoded into a [`LongCLI`] model, whose 32 bit float
+//! coordinates represent all short coordinates exactly, and the type each loop and set of hatches
+//! was stored with is kept next to it.
+//!
+//! ```
+//! use colain::{MixedCLI, Variant};
+//! # let buf = colain::CLIBuilder::new().layer(1.0).polyline(1, 1, vec![0.0, 0.0]).to_bytes().unwrap();
+//!
+//! let mixed = MixedCLI::new(&buf).unwrap();
+//! for (n, layer) in mixed.model().iter().enumerate() {
+//!     let short = (0..layer.iter_loops().count())
+//!         .filter(|&i| mixed.loop_variant(n, i) == Some(Variant::Short))
+//!         .count();
+//!     println!("layer {}: {} loops with 16 bit coordinates", n, short);
+//! }
+//! ```
+//!
+//! Loops stored with 32 bit coordinates borrow them from the file, short ones are converted into
+//! owned copies. The model is written back with long commands only.
+
+use crate::any::{is_command, Variant};
+use crate::clitype::{CLIType, LongCLI, ShortCLI};
+use crate::token::{payload_len, stride, Token};
+use crate::verbatim::Verbatim;
+use crate::{
+    checksum, Block, Cursor, Error, Hatches, Layer, Loop, Parser, ParserOptions, UserData,
+    VectorParams, CLI,
+};
+use bytes::Buf;
+use std::borrow::Cow;
+
+/// Types of the loops and hatches of a layer
+#[derive(Debug, Clone, Default)]
+struct Variants {
+    loops: Vec<Variant>,
+    hatches: Vec<Variant>,
+}
+
+/// A model read from a file mixing short and long commands, see the
+/// [module documentation](self)
+pub struct MixedCLI<'a> {
+    model: CLI<'a, LongCLI>,
+    variants: Vec<Variants>,
+}
+
+impl<'a> MixedCLI<'a> {
+    /// Parse a file that may contain both short and long commands
+    pub fn new(raw: &'a [u8]) -> Result<Self, Error> {
+        MixedCLI::new_with_options(raw, &ParserOptions::default())
+    }
+
+    /// Same as [`MixedCLI::new`] but with control over which deviations from the spec are
+    /// tolerated, see [`ParserOptions`]
+    pub fn new_with_options(raw: &'a [u8], options: &ParserOptions) -> Result<Self, Error> {
+        let (header, text, geom) = Parser::<LongCLI>::default().options(options).split(raw)?;
+        // The footer of a file written with short commands
+        let geom = checksum::strip_footer::<ShortCLI>(raw, geom, header.aligned, false)?;
+        let aligned = header.aligned;
+        let mut mixed = MixedCLI {
+            model: CLI {
+                header,
+                layers: Vec::new(),
+                verbatim: Verbatim {
+                    header: text.map(Cow::Borrowed),
+                    ..Default::default()
+                },
+            },
+            variants: Vec::new(),
+        };
+
+        let mut cursor = Cursor::default();
+        let word = stride(2, aligned);
+        let mut pos = 0;
+        while pos < geom.len() {
+            let mut rest = &geom[pos..];
+            if options.dialect.zero_padding() {
+                if rest.iter().all(|&b| b == 0) {
+                    break;
+                }
+                if rest.starts_with(&[0, 0]) {
+                    pos += 2;
+                    continue;
+                }
+            }
+            if rest.len() < word {
+                return Err(Error::UnexpectedEOF);
+            }
+            let command = options.dialect.command(rest.get_u16_le());
+            let rest = &geom[pos + word..];
+            let variant = if is_command::<ShortCLI>(command, options.extensions) {
+                Variant::Short
+            } else if is_command::<LongCLI>(command, options.extensions) {
+                Variant::Long
+            } else {
+                return Err(Error::InvalidGeometryCommand(command));
+            };
+            let len = match variant {
+                Variant::Short => payload_len::<ShortCLI>(command, rest, aligned, true)?,
+                Variant::Long => payload_len::<LongCLI>(command, rest, aligned, true)?,
+            };
+            let token = Token {
+                offset: pos,
+                command,
+                payload: &rest[..len],
+                end: pos + word + len,
+            };
+            pos = token.end;
+
+            match variant {
+                Variant::Short => mixed.model.decode_short(&mut cursor, token)?,
+                Variant::Long => mixed.model.decode(&mut cursor, token)?,
+            }
+            // Note the type of every loop and set of hatches
+            match command {
+                ShortCLI::CMD_LAYER | LongCLI::CMD_LAYER => {
+                    mixed.variants.push(Variants::default())
+                }
+                ShortCLI::CMD_PLINE | LongCLI::CMD_PLINE => {
+                    mixed.variants.last_mut().unwrap().loops.push(variant)
+                }
+                ShortCLI::CMD_HATCH | LongCLI::CMD_HATCH => {
+                    mixed.variants.last_mut().unwrap().hatches.push(variant)
+                }
+                _ => {}
+            }
+        }
+        Ok(mixed)
+    }
+
+    /// The model, with every coordinate as a 32 bit float
+    pub fn model(&self) -> &CLI<'a, LongCLI> {
+        &self.model
+    }
+
+    /// Take the model, dropping the types of its elements
+    pub fn into_model(self) -> CLI<'a, LongCLI> {
+        self.model
+    }
+
+    /// Type a loop was stored with, `None` if there is no such loop
+    pub fn loop_variant(&self, layer: usize, index: usize) -> Option<Variant> {
+        self.variants.get(layer)?.loops.get(index).copied()
+    }
+
+    /// Type a set of hatches was stored with, `None` if there is no such set
+    pub fn hatches_variant(&self, layer: usize, index: usize) -> Option<Variant> {
+        self.variants.get(layer)?.hatches.get(index).copied()
+    }
+
+    /// True if the file uses both short and long commands for its loops and hatches
+    pub fn is_mixed(&self) -> bool {
+        let mut all = self
+            .variants
+            .iter()
+            .flat_map(|v| v.loops.iter().chain(&v.hatches));
+        match all.next() {
+            Some(first) => all.any(|v| v != first),
+            None => false,
+        }
+    }
+}
+
+impl<'a> CLI<'a, LongCLI> {
+    /// Add a record with short parameters to the model, see [`CLI::decode`]
+    fn decode_short(&mut self, cursor: &mut Cursor, token: Token<'a>) -> Result<(), Error> {
+        let aligned = self.header.aligned;
+        let mut payload = token.payload;
+        let buf = &mut payload;
+        let meta = |buf: &mut &[u8]| ShortCLI::get_meta(buf, aligned) as i32;
+        // Coordinates are tightly packed
+        let coords = |buf: &mut &[u8], n: usize| -> Cow<'a, [f32]> {
+            Cow::Owned(
+                (0..n)
+                    .map(|_| ShortCLI::get_coord(buf, false) as f32)
+                    .collect(),
+            )
+        };
+        if token.command == ShortCLI::CMD_LAYER {
+            self.layers
+                .push(Layer::empty(ShortCLI::get_coord(buf, aligned) as f32));
+            cursor.layer = Some(self.layers.len() - 1);
+            cursor.last = None;
+            return Ok(());
+        }
+        let layer = match cursor.layer {
+            Some(l) => &mut self.layers[l],
+            None => return Err(Error::ElementOutsideLayer),
+        };
+
+        match token.command {
+            ShortCLI::CMD_PLINE => {
+                let (id, dir) = (meta(buf), meta(buf));
+                let n = ShortCLI::get_usize(buf, aligned) * 2;
+                layer.push_loop(Loop {
+                    id,
+                    dir,
+                    points: coords(buf, n),
+                    params: VectorParams::default(),
+                });
+                cursor.last = Some(Block::Loop);
+            }
+            ShortCLI::CMD_HATCH => {
+                let id = meta(buf);
+                let n = ShortCLI::get_usize(buf, aligned) * 4;
+                layer.push_hatches(Hatches {
+                    id,
+                    points: coords(buf, n),
+                    params: VectorParams::default(),
+                });
+                cursor.last = Some(Block::Hatches);
+            }
+            c @ (ShortCLI::CMD_POWER | ShortCLI::CMD_SPEED) => {
+                let n = ShortCLI::get_usize(buf, aligned);
+                let values = Some(coords(buf, n));
+                let params = match cursor.last {
+                    Some(Block::Loop) => &mut layer.loops.last_mut().unwrap().params,
+                    Some(Block::Hatches) => &mut layer.hatches.last_mut().unwrap().params,
+                    None => return Err(Error::UnattachedExtension(c)),
+                };
+                if c == ShortCLI::CMD_POWER {
+                    params.power = values;
+                } else {
+                    params.speed = values;
+                }
+            }
+            ShortCLI::CMD_USERDATA => {
+                let id = meta(buf);
+                let n = ShortCLI::get_usize(buf, aligned);
+                let data: &'a [u8] = buf;
+                layer.user_data.push(UserData {
+                    id,
+                    position: layer.loops.len() + layer.hatches.len(),
+                    data: Cow::Borrowed(&data[..n]),
+                });
+            }
+            c => return Err(Error::InvalidGeometryCommand(c)),
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::checksum::Checksum;
+    use crate::CLIBuilder;
+
+    #[test]
+    fn mixed() -> Result<(), Error> {
+        for aligned in [false, true] {
+            let short = CLIBuilder::new()
+                .short()
+                .aligned(aligned)
+                .layer(1)
+                .polyline(3, 1, vec![0, 0, 10, 0, 10, 10, 0, 0])
+                .to_bytes()?;
+            let long = CLIBuilder::new()
+                .aligned(aligned)
+                .layer(1.0)
+                .hatches(4, vec![0.5, 0.5, 9.5, 0.5])
+                .to_bytes()?;
+            // Hatches of the long file appended to the layer of the short one
+            let (_, start) = crate::parse_header(&long)?;
+            let layer = stride(2, aligned) + 4;
+            let mut buf = short.clone();
+            buf.extend_from_slice(&long[start + layer..]);
+            assert!(matches!(
+                CLI::<ShortCLI>::new(&buf).map_err(Error::into_inner),
+                Err(Error::TypeMismatch)
+            ));
+
+            let mixed = MixedCLI::new(&buf)?;
+            assert!(mixed.is_mixed());
+            let layer = mixed.model().iter().next().unwrap();
+            assert_eq!(layer.height(), 1.0);
+            let l = layer.iter_loops().next().unwrap();
+            assert_eq!((l.id(), l.dir()), (3, 1));
+            assert_eq!(l.points(), [0.0, 0.0, 10.0, 0.0, 10.0, 10.0, 0.0, 0.0]);
+            assert_eq!(
+                layer.iter_hatches().next().unwrap().points(),
+                [0.5, 0.5, 9.5, 0.5]
+            );
+            assert_eq!(mixed.loop_variant(0, 0), Some(Variant::Short));
+            assert_eq!(mixed.hatches_variant(0, 0), Some(Variant::Long));
+            assert_eq!(mixed.hatches_variant(0, 1), None);
+
+            let single = MixedCLI::new(&short)?;
+            assert!(!single.is_mixed());
+            assert_eq!(single.model().iter().count(), 1);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn checksum() -> Result<(), Error> {
+        let short = CLIBuilder::new()
+            .short()
+            .layer(1)
+            .polyline(3, 1, vec![0, 0, 10, 0, 10, 10, 0, 0])
+            .build()?
+            .to_bytes_with_checksum(Checksum::Crc32);
+        let long = CLIBuilder::new()
+            .layer(1.0)
+            .hatches(4, vec![0.5, 0.5, 9.5, 0.5])
+            .build()?
+            .to_bytes_with_checksum(Checksum::Sha256);
+        for buf in [short, long] {
+            let mixed = MixedCLI::new(&buf)?;
+            let layers: Vec<_> = mixed.model().iter().collect();
+            assert_eq!(layers.len(), 1);
+            assert!(layers[0].user_data.is_empty());
+        }
+        Ok(())
+    }
+}
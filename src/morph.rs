@@ -0,0 +1,324 @@
+//! Interpolation between two revisions of a model
+//!
+//! Distortion compensation workflows produce a second version of a model (e.g. nominal and
+//! compensated geometry) with the same layers and contours, but moved points. A [`Morph`] finds
+//! which layers and contours of the two models correspond and blends between them, so intermediate
+//! amounts of compensation can be generated.
+//!
+//! ```
+//! use colain::{morph::Morph, CLI, clitype::LongCLI};
+//! # let bytes = colain::BuildPlate::<LongCLI>::new(1.0).to_bytes();
+//! # let (nominal, compensated) = (bytes.clone(), bytes);
+//!
+//! let nominal = CLI::<LongCLI>::new(&nominal).unwrap();
+//! let compensated = CLI::<LongCLI>::new(&compensated).unwrap();
+//! let half = Morph::new(&nominal, &compensated).at(0.5);
+//! ```
+
+use crate::clitype::CLIType;
+use crate::geom;
+use crate::{Header, Layer, Loop, LoopKind, CLI};
+use std::borrow::Cow;
+
+/// Layer of one model and the corresponding layer of the other
+#[derive(Debug, Clone, PartialEq)]
+pub struct LayerMatch {
+    /// Layer index in the model morphed from
+    pub from: usize,
+    /// Layer index in the model morphed to
+    pub to: usize,
+    /// Pairs of corresponding loop indices
+    pub loops: Vec<(usize, usize)>,
+}
+
+/// Correspondence between two models, see the [module documentation](self)
+pub struct Morph<'m, T: CLIType> {
+    from: &'m CLI<'m, T>,
+    to: &'m CLI<'m, T>,
+    layers: Vec<LayerMatch>,
+}
+
+impl<'m, T: CLIType> Morph<'m, T> {
+    /// Match the layers and contours of two models.
+    ///
+    /// Every layer of `from` is paired with the layer of `to` closest in height. Within a pair,
+    /// loops of the same [`LoopKind`] are matched greedily by the distance between their
+    /// centroids, closest first. Heights and distances are compared in millimeters, so the models
+    /// may use different units.
+    pub fn new(from: &'m CLI<'m, T>, to: &'m CLI<'m, T>) -> Self {
+        let heights: Vec<f64> = to.layers.iter().map(|l| height(to, l)).collect();
+        let layers = from
+            .layers
+            .iter()
+            .enumerate()
+            .filter_map(|(i, layer)| {
+                let z = height(from, layer);
+                let j = nearest(&heights, z)?;
+                Some(LayerMatch {
+                    from: i,
+                    to: j,
+                    loops: match_loops(from, layer, to, &to.layers[j]),
+                })
+            })
+            .collect();
+        Morph { from, to, layers }
+    }
+
+    /// The layer and contour correspondence
+    pub fn layers(&self) -> &[LayerMatch] {
+        &self.layers
+    }
+
+    /// Geometry at `t` between the two models, `0.0` being `from` and `1.0` being `to`.
+    ///
+    /// Matched loops are blended point by point. When the loops have a different number of points
+    /// both are resampled evenly along their length first, and closed loops are aligned so they
+    /// start at the same position and run in the same direction. Everything that cannot be blended
+    /// (unmatched loops, hatches, process parameters of resampled loops and user data) is taken
+    /// from whichever model `t` is closer to.
+    ///
+    /// The result uses the units of `from`.
+    pub fn at(&self, t: f64) -> CLI<'static, T> {
+        let units = self.from.header.units;
+        let scale_to = self.to.header.units / units;
+        let near_to = t >= 0.5;
+
+        let layers = self
+            .layers
+            .iter()
+            .map(|m| {
+                let a = &self.from.layers[m.from];
+                let b = &self.to.layers[m.to];
+                let z = lerp(height(self.from, a), height(self.to, b), t);
+                let mut layer = Layer::empty(T::coord_from_f64(z / units));
+
+                for &(i, j) in &m.loops {
+                    layer
+                        .loops
+                        .push(blend(&a.loops[i], &b.loops[j], scale_to, t));
+                }
+                let (source, matched, scale) = if near_to {
+                    (b, m.loops.iter().map(|p| p.1).collect::<Vec<_>>(), scale_to)
+                } else {
+                    (a, m.loops.iter().map(|p| p.0).collect(), 1.0)
+                };
+                for (i, l) in source.loops.iter().enumerate() {
+                    if !matched.contains(&i) {
+                        layer.loops.push(Loop {
+                            id: l.id,
+                            dir: l.dir,
+                            points: Cow::Owned(scaled::<T>(&l.points, scale)),
+                            params: l.params.to_owned_params(),
+                        });
+                    }
+                }
+                layer.hatches = source
+                    .hatches
+                    .iter()
+                    .map(|h| crate::Hatches {
+                        id: h.id,
+                        points: Cow::Owned(scaled::<T>(&h.points, scale)),
+                        params: h.params.to_owned_params(),
+                    })
+                    .collect();
+                let end = layer.loops.len() + layer.hatches.len();
+                layer.user_data = source
+                    .user_data
+                    .iter()
+                    .map(|u| crate::UserData {
+                        position: u.position.min(end),
+                        ..u.to_owned_data()
+                    })
+                    .collect();
+                layer
+            })
+            .collect::<Vec<_>>();
+
+        CLI {
+            header: Header {
+                layers: Some(layers.len()),
+                ..self.from.header.clone()
+            },
+            layers,
+            verbatim: Default::default(),
+        }
+    }
+}
+
+fn lerp(a: f64, b: f64, t: f64) -> f64 {
+    a + (b - a) * t
+}
+
+/// Height of a layer in millimeters
+fn height<T: CLIType>(model: &CLI<'_, T>, layer: &Layer<'_, T>) -> f64 {
+    T::coord_to_f64(layer.height) * model.header.units
+}
+
+/// Index of the height closest to `z`
+fn nearest(heights: &[f64], z: f64) -> Option<usize> {
+    (0..heights.len()).min_by(|&a, &b| {
+        (heights[a] - z)
+            .abs()
+            .partial_cmp(&(heights[b] - z).abs())
+            .unwrap_or(std::cmp::Ordering::Equal)
+    })
+}
+
+/// Points of a loop in millimeters
+fn points<T: CLIType>(l: &Loop<'_, T>, units: f64) -> Vec<[f64; 2]> {
+    geom::to_points(&l.points, |c| T::coord_to_f64(c) * units)
+}
+
+fn scaled<T: CLIType>(coords: &[<T as CLIType>::Coord], scale: f64) -> Vec<<T as CLIType>::Coord> {
+    coords
+        .iter()
+        .map(|&c| T::coord_from_f64(T::coord_to_f64(c) * scale))
+        .collect()
+}
+
+/// Greedily pair loops of the same kind by centroid distance
+fn match_loops<T: CLIType>(
+    from: &CLI<'_, T>,
+    a: &Layer<'_, T>,
+    to: &CLI<'_, T>,
+    b: &Layer<'_, T>,
+) -> Vec<(usize, usize)> {
+    let centroids = |model: &CLI<'_, T>, layer: &Layer<'_, T>| -> Vec<Option<[f64; 2]>> {
+        layer
+            .loops
+            .iter()
+            .map(|l| geom::centroid(&points(l, model.header.units)))
+            .collect()
+    };
+    let (ca, cb) = (centroids(from, a), centroids(to, b));
+
+    let mut candidates = Vec::new();
+    for (i, la) in a.loops.iter().enumerate() {
+        for (j, lb) in b.loops.iter().enumerate() {
+            if let (Some(pa), Some(pb), true) = (ca[i], cb[j], la.kind() == lb.kind()) {
+                candidates.push(((pa[0] - pb[0]).hypot(pa[1] - pb[1]), i, j));
+            }
+        }
+    }
+    candidates.sort_by(|x, y| x.0.partial_cmp(&y.0).unwrap_or(std::cmp::Ordering::Equal));
+
+    let (mut used_a, mut used_b) = (vec![false; a.loops.len()], vec![false; b.loops.len()]);
+    let mut pairs = Vec::new();
+    for (_, i, j) in candidates {
+        if !used_a[i] && !used_b[j] {
+            used_a[i] = true;
+            used_b[j] = true;
+            pairs.push((i, j));
+        }
+    }
+    pairs.sort_unstable();
+    pairs
+}
+
+/// Blend two corresponding loops, `b` is converted to the units of `a` by `scale`
+fn blend<T: CLIType>(a: &Loop<'_, T>, b: &Loop<'_, T>, scale: f64, t: f64) -> Loop<'static, T> {
+    let pa = points(a, 1.0);
+    let mut pb = points(b, scale);
+
+    let closed = |p: &[[f64; 2]]| p.len() > 2 && p.first() == p.last();
+    let same = pa.len() == pb.len();
+    if a.kind() == LoopKind::Closed && closed(&pa) && closed(&pb) {
+        if geom::signed_area(&pa).signum() != geom::signed_area(&pb).signum() {
+            pb.reverse();
+        }
+        // Start both loops at the same position
+        pb.pop();
+        let start = (0..pb.len())
+            .min_by(|&i, &j| {
+                let d = |k: usize| (pb[k][0] - pa[0][0]).hypot(pb[k][1] - pa[0][1]);
+                d(i).partial_cmp(&d(j)).unwrap_or(std::cmp::Ordering::Equal)
+            })
+            .unwrap_or(0);
+        pb.rotate_left(start);
+        pb.push(pb[0]);
+    }
+
+    let n = pa.len().max(pb.len());
+    let (ra, rb) = if same {
+        (pa, pb)
+    } else {
+        (geom::resample(&pa, n), geom::resample(&pb, n))
+    };
+    let mut coords = Vec::with_capacity(2 * n);
+    for (p, q) in ra.iter().zip(rb.iter()) {
+        coords.push(T::coord_from_f64(lerp(p[0], q[0], t)));
+        coords.push(T::coord_from_f64(lerp(p[1], q[1], t)));
+    }
+
+    let source = if t >= 0.5 { b } else { a };
+    Loop {
+        id: a.id,
+        dir: a.dir,
+        points: Cow::Owned(coords),
+        params: if same {
+            source.params.to_owned_params()
+        } else {
+            Default::default()
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clitype::LongCLI;
+
+    fn square(x: f32, size: f32, points: usize) -> Loop<'static, LongCLI> {
+        let corners = [[x, 0.0], [x + size, 0.0], [x + size, size], [x, size]];
+        let mut coords = Vec::new();
+        for i in 0..=points {
+            let k = i * 4 / points;
+            let c = corners[k % 4];
+            coords.extend_from_slice(&c);
+        }
+        Loop {
+            id: 1,
+            dir: 1,
+            points: Cow::Owned(coords),
+            params: Default::default(),
+        }
+    }
+
+    fn model(loops: Vec<Loop<'static, LongCLI>>, units: f64) -> CLI<'static, LongCLI> {
+        CLI {
+            header: Header {
+                units,
+                ..Header::default()
+            },
+            layers: vec![Layer {
+                loops,
+                ..Layer::empty((0.1 / units) as f32)
+            }],
+            verbatim: Default::default(),
+        }
+    }
+
+    #[test]
+    fn matching() {
+        let a = model(vec![square(0.0, 10.0, 4), square(50.0, 10.0, 4)], 1.0);
+        let b = model(vec![square(51.0, 10.0, 4), square(1.0, 10.0, 4)], 1.0);
+        let morph = Morph::new(&a, &b);
+        assert_eq!(morph.layers()[0].loops, vec![(0, 1), (1, 0)]);
+
+        let half = morph.at(0.5);
+        let first = half.layers[0].loops[0].points();
+        assert_eq!(&first[..4], &[0.5, 0.0, 10.5, 0.0]);
+    }
+
+    #[test]
+    fn different_units() {
+        let a = model(vec![square(0.0, 10.0, 4)], 1.0);
+        let b = model(vec![square(0.0, 200.0, 8)], 0.1);
+        let morph = Morph::new(&a, &b);
+        let end = morph.at(1.0);
+        let points = end.layers[0].loops[0].points();
+        assert_eq!(points.len(), 2 * 9);
+        assert!(points.iter().all(|&c| (0.0..=20.0).contains(&c)));
+        assert!((points[4] - 20.0).abs() < 1e-4);
+    }
+}
@@ -0,0 +1,150 @@
+//! Automatic arrangement of parts on a build plate
+//!
+//! [`BuildPlate::arrange`] chooses a [`Placement`] for every part so that all of them fit on a
+//! plate of a given size. Each part is first rotated so that its
+//! [minimum area rectangle](crate::CLI::min_area_rect) is aligned with the axes, then the
+//! rectangles are packed in rows, tallest first.
+//!
+//! ```
+//! use colain::{BuildPlate, Placement, CLI, clitype::LongCLI};
+//! # let bytes = BuildPlate::<LongCLI>::new(1.0).to_bytes();
+//!
+//! let mut plate = BuildPlate::new(0.001);
+//! for _ in 0..4 {
+//!     plate.place(CLI::<LongCLI>::new(&bytes).unwrap(), Placement::default()).unwrap();
+//! }
+//! plate.arrange(250.0, 250.0, 5.0).expect("parts do not fit");
+//! ```
+
+use crate::clitype::CLIType;
+use crate::{BuildPlate, Placement};
+use std::f64::consts::FRAC_PI_2;
+
+impl<'a, T: CLIType> BuildPlate<'a, T> {
+    /// Place every part on a plate of `width` by `depth` millimeters, starting at the origin.
+    ///
+    /// Parts keep at least `spacing` millimeters from each other and from the edges of the plate.
+    /// The z offset of each placement is kept. Parts without any geometry are left where they are.
+    ///
+    /// On failure the indices of the parts that did not fit are returned, these keep their previous
+    /// placement while all others are arranged.
+    pub fn arrange(&mut self, width: f64, depth: f64, spacing: f64) -> Result<(), Vec<usize>> {
+        // Footprint of every part, turned so the longer side runs along x
+        let mut footprints: Vec<(usize, [f64; 2], f64, [f64; 2])> = self
+            .parts
+            .iter()
+            .enumerate()
+            .filter_map(|(i, (model, _))| {
+                let rect = model.min_area_rect()?;
+                let (size, rotation) = if rect.size[0] >= rect.size[1] {
+                    (rect.size, -rect.angle)
+                } else {
+                    ([rect.size[1], rect.size[0]], FRAC_PI_2 - rect.angle)
+                };
+                Some((i, size, rotation, rect.center))
+            })
+            .collect();
+        footprints.sort_by(|a, b| {
+            b.1[1]
+                .partial_cmp(&a.1[1])
+                .unwrap_or(std::cmp::Ordering::Equal)
+                .then(a.0.cmp(&b.0))
+        });
+
+        let mut unplaced = Vec::new();
+        let (mut x, mut y, mut row) = (spacing, spacing, 0.0f64);
+        for (part, size, rotation, center) in footprints {
+            if x + size[0] + spacing > width && x > spacing {
+                // start a new row
+                x = spacing;
+                y += row + spacing;
+                row = 0.0;
+            }
+            if x + size[0] + spacing > width || y + size[1] + spacing > depth {
+                unplaced.push(part);
+                continue;
+            }
+            let target = [x + size[0] / 2.0, y + size[1] / 2.0];
+            let placement = &mut self.parts[part].1;
+            let turned = Placement {
+                x: 0.0,
+                y: 0.0,
+                rotation,
+                z: placement.z,
+            }
+            .apply(center);
+            *placement = Placement {
+                x: target[0] - turned[0],
+                y: target[1] - turned[1],
+                rotation,
+                z: placement.z,
+            };
+            x += size[0] + spacing;
+            row = row.max(size[1]);
+        }
+
+        if unplaced.is_empty() {
+            Ok(())
+        } else {
+            unplaced.sort_unstable();
+            Err(unplaced)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::clitype::LongCLI;
+    use crate::geom::BoundingBox;
+    use crate::{BuildPlate, Header, Layer, Loop, Placement, CLI};
+    use std::borrow::Cow;
+
+    fn rect(w: f32, h: f32) -> CLI<'static, LongCLI> {
+        CLI {
+            header: Header::default(),
+            layers: vec![Layer {
+                loops: vec![Loop {
+                    id: 1,
+                    dir: 1,
+                    points: Cow::Owned(vec![0.0, 0.0, w, 0.0, w, h, 0.0, h, 0.0, 0.0]),
+                    params: Default::default(),
+                }],
+                ..Layer::empty(1.0)
+            }],
+            verbatim: Default::default(),
+        }
+    }
+
+    #[test]
+    fn pack() {
+        let mut plate = BuildPlate::new(1.0);
+        plate
+            .place(rect(10.0, 40.0), Placement::new(500.0, 500.0))
+            .unwrap();
+        plate.place(rect(30.0, 20.0), Placement::default()).unwrap();
+        plate
+            .place(rect(30.0, 20.0), Placement::default().raised(1.0))
+            .unwrap();
+        assert_eq!(plate.arrange(80.0, 40.0, 2.0), Ok(()));
+
+        let bounds: Vec<BoundingBox> = (0..3)
+            .map(|part| {
+                let points = plate.part(part).unwrap().0.layers[0].loops[0].points();
+                let coords = plate.transform(part, points);
+                BoundingBox::of(coords.chunks(2).map(|p| [p[0] as f64, p[1] as f64])).unwrap()
+            })
+            .collect();
+        for bb in &bounds {
+            assert!(bb.min[0] >= 2.0 - 1e-3 && bb.min[1] >= 2.0 - 1e-3);
+            assert!(bb.max[0] <= 78.0 + 1e-3 && bb.max[1] <= 38.0 + 1e-3);
+        }
+        for (i, a) in bounds.iter().enumerate() {
+            for b in &bounds[i + 1..] {
+                assert!(a.intersection(b).is_none());
+            }
+        }
+        assert_eq!(plate.part(2).unwrap().1.z, 1.0);
+
+        assert_eq!(plate.arrange(50.0, 40.0, 2.0), Err(vec![2]));
+    }
+}
@@ -0,0 +1,257 @@
+//! NumPy `.npz` export
+//!
+//! Researchers working in Python can load slice geometry with a single `numpy.load` call, without
+//! bindings to this crate. [`CLI::write_npz`] writes an uncompressed `.npz` archive containing:
+//!
+//! - `heights`: `float64` array of shape `(layers,)` with the height of every layer,
+//! - `loops_<i>`: `float64` array of shape `(points, 2)` with the points of all loops of layer `i`,
+//! - `loop_offsets_<i>`: `int64` array of shape `(loops + 1,)`, loop `j` of layer `i` being
+//!   `loops_<i>[loop_offsets_<i>[j]:loop_offsets_<i>[j + 1]]`,
+//! - `hatches_<i>`: `float64` array of shape `(vectors, 4)` with the hatch vectors of layer `i` as
+//!   `x0, y0, x1, y1`.
+//!
+//! All values are in millimeters.
+//!
+//! ```python
+//! import numpy as np
+//! model = np.load("model.npz")
+//! heights = model["heights"]
+//! hatches = model["hatches_0"]
+//! ```
+
+use crate::checksum::crc32;
+use crate::clitype::CLIType;
+use crate::CLI;
+use std::convert::TryFrom;
+use std::io::{self, Write};
+
+impl<'a, T: CLIType> CLI<'a, T> {
+    /// Write the model as an `.npz` archive, see the [module documentation](crate::npz)
+    ///
+    /// The archive is not compressed. Fails with [`io::ErrorKind::InvalidInput`] if an array
+    /// or the archive exceeds the 4 GiB limit of the zip format without its zip64 extension.
+    pub fn write_npz<W: Write>(&self, out: W) -> io::Result<()> {
+        let units = self.header.units;
+        let f = |c| T::coord_to_f64(c) * units;
+        let mut archive = Archive::new(out);
+
+        let heights: Vec<f64> = self.layers.iter().map(|l| f(l.height)).collect();
+        archive.add("heights.npy", &npy_f64(&heights, &[heights.len()]))?;
+
+        for (i, layer) in self.layers.iter().enumerate() {
+            let mut points = Vec::new();
+            let mut offsets = vec![0i64];
+            for l in &layer.loops {
+                points.extend(l.points.chunks_exact(2).flat_map(|p| [f(p[0]), f(p[1])]));
+                offsets.push((points.len() / 2) as i64);
+            }
+            let hatches: Vec<f64> = layer
+                .hatches
+                .iter()
+                .flat_map(|h| h.points.chunks_exact(4))
+                .flat_map(|v| [f(v[0]), f(v[1]), f(v[2]), f(v[3])])
+                .collect();
+
+            archive.add(
+                &format!("loops_{}.npy", i),
+                &npy_f64(&points, &[points.len() / 2, 2]),
+            )?;
+            archive.add(
+                &format!("loop_offsets_{}.npy", i),
+                &npy(
+                    "<i8",
+                    &[offsets.len()],
+                    offsets.iter().flat_map(|o| o.to_le_bytes()),
+                ),
+            )?;
+            archive.add(
+                &format!("hatches_{}.npy", i),
+                &npy_f64(&hatches, &[hatches.len() / 4, 4]),
+            )?;
+        }
+        archive.finish()
+    }
+}
+
+fn npy_f64(values: &[f64], shape: &[usize]) -> Vec<u8> {
+    npy("<f8", shape, values.iter().flat_map(|v| v.to_le_bytes()))
+}
+
+/// Encode an array in the `.npy` format, version 1.0
+fn npy(descr: &str, shape: &[usize], data: impl Iterator<Item = u8>) -> Vec<u8> {
+    let shape = match shape {
+        [n] => format!("({},)", n),
+        _ => format!(
+            "({})",
+            shape
+                .iter()
+                .map(|n| n.to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+    };
+    let mut header = format!(
+        "{{'descr': '{}', 'fortran_order': False, 'shape': {}, }}",
+        descr, shape
+    );
+    // Magic, version and header length take 10 bytes, the data starts 64 byte aligned
+    let padding = 63 - (10 + header.len()) % 64;
+    header.push_str(&" ".repeat(padding));
+    header.push('\n');
+
+    let mut out = b"\x93NUMPY\x01\x00".to_vec();
+    out.extend((header.len() as u16).to_le_bytes());
+    out.extend(header.bytes());
+    out.extend(data);
+    out
+}
+
+/// Minimal writer of uncompressed zip archives
+struct Archive<W: Write> {
+    out: W,
+    offset: u64,
+    central: Vec<u8>,
+    entries: u16,
+}
+
+/// DOS date of 1980-01-01, the earliest date a zip entry can have
+const DOS_DATE: u16 = 0x21;
+
+impl<W: Write> Archive<W> {
+    fn new(out: W) -> Self {
+        Archive {
+            out,
+            offset: 0,
+            central: Vec::new(),
+            entries: 0,
+        }
+    }
+
+    fn add(&mut self, name: &str, data: &[u8]) -> io::Result<()> {
+        let size = to_u32(data.len() as u64)?;
+        let offset = to_u32(self.offset)?;
+        self.entries = self
+            .entries
+            .checked_add(1)
+            .ok_or_else(|| too_large("too many arrays"))?;
+        let crc = crc32(data);
+
+        // Fields shared by the local and the central header, from the version needed to extract
+        let mut common = Vec::with_capacity(26);
+        common.extend(20u16.to_le_bytes()); // version 2.0
+        common.extend(0u16.to_le_bytes()); // flags
+        common.extend(0u16.to_le_bytes()); // stored
+        common.extend(0u16.to_le_bytes()); // time
+        common.extend(DOS_DATE.to_le_bytes());
+        common.extend(crc.to_le_bytes());
+        common.extend(size.to_le_bytes()); // compressed
+        common.extend(size.to_le_bytes()); // uncompressed
+        common.extend((name.len() as u16).to_le_bytes());
+        common.extend(0u16.to_le_bytes()); // extra field
+
+        let mut local = 0x04034b50u32.to_le_bytes().to_vec();
+        local.extend(&common);
+        local.extend(name.as_bytes());
+        self.out.write_all(&local)?;
+        self.out.write_all(data)?;
+        self.offset += (local.len() + data.len()) as u64;
+
+        self.central.extend(0x02014b50u32.to_le_bytes());
+        self.central.extend(20u16.to_le_bytes()); // made by
+        self.central.extend(&common);
+        self.central.extend(0u16.to_le_bytes()); // comment
+        self.central.extend(0u16.to_le_bytes()); // disk
+        self.central.extend(0u16.to_le_bytes()); // internal attributes
+        self.central.extend(0u32.to_le_bytes()); // external attributes
+        self.central.extend(offset.to_le_bytes());
+        self.central.extend(name.as_bytes());
+        Ok(())
+    }
+
+    fn finish(mut self) -> io::Result<()> {
+        let offset = to_u32(self.offset)?;
+        let size = to_u32(self.central.len() as u64)?;
+        self.out.write_all(&self.central)?;
+
+        let mut end = 0x06054b50u32.to_le_bytes().to_vec();
+        end.extend(0u16.to_le_bytes()); // disk
+        end.extend(0u16.to_le_bytes()); // disk with the central directory
+        end.extend(self.entries.to_le_bytes());
+        end.extend(self.entries.to_le_bytes());
+        end.extend(size.to_le_bytes());
+        end.extend(offset.to_le_bytes());
+        end.extend(0u16.to_le_bytes()); // comment
+        self.out.write_all(&end)?;
+        self.out.flush()
+    }
+}
+
+fn to_u32(value: u64) -> io::Result<u32> {
+    u32::try_from(value).map_err(|_| too_large("archive exceeds 4 GiB"))
+}
+
+fn too_large(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clitype::ShortCLI;
+    use crate::{Hatches, Header, Layer, Loop};
+    use std::borrow::Cow;
+
+    #[test]
+    fn npy_header() {
+        let array = npy_f64(&[1.0, 2.0], &[2]);
+        assert_eq!(&array[..8], b"\x93NUMPY\x01\x00");
+        assert_eq!(array.len(), 128 + 16);
+        let header = std::str::from_utf8(&array[10..128]).unwrap();
+        assert!(header.starts_with("{'descr': '<f8', 'fortran_order': False, 'shape': (2,), }"));
+        assert!(header.ends_with(" \n"));
+        assert_eq!(&array[128..136], &1f64.to_le_bytes());
+    }
+
+    #[test]
+    fn archive() {
+        let model = CLI::<ShortCLI> {
+            header: Header {
+                units: 0.5,
+                ..Header::default()
+            },
+            layers: vec![Layer {
+                loops: vec![Loop {
+                    id: 0,
+                    dir: 1,
+                    points: Cow::Owned(vec![0, 0, 4, 0, 4, 4]),
+                    params: Default::default(),
+                }],
+                hatches: vec![Hatches {
+                    id: 0,
+                    points: Cow::Owned(vec![1, 1, 3, 1]),
+                    params: Default::default(),
+                }],
+                ..Layer::empty(1)
+            }],
+            verbatim: Default::default(),
+        };
+        let mut out = Vec::new();
+        model.write_npz(&mut out).unwrap();
+
+        let end = &out[out.len() - 22..];
+        assert_eq!(&end[..4], &0x06054b50u32.to_le_bytes());
+        assert_eq!(u16::from_le_bytes([end[8], end[9]]), 4);
+        let central = u32::from_le_bytes([end[16], end[17], end[18], end[19]]) as usize;
+        assert_eq!(&out[central..central + 4], &0x02014b50u32.to_le_bytes());
+
+        // First entry holds the heights
+        assert_eq!(&out[..4], &0x04034b50u32.to_le_bytes());
+        assert_eq!(&out[30..41], b"heights.npy");
+        let data = &out[41..41 + 136];
+        assert_eq!(
+            u32::from_le_bytes([out[14], out[15], out[16], out[17]]),
+            crc32(data)
+        );
+        assert_eq!(&data[128..], &0.5f64.to_le_bytes());
+    }
+}
@@ -0,0 +1,142 @@
+//! Configuration of the parser
+//!
+//! Files written by real machines and slicers do not always follow the spec to the letter.
+//! [`ParserOptions`] selects which of these deviations are tolerated. Pass them to
+//! [`CLI::new_with_options`](crate::CLI::new_with_options) or to a parser with
+//! [`Parser::options`](crate::Parser::options), the ones that were found are reported as
+//! [`Warning`](crate::Warning)s.
+//!
+//! ```
+//! use colain::{CLI, Dialect, ParserOptions, clitype::LongCLI};
+//! # let buf = colain::BuildPlate::<LongCLI>::new(1.0).to_bytes();
+//!
+//! let options = ParserOptions {
+//!     dialect: Dialect::ZeroPadded,
+//!     ..Default::default()
+//! };
+//! let model = CLI::<LongCLI>::new_with_options(&buf, &options).unwrap();
+//! ```
+
+/// Known variations of the binary CLI format
+///
+/// Each non-standard dialect describes the quirk it tolerates rather than a particular
+/// vendor, since the same quirk is usually shared by several machines.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Dialect {
+    /// Follow the spec exactly
+    #[default]
+    Standard,
+    /// Zero command words between records are treated as padding and skipped, as is any run of
+    /// zeros at the end of the file. Writers that pad records or the file to a fixed block size
+    /// produce these.
+    ZeroPadded,
+    /// Header keys are matched case insensitively and may contain whitespace, so that lines such
+    /// as `$$Units / 0.01` are understood.
+    RelaxedHeader,
+    /// The polyline commands are swapped: 129 is read as the long polyline and 130 as the short one.
+    SwappedPolylineCodes,
+}
+
+impl Dialect {
+    /// True if zero words are padding
+    pub(crate) fn zero_padding(self) -> bool {
+        self == Dialect::ZeroPadded
+    }
+
+    /// True if header keys should be normalized before matching
+    pub(crate) fn relaxed_header(self) -> bool {
+        self == Dialect::RelaxedHeader
+    }
+
+    /// True if the geometry section is encoded as the spec says, so its records can be copied
+    /// into files written by this crate
+    pub(crate) fn standard_geometry(self) -> bool {
+        matches!(self, Dialect::Standard | Dialect::RelaxedHeader)
+    }
+
+    /// Map a command word as written in the file to the standard command
+    pub(crate) fn command(self, cmd: u16) -> u16 {
+        match (self, cmd) {
+            (Dialect::SwappedPolylineCodes, 129) => 130,
+            (Dialect::SwappedPolylineCodes, 130) => 129,
+            _ => cmd,
+        }
+    }
+}
+
+/// Options controlling how a file is parsed
+///
+/// See [`CLI::new_with_options`](crate::CLI::new_with_options) and
+/// [`Parser::options`](crate::Parser::options). The defaults match [`CLI::new`](crate::CLI::new).
+#[derive(Debug, Clone, Default)]
+pub struct ParserOptions {
+    /// Deviations from the spec that are tolerated
+    pub dialect: Dialect,
+    /// Stop at the first invalid record and keep everything before it instead of failing, see
+    /// [`Parser::lenient`](crate::Parser::lenient)
+    pub lenient: bool,
+    /// Skip records with unknown commands, see [`Parser::skip_unknown`](crate::Parser::skip_unknown)
+    pub skip_unknown: bool,
+    /// Skip invalid records and continue with the next valid one, see
+    /// [`Parser::recover`](crate::Parser::recover)
+    pub recover: bool,
+    /// Fail with [`Error::LayerOrder`](crate::Error::LayerOrder) if a layer is below the one
+    /// before it. Otherwise the layers are kept in file order.
+    pub require_ordered_layers: bool,
+    /// Decode the power, speed and user data records defined by this crate, see
+    /// [`Parser::extensions`](crate::Parser::extensions). Otherwise their commands are unknown,
+    /// like any command outside of the spec.
+    pub extensions: bool,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clitype::LongCLI;
+    use crate::{Error, CLI};
+
+    fn options(dialect: Dialect) -> ParserOptions {
+        ParserOptions {
+            dialect,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn relaxed_header() -> Result<(), Error> {
+        let data = b"$$HEADERSTART\n$$binary\n$$Units / 0.5\n$$VERSION/200\n$$HEADEREND";
+        assert!(matches!(
+            CLI::<LongCLI>::new(data),
+            Err(Error::HeaderIncomplete(0))
+        ));
+        let model = CLI::<LongCLI>::new_with_options(data, &options(Dialect::RelaxedHeader))?;
+        assert_eq!(model.header().units, 0.5);
+        Ok(())
+    }
+
+    #[test]
+    fn zero_padding() -> Result<(), Error> {
+        let mut data = b"$$HEADERSTART\n$$BINARY\n$$UNITS/1\n$$VERSION/200\n$$HEADEREND".to_vec();
+        data.extend_from_slice(&[127, 0, 0, 0, 128, 63, 0, 0, 127, 0, 0, 0, 0, 64, 0, 0, 0]);
+        assert!(matches!(
+            CLI::<LongCLI>::new(&data).map_err(Error::into_inner),
+            Err(Error::InvalidGeometryCommand(0))
+        ));
+        let model = CLI::<LongCLI>::new_with_options(&data, &options(Dialect::ZeroPadded))?;
+        assert_eq!(model.iter().count(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn swapped_polylines() -> Result<(), Error> {
+        let mut data = b"$$HEADERSTART\n$$BINARY\n$$UNITS/1\n$$VERSION/200\n$$HEADEREND".to_vec();
+        data.extend_from_slice(&[127, 0, 0, 0, 128, 63]);
+        data.extend_from_slice(&[
+            129, 0, 1, 0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+        ]);
+        let model =
+            CLI::<LongCLI>::new_with_options(&data, &options(Dialect::SwappedPolylineCodes))?;
+        assert_eq!(model.iter().next().unwrap().iter_loops().count(), 1);
+        Ok(())
+    }
+}
@@ -0,0 +1,123 @@
+//! Detection of overhanging geometry
+//!
+//! Material can only be deposited a limited distance beyond the layer below it before the melt
+//! pool sags or curls. [`CLI::overhangs`] compares the solid region of every layer against the
+//! previous one and reports the area that sticks out further than a given distance.
+
+use crate::clitype::CLIType;
+use crate::geom::BoundingBox;
+use crate::raster::{self, Grid};
+use crate::CLI;
+
+/// Unsupported area of a layer, see [`CLI::overhangs`]
+///
+/// Values are in millimeters.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Overhang {
+    /// Index of the layer
+    pub layer: usize,
+    /// Height of the layer
+    pub height: f64,
+    /// Approximate unsupported area in square millimeters
+    pub area: f64,
+    /// Lower left corner of the bounding box of the unsupported area
+    pub min: [f64; 2],
+    /// Upper right corner of the bounding box of the unsupported area
+    pub max: [f64; 2],
+}
+
+impl<'a, T: CLIType> CLI<'a, T> {
+    /// Find solid area that is further than `max_overhang` millimeters from the solid region of
+    /// the layer below.
+    ///
+    /// Layers are compared by rasterizing them with square cells of `resolution` millimeters, so
+    /// areas smaller than a cell may go unreported. The first layer rests on the build plate and
+    /// is never reported. An island that appears without anything below it is entirely
+    /// unsupported. Resolutions that are not positive and finite, or that would take more than 16
+    /// million cells for a layer, are coarsened to stay below that.
+    pub fn overhangs(&self, max_overhang: f64, resolution: f64) -> Vec<Overhang> {
+        let units = self.header.units;
+        let mut found = Vec::new();
+        let mut below = match self.layers.first() {
+            Some(layer) => layer.solid(units),
+            None => return found,
+        };
+
+        for (index, layer) in self.layers.iter().enumerate().skip(1) {
+            let solid = layer.solid(units);
+            let bb = BoundingBox::of(solid.iter().chain(below.iter()).flatten().copied());
+            if let (Some(bb), false) = (bb, solid.is_empty()) {
+                let resolution = raster::resolution(&bb, resolution);
+                let grid = Grid::covering(&bb, resolution);
+                let distance = raster::distance(&grid, &raster::fill(&grid, &below));
+                let mut cells = 0;
+                let mut unsupported: Option<BoundingBox> = None;
+                for (i, inside) in raster::fill(&grid, &solid).into_iter().enumerate() {
+                    if inside && distance[i] > max_overhang {
+                        cells += 1;
+                        let (x, y) = ((i % grid.width) as f64, (i / grid.width) as f64);
+                        let lo = [
+                            grid.origin[0] + x * resolution,
+                            grid.origin[1] + y * resolution,
+                        ];
+                        let hi = [lo[0] + resolution, lo[1] + resolution];
+                        match unsupported.as_mut() {
+                            Some(u) => {
+                                u.add(lo);
+                                u.add(hi);
+                            }
+                            None => unsupported = BoundingBox::of([lo, hi]),
+                        }
+                    }
+                }
+                if let Some(u) = unsupported {
+                    found.push(Overhang {
+                        layer: index,
+                        height: T::coord_to_f64(layer.height) * units,
+                        area: cells as f64 * grid.cell_area(),
+                        min: u.min,
+                        max: u.max,
+                    });
+                }
+            }
+            below = solid;
+        }
+        found
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::clitype::LongCLI;
+    use crate::{Header, Layer, Loop, CLI};
+    use std::borrow::Cow;
+
+    fn layer(height: f32, width: f32) -> Layer<'static, LongCLI> {
+        Layer {
+            loops: vec![Loop {
+                id: 0,
+                dir: 1,
+                points: Cow::Owned(vec![0.0, 0.0, width, 0.0, width, 10.0, 0.0, 10.0, 0.0, 0.0]),
+                params: Default::default(),
+            }],
+            ..Layer::empty(height)
+        }
+    }
+
+    #[test]
+    fn ledge() {
+        let model = CLI {
+            header: Header::default(),
+            layers: vec![layer(0.1, 10.0), layer(0.2, 10.5), layer(0.3, 14.0)],
+            verbatim: Default::default(),
+        };
+        let overhangs = model.overhangs(1.0, 0.1);
+        assert_eq!(overhangs.len(), 1);
+        let o = &overhangs[0];
+        assert_eq!(o.layer, 2);
+        // 14 - 10.5 - 1 = 2.5mm ledge over 10mm
+        assert!((o.area - 25.0).abs() < 1.5, "{}", o.area);
+        assert!((o.min[0] - 11.5).abs() < 0.2);
+        assert!((o.max[0] - 14.0).abs() < 0.2);
+    }
+}
@@ -0,0 +1,308 @@
+//! Conversion to and from OpenVectorFormat
+//!
+//! [OpenVectorFormat](https://github.com/Digital-Production-Aachen/OpenVectorFormat) (OVF) is a
+//! protobuf based interchange format for laser powder bed fusion machines. Enable the `ovf` feature
+//! to use this module.
+//!
+//! Only the part of the OVF schema that has an equivalent in the CLI format is modelled by the
+//! messages in [`proto`]. Every layer becomes a [`WorkPlane`], every loop a
+//! `LineSequence` block and every set of hatches a `Hatches` block. The CLI id of a block is stored
+//! as its marking parameters key. Coordinates and heights are converted to millimeters using the
+//! units of the header. The direction of loops has no equivalent in OVF and is dropped.
+//!
+//! When importing, the direction is reconstructed: open line sequences get direction 2, closed
+//! ones (first point equal to the last point) get 1 when counter-clockwise and 0 when clockwise.
+//! Vector blocks of other types (arcs, point sequences, ...) have no CLI equivalent and are skipped.
+//!
+//! ```
+//! use colain::{CLI, clitype::LongCLI, ovf};
+//! use prost::Message;
+//! # let buf = colain::BuildPlate::<LongCLI>::new(1.0).to_bytes();
+//!
+//! let model = CLI::<LongCLI>::new(&buf).unwrap();
+//! let job = ovf::to_job(&model);
+//! let bytes = job.encode_to_vec();
+//!
+//! // And back, for machines that only accept CLI
+//! let job = ovf::proto::Job::decode(bytes.as_slice()).unwrap();
+//! let cli: Vec<u8> = ovf::from_job::<LongCLI>(&job, 0.001).to_bytes();
+//! ```
+
+use crate::clitype::CLIType;
+use crate::{geom, Header, Layer, Loop, CLI};
+use std::borrow::Cow;
+
+/// OVF messages used by the converter
+///
+/// The field numbers match the OVF schema, fields that are not listed here are skipped when
+/// decoding and never written when encoding.
+pub mod proto {
+    /// A complete build job
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct Job {
+        /// One work plane per layer
+        #[prost(message, repeated, tag = "1")]
+        pub work_planes: Vec<WorkPlane>,
+        /// Number of work planes in the job
+        #[prost(int32, tag = "6")]
+        pub num_work_planes: i32,
+    }
+
+    /// A layer
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct WorkPlane {
+        /// Geometry of the layer
+        #[prost(message, repeated, tag = "1")]
+        pub vector_blocks: Vec<VectorBlock>,
+        /// Height of the layer
+        #[prost(float, tag = "4")]
+        pub z_pos_in_mm: f32,
+        /// Number of vector blocks in the work plane
+        #[prost(int32, tag = "8")]
+        pub num_blocks: i32,
+        /// Index of the work plane in the job
+        #[prost(int32, tag = "10")]
+        pub work_plane_number: i32,
+    }
+
+    /// A block of vectors sharing the same marking parameters
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct VectorBlock {
+        /// The geometry
+        #[prost(oneof = "vector_block::VectorData", tags = "1, 2")]
+        pub vector_data: Option<vector_block::VectorData>,
+        /// Key into the marking parameters of the job
+        #[prost(int32, tag = "50")]
+        pub marking_params_key: i32,
+    }
+
+    /// Geometry types of a [`VectorBlock`]
+    pub mod vector_block {
+        /// Geometry of a [`VectorBlock`](super::VectorBlock)
+        #[derive(Clone, PartialEq, prost::Oneof)]
+        pub enum VectorData {
+            /// Connected polyline
+            #[prost(message, tag = "1")]
+            LineSequence(LineSequence),
+            /// Independent line segments
+            #[prost(message, tag = "2")]
+            Hatches(Hatches),
+        }
+
+        /// Polyline stored as x, y pairs
+        #[derive(Clone, PartialEq, prost::Message)]
+        pub struct LineSequence {
+            /// Interleaved x and y coordinates in millimeters
+            #[prost(float, repeated, tag = "1")]
+            pub points: Vec<f32>,
+        }
+
+        /// Line segments stored as pairs of x, y pairs
+        #[derive(Clone, PartialEq, prost::Message)]
+        pub struct Hatches {
+            /// Interleaved start and end points in millimeters
+            #[prost(float, repeated, tag = "1")]
+            pub points: Vec<f32>,
+        }
+    }
+}
+
+use proto::vector_block::{Hatches, LineSequence, VectorData};
+use proto::{Job, VectorBlock, WorkPlane};
+
+/// Convert a model into an OVF job
+pub fn to_job<T: CLIType>(model: &CLI<'_, T>) -> Job {
+    let units = model.header().units;
+    let mm = |points: &[<T as CLIType>::Coord]| -> Vec<f32> {
+        points
+            .iter()
+            .map(|&c| (T::coord_to_f64(c) * units) as f32)
+            .collect()
+    };
+
+    let work_planes: Vec<WorkPlane> = model
+        .iter()
+        .enumerate()
+        .map(|(i, layer)| {
+            let loops = layer.iter_loops().map(|l| VectorBlock {
+                vector_data: Some(VectorData::LineSequence(LineSequence {
+                    points: mm(l.points()),
+                })),
+                marking_params_key: T::meta_to_i64(l.id()) as i32,
+            });
+            let hatches = layer.iter_hatches().map(|h| VectorBlock {
+                vector_data: Some(VectorData::Hatches(Hatches {
+                    points: mm(h.points()),
+                })),
+                marking_params_key: T::meta_to_i64(h.id()) as i32,
+            });
+            let vector_blocks: Vec<VectorBlock> = loops.chain(hatches).collect();
+            WorkPlane {
+                num_blocks: vector_blocks.len() as i32,
+                vector_blocks,
+                z_pos_in_mm: (T::coord_to_f64(layer.height()) * units) as f32,
+                work_plane_number: i as i32,
+            }
+        })
+        .collect();
+
+    Job {
+        num_work_planes: work_planes.len() as i32,
+        work_planes,
+    }
+}
+
+/// Convert an OVF job into a model
+///
+/// `units` is the number of millimeters each coordinate unit of the model represents.
+/// Work planes are taken in the order they appear in the job.
+pub fn from_job<T: CLIType>(job: &Job, units: f64) -> CLI<'static, T> {
+    let coords = |points: &[f32]| -> Vec<<T as CLIType>::Coord> {
+        points
+            .iter()
+            .map(|&c| T::coord_from_f64(c as f64 / units))
+            .collect()
+    };
+
+    let layers: Vec<Layer<'static, T>> = job
+        .work_planes
+        .iter()
+        .map(|plane| {
+            let mut layer = Layer::empty(T::coord_from_f64(plane.z_pos_in_mm as f64 / units));
+            for block in plane.vector_blocks.iter() {
+                let id = T::meta_from_i64(block.marking_params_key as i64);
+                match &block.vector_data {
+                    Some(VectorData::LineSequence(l)) => layer.push_loop(Loop {
+                        id,
+                        dir: T::meta_from_i64(direction(&l.points)),
+                        points: Cow::Owned(coords(&l.points)),
+                        params: Default::default(),
+                    }),
+                    Some(VectorData::Hatches(h)) => layer.push_hatches(crate::Hatches {
+                        id,
+                        points: Cow::Owned(coords(&h.points)),
+                        params: Default::default(),
+                    }),
+                    None => {}
+                }
+            }
+            layer
+        })
+        .collect();
+
+    CLI {
+        header: Header {
+            units,
+            layers: Some(layers.len()),
+            ..Header::default()
+        },
+        layers,
+        verbatim: Default::default(),
+    }
+}
+
+/// CLI direction of a line sequence
+fn direction(points: &[f32]) -> i64 {
+    let n = points.len();
+    if n < 6 || points[..2] != points[n - 2..] {
+        return 2;
+    }
+    let points = geom::to_points(points, |c| c as f64);
+    if geom::signed_area(&points) >= 0.0 {
+        1
+    } else {
+        0
+    }
+}
+
+impl<T: CLIType> CLI<'_, T> {
+    /// Convert the model into an OVF job, see [`ovf`](crate::ovf)
+    pub fn to_ovf(&self) -> Job {
+        to_job(self)
+    }
+
+    /// Convert an OVF job into a model, see [`from_job`]
+    pub fn from_ovf(job: &Job, units: f64) -> CLI<'static, T> {
+        from_job(job, units)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clitype::ShortCLI;
+    use crate::{writer, Hatches as CliHatches, Header, Layer, Loop};
+    use prost::Message;
+    use std::borrow::Cow;
+
+    #[test]
+    fn export() {
+        let layer = Layer::<ShortCLI> {
+            loops: vec![Loop {
+                id: 4,
+                dir: 1,
+                points: Cow::Owned(vec![0, 0, 10, 0, 10, 10, 0, 0]),
+                params: Default::default(),
+            }],
+            hatches: vec![CliHatches {
+                id: 5,
+                points: Cow::Owned(vec![0, 2, 10, 2]),
+                params: Default::default(),
+            }],
+            ..Layer::empty(20)
+        };
+        let header = Header {
+            units: 0.1,
+            ..Header::default()
+        };
+        let bytes = writer::encode(&header, &[layer]);
+        let job = CLI::<ShortCLI>::new(&bytes).unwrap().to_ovf();
+
+        let job = Job::decode(job.encode_to_vec().as_slice()).unwrap();
+        assert_eq!(job.num_work_planes, 1);
+        let plane = &job.work_planes[0];
+        assert!((plane.z_pos_in_mm - 2.0).abs() < 1e-6);
+        assert_eq!(plane.num_blocks, 2);
+        assert_eq!(plane.vector_blocks[0].marking_params_key, 4);
+        match &plane.vector_blocks[1].vector_data {
+            Some(VectorData::Hatches(h)) => assert_eq!(h.points, vec![0.0, 0.2, 1.0, 0.2]),
+            _ => panic!("expected hatches"),
+        }
+    }
+
+    #[test]
+    fn import() {
+        let plane = |z: f32, points: Vec<f32>| WorkPlane {
+            vector_blocks: vec![VectorBlock {
+                vector_data: Some(VectorData::LineSequence(LineSequence { points })),
+                marking_params_key: 9,
+            }],
+            z_pos_in_mm: z,
+            num_blocks: 1,
+            work_plane_number: 0,
+        };
+        let job = Job {
+            work_planes: vec![
+                plane(0.5, vec![0.0, 0.0, 1.0, 0.0, 1.0, 1.0, 0.0, 0.0]),
+                plane(1.0, vec![0.0, 0.0, 1.0, 1.0, 1.0, 0.0, 0.0, 0.0]),
+                plane(1.5, vec![0.0, 0.0, 1.0, 1.0]),
+            ],
+            num_work_planes: 3,
+        };
+
+        let bytes = CLI::<ShortCLI>::from_ovf(&job, 0.01).to_bytes();
+        let model = CLI::<ShortCLI>::new(&bytes).unwrap();
+        let dirs: Vec<u16> = model
+            .iter()
+            .map(|l| l.iter_loops().next().unwrap().dir())
+            .collect();
+        assert_eq!(dirs, vec![1, 0, 2]);
+        let layer = model.iter().nth(1).unwrap();
+        assert_eq!(layer.height(), 100);
+        assert_eq!(layer.iter_loops().next().unwrap().id(), 9);
+        assert_eq!(
+            layer.iter_loops().next().unwrap().points(),
+            &[0, 0, 100, 100, 100, 0, 0, 0]
+        );
+    }
+}
@@ -0,0 +1,207 @@
+//! Parsing on several threads
+//!
+//! Once it is known where each layer starts, the layers of a binary file can be decoded
+//! independently. [`CLI::new_parallel`] first indexes the geometry section, see
+//! [`index`](crate::index), which is much cheaper than decoding it, and then decodes the layers
+//! on the [rayon](https://docs.rs/rayon) thread pool. The result is the same model as
+//! [`CLI::new`] would give.
+//!
+//! ```
+//! use colain::{clitype::LongCLI, CLI};
+//! # let buf = colain::BuildPlate::<LongCLI>::new(1.0).to_bytes();
+//!
+//! let model = CLI::<LongCLI>::new_parallel(&buf).unwrap();
+//! ```
+//!
+//! The layers of a model can also be processed in parallel: [`CLI::par_iter`] and the
+//! [`IntoParallelIterator`] implementations for `&CLI` and `&Layer` give rayon iterators over the
+//! layers of a model and the loops of a layer.
+//!
+//! ```
+//! use colain::{clitype::LongCLI, CLI};
+//! use rayon::prelude::*;
+//! # let buf = colain::BuildPlate::<LongCLI>::new(1.0).to_bytes();
+//!
+//! let model = CLI::<LongCLI>::new(&buf).unwrap();
+//! let area: f64 = model
+//!     .par_iter()
+//!     .map(|layer| layer.par_iter_loops().map(|l| l.signed_area()).sum::<f64>())
+//!     .sum();
+//! ```
+
+use crate::clitype::CLIType;
+use crate::index::layer_spans;
+use crate::verbatim::LayerSource;
+use crate::{Error, Hatches, Layer, Loop, Parser, ParserOptions, Verbatim, CLI};
+use rayon::prelude::*;
+use rayon::slice::{Iter, IterMut};
+use std::borrow::Cow;
+
+impl<'a, T: CLIType> CLI<'a, T>
+where
+    Layer<'a, T>: Send,
+{
+    /// Same as [`CLI::new`] but decoding the layers in parallel, see the
+    /// [module documentation](self)
+    pub fn new_parallel(raw: &'a [u8]) -> Result<Self, Error> {
+        CLI::new_parallel_with_options(raw, &ParserOptions::default())
+    }
+
+    /// Same as [`CLI::new_parallel`] but with control over which deviations from the spec are
+    /// tolerated.
+    ///
+    /// Only the [`Dialect`](crate::Dialect) of the options applies, parsing always fails on the
+    /// first invalid record. Use [`Parser`] to recover from invalid records.
+    pub fn new_parallel_with_options(
+        raw: &'a [u8],
+        options: &ParserOptions,
+    ) -> Result<Self, Error> {
+        let (header, text, geom) = Parser::<T>::default().options(options).split(raw)?;
+        let aligned = header.aligned;
+        let spans = layer_spans::<T>(geom, aligned, options)?;
+        let layers = spans
+            .par_iter()
+            .map(|span| {
+                let mut layer = CLI::decode_span(&header, geom, span, options)?;
+                if options.dialect.standard_geometry() {
+                    layer.source = Some(LayerSource {
+                        bytes: &geom[span.range.clone()],
+                        aligned,
+                    });
+                }
+                Ok(layer)
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+        Ok(CLI {
+            header,
+            layers,
+            verbatim: Verbatim {
+                header: text.map(Cow::Borrowed),
+                ..Default::default()
+            },
+        })
+    }
+}
+
+impl<'a, T: CLIType> CLI<'a, T>
+where
+    Layer<'a, T>: Sync,
+{
+    /// Parallel iterator over the layers, see [`CLI::iter`]
+    pub fn par_iter(&self) -> Iter<'_, Layer<'a, T>> {
+        self.layers.par_iter()
+    }
+}
+
+impl<'a, T: CLIType> CLI<'a, T>
+where
+    Layer<'a, T>: Send,
+{
+    /// Parallel iterator over the layers that allows modifying them, see [`CLI::iter_mut`]
+    pub fn par_iter_mut(&mut self) -> IterMut<'_, Layer<'a, T>> {
+        self.layers.par_iter_mut()
+    }
+}
+
+impl<'a, T: CLIType> Layer<'a, T> {
+    /// Parallel iterator over the loops, see [`Layer::iter_loops`]
+    pub fn par_iter_loops(&self) -> Iter<'_, Loop<'a, T>>
+    where
+        Loop<'a, T>: Sync,
+    {
+        self.loops.par_iter()
+    }
+
+    /// Parallel iterator over the hatches, see [`Layer::iter_hatches`]
+    pub fn par_iter_hatches(&self) -> Iter<'_, Hatches<'a, T>>
+    where
+        Hatches<'a, T>: Sync,
+    {
+        self.hatches.par_iter()
+    }
+}
+
+impl<'b, 'a, T: CLIType> IntoParallelIterator for &'b CLI<'a, T>
+where
+    Layer<'a, T>: Sync,
+{
+    type Item = &'b Layer<'a, T>;
+    type Iter = Iter<'b, Layer<'a, T>>;
+
+    fn into_par_iter(self) -> Self::Iter {
+        self.layers.par_iter()
+    }
+}
+
+impl<'b, 'a, T: CLIType> IntoParallelIterator for &'b Layer<'a, T>
+where
+    Loop<'a, T>: Sync,
+{
+    type Item = &'b Loop<'a, T>;
+    type Iter = Iter<'b, Loop<'a, T>>;
+
+    /// Iterate over the loops, see [`Layer::par_iter_loops`]
+    fn into_par_iter(self) -> Self::Iter {
+        self.loops.par_iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clitype::ShortCLI;
+    use crate::CLIBuilder;
+
+    #[test]
+    fn parse() -> Result<(), Error> {
+        let mut builder = CLIBuilder::new().short();
+        for z in 1..=50 {
+            builder = builder
+                .layer(z)
+                .polyline(1, 1, vec![0, 0, z, 0, z, z, 0, 0])
+                .hatches(2, vec![0, 1, z, 1]);
+        }
+        let buf = builder.to_bytes()?;
+        let serial = CLI::<ShortCLI>::new(&buf)?;
+        let parallel = CLI::<ShortCLI>::new_parallel(&buf)?;
+        assert_eq!(parallel.iter().count(), 50);
+        for (a, b) in serial.iter().zip(parallel.iter()) {
+            assert_eq!(a.height, b.height);
+            assert_eq!(a.loops[0].points, b.loops[0].points);
+            assert_eq!(a.hatches[0].points, b.hatches[0].points);
+        }
+        assert_eq!(parallel.to_bytes(), buf);
+
+        assert!(CLI::<ShortCLI>::new_parallel(&buf[..buf.len() - 3]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn iterate() -> Result<(), Error> {
+        let mut builder = CLIBuilder::new().short();
+        for z in 1..=20 {
+            builder = builder
+                .layer(z)
+                .polyline(1, 1, vec![0, 0, z, 0, z, z, 0, z])
+                .polyline(2, 2, vec![0, 0, 1, 1])
+                .hatches(3, vec![0, 1, z, 1]);
+        }
+        let mut model = builder.build()?;
+        let areas: Vec<f64> = model
+            .par_iter()
+            .map(|layer| layer.into_par_iter().map(|l| l.signed_area()).sum())
+            .collect();
+        let expected: Vec<f64> = (1..=20).map(|z| (z * z) as f64).collect();
+        assert_eq!(areas, expected);
+        assert_eq!((&model).into_par_iter().count(), 20);
+        let hatches: usize = model
+            .par_iter()
+            .map(|layer| layer.par_iter_hatches().count())
+            .sum();
+        assert_eq!(hatches, 20);
+
+        model.par_iter_mut().for_each(|layer| layer.hatches.clear());
+        assert!(model.iter().all(|layer| layer.hatches.is_empty()));
+        Ok(())
+    }
+}
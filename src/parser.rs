@@ -0,0 +1,1072 @@
+//! Fluent configuration of the parser
+//!
+//! [`Parser`] brings together everything that controls parsing: the [`CLIType`], the
+//! [`Dialect`], how strictly errors are treated, which layers to keep and limits protecting
+//! against oversized input.
+//!
+//! ```
+//! use colain::{Dialect, Parser};
+//! # let buf = colain::BuildPlate::<colain::clitype::LongCLI>::new(1.0).to_bytes();
+//!
+//! let model = Parser::new()
+//!     .long()
+//!     .dialect(Dialect::ZeroPadded)
+//!     .lenient()
+//!     .limit_points(1_000_000)
+//!     .z_range(0.0..10.0)
+//!     .parse(&buf)
+//!     .unwrap();
+//! ```
+
+use crate::ascii::{self, Record};
+use crate::clitype::{CLIType, LongCLI, ShortCLI};
+use crate::token::{stride, Token, Tokens};
+use crate::unknown::{self, UnknownCommand};
+use crate::verbatim::{LayerSource, RawRecord};
+use crate::warning::{self, Warning};
+use crate::{
+    checksum, trace, Cursor, Dialect, Error, Hatches, Header, Layer, Loop, ParserOptions, Verbatim,
+    CLI,
+};
+use std::borrow::Cow;
+use std::marker::PhantomData;
+use std::ops::{Bound, Range, RangeBounds};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Builder for parsing a file, see the [module documentation](self)
+///
+/// The defaults match [`CLI::new`]: the standard dialect, failing on the first error, keeping all
+/// layers and no limits.
+pub struct Parser<T: CLIType = LongCLI> {
+    pub(crate) options: ParserOptions,
+    verify_checksum: bool,
+    max_points: Option<usize>,
+    max_layers: Option<usize>,
+    z_range: (Bound<f64>, Bound<f64>),
+    vendor_commands: Option<VendorCommands>,
+    _type: PhantomData<T>,
+}
+
+/// Handler of vendor specific commands, see [`Parser::vendor_commands`]
+type VendorCommands = Arc<dyn Fn(u16, &[u8]) -> Option<usize> + Send + Sync>;
+
+impl Parser<LongCLI> {
+    /// A parser with the default configuration, reading [`LongCLI`] files
+    pub fn new() -> Self {
+        Parser::default()
+    }
+}
+
+impl<T: CLIType> Default for Parser<T> {
+    fn default() -> Self {
+        Parser {
+            options: ParserOptions::default(),
+            verify_checksum: false,
+            max_points: None,
+            max_layers: None,
+            z_range: (Bound::Unbounded, Bound::Unbounded),
+            vendor_commands: None,
+            _type: PhantomData,
+        }
+    }
+}
+
+impl<T: CLIType> Clone for Parser<T> {
+    fn clone(&self) -> Self {
+        Parser {
+            options: self.options.clone(),
+            vendor_commands: self.vendor_commands.clone(),
+            ..*self
+        }
+    }
+}
+
+impl<T: CLIType> Parser<T> {
+    fn with_type<U: CLIType>(self) -> Parser<U> {
+        Parser {
+            options: self.options,
+            verify_checksum: self.verify_checksum,
+            max_points: self.max_points,
+            max_layers: self.max_layers,
+            z_range: self.z_range,
+            vendor_commands: self.vendor_commands,
+            _type: PhantomData,
+        }
+    }
+
+    /// Read files with 32 bit coordinates, see [`LongCLI`]
+    pub fn long(self) -> Parser<LongCLI> {
+        self.with_type()
+    }
+
+    /// Read files with 16 bit coordinates, see [`ShortCLI`]
+    pub fn short(self) -> Parser<ShortCLI> {
+        self.with_type()
+    }
+
+    /// Tolerate the deviations from the spec of a [`Dialect`]
+    pub fn dialect(mut self, dialect: Dialect) -> Self {
+        self.options.dialect = dialect;
+        self
+    }
+
+    /// Use the given [`ParserOptions`], replacing the dialect and strictness set so far
+    pub fn options(mut self, options: &ParserOptions) -> Self {
+        self.options = options.clone();
+        self
+    }
+
+    /// Stop at the first invalid record of the geometry section and return everything before it,
+    /// instead of failing.
+    ///
+    /// Useful for files that were truncated or are still being written. Errors in the header and
+    /// exceeded limits still fail. If parsing stopped at an unknown command, the rest of the
+    /// geometry section is kept in [`CLI::unknown_records`] and written back by
+    /// [`CLI::to_bytes`].
+    pub fn lenient(mut self) -> Self {
+        self.options.lenient = true;
+        self
+    }
+
+    /// Skip commands unknown to the parser instead of stopping at them.
+    ///
+    /// The length of an unknown record is not known, it is guessed by looking for the next
+    /// position where known records continue. Every skipped record is listed in
+    /// [`CLI::skipped_commands`], see the [`unknown`] module. If no guess is
+    /// found, the unknown command is treated as if this was not set.
+    ///
+    /// Skipped records stay with the layer they are in and are written back by [`CLI::to_bytes`]
+    /// between the same geometry blocks, unless the model is written with a different
+    /// `$$ALIGN` layout. Records in layers left out by [`Parser::z_range`] are dropped.
+    pub fn skip_unknown(mut self) -> Self {
+        self.options.skip_unknown = true;
+        self
+    }
+
+    /// Read the records of commands unknown to the parser with `handler`.
+    ///
+    /// The handler is called with the command word and the rest of the geometry section after
+    /// it, and returns the number of bytes of parameters it consumed, or `None` if it does not
+    /// know the command either. Parsing continues after the consumed parameters. Such records
+    /// are listed in [`CLI::skipped_commands`] with the length given by the handler, and are
+    /// written back by [`CLI::to_bytes`] like those skipped with [`Parser::skip_unknown`]. Commands
+    /// the handler does not know, or for which it returns more bytes than are left, are treated as
+    /// unknown.
+    ///
+    /// ```
+    /// use colain::{clitype::LongCLI, Parser};
+    /// # let buf = colain::BuildPlate::<LongCLI>::new(1.0).to_bytes();
+    ///
+    /// // A machine writing a 4 byte exposure time after command 200
+    /// let model = Parser::new()
+    ///     .vendor_commands(|command, _| (command == 200).then_some(4))
+    ///     .parse(&buf)
+    ///     .unwrap();
+    /// ```
+    pub fn vendor_commands(
+        mut self,
+        handler: impl Fn(u16, &[u8]) -> Option<usize> + Send + Sync + 'static,
+    ) -> Self {
+        self.vendor_commands = Some(Arc::new(handler));
+        self
+    }
+
+    /// Skip invalid records of the geometry section and continue with the next valid one,
+    /// instead of failing or stopping.
+    ///
+    /// A record with an unknown command or whose parameters cannot be read is skipped up to the
+    /// next position where known records continue, found the same way as with
+    /// [`Parser::skip_unknown`]. A record that can be read but not used, such as geometry
+    /// before the first layer, is skipped as a whole. Every skipped range is reported as a
+    /// [`Warning::Resynchronized`] by [`Parser::parse_with_warnings`], the skipped bytes are not
+    /// written back by [`CLI::to_bytes`]. Unknown commands whose length can be guessed are still
+    /// skipped as such if [`Parser::skip_unknown`] is set. Exceeded limits still fail.
+    pub fn recover(mut self) -> Self {
+        self.options.recover = true;
+        self
+    }
+
+    /// Decode the power, speed and user data records of the [`extension`](crate::extension)
+    /// module.
+    ///
+    /// Their command codes are defined by this crate and not by the spec, so by default they are
+    /// unknown commands like those of any other vendor. Files written from models holding such
+    /// data, including [tags](crate::tags), need this to be read back.
+    pub fn extensions(mut self) -> Self {
+        self.options.extensions = true;
+        self
+    }
+
+    /// Fail on the first invalid record, the default
+    pub fn strict(mut self) -> Self {
+        self.options.lenient = false;
+        self.options.recover = false;
+        self
+    }
+
+    /// Fail with [`Error::LayerOrder`] if a layer is below the one before it, instead of keeping
+    /// the layers in file order
+    pub fn require_ordered_layers(mut self) -> Self {
+        self.options.require_ordered_layers = true;
+        self
+    }
+
+    /// Check the checksum footer of the file, failing with [`Error::ChecksumMismatch`] if it
+    /// does not match and with [`Error::MissingChecksum`] if there is none.
+    ///
+    /// See the [`checksum`] module.
+    pub fn verify_checksum(mut self) -> Self {
+        self.verify_checksum = true;
+        self
+    }
+
+    /// Fail with [`Error::LimitExceeded`] if the kept layers contain more than `points` points,
+    /// counting every loop point and both ends of every hatch
+    pub fn limit_points(mut self, points: usize) -> Self {
+        self.max_points = Some(points);
+        self
+    }
+
+    /// Fail with [`Error::LimitExceeded`] if more than `layers` layers are kept
+    pub fn limit_layers(mut self, layers: usize) -> Self {
+        self.max_layers = Some(layers);
+        self
+    }
+
+    /// Only keep layers with a height within `range`, given in millimeters.
+    ///
+    /// The records of other layers are checked but not decoded.
+    pub fn z_range<R: RangeBounds<f64>>(mut self, range: R) -> Self {
+        self.z_range = (range.start_bound().cloned(), range.end_bound().cloned());
+        self
+    }
+
+    /// Parse a file
+    pub fn parse<'a>(&self, raw: &'a [u8]) -> Result<CLI<'a, T>, Error> {
+        self.parse_with_metrics(raw).map(|(model, _)| model)
+    }
+
+    /// Parse a file and report what it took, see [`ParseMetrics`]
+    pub fn parse_with_metrics<'a>(
+        &self,
+        raw: &'a [u8],
+    ) -> Result<(CLI<'a, T>, ParseMetrics), Error> {
+        self.parse_checked(raw, &mut Vec::new())
+    }
+
+    /// Parse a file and report everything that was tolerated or looks suspicious, see
+    /// [`Warning`]
+    pub fn parse_with_warnings<'a>(
+        &self,
+        raw: &'a [u8],
+    ) -> Result<(CLI<'a, T>, Vec<Warning>), Error> {
+        let mut warnings = Vec::new();
+        let (model, _) = self.parse_checked(raw, &mut warnings)?;
+        Ok((model, warnings))
+    }
+
+    /// Parse a file, then check the order and number of its layers
+    fn parse_checked<'a>(
+        &self,
+        raw: &'a [u8],
+        warnings: &mut Vec<Warning>,
+    ) -> Result<(CLI<'a, T>, ParseMetrics), Error> {
+        let (model, metrics) = self.parse_geometry(raw, warnings)?;
+        for index in warning::unordered_layers(&model.layers) {
+            if self.options.require_ordered_layers {
+                return Err(Error::LayerOrder);
+            }
+            warnings.push(Warning::UnorderedLayer { index });
+        }
+        let filtered = self.z_range != (Bound::Unbounded, Bound::Unbounded);
+        match model.header.layers {
+            Some(declared) if !filtered && declared != model.layers.len() => {
+                warnings.push(Warning::LayerCount {
+                    declared,
+                    found: model.layers.len(),
+                });
+            }
+            _ => {}
+        }
+        Ok((model, metrics))
+    }
+
+    /// Parse the header and geometry section of a file, binary or ASCII
+    fn parse_geometry<'a>(
+        &self,
+        raw: &'a [u8],
+        warnings: &mut Vec<Warning>,
+    ) -> Result<(CLI<'a, T>, ParseMetrics), Error> {
+        let _span = trace::parse_span(raw.len());
+        let start = Instant::now();
+        let (header, text, geom) = CLI::<T>::split_any(raw, &self.options)?;
+        if !header.binary {
+            return self.parse_ascii(raw, header, text, geom, start, warnings);
+        }
+        let geom = checksum::strip_footer::<T>(raw, geom, header.aligned, self.verify_checksum)?;
+        let mut metrics = ParseMetrics {
+            bytes: raw.len(),
+            header_bytes: geom.as_ptr() as usize - raw.as_ptr() as usize,
+            geometry_bytes: geom.len(),
+            header_time: start.elapsed(),
+            ..Default::default()
+        };
+        let start = Instant::now();
+        let mut model = CLI {
+            header,
+            layers: Vec::new(),
+            verbatim: Verbatim {
+                header: text.map(Cow::Borrowed),
+                ..Default::default()
+            },
+        };
+
+        let _span = trace::geometry_span(geom.len());
+        let timer = trace::Timer::start();
+        let mut cursor = Cursor::default();
+        let (aligned, units) = (model.header.aligned, model.header.units);
+        // Records decoded into each layer
+        let mut spans: Vec<Range<usize>> = Vec::new();
+        // Offsets of the geometry blocks and the first block of each layer, to place skipped
+        // records in between them
+        let mut blocks = Vec::new();
+        let mut first_block = Vec::new();
+        let place = self.options.skip_unknown || self.vendor_commands.is_some();
+        let base = metrics.header_bytes;
+        let walked = self.walk(geom, base, aligned, units, |token| {
+            if token.command == T::CMD_LAYER {
+                if let Some(index) = cursor.layer {
+                    trace::layer_decoded(index, &model.layers[index]);
+                }
+            }
+            model.decode(&mut cursor, token)?;
+            if token.command == T::CMD_LAYER {
+                spans.push(token.offset..token.end);
+                first_block.push(blocks.len());
+            } else if let Some(span) = spans.last_mut() {
+                span.end = token.end;
+            }
+            if place && (token.command == T::CMD_PLINE || token.command == T::CMD_HATCH) {
+                blocks.push(token.offset);
+            }
+
+            let layer = cursor.layer.map(|l| &model.layers[l]);
+            Ok(match layer {
+                Some(l) if token.command == T::CMD_PLINE => {
+                    l.loops.last().unwrap().points.len() / 2
+                }
+                Some(l) if token.command == T::CMD_HATCH => {
+                    l.hatches.last().unwrap().points.len() / 2
+                }
+                _ => 0,
+            })
+        })?;
+        if let Some(index) = cursor.layer {
+            trace::layer_decoded(index, &model.layers[index]);
+        }
+        trace::geometry_decoded(&model.layers, walked.records, &timer);
+        if let Some(offset) = walked.unknown {
+            model.verbatim.unknown = Cow::Borrowed(&geom[offset..]);
+        }
+        // Skipped records are kept with the layer they are in, at their position between its blocks
+        for &i in walked.kept.iter() {
+            let u = walked.skipped[i];
+            let range = u.offset..u.offset + stride(2, aligned) + u.length;
+            let record = |position| RawRecord {
+                position,
+                bytes: Cow::Borrowed(&geom[range.clone()]),
+                aligned,
+            };
+            match spans.partition_point(|s| s.start < u.offset).checked_sub(1) {
+                Some(l) => {
+                    let position = blocks.partition_point(|&b| b < u.offset) - first_block[l];
+                    model.layers[l].raw.push(record(position));
+                    spans[l].end = spans[l].end.max(range.end);
+                }
+                None => model.verbatim.leading.push(record(0)),
+            }
+        }
+        for (layer, span) in model.layers.iter_mut().zip(spans) {
+            // Records skipped by recovering are not written back, so these layers must be
+            // encoded again
+            let resynced = walked.resynced.iter().any(|r| span.contains(&r.start));
+            if self.options.dialect.standard_geometry() && !resynced {
+                layer.source = Some(LayerSource {
+                    bytes: &geom[span],
+                    aligned,
+                });
+            }
+        }
+        warnings.extend(walked.skipped.iter().copied().map(Warning::SkippedCommand));
+        warnings.extend(walked.resynced.into_iter().map(Warning::Resynchronized));
+        warnings.extend(walked.stopped.map(|offset| Warning::Stopped { offset }));
+        model.verbatim.skipped = walked.skipped;
+
+        metrics.geometry_time = start.elapsed();
+        metrics.records = walked.records;
+        metrics.count(&model.layers);
+        Ok((model, metrics))
+    }
+
+    /// Parse a file whose header declares an ASCII geometry section.
+    ///
+    /// The records are decoded into owned layers with the same filters, limits and strictness as
+    /// binary files. Unknown commands are skipped if [`Parser::skip_unknown`] is set, but not
+    /// listed in [`CLI::skipped_commands`] since they have no binary encoding. They are reported
+    /// as [`Warning::SkippedAsciiCommand`] instead.
+    fn parse_ascii<'a>(
+        &self,
+        raw: &'a [u8],
+        header: Header,
+        text: Option<&'a str>,
+        geom: &'a [u8],
+        start: Instant,
+        warnings: &mut Vec<Warning>,
+    ) -> Result<(CLI<'a, T>, ParseMetrics), Error> {
+        let mut metrics = ParseMetrics {
+            bytes: raw.len(),
+            header_bytes: raw.len() - geom.len(),
+            geometry_bytes: geom.len(),
+            header_time: start.elapsed(),
+            ..Default::default()
+        };
+        let start = Instant::now();
+        let mut model = CLI {
+            header,
+            layers: Vec::new(),
+            verbatim: Verbatim {
+                header: text.map(Cow::Borrowed),
+                ..Default::default()
+            },
+        };
+
+        let _span = trace::geometry_span(geom.len());
+        let timer = trace::Timer::start();
+        let units = model.header.units;
+        let coords =
+            |values: Vec<f64>| Cow::Owned(values.into_iter().map(T::coord_from_f64).collect());
+        let (mut skipping, mut points) = (false, 0);
+        let header_text = &raw[..raw.len() - geom.len()];
+        let mut line = header_text.iter().filter(|&&b| b == b'\n').count() + 1;
+        for record in ascii::Records::new(geom, line, self.options.dialect)? {
+            let decoded = record.and_then(|(start, record)| {
+                line = start;
+                let layer = match record {
+                    Record::Layer(z) => {
+                        skipping = !self.z_range.contains(&(z * units));
+                        if !skipping {
+                            if let Some(last) = model.layers.last() {
+                                trace::layer_decoded(model.layers.len() - 1, last);
+                            }
+                            if matches!(self.max_layers, Some(n) if model.layers.len() >= n) {
+                                return Err(Error::LimitExceeded);
+                            }
+                            model.layers.push(Layer::empty(T::coord_from_f64(z)));
+                        }
+                        return Ok(());
+                    }
+                    Record::Unknown(command) if self.options.skip_unknown => {
+                        warnings.push(Warning::SkippedAsciiCommand { line, command });
+                        return Ok(());
+                    }
+                    Record::Unknown(_) => return Err(Error::InvalidAsciiGeometry(line)),
+                    _ if skipping => return Ok(()),
+                    _ => model.layers.last_mut().ok_or(Error::ElementOutsideLayer)?,
+                };
+                match record {
+                    Record::Polyline { id, dir, points: p } => {
+                        points += p.len() / 2;
+                        layer.push_loop(Loop {
+                            id: T::meta_from_i64(id),
+                            dir: T::meta_from_i64(dir),
+                            points: coords(p),
+                            params: Default::default(),
+                        });
+                    }
+                    Record::Hatches { id, points: p } => {
+                        points += p.len() / 2;
+                        layer.push_hatches(Hatches {
+                            id: T::meta_from_i64(id),
+                            points: coords(p),
+                            params: Default::default(),
+                        });
+                    }
+                    _ => unreachable!(),
+                }
+                if matches!(self.max_points, Some(n) if points > n) {
+                    return Err(Error::LimitExceeded);
+                }
+                Ok(())
+            });
+            match decoded {
+                Ok(()) => metrics.records += 1,
+                Err(e) => {
+                    trace::geometry_rejected(&e, metrics.records);
+                    if self.options.lenient && !matches!(e, Error::LimitExceeded) {
+                        if let Error::InvalidAsciiGeometry(l) = e {
+                            line = l;
+                        }
+                        warnings.push(Warning::StoppedAtLine { line });
+                        break;
+                    }
+                    return Err(e);
+                }
+            }
+        }
+        if let Some(last) = model.layers.last() {
+            trace::layer_decoded(model.layers.len() - 1, last);
+        }
+        trace::geometry_decoded(&model.layers, metrics.records, &timer);
+
+        metrics.geometry_time = start.elapsed();
+        metrics.count(&model.layers);
+        Ok((model, metrics))
+    }
+
+    /// Parse a buffer holding several complete files one after the other.
+    ///
+    /// Each file ends where a record of its geometry section cannot be read and a new
+    /// `$$HEADERSTART` follows, optionally after whitespace that is dropped. Every file is parsed
+    /// on its own with this configuration. A buffer with a single file gives a single model.
+    pub fn parse_all<'a>(&self, raw: &'a [u8]) -> Result<Vec<CLI<'a, T>>, Error> {
+        let mut models = Vec::new();
+        let mut rest = raw;
+        loop {
+            let (header, _, geom) = CLI::<T>::split(rest, &self.options)?;
+            let mut tokens = Tokens::<T>::with_options(geom, header.aligned, &self.options);
+            // End of this file and start of the next one within the geometry section
+            let (mut end, mut next) = (geom.len(), geom.len());
+            while let Some(token) = tokens.next() {
+                if token.is_err() {
+                    let position = tokens.position();
+                    let skip = geom[position..]
+                        .iter()
+                        .take_while(|b| b.is_ascii_whitespace())
+                        .count();
+                    if geom[position + skip..].starts_with(b"$$HEADERSTART") {
+                        end = position;
+                        next = position + skip;
+                    }
+                    break;
+                }
+            }
+            let offset = geom.as_ptr() as usize - rest.as_ptr() as usize;
+            models.push(self.parse(&rest[..offset + end])?);
+            rest = &rest[offset + next..];
+            if rest.is_empty() {
+                return Ok(models);
+            }
+        }
+    }
+
+    /// Split off the header of a file, see [`CLI::split`], and the checksum footer
+    pub(crate) fn split<'a>(
+        &self,
+        raw: &'a [u8],
+    ) -> Result<(Header, Option<&'a str>, &'a [u8]), Error> {
+        let (header, text, geom) = CLI::<T>::split(raw, &self.options)?;
+        let geom = checksum::strip_footer::<T>(raw, geom, header.aligned, self.verify_checksum)?;
+        Ok((header, text, geom))
+    }
+
+    /// Pass the records of a geometry section to `decode`, applying the filters, limits and
+    /// strictness of the parser.
+    ///
+    /// `decode` returns the number of points in the record. Errors are returned as
+    /// [`Error::Located`], with offsets counted from `base`, the offset of the geometry section
+    /// in the file.
+    pub(crate) fn walk<'a>(
+        &self,
+        geom: &'a [u8],
+        base: usize,
+        aligned: bool,
+        units: f64,
+        mut decode: impl FnMut(Token<'a>) -> Result<usize, Error>,
+    ) -> Result<Walked, Error> {
+        let mut next = 0;
+        let mut unknown = None;
+        let mut stopped = None;
+        let mut skipped = Vec::new();
+        let mut kept = Vec::new();
+        let mut resynced = Vec::new();
+        let mut skipping = false;
+        let mut layers = 0;
+        let mut points = 0;
+        let mut records = 0;
+        // Index of the current layer in the file and of the last record within it
+        let (mut layer, mut element) = (None, None);
+        let mut tokens = Tokens::<T>::with_options(geom, aligned, &self.options);
+        while let Some(token) = tokens.next() {
+            // Start of the record, or of an invalid command word
+            let offset = token.as_ref().map_or(tokens.position(), |t| t.offset);
+            let well_formed = token.is_ok();
+            if matches!(&token, Ok(t) if t.command == T::CMD_LAYER) {
+                layer = Some(layer.map_or(0, |l| l + 1));
+                element = None;
+            } else {
+                element = Some(element.map_or(0, |e| e + 1));
+            }
+            let decoded = token.and_then(|token| {
+                next = token.end;
+                if token.command == T::CMD_LAYER {
+                    let mut payload = token.payload;
+                    let z = T::coord_to_f64(T::get_coord(&mut payload, aligned)) * units;
+                    skipping = !self.z_range.contains(&z);
+                    if !skipping {
+                        layers += 1;
+                        if matches!(self.max_layers, Some(n) if layers > n) {
+                            return Err(Error::LimitExceeded);
+                        }
+                    }
+                }
+                if skipping {
+                    return Ok(());
+                }
+                points += decode(token)?;
+                if matches!(self.max_points, Some(n) if points > n) {
+                    return Err(Error::LimitExceeded);
+                }
+                Ok(())
+            });
+            let e = match decoded {
+                Ok(()) => {
+                    records += 1;
+                    continue;
+                }
+                Err(e) => e,
+            };
+            let options = &self.options;
+            if let Error::InvalidGeometryCommand(command) = e {
+                let params = geom.get(offset + stride(2, aligned)..).unwrap_or_default();
+                let handled = self.vendor_commands.as_ref().and_then(|handle| {
+                    handle(command, params).filter(|&length| length <= params.len())
+                });
+                let length = handled.or_else(|| {
+                    let skip = self.options.skip_unknown;
+                    skip.then(|| unknown::guess_length::<T>(geom, offset, aligned, options))
+                        .flatten()
+                });
+                if let Some(length) = length {
+                    if !skipping {
+                        kept.push(skipped.len());
+                    }
+                    skipped.push(UnknownCommand {
+                        command,
+                        offset,
+                        length,
+                    });
+                    next = offset + stride(2, aligned) + length;
+                    tokens.resume(next);
+                    continue;
+                }
+            }
+            trace::geometry_rejected(&e, records);
+            if self.options.recover && !matches!(e, Error::LimitExceeded) {
+                // A well-formed record is skipped as a whole, otherwise its length is unknown
+                next = if well_formed {
+                    next
+                } else {
+                    unknown::resync::<T>(geom, offset + stride(2, aligned), aligned, options)
+                };
+                resynced.push(offset..next);
+                tokens.resume(next);
+                continue;
+            }
+            if self.options.lenient && !matches!(e, Error::LimitExceeded) {
+                if let Error::InvalidGeometryCommand(_) = e {
+                    unknown = Some(next);
+                }
+                stopped = Some(offset);
+                break;
+            }
+            return Err(e.located(base + offset, layer, element));
+        }
+        Ok(Walked {
+            records,
+            unknown,
+            stopped,
+            skipped,
+            kept,
+            resynced,
+        })
+    }
+}
+
+/// What parsing a file took, see [`Parser::parse_with_metrics`]
+///
+/// Applications embedding the parser can log these to tell whether a slow load was spent reading
+/// the file, parsing its header or decoding the geometry, and how much data it contained.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ParseMetrics {
+    /// Size of the file
+    pub bytes: usize,
+    /// Size of the header section, including anything before the geometry section starts
+    pub header_bytes: usize,
+    /// Size of the geometry section scanned for records, without a checksum footer
+    pub geometry_bytes: usize,
+    /// Time spent parsing and validating the header
+    pub header_time: Duration,
+    /// Time spent decoding the geometry section
+    pub geometry_time: Duration,
+    /// Records read from the geometry section, including skipped ones
+    pub records: usize,
+    /// Layers decoded
+    pub layers: usize,
+    /// Loops decoded
+    pub loops: usize,
+    /// Hatch blocks decoded
+    pub hatches: usize,
+    /// Coordinate buffers that had to be copied into an allocation rather than borrowed from the
+    /// file, because the data was not aligned, the host is big endian or the `safe` feature is
+    /// enabled
+    pub copies: usize,
+}
+
+impl ParseMetrics {
+    /// Count the decoded geometry
+    fn count<T: CLIType>(&mut self, layers: &[Layer<'_, T>]) {
+        self.layers = layers.len();
+        for layer in layers {
+            self.loops += layer.loops.len();
+            self.hatches += layer.hatches.len();
+            let points = layer.loops.iter().map(|l| &l.points);
+            let points = points.chain(layer.hatches.iter().map(|h| &h.points));
+            self.copies += points.filter(|p| matches!(p, Cow::Owned(_))).count();
+        }
+    }
+
+    /// Total time spent parsing
+    pub fn total_time(&self) -> Duration {
+        self.header_time + self.geometry_time
+    }
+}
+
+/// Outcome of [`Parser::walk`]
+pub(crate) struct Walked {
+    /// Number of records that were passed on or skipped
+    pub records: usize,
+    /// Offset of the unknown command a lenient walk stopped at
+    pub unknown: Option<usize>,
+    /// Offset of the invalid record a lenient walk stopped at
+    pub stopped: Option<usize>,
+    /// Unknown commands that were skipped
+    pub skipped: Vec<UnknownCommand>,
+    /// Indices into `skipped` of the commands not within a layer outside of the height range
+    pub kept: Vec<usize>,
+    /// Invalid records skipped by a recovering walk
+    pub resynced: Vec<Range<usize>>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Hatches, Header, Layer, Loop};
+    use std::borrow::Cow;
+
+    fn file() -> Vec<u8> {
+        file_model().to_bytes()
+    }
+
+    fn file_model() -> CLI<'static, ShortCLI> {
+        let layer = |height| Layer::<ShortCLI> {
+            loops: vec![Loop {
+                id: 1,
+                dir: 1,
+                points: Cow::Owned(vec![0, 0, 1, 0, 1, 1, 0, 0]),
+                params: Default::default(),
+            }],
+            hatches: vec![Hatches {
+                id: 1,
+                points: Cow::Owned(vec![0, 0, 1, 1]),
+                params: Default::default(),
+            }],
+            ..Layer::empty(height)
+        };
+        CLI {
+            header: Header {
+                units: 0.5,
+                ..Header::default()
+            },
+            layers: (1..=4).map(layer).collect(),
+            verbatim: Default::default(),
+        }
+    }
+
+    #[test]
+    fn filters() -> Result<(), Error> {
+        let buf = file();
+        let all = Parser::new().short().parse(&buf)?;
+        assert_eq!(all.iter().count(), 4);
+
+        let some = Parser::new().short().z_range(0.75..=1.5).parse(&buf)?;
+        let heights: Vec<u16> = some.iter().map(|l| l.height()).collect();
+        assert_eq!(heights, vec![2, 3]);
+        assert_eq!(some.iter().next().unwrap().iter_loops().count(), 1);
+
+        assert!(matches!(
+            Parser::new().long().parse(&buf).map_err(Error::into_inner),
+            Err(Error::TypeMismatch)
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn metrics() -> Result<(), Error> {
+        let buf = file();
+        let (model, metrics) = Parser::new().short().parse_with_metrics(&buf)?;
+        assert_eq!(model.iter().count(), 4);
+        assert_eq!(metrics.bytes, buf.len());
+        assert_eq!(metrics.header_bytes + metrics.geometry_bytes, buf.len());
+        assert_eq!(
+            (
+                metrics.layers,
+                metrics.loops,
+                metrics.hatches,
+                metrics.records
+            ),
+            (4, 4, 4, 12)
+        );
+        assert!(metrics.copies <= 8);
+        assert!(metrics.total_time() >= metrics.geometry_time);
+        Ok(())
+    }
+
+    #[test]
+    fn limits() -> Result<(), Error> {
+        let buf = file();
+        // 6 points per layer
+        assert!(Parser::new().short().limit_points(24).parse(&buf).is_ok());
+        assert!(matches!(
+            Parser::new()
+                .short()
+                .limit_points(23)
+                .parse(&buf)
+                .map_err(Error::into_inner),
+            Err(Error::LimitExceeded)
+        ));
+        assert!(matches!(
+            Parser::new()
+                .short()
+                .limit_layers(3)
+                .parse(&buf)
+                .map_err(Error::into_inner),
+            Err(Error::LimitExceeded)
+        ));
+        let filtered = Parser::new()
+            .short()
+            .limit_layers(2)
+            .z_range(..=1.0)
+            .parse(&buf)?;
+        assert_eq!(filtered.iter().count(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn lenient() -> Result<(), Error> {
+        let buf = file();
+        let truncated = &buf[..buf.len() - 3];
+        assert!(matches!(
+            Parser::new()
+                .short()
+                .parse(truncated)
+                .map_err(Error::into_inner),
+            Err(Error::UnexpectedEOF)
+        ));
+        let model = Parser::new().short().lenient().parse(truncated)?;
+        assert_eq!(model.iter().count(), 4);
+        assert_eq!(model.iter().last().unwrap().iter_hatches().count(), 0);
+        Ok(())
+    }
+
+    #[test]
+    fn recover() -> Result<(), Error> {
+        let mut buf = file();
+        // Corrupt the command word of the hatches in the second layer
+        let (_, start) = crate::parse_header(&buf)?;
+        let hatches = 42 + 4 + 24;
+        buf[start + hatches + 1] = 3;
+        assert!(Parser::new().short().parse(&buf).is_err());
+        let (model, warnings) = Parser::new().short().recover().parse_with_warnings(&buf)?;
+        assert_eq!(warnings, [Warning::Resynchronized(hatches..hatches + 14)]);
+        let counts: Vec<usize> = model.iter().map(|l| l.iter_hatches().count()).collect();
+        assert_eq!(counts, [1, 0, 1, 1]);
+        let written = model.to_bytes();
+        assert_eq!(written.len(), buf.len() - 14);
+        assert!(Parser::new().short().parse(&written).is_ok());
+        Ok(())
+    }
+
+    #[test]
+    fn vendor_commands() -> Result<(), Error> {
+        let mut model = file_model();
+        model.header.aligned = true;
+        let mut buf = model.to_bytes();
+        // A vendor record with a 4 byte payload, starting with its sequence number
+        let (_, start) = crate::parse_header(&buf)?;
+        let vendor = [0xC8, 0, 0, 0, 7, 0, 0, 0];
+        buf.splice(start..start, vendor);
+        assert!(Parser::new().short().parse(&buf).is_err());
+
+        let seen = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let handler = {
+            let seen = seen.clone();
+            move |command, params: &[u8]| {
+                seen.lock().unwrap().push((command, params[0]));
+                (command == 200).then_some(4)
+            }
+        };
+        let parsed = Parser::new().short().vendor_commands(handler).parse(&buf)?;
+        assert_eq!(parsed.iter().count(), 4);
+        assert_eq!(*seen.lock().unwrap(), [(200, 7)]);
+        assert_eq!(parsed.skipped_commands()[0].length, 4);
+        assert_eq!(parsed.to_bytes(), buf);
+        assert_eq!(parsed.to_owned_model().to_bytes(), buf);
+
+        let unknown = Parser::new()
+            .short()
+            .vendor_commands(|_, _| None)
+            .parse(&buf);
+        assert!(matches!(
+            unknown.map_err(Error::into_inner),
+            Err(Error::InvalidGeometryCommand(200))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn located() -> Result<(), Error> {
+        let buf = file();
+        let (_, start) = crate::parse_header(&buf)?;
+        match Parser::new().short().parse(&buf[..buf.len() - 3]) {
+            Err(Error::Located {
+                offset,
+                layer,
+                element,
+                error,
+            }) => {
+                // 42 bytes per layer, the hatches follow the layer command and the polyline
+                assert_eq!(offset, start + 3 * 42 + 4 + 24);
+                assert_eq!((layer, element), (Some(3), Some(1)));
+                assert!(matches!(*error, Error::UnexpectedEOF));
+            }
+            _ => panic!("expected a located error"),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn ascii() -> Result<(), Error> {
+        let mut text = String::from(
+            "$$HEADERSTART\n$$ASCII\n$$UNITS/0.5\n$$VERSION/200\n$$LAYERS/4\n$$HEADEREND\n\
+            $$GEOMETRYSTART\n",
+        );
+        for z in 1..=4 {
+            text += &format!(
+                "$$LAYER/{}\n$$POLYLINE/1,1,4,0,0,1,0,\n1,1,0,0 // square\n$$HATCHES/1,1,0,0,1,1\n",
+                z
+            );
+        }
+        text += "$$GEOMETRYEND\n";
+
+        let (model, metrics) = Parser::new().short().parse_with_metrics(text.as_bytes())?;
+        assert!(!model.header().binary);
+        assert_eq!(metrics.records, 12);
+        assert_eq!(metrics.copies, 8);
+        // Written back as a binary file
+        assert_eq!(model.to_bytes(), file());
+
+        let filtered = Parser::new()
+            .short()
+            .z_range(1.0..2.0)
+            .parse(text.as_bytes())?;
+        assert_eq!(
+            filtered.iter().map(|l| l.height).collect::<Vec<_>>(),
+            [2, 3]
+        );
+
+        let unknown = text.replace("$$HATCHES", "$$LABEL/1,x\n$$HATCHES");
+        assert!(matches!(
+            Parser::new().short().parse(unknown.as_bytes()),
+            Err(Error::InvalidAsciiGeometry(11))
+        ));
+        let skipped = Parser::new()
+            .short()
+            .skip_unknown()
+            .parse(unknown.as_bytes())?;
+        assert_eq!(skipped.to_bytes(), file());
+        let lenient = Parser::new().short().lenient().parse(unknown.as_bytes())?;
+        assert_eq!(lenient.iter().count(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn concatenated() -> Result<(), Error> {
+        let one = file();
+        let mut buf = one.clone();
+        buf.push(b'\n');
+        buf.extend_from_slice(&one);
+        buf.extend_from_slice(&one);
+
+        let models = Parser::new().short().parse_all(&buf)?;
+        assert_eq!(models.len(), 3);
+        for model in models.iter() {
+            assert_eq!(model.iter().count(), 4);
+            assert_eq!(model.to_bytes(), one);
+        }
+        assert_eq!(CLI::<ShortCLI>::parse_all(&one)?.len(), 1);
+        assert!(Parser::new().short().parse(&buf).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn splice() -> Result<(), Error> {
+        let mut buf = CLI::<ShortCLI> {
+            header: Header {
+                aligned: true,
+                ..Header::default()
+            },
+            ..file_model()
+        }
+        .to_bytes();
+        // The padding of aligned command words is not read, mark it in every layer command
+        let (_, start) = crate::parse_header(&buf)?;
+        let mut layers = Vec::new();
+        for token in CLI::<ShortCLI>::tokens(&buf, &ParserOptions::default())? {
+            let token = token?;
+            if token.command == ShortCLI::CMD_LAYER {
+                layers.push(start + token.offset + 2);
+            }
+        }
+        for &offset in layers.iter() {
+            buf[offset] = 0xAA;
+        }
+
+        let mut model = Parser::new().short().parse(&buf)?;
+        assert_eq!(model.to_bytes(), buf);
+
+        // Only the modified layer is encoded again
+        model.iter_mut().nth(1).unwrap().tag("edited", "yes")?;
+        let written = model.to_bytes();
+        let edited = Parser::new().short().extensions().parse(&written)?;
+        assert_eq!(
+            edited.iter().nth(1).unwrap().tag_value("edited").as_deref(),
+            Some("yes")
+        );
+        assert_eq!(written[layers[0]], 0xAA);
+        assert_eq!(written[layers[1]], 0);
+        let shift = written.len() - buf.len();
+        assert_eq!(written[layers[2] + shift], 0xAA);
+
+        // Copies from a file with another layout are not used
+        let unaligned = CLI {
+            header: Header::default(),
+            layers: model.iter().cloned().collect(),
+            verbatim: Default::default(),
+        };
+        let bytes = unaligned.to_bytes();
+        let parser = Parser::new().short().extensions();
+        assert_eq!(parser.parse(&bytes)?.iter().count(), 4);
+        Ok(())
+    }
+}
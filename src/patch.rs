@@ -0,0 +1,328 @@
+//! Compact patches between revisions of a build file
+//!
+//! A patch describes the revised file as a sequence of runs of layers copied from the base
+//! model and layers included verbatim. Layers are matched by their
+//! [`content_hash`](crate::Layer::content_hash) and confirmed byte for byte, so a revision
+//! touching a few layers of a large build produces a patch of about the size of those layers.
+//!
+//! ```no_run
+//! # use colain::{CLI, clitype::LongCLI};
+//! # let (old, new) = (Vec::new(), Vec::new());
+//! let old = CLI::<LongCLI>::new(&old).unwrap();
+//! let new = CLI::<LongCLI>::new(&new).unwrap();
+//! let patch = old.diff(&new);
+//!
+//! // on the machine, which already has the old file
+//! let revised = old.apply_patch(&patch).unwrap();
+//! ```
+//!
+//! # Format
+//!
+//! All integers are little endian.
+//!
+//! | Field        | Type        | Description                                         |
+//! |--------------|-------------|-----------------------------------------------------|
+//! | magic        | `[u8; 8]`   | `CLIPATCH`                                          |
+//! | version      | `u32`       | `2`                                                 |
+//! | base         | `u64`       | Combined content hash of the layers of the base     |
+//! | flags        | `u8`        | `1` if the revised file is aligned, `0` otherwise   |
+//! | header       | `u32`, data | Length and bytes of the header of the revised file  |
+//! | operations   | `u32`       | Number of operations that follow                    |
+//!
+//! Each operation starts with a kind byte. `0` copies layers of the base and is followed by the
+//! index of the first layer and the number of layers as `u32`. `1` inserts new layers and is
+//! followed by the number of layers, the length of the data as `u32` and the geometry records
+//! of the layers, encoded as in the revised file. The
+//! [unknown records](CLI::unknown_records) of the revised file, if any, follow as a last insert
+//! of no layers.
+
+use crate::clitype::CLIType;
+use crate::{hash, writer, Layer, CLI};
+use bytes::{Buf, BufMut};
+
+const MAGIC: &[u8; 8] = b"CLIPATCH";
+const VERSION: u32 = 2;
+
+const COPY: u8 = 0;
+const INSERT: u8 = 1;
+
+/// Errors when applying a patch
+#[derive(Debug, Clone, PartialEq)]
+pub enum PatchError {
+    /// The data does not start with the patch magic
+    NotAPatch,
+    /// The patch was written by a different format version
+    UnsupportedVersion(u32),
+    /// The patch was created against a different base model
+    BaseMismatch,
+    /// The patch ended in the middle of an operation
+    Truncated,
+    /// An unknown operation kind
+    InvalidOperation(u8),
+    /// A copy operation refers to layers beyond the end of the base model
+    LayerOutOfRange,
+}
+
+impl std::fmt::Display for PatchError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+impl std::error::Error for PatchError {}
+
+/// One step of rebuilding the revised layer list
+enum Op {
+    Copy { first: usize, count: usize },
+    Insert { count: usize, data: Vec<u8> },
+}
+
+fn base_hash<T: CLIType>(layers: &[Layer<'_, T>]) -> u64 {
+    hash::combine(layers.iter().map(|l| l.content_hash()))
+}
+
+/// Split `n` bytes off the front of the patch
+fn take<'p>(buf: &mut &'p [u8], n: usize) -> Result<&'p [u8], PatchError> {
+    if buf.len() < n {
+        return Err(PatchError::Truncated);
+    }
+    let (data, rest) = buf.split_at(n);
+    *buf = rest;
+    Ok(data)
+}
+
+fn u32(buf: &mut &[u8]) -> Result<usize, PatchError> {
+    Ok(take(buf, 4)?.get_u32_le() as usize)
+}
+
+impl<'a, T: CLIType> CLI<'a, T> {
+    /// Create a patch turning this model into `revised`, see the [module documentation](self)
+    ///
+    /// Applying the patch to this model with [`CLI::apply_patch`] gives the same bytes as
+    /// [`revised.to_bytes()`](CLI::to_bytes).
+    pub fn diff(&self, revised: &CLI<'_, T>) -> Vec<u8> {
+        let aligned = revised.header.aligned;
+        let encode = |layer: &Layer<'_, T>| {
+            let mut out = Vec::new();
+            writer::write_layer(&mut out, layer, aligned);
+            out
+        };
+        let mut by_hash = std::collections::HashMap::new();
+        for (i, layer) in self.layers.iter().enumerate() {
+            by_hash
+                .entry(layer.content_hash())
+                .or_insert_with(Vec::new)
+                .push(i);
+        }
+        let same = |base: usize, encoded: &[u8]| encode(&self.layers[base]) == encoded;
+
+        let mut ops: Vec<Op> = Vec::new();
+        for layer in revised.layers.iter() {
+            let encoded = encode(layer);
+            // continuing the current run is preferred over starting a new one
+            if let Some(Op::Copy { first, count }) = ops.last_mut() {
+                let next = *first + *count;
+                if next < self.layers.len()
+                    && self.layers[next].content_hash() == layer.content_hash()
+                    && same(next, &encoded)
+                {
+                    *count += 1;
+                    continue;
+                }
+            }
+            let found = by_hash
+                .get(&layer.content_hash())
+                .and_then(|candidates| candidates.iter().copied().find(|&i| same(i, &encoded)));
+            match (found, ops.last_mut()) {
+                (Some(first), _) => ops.push(Op::Copy { first, count: 1 }),
+                (None, Some(Op::Insert { count, data })) => {
+                    *count += 1;
+                    data.extend_from_slice(&encoded);
+                }
+                (None, _) => ops.push(Op::Insert {
+                    count: 1,
+                    data: encoded,
+                }),
+            }
+        }
+        if !revised.verbatim.unknown.is_empty() {
+            ops.push(Op::Insert {
+                count: 0,
+                data: revised.verbatim.unknown.to_vec(),
+            });
+        }
+
+        let mut header = Vec::new();
+        revised.write_header(&mut header, revised.layers.len());
+        revised.write_leading(&mut header);
+
+        let mut out = Vec::new();
+        out.put_slice(MAGIC);
+        out.put_u32_le(VERSION);
+        out.put_u64_le(base_hash(&self.layers));
+        out.put_u8(aligned as u8);
+        out.put_u32_le(header.len() as u32);
+        out.put_slice(&header);
+        out.put_u32_le(ops.len() as u32);
+        for op in ops {
+            match op {
+                Op::Copy { first, count } => {
+                    out.put_u8(COPY);
+                    out.put_u32_le(first as u32);
+                    out.put_u32_le(count as u32);
+                }
+                Op::Insert { count, data } => {
+                    out.put_u8(INSERT);
+                    out.put_u32_le(count as u32);
+                    out.put_u32_le(data.len() as u32);
+                    out.put_slice(&data);
+                }
+            }
+        }
+        out
+    }
+
+    /// Apply a patch created by [`CLI::diff`] with this model as the base, returning the revised
+    /// file.
+    ///
+    /// Fails with [`PatchError::BaseMismatch`] if the patch was made against a different model.
+    pub fn apply_patch(&self, patch: &[u8]) -> Result<Vec<u8>, PatchError> {
+        let mut buf = patch;
+        if take(&mut buf, MAGIC.len()).ok() != Some(&MAGIC[..]) {
+            return Err(PatchError::NotAPatch);
+        }
+        match u32(&mut buf)? {
+            v if v as u32 == VERSION => {}
+            v => return Err(PatchError::UnsupportedVersion(v as u32)),
+        }
+        if take(&mut buf, 8)?.get_u64_le() != base_hash(&self.layers) {
+            return Err(PatchError::BaseMismatch);
+        }
+        let aligned = take(&mut buf, 1)?[0] & 1 != 0;
+        let n = u32(&mut buf)?;
+        let header = take(&mut buf, n)?;
+        let mut out = header.to_vec();
+
+        for _ in 0..u32(&mut buf)? {
+            match take(&mut buf, 1)?[0] {
+                COPY => {
+                    let first = u32(&mut buf)?;
+                    let count = u32(&mut buf)?;
+                    let layers = first
+                        .checked_add(count)
+                        .and_then(|end| self.layers.get(first..end))
+                        .ok_or(PatchError::LayerOutOfRange)?;
+                    for layer in layers {
+                        writer::write_layer(&mut out, layer, aligned);
+                    }
+                }
+                INSERT => {
+                    let _count = u32(&mut buf)?;
+                    let n = u32(&mut buf)?;
+                    out.extend_from_slice(take(&mut buf, n)?);
+                }
+                kind => return Err(PatchError::InvalidOperation(kind)),
+            }
+        }
+        Ok(out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clitype::ShortCLI;
+    use crate::{Error, Header, Loop};
+    use std::borrow::Cow;
+
+    fn layer(height: u16, x: u16) -> Layer<'static, ShortCLI> {
+        Layer {
+            loops: vec![Loop {
+                id: 1,
+                dir: 1,
+                points: Cow::Owned((0..200).map(|i| i * x).collect()),
+                params: Default::default(),
+            }],
+            ..Layer::empty(height)
+        }
+    }
+
+    fn model(aligned: bool, layers: Vec<Layer<'static, ShortCLI>>) -> CLI<'static, ShortCLI> {
+        CLI {
+            header: Header {
+                aligned,
+                ..Header::default()
+            },
+            layers,
+            verbatim: Default::default(),
+        }
+    }
+
+    #[test]
+    fn round_trip() -> Result<(), Error> {
+        let base = model(false, (1..=6).map(|h| layer(h, 1)).collect());
+        let mut layers: Vec<_> = (1..=6).map(|h| layer(h, 1)).collect();
+        layers[2] = layer(3, 2);
+        layers.remove(4);
+        layers.push(layer(7, 1));
+
+        for aligned in [false, true] {
+            let revised = model(aligned, layers.clone());
+            let patch = base.diff(&revised);
+            let expected = revised.to_bytes();
+            // two changed layers plus some overhead
+            assert!(patch.len() < expected.len() / 2);
+            assert_eq!(base.apply_patch(&patch), Ok(expected.clone()));
+            assert_eq!(CLI::<ShortCLI>::new(&expected)?.iter().count(), 6);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn unknown_records() -> Result<(), Error> {
+        let base = model(false, vec![layer(1, 1), layer(2, 1)]);
+        let mut buf = model(false, vec![layer(1, 1), layer(2, 2)]).to_bytes();
+        buf.extend_from_slice(&[0x99, 0, 1, 2, 3]);
+        let revised = crate::Parser::new().short().lenient().parse(&buf)?;
+        assert_eq!(revised.unknown_records().len(), 5);
+        let patch = base.diff(&revised);
+        assert_eq!(base.apply_patch(&patch), Ok(revised.to_bytes()));
+        Ok(())
+    }
+
+    #[test]
+    fn align_in_comment() -> Result<(), Error> {
+        let header = "$$HEADERSTART\n// no $$ALIGN here\n$$BINARY\n$$UNITS/1\n$$VERSION/200\n\
+            $$LAYERS/2\n$$HEADEREND";
+        let bytes = model(false, vec![layer(1, 1), layer(2, 1)]).to_bytes();
+        let (_, start) = crate::parse_header(&bytes)?;
+        let buf = [header.as_bytes(), &bytes[start..]].concat();
+        let base = CLI::<ShortCLI>::new(&buf)?;
+        let mut revised = CLI::<ShortCLI>::new(&buf)?;
+        let last = revised.iter_mut().last().unwrap();
+        // Encoded again instead of copied from the file
+        last.source = None;
+        last.height = 3;
+        let patched = base.apply_patch(&base.diff(&revised)).unwrap();
+        assert_eq!(patched, revised.to_bytes());
+        let heights: Vec<_> = CLI::<ShortCLI>::new(&patched)?
+            .iter()
+            .map(|l| l.height())
+            .collect();
+        assert_eq!(heights, [1, 3]);
+        Ok(())
+    }
+
+    #[test]
+    fn rejects() {
+        let a = model(false, vec![layer(1, 1), layer(2, 1)]);
+        let b = model(false, vec![layer(1, 1), layer(2, 2)]);
+        let patch = a.diff(&b);
+        assert_eq!(b.apply_patch(&patch), Err(PatchError::BaseMismatch));
+        assert_eq!(a.apply_patch(b"CLI"), Err(PatchError::NotAPatch));
+        assert_eq!(
+            a.apply_patch(&patch[..patch.len() - 1]),
+            Err(PatchError::Truncated)
+        );
+    }
+}
@@ -0,0 +1,500 @@
+//! Composition of several models onto a single build plate
+//!
+//! A [`BuildPlate`] takes ownership of parsed models, positions each one with a [`Placement`]
+//! and merges their layer stacks into a single model that can be written out as one CLI file.
+//!
+//! ```
+//! use colain::{BuildPlate, Placement, CLI, clitype::LongCLI};
+//! # let bytes = BuildPlate::<LongCLI>::new(1.0).to_bytes();
+//! # let (a, b) = (bytes.clone(), bytes);
+//!
+//! let mut plate = BuildPlate::new(0.001);
+//! let a = CLI::<LongCLI>::new(&a).unwrap();
+//! let b = CLI::<LongCLI>::new(&b).unwrap();
+//! plate.place(a, Placement::new(10.0, 10.0)).unwrap();
+//! plate.place(b, Placement::new(60.0, 10.0).rotated(1.57)).unwrap();
+//!
+//! let file: Vec<u8> = plate.to_bytes();
+//! ```
+
+use crate::clitype::CLIType;
+use crate::geom::{self, BoundingBox};
+use crate::raster::{self, Grid};
+use crate::{Error, Hatches, Header, Layer, Loop, UserData, CLI};
+use std::borrow::Cow;
+
+/// Heights closer than this (in millimeters) are considered to be the same slice
+const EPS: f64 = 1e-6;
+
+/// Position of a model on a [`BuildPlate`]
+///
+/// The rotation is applied about the origin of the model before it is translated.
+/// Distances are in millimeters and the rotation is in radians, counter-clockwise.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Placement {
+    /// Translation along the x axis
+    pub x: f64,
+    /// Translation along the y axis
+    pub y: f64,
+    /// Rotation about the z axis
+    pub rotation: f64,
+    /// Offset added to every layer height
+    pub z: f64,
+}
+
+impl Placement {
+    /// Translate a model in the XY plane
+    pub fn new(x: f64, y: f64) -> Self {
+        Placement {
+            x,
+            y,
+            ..Default::default()
+        }
+    }
+    /// Set the rotation about the z axis
+    pub fn rotated(self, rotation: f64) -> Self {
+        Placement { rotation, ..self }
+    }
+    /// Set the z offset
+    pub fn raised(self, z: f64) -> Self {
+        Placement { z, ..self }
+    }
+
+    /// Apply the XY part of the transform to a point in millimeters
+    pub(crate) fn apply(&self, p: [f64; 2]) -> [f64; 2] {
+        let (s, c) = self.rotation.sin_cos();
+        [c * p[0] - s * p[1] + self.x, s * p[0] + c * p[1] + self.y]
+    }
+}
+
+/// One layer of the composed stack and the source layers that contribute to it
+#[derive(Debug, Clone)]
+pub(crate) struct Slice {
+    /// Height in millimeters
+    pub z: f64,
+    /// (part, layer) pairs of every part present at this height
+    pub sources: Vec<(usize, usize)>,
+}
+
+/// Several models positioned on a single build plate
+///
+/// When the parts were sliced with different layer heights the stacks are resampled: the composed
+/// file contains a layer at every height found in any part, and each part contributes the layer
+/// covering that height. Following the CLI convention a layer at height `h` covers everything from
+/// the previous layer up to `h`. The first layer of a part is assumed to be as thick as the second.
+pub struct BuildPlate<'a, T: CLIType> {
+    units: f64,
+    aligned: bool,
+    pub(crate) parts: Vec<(CLI<'a, T>, Placement)>,
+}
+
+impl<'a, T: CLIType> BuildPlate<'a, T> {
+    /// Create an empty build plate.
+    ///
+    /// `units` is the number of millimeters each coordinate unit of the composed file represents.
+    /// Parts are converted from their own units as they are composed.
+    pub fn new(units: f64) -> Self {
+        BuildPlate {
+            units,
+            aligned: false,
+            parts: Vec::new(),
+        }
+    }
+
+    /// Whether the composed file should use the `$$ALIGN` layout
+    pub fn aligned(mut self, aligned: bool) -> Self {
+        self.aligned = aligned;
+        self
+    }
+
+    /// Place a model on the plate and return its part index.
+    ///
+    /// Fails with [`Error::CoordinateOutOfRange`] if the placement moves part of the model to
+    /// coordinates or heights the coordinate type cannot hold in the units of the plate, such as
+    /// negative ones for [`ShortCLI`](crate::clitype::ShortCLI). The model is not placed then.
+    pub fn place(&mut self, model: CLI<'a, T>, placement: Placement) -> Result<usize, Error> {
+        self.parts.push((model, placement));
+        let part = self.parts.len() - 1;
+        if !self.fits(part) {
+            self.parts.pop();
+            return Err(Error::CoordinateOutOfRange);
+        }
+        Ok(part)
+    }
+
+    /// Get a placed model and its placement by part index
+    pub fn part(&self, index: usize) -> Option<(&CLI<'a, T>, &Placement)> {
+        self.parts.get(index).map(|(m, p)| (m, p))
+    }
+
+    /// Number of parts on the plate
+    pub fn len(&self) -> usize {
+        self.parts.len()
+    }
+
+    /// True if no parts have been placed
+    pub fn is_empty(&self) -> bool {
+        self.parts.is_empty()
+    }
+
+    /// Millimeters per coordinate unit of the composed file
+    pub fn units(&self) -> f64 {
+        self.units
+    }
+
+    /// Merge every part into a single model
+    pub fn compose(&self) -> CLI<'static, T> {
+        let layers = self
+            .slices()
+            .iter()
+            .map(|slice| {
+                let mut layer = Layer::empty(T::coord_from_f64(slice.z / self.units));
+                for &(part, index) in slice.sources.iter() {
+                    let source = &self.parts[part].0.layers[index];
+                    layer.loops.extend(source.loops.iter().map(|l| Loop {
+                        id: l.id,
+                        dir: l.dir,
+                        points: Cow::Owned(self.transform(part, &l.points)),
+                        params: l.params.to_owned_params(),
+                    }));
+                    layer.hatches.extend(source.hatches.iter().map(|h| Hatches {
+                        id: h.id,
+                        points: Cow::Owned(self.transform(part, &h.points)),
+                        params: h.params.to_owned_params(),
+                    }));
+                }
+                // User data of the parts is kept after all of the geometry
+                let end = layer.loops.len() + layer.hatches.len();
+                for &(part, index) in slice.sources.iter() {
+                    let source = &self.parts[part].0.layers[index];
+                    layer
+                        .user_data
+                        .extend(source.user_data.iter().map(|u| UserData {
+                            position: end,
+                            ..u.to_owned_data()
+                        }));
+                }
+                layer
+            })
+            .collect::<Vec<_>>();
+
+        CLI {
+            header: Header {
+                units: self.units,
+                aligned: self.aligned,
+                layers: Some(layers.len()),
+                ..Header::default()
+            },
+            layers,
+            verbatim: Default::default(),
+        }
+    }
+
+    /// Merge every part and serialize the result as a binary CLI file
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.compose().to_bytes()
+    }
+
+    /// Compute the resampled layer stack
+    pub(crate) fn slices(&self) -> Vec<Slice> {
+        let heights: Vec<Vec<f64>> = self
+            .parts
+            .iter()
+            .map(|(model, placement)| {
+                model
+                    .layers
+                    .iter()
+                    .map(|l| T::coord_to_f64(l.height) * model.header.units + placement.z)
+                    .collect()
+            })
+            .collect();
+
+        let mut all: Vec<f64> = heights.iter().flatten().copied().collect();
+        all.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+        all.dedup_by(|a, b| (*a - *b).abs() < EPS);
+
+        all.into_iter()
+            .map(|z| Slice {
+                z,
+                sources: heights
+                    .iter()
+                    .enumerate()
+                    .filter_map(|(part, h)| covering(h, z).map(|i| (part, i)))
+                    .collect(),
+            })
+            .collect()
+    }
+
+    /// Move a coordinate array of a part into plate coordinates
+    pub(crate) fn transform(
+        &self,
+        part: usize,
+        points: &[<T as CLIType>::Coord],
+    ) -> Vec<<T as CLIType>::Coord> {
+        let mut out = Vec::with_capacity(points.len());
+        for p in points.chunks_exact(2) {
+            let [x, y] = self.plate_point(part, p);
+            out.push(T::coord_from_f64(x));
+            out.push(T::coord_from_f64(y));
+        }
+        out
+    }
+
+    /// Position of a point of a part in coordinate units of the plate
+    fn plate_point(&self, part: usize, p: &[<T as CLIType>::Coord]) -> [f64; 2] {
+        let (model, placement) = &self.parts[part];
+        let scale = model.header.units;
+        let [x, y] =
+            placement.apply([T::coord_to_f64(p[0]) * scale, T::coord_to_f64(p[1]) * scale]);
+        [x / self.units, y / self.units]
+    }
+
+    /// True if every point and layer height of a part can be held by the coordinate type
+    fn fits(&self, part: usize) -> bool {
+        let fits = |v: f64| {
+            let back = T::coord_to_f64(T::coord_from_f64(v));
+            (back - v).abs() <= f64::max(0.5, v.abs() * 1e-6)
+        };
+        let (model, placement) = &self.parts[part];
+        model.layers.iter().all(|layer| {
+            let z = T::coord_to_f64(layer.height) * model.header.units + placement.z;
+            let loops = layer.loops.iter().map(|l| &l.points);
+            let mut points = loops.chain(layer.hatches.iter().map(|h| &h.points));
+            fits(z / self.units)
+                && points.all(|points| {
+                    points.chunks_exact(2).all(|p| {
+                        let [x, y] = self.plate_point(part, p);
+                        fits(x) && fits(y)
+                    })
+                })
+        })
+    }
+
+    /// Find overlapping solid regions between different parts.
+    ///
+    /// Every layer of the composed stack is checked for each pair of parts present at that height.
+    /// The overlap area is approximated by rasterizing both parts with square cells of `resolution`
+    /// millimeters, so overlaps smaller than a cell may go unreported. A resolution that is not
+    /// positive and finite, or too fine for 16 million cells per overlap, is coarsened to fit.
+    pub fn collisions(&self, resolution: f64) -> Vec<Collision> {
+        let mut found = Vec::new();
+        for (layer, slice) in self.slices().iter().enumerate() {
+            let solids: Vec<_> = slice
+                .sources
+                .iter()
+                .filter_map(|&(part, index)| {
+                    let polygons = self.solid(part, index);
+                    let bb = BoundingBox::of(polygons.iter().flatten().copied())?;
+                    Some((part, polygons, bb))
+                })
+                .collect();
+
+            for (i, (part_a, solid_a, bb_a)) in solids.iter().enumerate() {
+                for (part_b, solid_b, bb_b) in solids[i + 1..].iter() {
+                    let bb = match bb_a.intersection(bb_b) {
+                        Some(bb) => bb,
+                        None => continue,
+                    };
+                    let resolution = raster::resolution(&bb, resolution);
+                    let grid = Grid::covering(&bb, resolution);
+                    let cells = raster::fill(&grid, solid_a)
+                        .into_iter()
+                        .zip(raster::fill(&grid, solid_b))
+                        .filter(|&(a, b)| a && b)
+                        .count();
+                    if cells > 0 {
+                        found.push(Collision {
+                            layer,
+                            height: slice.z,
+                            parts: (*part_a, *part_b),
+                            area: cells as f64 * grid.cell_area(),
+                        });
+                    }
+                }
+            }
+        }
+        found
+    }
+
+    /// Closed loops of a layer of a part in plate millimeters
+    fn solid(&self, part: usize, index: usize) -> Vec<Vec<[f64; 2]>> {
+        let (model, placement) = &self.parts[part];
+        let scale = model.header.units;
+        model.layers[index]
+            .loops
+            .iter()
+            .filter(|l| l.is_solid())
+            .map(|l| {
+                geom::to_points(&l.points, |c| T::coord_to_f64(c) * scale)
+                    .into_iter()
+                    .map(|p| placement.apply(p))
+                    .collect()
+            })
+            .collect()
+    }
+}
+
+/// Overlap between two parts at one layer of a [`BuildPlate`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct Collision {
+    /// Index of the layer in the composed stack
+    pub layer: usize,
+    /// Height of the layer in millimeters
+    pub height: f64,
+    /// Part indices of the two overlapping parts
+    pub parts: (usize, usize),
+    /// Approximate overlapping area in square millimeters
+    pub area: f64,
+}
+
+/// Index of the layer covering height `z` in a sorted stack, if any
+fn covering(heights: &[f64], z: f64) -> Option<usize> {
+    let i = heights.partition_point(|&h| h < z - EPS);
+    if i == heights.len() {
+        return None;
+    }
+    if i == 0 && heights[0] - z > EPS {
+        // Below the first slice, only covered within the thickness of the first layer
+        let thickness = heights.get(1).map_or(0.0, |h| h - heights[0]);
+        if heights[0] - z >= thickness - EPS {
+            return None;
+        }
+    }
+    Some(i)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clitype::{LongCLI, ShortCLI};
+    use crate::Point;
+
+    fn model(heights: &[f32]) -> CLI<'static, LongCLI> {
+        CLI {
+            header: Header::default(),
+            layers: heights
+                .iter()
+                .map(|&height| Layer {
+                    loops: vec![Loop {
+                        id: 1,
+                        dir: 1,
+                        points: Cow::Owned(vec![0.0, 0.0, 1.0, 0.0, 1.0, 1.0, 0.0, 0.0]),
+                        params: Default::default(),
+                    }],
+                    ..Layer::empty(height)
+                })
+                .collect(),
+            verbatim: Default::default(),
+        }
+    }
+
+    #[test]
+    fn resample() {
+        let mut plate = BuildPlate::new(1.0);
+        plate
+            .place(model(&[1.0, 2.0, 3.0]), Placement::new(0.0, 0.0))
+            .unwrap();
+        plate
+            .place(model(&[1.5, 3.0]), Placement::new(5.0, 0.0))
+            .unwrap();
+
+        let slices = plate.slices();
+        let z: Vec<f64> = slices.iter().map(|s| s.z).collect();
+        assert_eq!(z, vec![1.0, 1.5, 2.0, 3.0]);
+        assert_eq!(slices[0].sources, vec![(0, 0), (1, 0)]);
+        assert_eq!(slices[1].sources, vec![(0, 1), (1, 0)]);
+        assert_eq!(slices[2].sources, vec![(0, 1), (1, 1)]);
+        assert_eq!(slices[3].sources, vec![(0, 2), (1, 1)]);
+    }
+
+    #[test]
+    fn z_offset() {
+        let mut plate = BuildPlate::new(1.0);
+        plate
+            .place(model(&[1.0, 2.0]), Placement::default())
+            .unwrap();
+        plate
+            .place(model(&[1.0, 2.0]), Placement::default().raised(5.0))
+            .unwrap();
+
+        let slices = plate.slices();
+        assert_eq!(slices.len(), 4);
+        assert_eq!(slices[1].sources, vec![(0, 1)]);
+        assert_eq!(slices[2].sources, vec![(1, 0)]);
+    }
+
+    #[test]
+    fn collisions() {
+        let mut plate = BuildPlate::new(1.0);
+        plate
+            .place(model(&[1.0, 2.0]), Placement::default())
+            .unwrap();
+        plate
+            .place(model(&[2.0]), Placement::new(0.5, 0.0))
+            .unwrap();
+        plate
+            .place(model(&[1.0, 2.0]), Placement::new(5.0, 0.0))
+            .unwrap();
+
+        let found = plate.collisions(0.01);
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].layer, 1);
+        assert_eq!(found[0].parts, (0, 1));
+        // Two right triangles with legs of 1mm shifted by 0.5mm overlap in a 0.125mm² triangle
+        assert!((found[0].area - 0.125).abs() < 0.01);
+    }
+
+    #[test]
+    fn out_of_range() {
+        let part = || CLI::<ShortCLI> {
+            header: Header::default(),
+            layers: vec![Layer {
+                loops: vec![Loop {
+                    id: 1,
+                    dir: 1,
+                    points: Cow::Owned(vec![0, 0, 10, 0, 10, 10, 0, 0]),
+                    params: Default::default(),
+                }],
+                ..Layer::empty(1)
+            }],
+            verbatim: Default::default(),
+        };
+        let mut plate = BuildPlate::new(1.0);
+        assert!(matches!(
+            plate.place(part(), Placement::new(-5.0, 0.0)),
+            Err(Error::CoordinateOutOfRange)
+        ));
+        assert!(matches!(
+            plate.place(part(), Placement::new(65530.0, 0.0)),
+            Err(Error::CoordinateOutOfRange)
+        ));
+        assert!(matches!(
+            plate.place(part(), Placement::default().raised(-2.0)),
+            Err(Error::CoordinateOutOfRange)
+        ));
+        assert!(plate.is_empty());
+        assert_eq!(plate.place(part(), Placement::new(5.0, 0.0)).ok(), Some(0));
+    }
+
+    #[test]
+    fn round_trip() -> Result<(), crate::Error> {
+        let mut plate = BuildPlate::new(0.5).aligned(true);
+        plate
+            .place(
+                model(&[1.0]),
+                Placement::new(10.0, 0.0).rotated(std::f64::consts::FRAC_PI_2),
+            )
+            .unwrap();
+        let bytes = plate.to_bytes();
+
+        let parsed = CLI::<LongCLI>::new(&bytes)?;
+        assert_eq!(parsed.header().units, 0.5);
+        let layer = parsed.iter().next().unwrap();
+        assert_eq!(layer.height(), 2.0);
+        let p = layer.iter_loops().next().unwrap().iter().nth(1).unwrap();
+        // (1, 0) rotated a quarter turn then moved 10mm along x, in half millimeters
+        assert!((p.x() - 20.0).abs() < 1e-5);
+        assert!((p.y() - 2.0).abs() < 1e-5);
+        Ok(())
+    }
+}
@@ -0,0 +1,118 @@
+//! Points of a model in 3D
+
+use crate::clitype::CLIType;
+use crate::{Layer, CLI};
+
+impl<'a, T: CLIType> Layer<'a, T> {
+    /// Iterate over every point of the layer as `[x, y, z]`, with z being the height of the layer.
+    ///
+    /// Coordinates are multiplied by `units`, pass [`Header::units`](crate::Header::units) to get
+    /// millimeters. Loop points come first in file order, followed by the start and end point of
+    /// each hatch.
+    pub fn iter_points_3d(&self, units: f64) -> impl Iterator<Item = [f64; 3]> + '_ {
+        let z = T::coord_to_f64(self.height) * units;
+        self.loops
+            .iter()
+            .flat_map(|l| l.points.chunks_exact(2))
+            .chain(self.hatches.iter().flat_map(|h| h.points.chunks_exact(2)))
+            .map(move |p| {
+                [
+                    T::coord_to_f64(p[0]) * units,
+                    T::coord_to_f64(p[1]) * units,
+                    z,
+                ]
+            })
+    }
+
+    /// Like [`Layer::iter_points_3d`] but only the points of the loops
+    pub fn iter_loop_points_3d(&self, units: f64) -> impl Iterator<Item = [f64; 3]> + '_ {
+        let z = T::coord_to_f64(self.height) * units;
+        self.loops
+            .iter()
+            .flat_map(|l| l.points.chunks_exact(2))
+            .map(move |p| {
+                [
+                    T::coord_to_f64(p[0]) * units,
+                    T::coord_to_f64(p[1]) * units,
+                    z,
+                ]
+            })
+    }
+
+    /// Like [`Layer::iter_points_3d`] but only the end points of the hatches
+    pub fn iter_hatch_points_3d(&self, units: f64) -> impl Iterator<Item = [f64; 3]> + '_ {
+        let z = T::coord_to_f64(self.height) * units;
+        self.hatches
+            .iter()
+            .flat_map(|h| h.points.chunks_exact(2))
+            .map(move |p| {
+                [
+                    T::coord_to_f64(p[0]) * units,
+                    T::coord_to_f64(p[1]) * units,
+                    z,
+                ]
+            })
+    }
+}
+
+impl<'a, T: CLIType> CLI<'a, T> {
+    /// Iterate over every point of the model as `[x, y, z]` in millimeters, layer by layer.
+    ///
+    /// See [`Layer::iter_points_3d`] for the order within a layer.
+    ///
+    /// ```no_run
+    /// # use colain::{CLI, clitype::LongCLI};
+    /// # let buf = Vec::new();
+    /// let model = CLI::<LongCLI>::new(&buf).unwrap();
+    /// let top = model.iter_points_3d().map(|p| p[2]).fold(0.0, f64::max);
+    /// ```
+    pub fn iter_points_3d(&self) -> impl Iterator<Item = [f64; 3]> + '_ {
+        let units = self.header.units;
+        self.layers
+            .iter()
+            .flat_map(move |l| l.iter_points_3d(units))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::clitype::ShortCLI;
+    use crate::{Hatches, Header, Layer, Loop, CLI};
+    use std::borrow::Cow;
+
+    #[test]
+    fn points() {
+        let layer = |height| Layer::<ShortCLI> {
+            loops: vec![Loop {
+                id: 0,
+                dir: 2,
+                points: Cow::Owned(vec![0, 0, 2, 0]),
+                params: Default::default(),
+            }],
+            hatches: vec![Hatches {
+                id: 0,
+                points: Cow::Owned(vec![4, 4, 6, 4]),
+                params: Default::default(),
+            }],
+            ..Layer::empty(height)
+        };
+        let model = CLI {
+            header: Header {
+                units: 0.5,
+                ..Header::default()
+            },
+            layers: vec![layer(1), layer(2)],
+            verbatim: Default::default(),
+        };
+        let points: Vec<_> = model.iter_points_3d().collect();
+        assert_eq!(points.len(), 8);
+        assert_eq!(points[0], [0.0, 0.0, 0.5]);
+        assert_eq!(points[3], [3.0, 2.0, 0.5]);
+        assert_eq!(points[7], [3.0, 2.0, 1.0]);
+        assert_eq!(model.layers[0].iter_loop_points_3d(1.0).count(), 2);
+        assert_eq!(
+            model.layers[0].iter_hatch_points_3d(1.0).next(),
+            Some([4.0, 4.0, 1.0])
+        );
+    }
+}
@@ -0,0 +1,229 @@
+//! Distinguishing closed contours from open paths
+//!
+//! The spec uses the direction of a [`Loop`] to mark it as an open line (direction 2) rather
+//! than a closed contour. Open paths have no interior, so they are left out of any calculation
+//! involving area or containment. Single-track scan strategies use them as plain toolpaths,
+//! which is what [`OpenPolyline`] provides a view for.
+
+use crate::clitype::CLIType;
+use crate::geom;
+use crate::{ArrayChunksCopy, Loop, VectorParams};
+use std::borrow::Cow;
+
+/// Most parts a path is divided into by [`Loop::resampled`]
+const MAX_PARTS: usize = 1 << 20;
+
+/// What a [`Loop`] represents according to its direction
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LoopKind {
+    /// A closed contour, direction 0 (internal) or 1 (external)
+    Closed,
+    /// An open line without a solid interior, direction 2
+    Open,
+    /// A direction not defined by the spec
+    Unknown,
+}
+
+/// Direction of a [`Loop`] as defined by the spec, see [`Loop::direction`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Direction {
+    /// Direction 0, an internal contour
+    Clockwise,
+    /// Direction 1, an external contour
+    CounterClockwise,
+    /// Direction 2, an open line without a solid interior
+    OpenLine,
+    /// A value not defined by the spec
+    Other(i64),
+}
+
+impl Direction {
+    /// The direction with a value as stored in a file
+    pub fn from_raw(dir: i64) -> Self {
+        match dir {
+            0 => Direction::Clockwise,
+            1 => Direction::CounterClockwise,
+            2 => Direction::OpenLine,
+            other => Direction::Other(other),
+        }
+    }
+
+    /// Value of the direction as stored in a file
+    pub fn raw(self) -> i64 {
+        match self {
+            Direction::Clockwise => 0,
+            Direction::CounterClockwise => 1,
+            Direction::OpenLine => 2,
+            Direction::Other(other) => other,
+        }
+    }
+}
+
+impl<'a, T: CLIType> Loop<'a, T> {
+    /// Direction of the loop, [`Loop::dir`] as a [`Direction`]
+    pub fn direction(&self) -> Direction {
+        Direction::from_raw(T::meta_to_i64(self.dir))
+    }
+
+    /// Classify the loop according to its direction
+    pub fn kind(&self) -> LoopKind {
+        match self.direction() {
+            Direction::Clockwise | Direction::CounterClockwise => LoopKind::Closed,
+            Direction::OpenLine => LoopKind::Open,
+            Direction::Other(_) => LoopKind::Unknown,
+        }
+    }
+
+    /// View the loop as an open polyline, `None` unless its direction marks it as open
+    pub fn as_open(&self) -> Option<OpenPolyline<'_, 'a, T>> {
+        if self.kind() == LoopKind::Open {
+            Some(OpenPolyline { inner: self })
+        } else {
+            None
+        }
+    }
+
+    /// Copy of the loop with its points evenly spaced along the path, at most `spacing` apart.
+    ///
+    /// The path is divided into the smallest number of equal parts no longer than `spacing`
+    /// (in coordinate units), so the first and last points are kept and closed loops stay closed.
+    /// The path is divided into at most 2^20 parts, so a finer spacing gives points further apart.
+    /// Process parameters are dropped since the vectors no longer match the original ones.
+    pub fn resampled(&self, spacing: f64) -> Loop<'static, T> {
+        let points = geom::to_points(&self.points, |c| T::coord_to_f64(c));
+        let length = geom::path_length(&points);
+        let parts = if spacing > 0.0 {
+            ((length / spacing).ceil().min(MAX_PARTS as f64) as usize).max(1)
+        } else {
+            1
+        };
+        let points = if points.len() < 2 {
+            points
+        } else {
+            geom::resample(&points, parts + 1)
+        };
+        Loop {
+            id: self.id,
+            dir: self.dir,
+            points: Cow::Owned(
+                points
+                    .iter()
+                    .flat_map(|p| [T::coord_from_f64(p[0]), T::coord_from_f64(p[1])])
+                    .collect(),
+            ),
+            params: VectorParams::default(),
+        }
+    }
+
+    /// True if the loop encloses an area, i.e. it is not an open line.
+    ///
+    /// Loops with a nonstandard direction are assumed to be closed.
+    pub(crate) fn is_solid(&self) -> bool {
+        self.kind() != LoopKind::Open
+    }
+}
+
+/// An open path, see [`Loop::as_open`]
+#[derive(Debug, Clone, Copy)]
+pub struct OpenPolyline<'l, 'a, T: CLIType> {
+    inner: &'l Loop<'a, T>,
+}
+
+impl<'l, 'a, T: CLIType> OpenPolyline<'l, 'a, T> {
+    /// Iterate over the points of the path in order
+    pub fn iter(&self) -> ArrayChunksCopy<'l, <T as CLIType>::Coord, 2> {
+        ArrayChunksCopy::new(&self.inner.points)
+    }
+    /// Iterate over the segments of the path as [x0, y0, x1, y1]
+    pub fn segments(&self) -> impl Iterator<Item = [<T as CLIType>::Coord; 4]> + 'l {
+        let mut points = self.iter().peekable();
+        std::iter::from_fn(move || {
+            let a = points.next()?;
+            let b = *points.peek()?;
+            Some([a[0], a[1], b[0], b[1]])
+        })
+    }
+    /// First point of the path
+    pub fn start(&self) -> Option<[<T as CLIType>::Coord; 2]> {
+        self.iter().next()
+    }
+    /// Last point of the path
+    pub fn end(&self) -> Option<[<T as CLIType>::Coord; 2]> {
+        self.iter().last()
+    }
+    /// Length of the path in coordinate units
+    pub fn length(&self) -> f64 {
+        self.segments()
+            .map(|s| {
+                let dx = T::coord_to_f64(s[2]) - T::coord_to_f64(s[0]);
+                let dy = T::coord_to_f64(s[3]) - T::coord_to_f64(s[1]);
+                dx.hypot(dy)
+            })
+            .sum()
+    }
+    /// The underlying loop
+    pub fn as_loop(&self) -> &'l Loop<'a, T> {
+        self.inner
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clitype::LongCLI;
+
+    fn path(dir: i32) -> Loop<'static, LongCLI> {
+        Loop {
+            id: 0,
+            dir,
+            points: Cow::Owned(vec![0.0, 0.0, 3.0, 0.0, 3.0, 4.0]),
+            params: Default::default(),
+        }
+    }
+
+    #[test]
+    fn kinds() {
+        assert_eq!(path(0).kind(), LoopKind::Closed);
+        assert_eq!(path(1).kind(), LoopKind::Closed);
+        assert_eq!(path(2).kind(), LoopKind::Open);
+        assert_eq!(path(-1).kind(), LoopKind::Unknown);
+        assert!(path(1).as_open().is_none());
+
+        assert_eq!(path(0).direction(), Direction::Clockwise);
+        assert_eq!(path(1).direction(), Direction::CounterClockwise);
+        assert_eq!(path(2).direction(), Direction::OpenLine);
+        assert_eq!(path(7).direction(), Direction::Other(7));
+        assert_eq!(path(7).direction().raw(), 7);
+    }
+
+    #[test]
+    fn open_polyline() {
+        let l = path(2);
+        let open = l.as_open().unwrap();
+        assert_eq!(open.segments().count(), 2);
+        assert_eq!(open.start(), Some([0.0, 0.0]));
+        assert_eq!(open.end(), Some([3.0, 4.0]));
+        assert_eq!(open.length(), 7.0);
+    }
+
+    #[test]
+    fn resampled() {
+        let square = Loop::<LongCLI> {
+            id: 3,
+            dir: 1,
+            points: Cow::Owned(vec![0.0, 0.0, 4.0, 0.0, 4.0, 4.0, 0.0, 4.0, 0.0, 0.0]),
+            params: Default::default(),
+        };
+        let r = square.resampled(1.5);
+        assert_eq!(r.points().len(), 2 * 12);
+        assert!((r.points()[2] - 16.0 / 11.0).abs() < 1e-6);
+        assert_eq!(r.points()[22..], [0.0, 0.0]);
+        assert_eq!(r.id(), 3);
+
+        let open = path(2).resampled(10.0);
+        assert_eq!(open.points(), &[0.0, 0.0, 3.0, 4.0]);
+
+        let fine = square.resampled(f64::MIN_POSITIVE);
+        assert_eq!(fine.points().len(), 2 * (MAX_PARTS + 1));
+    }
+}
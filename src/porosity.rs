@@ -0,0 +1,208 @@
+//! Hatch density and lack-of-fusion risk
+//!
+//! Lack-of-fusion porosity forms where the melt tracks of neighbouring hatches do not overlap.
+//! [`Layer::hatch_density`] measures the spacing of the hatches and how much of the solid region is
+//! not reached by any melt track, given the width of the beam.
+
+use crate::clitype::CLIType;
+use crate::geom::{self, BoundingBox};
+use crate::raster::{self, Grid};
+use crate::{Layer, CLI};
+
+/// Hatch metrics of a layer, see [`Layer::hatch_density`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct HatchDensity {
+    /// Spacing between neighbouring parallel hatches as (mean, min, max, standard deviation),
+    /// `None` if there are no parallel hatches
+    pub spacing: Option<(f64, f64, f64, f64)>,
+    /// Solid area not covered by any melt track
+    pub uncovered_area: f64,
+    /// Fraction of the solid area not covered by any melt track, 0 for layers without solid area
+    pub uncovered_fraction: f64,
+}
+
+impl<'a, T: CLIType> Layer<'a, T> {
+    /// Measure hatch spacing and coverage of the solid region by melt tracks of `beam_width`.
+    ///
+    /// Spacing is measured perpendicular to the hatches within each hatch block. Coverage is
+    /// approximated by rasterizing with square cells of `resolution`, coarsened to give at most 16
+    /// million cells if it is finer than that or not positive and finite. All values are in
+    /// coordinate units.
+    pub fn hatch_density(&self, beam_width: f64, resolution: f64) -> HatchDensity {
+        let f = |c| T::coord_to_f64(c);
+        let mut gaps = Vec::new();
+        for block in self.hatches.iter() {
+            let vectors: Vec<[[f64; 2]; 2]> = block
+                .iter()
+                .map(|h| [[f(h[0]), f(h[1])], [f(h[2]), f(h[3])]])
+                .filter(|v| v[0] != v[1])
+                .collect();
+            let first = match vectors.first() {
+                Some(v) => v,
+                None => continue,
+            };
+            let length = geom::path_length(first);
+            let normal = [
+                -(first[1][1] - first[0][1]) / length,
+                (first[1][0] - first[0][0]) / length,
+            ];
+            let mut offsets: Vec<f64> = vectors
+                .iter()
+                .map(|v| {
+                    normal[0] * (v[0][0] + v[1][0]) / 2.0 + normal[1] * (v[0][1] + v[1][1]) / 2.0
+                })
+                .collect();
+            offsets.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+            // Collinear pieces of one scan line, e.g. split by a hole, are not a gap
+            gaps.extend(
+                offsets
+                    .windows(2)
+                    .map(|w| w[1] - w[0])
+                    .filter(|&g| g > 1e-9),
+            );
+        }
+        let spacing = if gaps.is_empty() {
+            None
+        } else {
+            let n = gaps.len() as f64;
+            let mean = gaps.iter().sum::<f64>() / n;
+            let var = gaps.iter().map(|g| (g - mean) * (g - mean)).sum::<f64>() / n;
+            let min = gaps.iter().copied().fold(f64::INFINITY, f64::min);
+            let max = gaps.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+            Some((mean, min, max, var.sqrt()))
+        };
+
+        let solid = self.solid(1.0);
+        let (uncovered_area, uncovered_fraction) =
+            match BoundingBox::of(solid.iter().flatten().copied()) {
+                None => (0.0, 0.0),
+                Some(bb) => {
+                    let resolution = raster::resolution(&bb, resolution);
+                    let grid = Grid::covering(&bb, resolution);
+                    let mask = raster::fill(&grid, &solid);
+                    let mut covered = vec![false; grid.len()];
+                    let radius = beam_width / 2.0;
+                    for h in self.hatches.iter().flat_map(|b| b.iter()) {
+                        let (a, b) = ([f(h[0]), f(h[1])], [f(h[2]), f(h[3])]);
+                        let cell = |v: f64, o: f64, n: usize| {
+                            (((v - o) / resolution).floor().max(0.0) as usize)
+                                .min(n.saturating_sub(1))
+                        };
+                        let (x0, x1) = (
+                            cell(a[0].min(b[0]) - radius, grid.origin[0], grid.width),
+                            cell(a[0].max(b[0]) + radius, grid.origin[0], grid.width),
+                        );
+                        let (y0, y1) = (
+                            cell(a[1].min(b[1]) - radius, grid.origin[1], grid.height),
+                            cell(a[1].max(b[1]) + radius, grid.origin[1], grid.height),
+                        );
+                        for y in y0..=y1 {
+                            for x in x0..=x1 {
+                                let center = [
+                                    grid.origin[0] + (x as f64 + 0.5) * resolution,
+                                    grid.origin[1] + (y as f64 + 0.5) * resolution,
+                                ];
+                                if geom::point_segment_distance(center, a, b) <= radius {
+                                    covered[y * grid.width + x] = true;
+                                }
+                            }
+                        }
+                    }
+                    let solid_cells = mask.iter().filter(|&&m| m).count();
+                    let uncovered = mask
+                        .iter()
+                        .zip(covered.iter())
+                        .filter(|&(&m, &c)| m && !c)
+                        .count();
+                    let fraction = if solid_cells == 0 {
+                        0.0
+                    } else {
+                        uncovered as f64 / solid_cells as f64
+                    };
+                    (uncovered as f64 * grid.cell_area(), fraction)
+                }
+            };
+
+        HatchDensity {
+            spacing,
+            uncovered_area,
+            uncovered_fraction,
+        }
+    }
+}
+
+impl<'a, T: CLIType> CLI<'a, T> {
+    /// Indices of the layers where more than `max_uncovered` of the solid area is not covered by
+    /// melt tracks of `beam_width` millimeters, i.e. layers likely to contain lack-of-fusion
+    /// porosity. `resolution` is in millimeters, see [`Layer::hatch_density`].
+    pub fn porous_layers(
+        &self,
+        beam_width: f64,
+        resolution: f64,
+        max_uncovered: f64,
+    ) -> Vec<usize> {
+        let units = self.header.units;
+        self.layers
+            .iter()
+            .enumerate()
+            .filter(|(_, l)| {
+                l.hatch_density(beam_width / units, resolution / units)
+                    .uncovered_fraction
+                    > max_uncovered
+            })
+            .map(|(i, _)| i)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::clitype::LongCLI;
+    use crate::{Hatches, Header, Layer, Loop, CLI};
+    use std::borrow::Cow;
+
+    fn layer(spacing: f32) -> Layer<'static, LongCLI> {
+        let mut points = Vec::new();
+        let mut y = spacing / 2.0;
+        while y < 10.0 {
+            points.extend([0.0, y, 10.0, y]);
+            y += spacing;
+        }
+        Layer {
+            loops: vec![Loop {
+                id: 0,
+                dir: 1,
+                points: Cow::Owned(vec![0.0, 0.0, 10.0, 0.0, 10.0, 10.0, 0.0, 10.0, 0.0, 0.0]),
+                params: Default::default(),
+            }],
+            hatches: vec![Hatches {
+                id: 0,
+                points: Cow::Owned(points),
+                params: Default::default(),
+            }],
+            ..Layer::empty(1.0)
+        }
+    }
+
+    #[test]
+    fn density() {
+        let dense = layer(0.1).hatch_density(0.12, 0.02);
+        let (mean, min, max, _) = dense.spacing.unwrap();
+        assert!((mean - 0.1).abs() < 1e-4 && (max - min) < 1e-4);
+        assert!(dense.uncovered_fraction < 0.01);
+
+        let sparse = layer(0.2).hatch_density(0.1, 0.02);
+        assert!(
+            (sparse.uncovered_fraction - 0.5).abs() < 0.1,
+            "{}",
+            sparse.uncovered_fraction
+        );
+
+        let model = CLI {
+            header: Header::default(),
+            layers: vec![layer(0.1), layer(0.2)],
+            verbatim: Default::default(),
+        };
+        assert_eq!(model.porous_layers(0.12, 0.02, 0.05), vec![1]);
+    }
+}
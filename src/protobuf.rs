@@ -0,0 +1,476 @@
+//! Protobuf interchange of parsed models
+//!
+//! Services in a build pipeline that are written in other languages can exchange parsed models as
+//! protobuf messages instead of each parsing the CLI file again. Enable the `protobuf` feature to
+//! use this module. The schema is `proto/colain.proto` in the repository and the messages in
+//! [`proto`] are a faithful implementation of it: the header and every layer, including process
+//! parameters and user data, survive a round trip. The text the model was parsed from, that is
+//! [`CLI::raw_header`] and [`CLI::unknown_records`], is not carried.
+//!
+//! Coordinates are stored in coordinate units as doubles, which represents the values of both
+//! [`ShortCLI`](crate::clitype::ShortCLI) and [`LongCLI`](crate::clitype::LongCLI) exactly.
+//!
+//! ```
+//! use colain::{CLI, clitype::LongCLI, protobuf};
+//! use prost::Message;
+//! # let buf = colain::BuildPlate::<LongCLI>::new(1.0).to_bytes();
+//!
+//! let model = CLI::<LongCLI>::new(&buf).unwrap();
+//! let bytes = model.to_proto().encode_to_vec();
+//!
+//! let message = protobuf::proto::Model::decode(bytes.as_slice()).unwrap();
+//! let model = CLI::<LongCLI>::from_proto(&message).unwrap();
+//! ```
+
+use crate::clitype::CLIType;
+use crate::{
+    Block, Date, Error, Hatches, Header, HeaderUserData, Layer, Loop, UserData, VectorParams, CLI,
+};
+use std::borrow::Cow;
+
+/// Messages of `proto/colain.proto`
+pub mod proto {
+    /// A complete model
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct Model {
+        /// The header
+        #[prost(message, optional, tag = "1")]
+        pub header: Option<Header>,
+        /// The layers in order
+        #[prost(message, repeated, tag = "2")]
+        pub layers: Vec<Layer>,
+    }
+
+    /// Header of a model, see [`crate::Header`]
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct Header {
+        /// True for binary files
+        #[prost(bool, tag = "1")]
+        pub binary: bool,
+        /// Millimeters per coordinate unit
+        #[prost(double, tag = "2")]
+        pub units: f64,
+        /// CLI version
+        #[prost(float, tag = "3")]
+        pub version: f32,
+        /// True if the binary file is aligned
+        #[prost(bool, tag = "4")]
+        pub aligned: bool,
+        /// Number of layers declared by the header
+        #[prost(uint64, optional, tag = "5")]
+        pub layers: Option<u64>,
+        /// Build date as written in the header
+        #[prost(string, optional, tag = "6")]
+        pub date: Option<String>,
+        /// Box around the part as x1, y1, z1, x2, y2, z2 in millimeters, empty if not declared
+        #[prost(double, repeated, tag = "7")]
+        pub dimension: Vec<f64>,
+        /// Names given to the ids of loops and hatches
+        #[prost(btree_map = "int64, string", tag = "8")]
+        pub labels: std::collections::BTreeMap<i64, String>,
+        /// Vendor specific entries in the order they appear
+        #[prost(message, repeated, tag = "9")]
+        pub user_data: Vec<HeaderUserData>,
+        /// Commands not known to the crate in the order they appear
+        #[prost(message, repeated, tag = "10")]
+        pub extra: Vec<Extra>,
+    }
+
+    /// A vendor specific header entry, see [`crate::HeaderUserData`]
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct HeaderUserData {
+        /// Identifier of the vendor or application
+        #[prost(string, tag = "1")]
+        pub uid: String,
+        /// The payload
+        #[prost(bytes = "vec", tag = "2")]
+        pub data: Vec<u8>,
+    }
+
+    /// A header command not known to the crate, see [`crate::Header::extra`]
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct Extra {
+        /// Name of the command
+        #[prost(string, tag = "1")]
+        pub name: String,
+        /// Its value, empty if it has none
+        #[prost(string, tag = "2")]
+        pub value: String,
+    }
+
+    /// A layer, see [`crate::Layer`]
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct Layer {
+        /// Height in coordinate units
+        #[prost(double, tag = "1")]
+        pub height: f64,
+        /// Loops in file order
+        #[prost(message, repeated, tag = "2")]
+        pub loops: Vec<Loop>,
+        /// Hatch blocks in file order
+        #[prost(message, repeated, tag = "3")]
+        pub hatches: Vec<Hatches>,
+        /// User data records
+        #[prost(message, repeated, tag = "4")]
+        pub user_data: Vec<UserData>,
+        /// Kind of each geometry block in file order, true for hatches
+        #[prost(bool, repeated, tag = "5")]
+        pub blocks: Vec<bool>,
+    }
+
+    /// A loop, see [`crate::Loop`]
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct Loop {
+        /// Identifier
+        #[prost(int64, tag = "1")]
+        pub id: i64,
+        /// Direction, 2 for open lines
+        #[prost(int64, tag = "2")]
+        pub dir: i64,
+        /// Interleaved x and y coordinates
+        #[prost(double, repeated, tag = "3")]
+        pub points: Vec<f64>,
+        /// Process parameters
+        #[prost(message, optional, tag = "4")]
+        pub params: Option<Params>,
+    }
+
+    /// A hatch block, see [`crate::Hatches`]
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct Hatches {
+        /// Identifier
+        #[prost(int64, tag = "1")]
+        pub id: i64,
+        /// Interleaved start and end points
+        #[prost(double, repeated, tag = "2")]
+        pub points: Vec<f64>,
+        /// Process parameters
+        #[prost(message, optional, tag = "3")]
+        pub params: Option<Params>,
+    }
+
+    /// Per-vector process parameters, see [`crate::VectorParams`]
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct Params {
+        /// Laser power of each vector
+        #[prost(message, optional, tag = "1")]
+        pub power: Option<Values>,
+        /// Scan speed of each vector
+        #[prost(message, optional, tag = "2")]
+        pub speed: Option<Values>,
+    }
+
+    /// A list of parameter values
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct Values {
+        /// The values
+        #[prost(double, repeated, tag = "1")]
+        pub values: Vec<f64>,
+    }
+
+    /// A user data record, see [`crate::UserData`]
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct UserData {
+        /// Identifier
+        #[prost(int64, tag = "1")]
+        pub id: i64,
+        /// Number of geometry blocks preceding the record
+        #[prost(uint64, tag = "2")]
+        pub position: u64,
+        /// The payload
+        #[prost(bytes = "vec", tag = "3")]
+        pub data: Vec<u8>,
+    }
+}
+
+fn values<T: CLIType>(coords: &[<T as CLIType>::Coord]) -> Vec<f64> {
+    coords.iter().map(|&c| T::coord_to_f64(c)).collect()
+}
+
+fn coords<T: CLIType>(values: &[f64]) -> Vec<<T as CLIType>::Coord> {
+    values.iter().map(|&v| T::coord_from_f64(v)).collect()
+}
+
+/// Coordinates of whole points, `stride` being 2 for loops and 4 for hatches
+fn points<T: CLIType>(
+    values: &[f64],
+    stride: usize,
+) -> Result<Cow<'static, [<T as CLIType>::Coord]>, Error> {
+    if values.len() % stride != 0 {
+        return Err(Error::IncompletePoints);
+    }
+    Ok(Cow::Owned(coords::<T>(values)))
+}
+
+fn params_to_proto<T: CLIType>(params: &VectorParams<'_, T>) -> Option<proto::Params> {
+    if params.is_empty() {
+        return None;
+    }
+    let wrap = |p: Option<&[<T as CLIType>::Coord]>| {
+        p.map(|p| proto::Values {
+            values: values::<T>(p),
+        })
+    };
+    Some(proto::Params {
+        power: wrap(params.power()),
+        speed: wrap(params.speed()),
+    })
+}
+
+fn params_from_proto<T: CLIType>(params: &Option<proto::Params>) -> VectorParams<'static, T> {
+    let unwrap = |v: &Option<proto::Values>| v.as_ref().map(|v| Cow::Owned(coords::<T>(&v.values)));
+    match params {
+        Some(p) => VectorParams {
+            power: unwrap(&p.power),
+            speed: unwrap(&p.speed),
+        },
+        None => VectorParams::default(),
+    }
+}
+
+impl<'a, T: CLIType> CLI<'a, T> {
+    /// Convert the model into its protobuf message, see [`protobuf`](crate::protobuf)
+    pub fn to_proto(&self) -> proto::Model {
+        let header = &self.header;
+        proto::Model {
+            header: Some(proto::Header {
+                binary: header.binary,
+                units: header.units,
+                version: header.version,
+                aligned: header.aligned,
+                layers: header.layers.map(|n| n as u64),
+                date: header.date.as_ref().map(|d| d.raw.clone()),
+                dimension: header.dimension.map(Vec::from).unwrap_or_default(),
+                labels: header.labels.clone(),
+                user_data: header
+                    .user_data
+                    .iter()
+                    .map(|u| proto::HeaderUserData {
+                        uid: u.uid.clone(),
+                        data: u.data.clone(),
+                    })
+                    .collect(),
+                extra: header
+                    .extra
+                    .iter()
+                    .map(|(name, value)| proto::Extra {
+                        name: name.clone(),
+                        value: value.clone(),
+                    })
+                    .collect(),
+            }),
+            layers: self
+                .layers
+                .iter()
+                .map(|layer| proto::Layer {
+                    height: T::coord_to_f64(layer.height),
+                    loops: layer
+                        .loops
+                        .iter()
+                        .map(|l| proto::Loop {
+                            id: T::meta_to_i64(l.id),
+                            dir: T::meta_to_i64(l.dir),
+                            points: values::<T>(&l.points),
+                            params: params_to_proto(&l.params),
+                        })
+                        .collect(),
+                    hatches: layer
+                        .hatches
+                        .iter()
+                        .map(|h| proto::Hatches {
+                            id: T::meta_to_i64(h.id),
+                            points: values::<T>(&h.points),
+                            params: params_to_proto(&h.params),
+                        })
+                        .collect(),
+                    user_data: layer
+                        .user_data
+                        .iter()
+                        .map(|u| proto::UserData {
+                            id: T::meta_to_i64(u.id),
+                            position: u.position as u64,
+                            data: u.data.to_vec(),
+                        })
+                        .collect(),
+                    blocks: layer.order.iter().map(|&b| b == Block::Hatches).collect(),
+                })
+                .collect(),
+        }
+    }
+
+    /// Convert a protobuf message into a model, see [`protobuf`](crate::protobuf)
+    ///
+    /// Fails with [`Error::NoHeader`] if the message has no header, with
+    /// [`Error::InvalidHeaderValue`] if the dimension does not have 6 values and with
+    /// [`Error::IncompletePoints`] if a loop or set of hatches ends in the middle of a point.
+    pub fn from_proto(model: &proto::Model) -> Result<CLI<'static, T>, Error> {
+        let header = model.header.as_ref().ok_or(Error::NoHeader)?;
+        Ok(CLI {
+            header: Header {
+                binary: header.binary,
+                units: header.units,
+                version: header.version,
+                aligned: header.aligned,
+                layers: header.layers.map(|n| n as usize),
+                date: header.date.as_deref().map(Date::new),
+                dimension: match header.dimension[..] {
+                    [] => None,
+                    [x1, y1, z1, x2, y2, z2] => Some([x1, y1, z1, x2, y2, z2]),
+                    _ => return Err(Error::InvalidHeaderValue),
+                },
+                labels: header.labels.clone(),
+                user_data: header
+                    .user_data
+                    .iter()
+                    .map(|u| HeaderUserData {
+                        uid: u.uid.clone(),
+                        data: u.data.clone(),
+                    })
+                    .collect(),
+                extra: header
+                    .extra
+                    .iter()
+                    .map(|e| (e.name.clone(), e.value.clone()))
+                    .collect(),
+            },
+            layers: model
+                .layers
+                .iter()
+                .map(|layer| {
+                    Ok(Layer {
+                        loops: layer
+                            .loops
+                            .iter()
+                            .map(|l| {
+                                Ok(Loop {
+                                    id: T::meta_from_i64(l.id),
+                                    dir: T::meta_from_i64(l.dir),
+                                    points: points::<T>(&l.points, 2)?,
+                                    params: params_from_proto(&l.params),
+                                })
+                            })
+                            .collect::<Result<_, Error>>()?,
+                        hatches: layer
+                            .hatches
+                            .iter()
+                            .map(|h| {
+                                Ok(Hatches {
+                                    id: T::meta_from_i64(h.id),
+                                    points: points::<T>(&h.points, 4)?,
+                                    params: params_from_proto(&h.params),
+                                })
+                            })
+                            .collect::<Result<_, Error>>()?,
+                        user_data: layer
+                            .user_data
+                            .iter()
+                            .map(|u| UserData {
+                                id: T::meta_from_i64(u.id),
+                                position: u.position as usize,
+                                data: Cow::Owned(u.data.clone()),
+                            })
+                            .collect(),
+                        order: layer
+                            .blocks
+                            .iter()
+                            .map(|&h| if h { Block::Hatches } else { Block::Loop })
+                            .collect(),
+                        ..Layer::empty(T::coord_from_f64(layer.height))
+                    })
+                })
+                .collect::<Result<_, Error>>()?,
+            verbatim: Default::default(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clitype::ShortCLI;
+    use prost::Message;
+
+    #[test]
+    fn round_trip() {
+        let model = CLI::<ShortCLI> {
+            header: Header {
+                units: 0.005,
+                aligned: true,
+                layers: Some(1),
+                date: Some(Date::new("070493")),
+                dimension: Some([0.0, 0.0, 0.0, 50.0, 25.0, 0.1]),
+                labels: vec![(4, "contour".to_string())].into_iter().collect(),
+                user_data: vec![HeaderUserData {
+                    uid: "VENDOR".to_string(),
+                    data: b"\x00,\n".to_vec(),
+                }],
+                extra: vec![("VENDOR".to_string(), "x=1".to_string())],
+                ..Header::default()
+            },
+            layers: vec![Layer {
+                loops: vec![Loop {
+                    id: 4,
+                    dir: 2,
+                    points: Cow::Owned(vec![0, 0, 10, 0, 65535, 10]),
+                    params: VectorParams {
+                        power: Some(Cow::Owned(vec![100, 200])),
+                        speed: None,
+                    },
+                }],
+                hatches: vec![Hatches {
+                    id: 5,
+                    points: Cow::Owned(vec![0, 2, 10, 2]),
+                    params: Default::default(),
+                }],
+                user_data: vec![UserData {
+                    id: 9,
+                    position: 1,
+                    data: Cow::Owned(b"data".to_vec()),
+                }],
+                order: vec![Block::Hatches, Block::Loop],
+                ..Layer::empty(20)
+            }],
+            verbatim: Default::default(),
+        };
+
+        let bytes = model.to_proto().encode_to_vec();
+        let decoded = proto::Model::decode(bytes.as_slice()).unwrap();
+        let back = CLI::<ShortCLI>::from_proto(&decoded).unwrap();
+        assert_eq!(back.to_bytes(), model.to_bytes());
+        assert_eq!(
+            back.layers[0].loops[0].params.power(),
+            Some(&[100, 200][..])
+        );
+        assert!(back.layers[0].loops[0].params.speed().is_none());
+        assert_eq!(back.header.layers, Some(1));
+        assert_eq!(back.header.date, model.header.date);
+        assert_eq!(back.header.dimension, model.header.dimension);
+        assert_eq!(back.header.label(4), Some("contour"));
+        assert_eq!(back.header.user_data, model.header.user_data);
+        assert_eq!(back.header.extra, model.header.extra);
+
+        assert!(matches!(
+            CLI::<ShortCLI>::from_proto(&proto::Model::default()),
+            Err(Error::NoHeader)
+        ));
+
+        let mut dimension = model.to_proto();
+        dimension.header.as_mut().unwrap().dimension.pop();
+        assert!(matches!(
+            CLI::<ShortCLI>::from_proto(&dimension),
+            Err(Error::InvalidHeaderValue)
+        ));
+        let mut odd = model.to_proto();
+        odd.layers[0].loops[0].points.pop();
+        assert!(matches!(
+            CLI::<ShortCLI>::from_proto(&odd),
+            Err(Error::IncompletePoints)
+        ));
+        let mut partial = model.to_proto();
+        partial.layers[0].hatches[0].points.truncate(2);
+        assert!(matches!(
+            CLI::<ShortCLI>::from_proto(&partial),
+            Err(Error::IncompletePoints)
+        ));
+    }
+}
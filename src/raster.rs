@@ -0,0 +1,360 @@
+//! Rasterization of layer geometry for approximate area calculations
+
+use crate::geom::BoundingBox;
+use std::collections::HashMap;
+
+/// Most cells of a grid, see [`resolution`]
+pub(crate) const MAX_CELLS: usize = 1 << 24;
+
+/// Resolution to rasterize a bounding box with.
+///
+/// A resolution that is not positive and finite, or so fine that a grid covering the box with a
+/// margin of one cell would have more than [`MAX_CELLS`] cells, is replaced by the finest
+/// resolution that keeps a sixteenth of that budget along each axis and in the area.
+pub(crate) fn resolution(bb: &BoundingBox, resolution: f64) -> f64 {
+    let (w, h) = (bb.max[0] - bb.min[0], bb.max[1] - bb.min[1]);
+    // At most a sixteenth of the cells along each axis and in the area, leaving room for the
+    // rounding up and the margin
+    let budget = (MAX_CELLS / 16) as f64;
+    let finest = f64::max((w * h / budget).sqrt(), w.max(h) / budget).max(f64::MIN_POSITIVE);
+    if resolution.is_finite() && resolution >= finest {
+        resolution
+    } else {
+        finest
+    }
+}
+
+/// A regular grid of square cells
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Grid {
+    /// Lower left corner of the first cell
+    pub origin: [f64; 2],
+    /// Edge length of a cell
+    pub resolution: f64,
+    /// Number of columns
+    pub width: usize,
+    /// Number of rows
+    pub height: usize,
+}
+
+impl Grid {
+    /// Smallest grid of the given resolution covering a bounding box.
+    ///
+    /// The resolution has to be one given by [`resolution`] for the box, or for the box without
+    /// a margin of one cell.
+    pub fn covering(bb: &BoundingBox, resolution: f64) -> Self {
+        let cells = |span: f64| ((span / resolution).ceil() as usize).max(1);
+        Grid {
+            origin: bb.min,
+            resolution,
+            width: cells(bb.max[0] - bb.min[0]),
+            height: cells(bb.max[1] - bb.min[1]),
+        }
+    }
+
+    /// Area of a single cell
+    pub fn cell_area(&self) -> f64 {
+        self.resolution * self.resolution
+    }
+
+    /// Total number of cells
+    pub fn len(&self) -> usize {
+        self.width * self.height
+    }
+}
+
+/// Even-odd fill of a set of closed polygons, sampled at the cell centers.
+///
+/// The returned mask is stored row by row starting at the bottom of the grid.
+pub(crate) fn fill(grid: &Grid, polygons: &[Vec<[f64; 2]>]) -> Vec<bool> {
+    let mut mask = vec![false; grid.len()];
+    let mut crossings = Vec::new();
+    let column = |x: f64| {
+        let c = ((x - grid.origin[0]) / grid.resolution - 0.5).ceil();
+        (c.max(0.0) as usize).min(grid.width)
+    };
+
+    for row in 0..grid.height {
+        let y = grid.origin[1] + (row as f64 + 0.5) * grid.resolution;
+        crossings.clear();
+        for poly in polygons {
+            for (i, a) in poly.iter().enumerate() {
+                let b = poly[(i + 1) % poly.len()];
+                if (a[1] > y) != (b[1] > y) {
+                    crossings.push(a[0] + (y - a[1]) / (b[1] - a[1]) * (b[0] - a[0]));
+                }
+            }
+        }
+        crossings.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+
+        let cells = &mut mask[row * grid.width..(row + 1) * grid.width];
+        for span in crossings.chunks_exact(2) {
+            for cell in cells[column(span[0])..column(span[1])].iter_mut() {
+                *cell = true;
+            }
+        }
+    }
+    mask
+}
+
+/// Trace the outlines of the set cells of a mask.
+///
+/// Outlines follow the cell edges and are returned as closed polygons (repeating the first point)
+/// in world coordinates, counter-clockwise around set cells and clockwise around holes. Cells
+/// touching only at a corner are not connected.
+pub(crate) fn contours(grid: &Grid, mask: &[bool]) -> Vec<Vec<[f64; 2]>> {
+    let set = |x: i64, y: i64| {
+        x >= 0
+            && y >= 0
+            && (x as usize) < grid.width
+            && (y as usize) < grid.height
+            && mask[y as usize * grid.width + x as usize]
+    };
+
+    // Directed boundary edges with the set cell on their left
+    let mut edges: Vec<([i64; 2], [i64; 2])> = Vec::new();
+    for y in 0..grid.height as i64 {
+        for x in 0..grid.width as i64 {
+            if !set(x, y) {
+                continue;
+            }
+            if !set(x, y - 1) {
+                edges.push(([x, y], [x + 1, y]));
+            }
+            if !set(x + 1, y) {
+                edges.push(([x + 1, y], [x + 1, y + 1]));
+            }
+            if !set(x, y + 1) {
+                edges.push(([x + 1, y + 1], [x, y + 1]));
+            }
+            if !set(x - 1, y) {
+                edges.push(([x, y + 1], [x, y]));
+            }
+        }
+    }
+    let mut outgoing: HashMap<[i64; 2], Vec<usize>> = HashMap::new();
+    for (i, e) in edges.iter().enumerate() {
+        outgoing.entry(e.0).or_default().push(i);
+    }
+
+    let mut used = vec![false; edges.len()];
+    let mut found = Vec::new();
+    for first in 0..edges.len() {
+        if used[first] {
+            continue;
+        }
+        let mut vertices = vec![edges[first].0];
+        let mut current = first;
+        loop {
+            used[current] = true;
+            let (from, to) = edges[current];
+            let dir = [to[0] - from[0], to[1] - from[1]];
+            vertices.push(to);
+            // Where two outlines touch at a corner, turn left to keep them apart
+            let next = outgoing[&to]
+                .iter()
+                .copied()
+                .filter(|&e| !used[e])
+                .max_by_key(|&e| {
+                    let d = [edges[e].1[0] - to[0], edges[e].1[1] - to[1]];
+                    dir[0] * d[1] - dir[1] * d[0]
+                });
+            match next {
+                Some(e) => current = e,
+                None => break,
+            }
+        }
+
+        // Drop the vertices in the middle of straight runs
+        let mut polygon: Vec<[i64; 2]> = Vec::new();
+        vertices.pop();
+        let n = vertices.len();
+        for i in 0..n {
+            let (a, b, c) = (
+                vertices[(i + n - 1) % n],
+                vertices[i],
+                vertices[(i + 1) % n],
+            );
+            if (b[0] - a[0]) * (c[1] - b[1]) != (b[1] - a[1]) * (c[0] - b[0]) {
+                polygon.push(b);
+            }
+        }
+        if let Some(&start) = polygon.first() {
+            polygon.push(start);
+            found.push(
+                polygon
+                    .into_iter()
+                    .map(|[x, y]| {
+                        [
+                            grid.origin[0] + x as f64 * grid.resolution,
+                            grid.origin[1] + y as f64 * grid.resolution,
+                        ]
+                    })
+                    .collect(),
+            );
+        }
+    }
+    found
+}
+
+/// Euclidean distance from every cell center to the nearest set cell center of a mask.
+///
+/// Cells are infinitely far away if the mask is empty. Uses the separable algorithm of
+/// Felzenszwalb and Huttenlocher, linear in the number of cells.
+pub(crate) fn distance(grid: &Grid, mask: &[bool]) -> Vec<f64> {
+    let mut d: Vec<f64> = mask
+        .iter()
+        .map(|&set| if set { 0.0 } else { f64::INFINITY })
+        .collect();
+    let mut line = Vec::new();
+    // columns
+    for x in 0..grid.width {
+        line.clear();
+        line.extend((0..grid.height).map(|y| d[y * grid.width + x]));
+        for (y, v) in transform(&line).into_iter().enumerate() {
+            d[y * grid.width + x] = v;
+        }
+    }
+    // rows
+    for y in 0..grid.height {
+        let row = &mut d[y * grid.width..(y + 1) * grid.width];
+        let t = transform(row);
+        row.copy_from_slice(&t);
+    }
+    d.into_iter().map(|v| v.sqrt() * grid.resolution).collect()
+}
+
+/// One dimensional squared distance transform of a sampled function
+fn transform(f: &[f64]) -> Vec<f64> {
+    let intersection = |p: usize, q: usize| {
+        ((f[q] + (q * q) as f64) - (f[p] + (p * p) as f64)) / (2.0 * (q as f64 - p as f64))
+    };
+    // Parabolas of the lower envelope and where each of them starts
+    let mut v: Vec<usize> = Vec::new();
+    let mut z: Vec<f64> = Vec::new();
+    for q in (0..f.len()).filter(|&q| f[q].is_finite()) {
+        while let (Some(&p), Some(&start)) = (v.last(), z.last()) {
+            if intersection(p, q) <= start {
+                v.pop();
+                z.pop();
+            } else {
+                break;
+            }
+        }
+        z.push(v.last().map_or(f64::NEG_INFINITY, |&p| intersection(p, q)));
+        v.push(q);
+    }
+    if v.is_empty() {
+        return vec![f64::INFINITY; f.len()];
+    }
+    z.push(f64::INFINITY);
+
+    let mut j = 0;
+    (0..f.len())
+        .map(|q| {
+            while z[j + 1] < q as f64 {
+                j += 1;
+            }
+            let d = q as f64 - v[j] as f64;
+            d * d + f[v[j]]
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn square_with_hole() {
+        let outer = vec![[0.0, 0.0], [10.0, 0.0], [10.0, 10.0], [0.0, 10.0]];
+        let hole = vec![[2.0, 2.0], [2.0, 8.0], [8.0, 8.0], [8.0, 2.0]];
+        let grid = Grid::covering(
+            &BoundingBox {
+                min: [0.0, 0.0],
+                max: [10.0, 10.0],
+            },
+            0.5,
+        );
+        let mask = fill(&grid, &[outer, hole]);
+        let area = mask.iter().filter(|&&c| c).count() as f64 * grid.cell_area();
+        assert!((area - 64.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn distance_transform() {
+        let grid = Grid {
+            origin: [0.0, 0.0],
+            resolution: 0.5,
+            width: 5,
+            height: 4,
+        };
+        let mut mask = vec![false; grid.len()];
+        mask[0] = true;
+        let d = distance(&grid, &mask);
+        assert_eq!(d[0], 0.0);
+        assert_eq!(d[4], 2.0);
+        assert!((d[3 * 5 + 4] - 2.5).abs() < 1e-12);
+        assert!(distance(&grid, &vec![false; grid.len()])
+            .iter()
+            .all(|d| d.is_infinite()));
+    }
+
+    #[test]
+    fn resolution_limits() {
+        let bb = BoundingBox {
+            min: [0.0, 0.0],
+            max: [1000.0, 1.0],
+        };
+        assert_eq!(resolution(&bb, 0.5), 0.5);
+        for r in [0.0, -1.0, 1e-300, f64::NAN, f64::INFINITY] {
+            let r = resolution(&bb, r);
+            let margin = BoundingBox {
+                min: [bb.min[0] - r, bb.min[1] - r],
+                max: [bb.max[0] + r, bb.max[1] + r],
+            };
+            assert!(r > 0.0 && Grid::covering(&margin, r).len() <= MAX_CELLS);
+        }
+        let point = BoundingBox {
+            min: [1.0, 1.0],
+            max: [1.0, 1.0],
+        };
+        assert_eq!(Grid::covering(&point, resolution(&point, 0.0)).len(), 1);
+    }
+
+    #[test]
+    fn outlines() {
+        let grid = Grid {
+            origin: [1.0, 0.0],
+            resolution: 1.0,
+            width: 4,
+            height: 4,
+        };
+        // A 3x3 block with a hole in the middle and a separate cell touching a corner
+        let mut mask = vec![false; grid.len()];
+        for y in 0..3 {
+            for x in 0..3 {
+                mask[y * 4 + x] = (x, y) != (1, 1);
+            }
+        }
+        mask[3 * 4 + 3] = true;
+        let contours = contours(&grid, &mask);
+        assert_eq!(contours.len(), 3);
+        let areas: Vec<f64> = contours
+            .iter()
+            .map(|c| crate::geom::signed_area(c))
+            .collect();
+        assert!(areas.contains(&9.0));
+        assert!(areas.contains(&-1.0));
+        assert!(areas.contains(&1.0));
+        assert!(contours.iter().all(|c| c.first() == c.last()));
+        assert_eq!(
+            contours
+                .iter()
+                .find(|c| crate::geom::signed_area(c) == 9.0)
+                .unwrap()
+                .len(),
+            5
+        );
+    }
+}
@@ -0,0 +1,172 @@
+//! Grouping of hatch vectors into scan islands
+//!
+//! Scan strategies such as chessboard or island scanning split the hatching of a layer into
+//! patches that are exposed one after another. The hatch blocks of a file do not necessarily
+//! follow these patches, so [`Layer::hatch_clusters`] recovers them from the geometry.
+
+use crate::clitype::CLIType;
+use crate::geom::{self, BoundingBox};
+use crate::Layer;
+use std::collections::HashMap;
+
+/// Spatially connected group of hatch vectors within a layer
+///
+/// All values are in coordinate units.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HatchCluster {
+    /// (hatch block, hatch index) pairs of the vectors in the cluster, in file order
+    pub vectors: Vec<(usize, usize)>,
+    /// Lower left corner of the bounding box
+    pub min: [f64; 2],
+    /// Upper right corner of the bounding box
+    pub max: [f64; 2],
+    /// Total length of the vectors
+    pub length: f64,
+}
+
+/// Disjoint sets with path halving
+struct UnionFind(Vec<usize>);
+
+impl UnionFind {
+    fn find(&mut self, mut i: usize) -> usize {
+        while self.0[i] != i {
+            self.0[i] = self.0[self.0[i]];
+            i = self.0[i];
+        }
+        i
+    }
+
+    fn union(&mut self, a: usize, b: usize) {
+        let (a, b) = (self.find(a), self.find(b));
+        if a != b {
+            self.0[a.max(b)] = a.min(b);
+        }
+    }
+}
+
+impl<'a, T: CLIType> Layer<'a, T> {
+    /// Group the hatch vectors of the layer into clusters of vectors that are at most `max_gap`
+    /// apart, across all hatch blocks.
+    ///
+    /// `max_gap` should be a little larger than the hatch distance, so that neighbouring vectors
+    /// of a patch are connected but separate patches are not. Clusters are ordered by their first
+    /// vector in file order.
+    pub fn hatch_clusters(&self, max_gap: f64) -> Vec<HatchCluster> {
+        let mut ids = Vec::new();
+        let mut segments = Vec::new();
+        for (b, block) in self.hatches.iter().enumerate() {
+            for (i, h) in block.iter().enumerate() {
+                let f = |c| T::coord_to_f64(c);
+                ids.push((b, i));
+                segments.push([[f(h[0]), f(h[1])], [f(h[2]), f(h[3])]]);
+            }
+        }
+        if segments.is_empty() {
+            return Vec::new();
+        }
+
+        // Bucket the segments on a grid sized to the typical vector so each lands in a few cells
+        let mean =
+            segments.iter().map(|s| geom::path_length(s)).sum::<f64>() / segments.len() as f64;
+        let cell = mean.max(max_gap).max(f64::EPSILON);
+        let mut grid: HashMap<(i64, i64), Vec<usize>> = HashMap::new();
+        for (i, s) in segments.iter().enumerate() {
+            let bb = BoundingBox::of(s.iter().copied()).unwrap();
+            let lo = |v: f64| ((v - max_gap) / cell).floor() as i64;
+            let hi = |v: f64| ((v + max_gap) / cell).floor() as i64;
+            for x in lo(bb.min[0])..=hi(bb.max[0]) {
+                for y in lo(bb.min[1])..=hi(bb.max[1]) {
+                    grid.entry((x, y)).or_default().push(i);
+                }
+            }
+        }
+
+        let mut sets = UnionFind((0..segments.len()).collect());
+        for bucket in grid.values() {
+            for (k, &i) in bucket.iter().enumerate() {
+                for &j in &bucket[k + 1..] {
+                    if sets.find(i) != sets.find(j)
+                        && geom::segment_distance(segments[i], segments[j]) <= max_gap
+                    {
+                        sets.union(i, j);
+                    }
+                }
+            }
+        }
+
+        let mut clusters: Vec<HatchCluster> = Vec::new();
+        let mut index: HashMap<usize, usize> = HashMap::new();
+        for (i, s) in segments.iter().enumerate() {
+            let root = sets.find(i);
+            let c = *index.entry(root).or_insert_with(|| {
+                clusters.push(HatchCluster {
+                    vectors: Vec::new(),
+                    min: s[0],
+                    max: s[0],
+                    length: 0.0,
+                });
+                clusters.len() - 1
+            });
+            let cluster = &mut clusters[c];
+            let mut bb = BoundingBox {
+                min: cluster.min,
+                max: cluster.max,
+            };
+            bb.add(s[0]);
+            bb.add(s[1]);
+            cluster.min = bb.min;
+            cluster.max = bb.max;
+            cluster.vectors.push(ids[i]);
+            cluster.length += geom::path_length(s);
+        }
+        clusters
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::clitype::LongCLI;
+    use crate::{Hatches, Layer};
+    use std::borrow::Cow;
+
+    /// Five horizontal vectors of length 10 spaced 0.1 apart, starting at `x`
+    fn patch(x: f32) -> Vec<f32> {
+        (0..5)
+            .flat_map(|i| {
+                let y = i as f32 * 0.1;
+                [x, y, x + 10.0, y]
+            })
+            .collect()
+    }
+
+    #[test]
+    fn two_patches() {
+        let mut first = patch(0.0);
+        first.extend(patch(20.0).drain(..8));
+        let layer = Layer::<LongCLI> {
+            hatches: vec![
+                Hatches {
+                    id: 0,
+                    points: Cow::Owned(first),
+                    params: Default::default(),
+                },
+                Hatches {
+                    id: 0,
+                    points: Cow::Owned(patch(20.0)[8..].to_vec()),
+                    params: Default::default(),
+                },
+            ],
+            ..Layer::empty(1.0)
+        };
+
+        let clusters = layer.hatch_clusters(0.15);
+        assert_eq!(clusters.len(), 2);
+        assert_eq!(clusters[0].vectors.len(), 5);
+        assert_eq!(clusters[1].vectors[..3], [(0, 5), (0, 6), (1, 0)]);
+        assert!((clusters[1].length - 50.0).abs() < 1e-4);
+        assert_eq!(clusters[1].min, [20.0, 0.0]);
+        assert!((clusters[1].max[1] - 0.4).abs() < 1e-6);
+
+        assert_eq!(layer.hatch_clusters(10.5).len(), 1);
+    }
+}
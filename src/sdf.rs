@@ -0,0 +1,144 @@
+//! Signed distance fields of layers
+//!
+//! A signed distance field stores, for every cell of a grid, the distance to the boundary of the
+//! solid region, negative inside of it. Offsetting contours, finding thin features and rendering on
+//! a GPU can all work from this single precomputed structure.
+
+use crate::clitype::CLIType;
+use crate::geom::BoundingBox;
+use crate::raster::{self, Grid};
+use crate::Layer;
+
+/// Signed distances sampled on a regular grid, see [`Layer::distance_field`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct DistanceField {
+    /// Lower left corner of the first cell
+    pub origin: [f64; 2],
+    /// Edge length of a cell
+    pub resolution: f64,
+    /// Number of columns
+    pub width: usize,
+    /// Number of rows
+    pub height: usize,
+    /// Distance at the center of each cell, row by row starting at the bottom
+    pub values: Vec<f64>,
+}
+
+impl DistanceField {
+    /// Distance at the center of a cell
+    pub fn get(&self, column: usize, row: usize) -> Option<f64> {
+        if column < self.width && row < self.height {
+            Some(self.values[row * self.width + column])
+        } else {
+            None
+        }
+    }
+
+    /// Distance at an arbitrary point, interpolated bilinearly between the cell centers.
+    ///
+    /// `None` outside of the grid.
+    pub fn sample(&self, p: [f64; 2]) -> Option<f64> {
+        let x = (p[0] - self.origin[0]) / self.resolution - 0.5;
+        let y = (p[1] - self.origin[1]) / self.resolution - 0.5;
+        if self.width == 0 || self.height == 0 || x < -0.5 || y < -0.5 {
+            return None;
+        }
+        let clamp = |v: f64, n: usize| v.clamp(0.0, (n - 1) as f64);
+        let (x, y) = (clamp(x, self.width), clamp(y, self.height));
+        let (x0, y0) = (x.floor() as usize, y.floor() as usize);
+        if p[0] > self.origin[0] + self.width as f64 * self.resolution
+            || p[1] > self.origin[1] + self.height as f64 * self.resolution
+        {
+            return None;
+        }
+        let (x1, y1) = ((x0 + 1).min(self.width - 1), (y0 + 1).min(self.height - 1));
+        let (fx, fy) = (x - x0 as f64, y - y0 as f64);
+        let v = |c, r| self.values[r * self.width + c];
+        let bottom = v(x0, y0) * (1.0 - fx) + v(x1, y0) * fx;
+        let top = v(x0, y1) * (1.0 - fx) + v(x1, y1) * fx;
+        Some(bottom * (1.0 - fy) + top * fy)
+    }
+}
+
+impl<'a, T: CLIType> Layer<'a, T> {
+    /// Signed distance field of the solid region of the layer.
+    ///
+    /// The grid has square cells of `resolution` coordinate units and covers the layer with a margin
+    /// of one cell. Distances are in coordinate units, negative inside the solid region and
+    /// accurate to about half a cell. An empty layer gives an empty grid.
+    ///
+    /// The grid has at most 16 million cells, a resolution that is finer, or not positive and
+    /// finite, is replaced by a coarser one within that. [`DistanceField::resolution`] holds the
+    /// one used.
+    pub fn distance_field(&self, resolution: f64) -> DistanceField {
+        let solid = self.solid(1.0);
+        let bb = match BoundingBox::of(solid.iter().flatten().copied()) {
+            Some(bb) => bb,
+            None => {
+                return DistanceField {
+                    origin: [0.0, 0.0],
+                    resolution,
+                    width: 0,
+                    height: 0,
+                    values: Vec::new(),
+                }
+            }
+        };
+        let resolution = raster::resolution(&bb, resolution);
+        let bb = BoundingBox {
+            min: [bb.min[0] - resolution, bb.min[1] - resolution],
+            max: [bb.max[0] + resolution, bb.max[1] + resolution],
+        };
+        let grid = Grid::covering(&bb, resolution);
+        let inside = raster::fill(&grid, &solid);
+        let outside: Vec<bool> = inside.iter().map(|&i| !i).collect();
+        let to_inside = raster::distance(&grid, &inside);
+        let to_outside = raster::distance(&grid, &outside);
+
+        // Distances between cell centers are half a cell longer than to the boundary between them
+        let values = (0..grid.len())
+            .map(|i| {
+                if inside[i] {
+                    -(to_outside[i] - resolution / 2.0)
+                } else {
+                    to_inside[i] - resolution / 2.0
+                }
+            })
+            .collect();
+        DistanceField {
+            origin: grid.origin,
+            resolution,
+            width: grid.width,
+            height: grid.height,
+            values,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::clitype::LongCLI;
+    use crate::{Layer, Loop};
+    use std::borrow::Cow;
+
+    #[test]
+    fn square() {
+        let layer = Layer::<LongCLI> {
+            loops: vec![Loop {
+                id: 0,
+                dir: 1,
+                points: Cow::Owned(vec![0.0, 0.0, 10.0, 0.0, 10.0, 10.0, 0.0, 10.0, 0.0, 0.0]),
+                params: Default::default(),
+            }],
+            ..Layer::empty(1.0)
+        };
+        let field = layer.distance_field(0.5);
+        assert_eq!((field.width, field.height), (22, 22));
+        assert_eq!(field.get(0, 10), Some(0.25));
+        assert!((field.sample([5.0, 5.0]).unwrap() + 5.0).abs() < 0.3);
+        assert!((field.sample([5.0, 0.0]).unwrap()).abs() < 0.3);
+        assert!(field.sample([-0.3, 5.0]).unwrap() > 0.0);
+        assert_eq!(field.sample([50.0, 5.0]), None);
+        assert_eq!(field.get(22, 0), None);
+    }
+}
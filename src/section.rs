@@ -0,0 +1,150 @@
+//! One dimensional cross-sections of a layer
+//!
+//! Intersecting a layer with a straight line gives the intervals along the line that lie inside
+//! solid material. Their lengths are the wall thicknesses in the direction of the line, and their
+//! end points are where a scan vector along the line would have to start and stop.
+
+use crate::clitype::CLIType;
+use crate::Layer;
+
+/// Part of a line that passes through solid material, see [`Layer::section`]
+///
+/// All values are in coordinate units.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SectionInterval {
+    /// Distance of the entry point from the origin of the line, negative behind it
+    pub start: f64,
+    /// Distance of the exit point from the origin of the line
+    pub end: f64,
+    /// Point where the line enters the material
+    pub from: [f64; 2],
+    /// Point where the line leaves the material
+    pub to: [f64; 2],
+}
+
+impl SectionInterval {
+    /// Length of the interval, i.e. the thickness of the material along the line
+    pub fn length(&self) -> f64 {
+        self.end - self.start
+    }
+}
+
+impl<'a, T: CLIType> Layer<'a, T> {
+    /// Intervals where the infinite line through `line[0]` and `line[1]` passes through solid
+    /// material, ordered along the line.
+    ///
+    /// Distances are measured from `line[0]` towards `line[1]`. Solid material is determined with
+    /// the even-odd rule over the closed loops of the layer, so holes are excluded regardless of
+    /// loop direction. Intervals that touch are merged into one. Returns nothing if both points of
+    /// the line are the same.
+    pub fn section(&self, line: [[f64; 2]; 2]) -> Vec<SectionInterval> {
+        let [origin, through] = line;
+        let d = [through[0] - origin[0], through[1] - origin[1]];
+        let len = d[0].hypot(d[1]);
+        if len == 0.0 {
+            return Vec::new();
+        }
+        let dir = [d[0] / len, d[1] / len];
+        // Distance along the line and signed distance from it
+        let local = |p: [f64; 2]| {
+            let v = [p[0] - origin[0], p[1] - origin[1]];
+            [v[0] * dir[0] + v[1] * dir[1], v[1] * dir[0] - v[0] * dir[1]]
+        };
+
+        let mut crossings = Vec::new();
+        for polygon in self.solid(1.0) {
+            let points: Vec<[f64; 2]> = polygon.into_iter().map(local).collect();
+            for (i, a) in points.iter().enumerate() {
+                let b = points[(i + 1) % points.len()];
+                // Half-open test, so a line through a vertex is counted once
+                if (a[1] > 0.0) != (b[1] > 0.0) {
+                    crossings.push(a[0] + (b[0] - a[0]) * a[1] / (a[1] - b[1]));
+                }
+            }
+        }
+        crossings.sort_by(f64::total_cmp);
+
+        let mut intervals: Vec<SectionInterval> = Vec::new();
+        let at = |t: f64| [origin[0] + t * dir[0], origin[1] + t * dir[1]];
+        for pair in crossings.chunks_exact(2) {
+            let (start, end) = (pair[0], pair[1]);
+            match intervals.last_mut() {
+                Some(last) if last.end >= start => {
+                    last.end = end;
+                    last.to = at(end);
+                }
+                _ if end > start => intervals.push(SectionInterval {
+                    start,
+                    end,
+                    from: at(start),
+                    to: at(end),
+                }),
+                _ => {}
+            }
+        }
+        intervals
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clitype::LongCLI;
+    use crate::Loop;
+    use std::borrow::Cow;
+
+    fn rect(x: [f32; 2], y: [f32; 2], dir: i32) -> Loop<'static, LongCLI> {
+        Loop {
+            id: 0,
+            dir,
+            points: Cow::Owned(vec![
+                x[0], y[0], x[1], y[0], x[1], y[1], x[0], y[1], x[0], y[0],
+            ]),
+            params: Default::default(),
+        }
+    }
+
+    #[test]
+    fn square_with_hole() {
+        let layer = Layer::<LongCLI> {
+            loops: vec![
+                rect([0.0, 10.0], [0.0, 10.0], 1),
+                rect([4.0, 6.0], [4.0, 6.0], 0),
+                rect([20.0, 30.0], [0.0, 30.0], 1),
+            ],
+            ..Layer::empty(1.0)
+        };
+
+        let section = layer.section([[-5.0, 5.0], [0.0, 5.0]]);
+        let spans: Vec<[f64; 2]> = section.iter().map(|s| [s.start, s.end]).collect();
+        assert_eq!(spans, vec![[5.0, 9.0], [11.0, 15.0], [25.0, 35.0]]);
+        assert_eq!(section[1].from, [6.0, 5.0]);
+        assert_eq!(section[2].length(), 10.0);
+
+        // Diagonal through the corners of the outer square and the hole
+        let diagonal = layer.section([[0.0, 0.0], [1.0, 1.0]]);
+        let lengths: Vec<f64> = diagonal.iter().map(|s| s.length()).collect();
+        let s = 2f64.sqrt();
+        assert_eq!(diagonal.len(), 3);
+        for (l, e) in lengths.iter().zip([4.0 * s, 4.0 * s, 10.0 * s]) {
+            assert!((l - e).abs() < 1e-9);
+        }
+
+        assert!(layer.section([[0.0, 50.0], [1.0, 50.0]]).is_empty());
+        assert!(layer.section([[1.0, 1.0], [1.0, 1.0]]).is_empty());
+    }
+
+    #[test]
+    fn touching_loops() {
+        let layer = Layer::<LongCLI> {
+            loops: vec![
+                rect([0.0, 10.0], [0.0, 10.0], 1),
+                rect([10.0, 20.0], [0.0, 10.0], 1),
+            ],
+            ..Layer::empty(1.0)
+        };
+        let section = layer.section([[0.0, 5.0], [1.0, 5.0]]);
+        assert_eq!(section.len(), 1);
+        assert_eq!(section[0].length(), 20.0);
+    }
+}
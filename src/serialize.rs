@@ -0,0 +1,86 @@
+//! serde support for models, enabled by the `serde` feature
+//!
+//! Models serialize as their header and layers. Deserialized models own their data, see
+//! [`OwnedCLI`](crate::OwnedCLI), and are checked like [`CLI::try_extend`]: layers must be in
+//! ascending order of height. The original header text and unknown records are not part of the
+//! serialized form, so writing a deserialized model gives a freshly encoded file.
+
+use crate::clitype::CLIType;
+use crate::{Header, Layer, CLI};
+use serde::de::Error as _;
+use serde::ser::SerializeStruct;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+impl<'a, T: CLIType> Serialize for CLI<'a, T>
+where
+    <T as CLIType>::Coord: Serialize,
+    <T as CLIType>::Meta: Serialize,
+{
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut model = serializer.serialize_struct("CLI", 2)?;
+        model.serialize_field("header", &self.header)?;
+        model.serialize_field("layers", &self.layers)?;
+        model.end()
+    }
+}
+
+/// Serialized form of a model
+#[derive(Deserialize)]
+#[serde(
+    rename = "CLI",
+    bound = "<T as CLIType>::Coord: Deserialize<'de>, <T as CLIType>::Meta: Deserialize<'de>"
+)]
+struct Model<T: CLIType> {
+    header: Header,
+    layers: Vec<Layer<'static, T>>,
+}
+
+impl<'de, T: CLIType> Deserialize<'de> for CLI<'static, T>
+where
+    <T as CLIType>::Coord: Deserialize<'de>,
+    <T as CLIType>::Meta: Deserialize<'de>,
+{
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let Model { header, layers } = Model::<T>::deserialize(deserializer)?;
+        let mut model = CLI {
+            header,
+            layers: Vec::new(),
+            verbatim: Default::default(),
+        };
+        model
+            .try_extend(layers)
+            .map_err(|_| D::Error::custom("layers must be in ascending order of height"))?;
+        Ok(model)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::clitype::{LongCLI, ShortCLI};
+    use crate::{CLIBuilder, OwnedCLI};
+
+    #[test]
+    fn round_trip() {
+        let model = CLIBuilder::new()
+            .short()
+            .units(0.01)
+            .layer(1)
+            .polyline(7, 1, vec![0, 0, 10, 0, 10, 10, 0, 0])
+            .hatches(8, vec![1, 1, 9, 1])
+            .layer(2)
+            .build()
+            .unwrap();
+        let json = serde_json::to_string(&model).unwrap();
+        let back: OwnedCLI<ShortCLI> = serde_json::from_str(&json).unwrap();
+        assert_eq!(back.to_bytes(), model.to_bytes());
+        assert_eq!(back.header().units, 0.01);
+
+        let layer = serde_json::to_value(model.iter().next().unwrap()).unwrap();
+        assert_eq!(layer["loops"][0]["points"][2], 10);
+        assert_eq!(layer["hatches"][0]["id"], 8);
+
+        let unordered = json.replacen("\"height\":1", "\"height\":3", 1);
+        assert!(serde_json::from_str::<OwnedCLI<ShortCLI>>(&unordered).is_err());
+        assert!(serde_json::from_str::<OwnedCLI<LongCLI>>("{\"layers\":[]}").is_err());
+    }
+}
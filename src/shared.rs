@@ -0,0 +1,195 @@
+//! Models shared between threads
+//!
+//! A [`CLI`] borrows its geometry from the buffer it was parsed from, which ties it to the
+//! lifetime of that buffer. [`SharedCLI`] owns the buffer in an [`Arc`] together with the model
+//! borrowing from it, so it can be cloned cheaply and handed to other threads, e.g. a render
+//! thread and an analysis thread working on the same build file.
+//!
+//! ```
+//! use colain::{SharedCLI, clitype::LongCLI};
+//! # let buf = colain::BuildPlate::<LongCLI>::new(1.0).to_bytes();
+//!
+//! let model = SharedCLI::<LongCLI>::new(buf).unwrap();
+//! let worker = model.clone();
+//! let layers = std::thread::spawn(move || worker.model().iter().count());
+//! assert_eq!(layers.join().unwrap(), model.model().iter().count());
+//! ```
+//!
+//! [`CLI::from_reader`] reads a whole file into a [`SharedCLI`], which is the simplest way to load
+//! a model from disk:
+//!
+//! ```no_run
+//! use colain::{CLI, clitype::LongCLI};
+//!
+//! let model = CLI::<LongCLI>::from_reader(std::fs::File::open("example.cli")?)?;
+//! println!("{} layers", model.model().iter().count());
+//! # Ok::<(), colain::Error>(())
+//! ```
+//!
+//! With the `safe` feature the model is a copy of the geometry rather than borrowing from the
+//! buffer, since tying the two together needs unsafe code.
+
+use crate::clitype::{CLIType, LongCLI};
+use crate::{Error, Parser, CLI};
+use std::io::Read;
+use std::sync::Arc;
+
+/// A parsed model sharing ownership of its buffer, see the [module documentation](self)
+///
+/// Clones refer to the same model and buffer.
+pub struct SharedCLI<T: CLIType = LongCLI> {
+    inner: Arc<Shared<T>>,
+}
+
+struct Shared<T: CLIType> {
+    /// Borrows from `buffer`, so it must be dropped first
+    model: CLI<'static, T>,
+    buffer: Arc<[u8]>,
+}
+
+impl<T: CLIType> Clone for SharedCLI<T> {
+    fn clone(&self) -> Self {
+        SharedCLI {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl<T: CLIType> std::fmt::Debug for SharedCLI<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SharedCLI")
+            .field("header", self.model().header())
+            .field("layers", &self.model().layers.len())
+            .field("bytes", &self.inner.buffer.len())
+            .finish()
+    }
+}
+
+impl<T: CLIType> SharedCLI<T> {
+    /// Parse a buffer with the default configuration, see [`CLI::new`]
+    pub fn new(buffer: impl Into<Arc<[u8]>>) -> Result<Self, Error> {
+        Self::with_parser(buffer, &Parser::default())
+    }
+
+    /// Parse a buffer with a configured [`Parser`]
+    pub fn with_parser(buffer: impl Into<Arc<[u8]>>, parser: &Parser<T>) -> Result<Self, Error> {
+        let buffer = buffer.into();
+        #[cfg(not(feature = "safe"))]
+        // SAFETY: the bytes live on the heap as long as the Arc they are shared with, which is
+        // kept next to the model and dropped after it. They are never mutated, and the model is
+        // only handed out with a lifetime bound to `self`.
+        let model = parser.parse(unsafe { &*Arc::as_ptr(&buffer) })?;
+        #[cfg(feature = "safe")]
+        let model = parser.parse(&buffer)?.to_owned_model();
+        Ok(SharedCLI {
+            inner: Arc::new(Shared { model, buffer }),
+        })
+    }
+
+    /// Read everything from `reader` and parse it with the default configuration.
+    ///
+    /// Fails with [`Error::Io`] if reading fails.
+    pub fn from_reader<R: Read>(reader: R) -> Result<Self, Error> {
+        Parser::default().parse_reader(reader)
+    }
+
+    /// The parsed model
+    pub fn model(&self) -> &CLI<'_, T> {
+        &self.inner.model
+    }
+
+    /// The buffer the model was parsed from
+    pub fn buffer(&self) -> &Arc<[u8]> {
+        &self.inner.buffer
+    }
+}
+
+impl<T: CLIType> CLI<'static, T> {
+    /// Read and parse a file, keeping the bytes alongside the model, see [`SharedCLI`]
+    pub fn from_reader<R: Read>(reader: R) -> Result<SharedCLI<T>, Error> {
+        SharedCLI::from_reader(reader)
+    }
+}
+
+impl<T: CLIType> Parser<T> {
+    /// Read everything from `reader` and parse it, keeping the bytes alongside the model, see
+    /// [`SharedCLI`]
+    ///
+    /// Fails with [`Error::Io`] if reading fails.
+    pub fn parse_reader<R: Read>(&self, mut reader: R) -> Result<SharedCLI<T>, Error> {
+        let mut buffer = Vec::new();
+        reader.read_to_end(&mut buffer)?;
+        SharedCLI::with_parser(buffer, self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clitype::ShortCLI;
+    use crate::{Header, Layer, Loop};
+    use std::borrow::Cow;
+
+    fn assert_send_sync<S: Send + Sync>() {}
+
+    #[test]
+    fn threads() -> Result<(), Error> {
+        assert_send_sync::<SharedCLI<ShortCLI>>();
+        assert_send_sync::<SharedCLI<LongCLI>>();
+        assert_send_sync::<CLI<'_, LongCLI>>();
+
+        let buf = CLI::<ShortCLI> {
+            header: Header {
+                units: 0.5,
+                aligned: true,
+                ..Header::default()
+            },
+            layers: (1..=3)
+                .map(|height| Layer {
+                    loops: vec![Loop {
+                        id: 1,
+                        dir: 1,
+                        points: Cow::Owned(vec![0, 0, height, 0, height, height, 0, 0]),
+                        params: Default::default(),
+                    }],
+                    ..Layer::empty(height)
+                })
+                .collect(),
+            verbatim: Default::default(),
+        }
+        .to_bytes();
+
+        let shared = SharedCLI::with_parser(buf.clone(), &Parser::new().short())?;
+        let workers: Vec<_> = (0..4)
+            .map(|_| {
+                let shared = shared.clone();
+                std::thread::spawn(move || {
+                    shared
+                        .model()
+                        .iter()
+                        .map(|l| l.iter_loops().map(|l| l.points().len()).sum::<usize>())
+                        .sum::<usize>()
+                })
+            })
+            .collect();
+        for worker in workers {
+            assert_eq!(worker.join().unwrap(), 24);
+        }
+        assert_eq!(&shared.buffer()[..], &buf[..]);
+        assert_eq!(shared.model().to_bytes(), buf);
+
+        let read = Parser::new().short().parse_reader(&buf[..])?;
+        assert_eq!(read.model().to_bytes(), buf);
+        struct Failing;
+        impl Read for Failing {
+            fn read(&mut self, _: &mut [u8]) -> std::io::Result<usize> {
+                Err(std::io::ErrorKind::BrokenPipe.into())
+            }
+        }
+        assert!(matches!(
+            CLI::<LongCLI>::from_reader(Failing),
+            Err(Error::Io(_))
+        ));
+        Ok(())
+    }
+}
@@ -0,0 +1,79 @@
+//! Geometric similarity of layers
+
+use crate::clitype::CLIType;
+use crate::geom::BoundingBox;
+use crate::raster::{self, Grid};
+use crate::Layer;
+
+impl<'a, T: CLIType> Layer<'a, T> {
+    /// Intersection over union of the solid regions of two layers.
+    ///
+    /// Both layers are rasterized with square cells of `resolution` coordinate units, so they are
+    /// expected to use the same units. The result ranges from 0 for disjoint regions to 1 for
+    /// identical ones, two layers without any solid region are considered identical.
+    ///
+    /// A resolution that is not positive and finite, or so fine that the grid would have more than
+    /// 16 million cells, is replaced by a coarser one giving fewer cells.
+    ///
+    /// ```no_run
+    /// # use colain::{CLI, clitype::LongCLI};
+    /// # let (a, b) = (Vec::new(), Vec::new());
+    /// let old = CLI::<LongCLI>::new(&a).unwrap();
+    /// let new = CLI::<LongCLI>::new(&b).unwrap();
+    /// for (a, b) in old.iter().zip(new.iter()) {
+    ///     println!("{}", a.iou(b, 10.0));
+    /// }
+    /// ```
+    pub fn iou(&self, other: &Layer<'_, T>, resolution: f64) -> f64 {
+        let (a, b) = (self.solid(1.0), other.solid(1.0));
+        let bb = match BoundingBox::of(a.iter().chain(b.iter()).flatten().copied()) {
+            Some(bb) => bb,
+            None => return 1.0,
+        };
+        let resolution = raster::resolution(&bb, resolution);
+        let grid = Grid::covering(&bb, resolution);
+        let (mut intersection, mut union) = (0usize, 0usize);
+        for (a, b) in raster::fill(&grid, &a)
+            .into_iter()
+            .zip(raster::fill(&grid, &b))
+        {
+            intersection += (a && b) as usize;
+            union += (a || b) as usize;
+        }
+        if union == 0 {
+            1.0
+        } else {
+            intersection as f64 / union as f64
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::clitype::ShortCLI;
+    use crate::{Layer, Loop};
+    use std::borrow::Cow;
+
+    fn square(x: u16) -> Layer<'static, ShortCLI> {
+        Layer {
+            loops: vec![Loop {
+                id: 0,
+                dir: 1,
+                points: Cow::Owned(vec![x, 0, x + 10, 0, x + 10, 10, x, 10, x, 0]),
+                params: Default::default(),
+            }],
+            ..Layer::empty(1)
+        }
+    }
+
+    #[test]
+    fn iou() {
+        assert_eq!(square(0).iou(&square(0), 1.0), 1.0);
+        // 50 overlapping out of 150
+        assert!((square(0).iou(&square(5), 0.5) - 1.0 / 3.0).abs() < 1e-9);
+        assert_eq!(square(0).iou(&square(20), 1.0), 0.0);
+        for resolution in [0.0, -1.0, f64::NAN, 1e-300] {
+            assert_eq!(square(0).iou(&square(0), resolution), 1.0);
+        }
+    }
+}
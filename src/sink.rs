@@ -0,0 +1,198 @@
+//! Streaming models layer by layer
+//!
+//! Instead of serializing a whole model and transferring the file, a [`LayerSink`] receives
+//! one layer at a time. [`WriteSink`] encodes the layers as a binary CLI stream into anything
+//! implementing [`Write`], such as a [`TcpStream`] connected to a machine controller or print server.
+//!
+//! Backpressure comes from the writer: every layer is written with [`Write::write_all`] and
+//! flushed before the next one is accepted, so a slow receiver blocks the producer instead of
+//! letting data pile up in memory.
+//!
+//! ```no_run
+//! use colain::{CLI, LayerSink, WriteSink, clitype::LongCLI};
+//! # let buf = colain::BuildPlate::<LongCLI>::new(1.0).to_bytes();
+//!
+//! let model = CLI::<LongCLI>::new(&buf).unwrap();
+//! let mut sink = WriteSink::<_, LongCLI>::connect("192.168.0.10:5000").unwrap();
+//! model.stream_to(&mut sink).unwrap();
+//! ```
+
+use crate::clitype::CLIType;
+use crate::{writer, Header, Layer, CLI};
+use std::io::{self, Write};
+use std::marker::PhantomData;
+use std::net::{TcpStream, ToSocketAddrs};
+
+/// Receiver of a model, one layer at a time
+pub trait LayerSink<T: CLIType> {
+    /// Called once before the first layer
+    fn begin(&mut self, header: &Header) -> io::Result<()>;
+    /// Called for each layer in order
+    fn layer(&mut self, layer: &Layer<'_, T>) -> io::Result<()>;
+    /// Called once after the last layer
+    fn finish(&mut self) -> io::Result<()>;
+}
+
+/// A [`LayerSink`] writing a binary CLI stream
+///
+/// The header is written on [`LayerSink::begin`]. Its `$$LAYERS` entry is only present if the
+/// header passed in declares a layer count, since the number of layers is not known in advance.
+/// A header declaring `$$ASCII` is written as `$$BINARY`, like [`CLI::to_bytes`] does.
+pub struct WriteSink<W: Write, T: CLIType> {
+    writer: W,
+    aligned: bool,
+    buf: Vec<u8>,
+    layers: usize,
+    _type: PhantomData<T>,
+}
+
+impl<W: Write, T: CLIType> WriteSink<W, T> {
+    /// Stream into a writer
+    pub fn new(writer: W) -> Self {
+        WriteSink {
+            writer,
+            aligned: false,
+            buf: Vec::new(),
+            layers: 0,
+            _type: PhantomData,
+        }
+    }
+
+    /// Number of layers written so far
+    pub fn layers_written(&self) -> usize {
+        self.layers
+    }
+
+    /// Get the underlying writer back
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+
+    fn send(&mut self) -> io::Result<()> {
+        self.writer.write_all(&self.buf)?;
+        self.writer.flush()?;
+        self.buf.clear();
+        Ok(())
+    }
+}
+
+impl<T: CLIType> WriteSink<TcpStream, T> {
+    /// Open a TCP connection and stream into it
+    pub fn connect<A: ToSocketAddrs>(addr: A) -> io::Result<Self> {
+        let stream = TcpStream::connect(addr)?;
+        // Layers are flushed explicitly, no need to wait for more data
+        stream.set_nodelay(true)?;
+        Ok(WriteSink::new(stream))
+    }
+}
+
+impl<W: Write, T: CLIType> LayerSink<T> for WriteSink<W, T> {
+    fn begin(&mut self, header: &Header) -> io::Result<()> {
+        self.aligned = header.aligned;
+        // The layers are always encoded in binary
+        let header = Header {
+            binary: true,
+            ..header.clone()
+        };
+        writer::write_header(&mut self.buf, &header, header.layers);
+        self.send()
+    }
+
+    fn layer(&mut self, layer: &Layer<'_, T>) -> io::Result<()> {
+        writer::write_layer(&mut self.buf, layer, self.aligned);
+        self.layers += 1;
+        self.send()
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+impl<T: CLIType> CLI<'_, T> {
+    /// Send the model to a sink layer by layer
+    pub fn stream_to<S: LayerSink<T>>(&self, sink: &mut S) -> io::Result<()> {
+        let mut header = self.header.clone();
+        header.layers = Some(self.layers.len());
+        sink.begin(&header)?;
+        for layer in self.layers.iter() {
+            sink.layer(layer)?;
+        }
+        sink.finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clitype::LongCLI;
+    use std::borrow::Cow;
+    use std::io::Read;
+    use std::net::TcpListener;
+
+    fn model() -> CLI<'static, LongCLI> {
+        let layer = |height| Layer {
+            loops: vec![crate::Loop {
+                id: 1,
+                dir: 1,
+                points: Cow::Owned(vec![0.0, 0.0, 1.0, 0.0, 1.0, 1.0, 0.0, 0.0]),
+                params: Default::default(),
+            }],
+            ..Layer::empty(height)
+        };
+        CLI {
+            header: Header {
+                aligned: true,
+                ..Header::default()
+            },
+            layers: vec![layer(1.0), layer(2.0)],
+            verbatim: Default::default(),
+        }
+    }
+
+    #[test]
+    fn matches_file() {
+        let model = model();
+        let mut sink = WriteSink::new(Vec::new());
+        model.stream_to(&mut sink).unwrap();
+        assert_eq!(sink.layers_written(), 2);
+        assert_eq!(sink.into_inner(), model.to_bytes());
+    }
+
+    #[test]
+    fn ascii() {
+        let text = "$$HEADERSTART\n$$ASCII\n$$UNITS/1\n$$VERSION/200\n$$HEADEREND\n\
+            $$GEOMETRYSTART\n$$LAYER/1\n$$POLYLINE/1,1,3,0,0,1,0,0,0\n$$GEOMETRYEND\n";
+        let model = crate::Parser::new().long().parse(text.as_bytes()).unwrap();
+        let mut sink = WriteSink::new(Vec::new());
+        model.stream_to(&mut sink).unwrap();
+        let streamed = sink.into_inner();
+        let parsed = CLI::<LongCLI>::new(&streamed).unwrap();
+        assert!(parsed.header().binary);
+        assert_eq!(parsed.iter().count(), 1);
+    }
+
+    #[test]
+    fn tcp() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let receiver = std::thread::spawn(move || {
+            let mut received = Vec::new();
+            listener
+                .accept()
+                .unwrap()
+                .0
+                .read_to_end(&mut received)
+                .unwrap();
+            received
+        });
+
+        let model = model();
+        let mut sink = WriteSink::connect(addr).unwrap();
+        model.stream_to(&mut sink).unwrap();
+        drop(sink);
+
+        let received = receiver.join().unwrap();
+        assert_eq!(CLI::<LongCLI>::new(&received).unwrap().iter().count(), 2);
+    }
+}
@@ -0,0 +1,252 @@
+//! Medial axis approximation of the solid region of a layer
+//!
+//! The skeleton runs along the middle of every wall, so thin-wall detection can measure wall
+//! thickness along it and single-track scan strategies can use it as the toolpath. It is found by
+//! rasterizing the layer and thinning the raster down to lines one cell wide.
+
+use crate::clitype::CLIType;
+use crate::geom::BoundingBox;
+use crate::raster::{self, Grid};
+use crate::{Layer, Loop, VectorParams};
+use std::borrow::Cow;
+use std::collections::HashSet;
+
+/// Offsets of the 8 neighbours of a cell, in order around it
+const NEIGHBOURS: [(isize, isize); 8] = [
+    (0, 1),
+    (1, 1),
+    (1, 0),
+    (1, -1),
+    (0, -1),
+    (-1, -1),
+    (-1, 0),
+    (-1, 1),
+];
+
+impl<'a, T: CLIType> Layer<'a, T> {
+    /// Skeleton of the solid region of the layer as open polylines (direction 2).
+    ///
+    /// The layer is rasterized with square cells of `resolution` coordinate units and thinned with
+    /// the Zhang-Suen algorithm. The remaining cells are connected into polylines running between
+    /// end points and junctions, or around closed rings. Points lie on cell centers, in the same
+    /// coordinate space as the layer. The polylines take the id of the first loop of the layer.
+    ///
+    /// A resolution that is not positive and finite, or so fine that the grid would exceed 16
+    /// million cells, is replaced by a coarser one within that.
+    pub fn skeleton(&self, resolution: f64) -> Vec<Loop<'static, T>> {
+        let solid = self.solid(1.0);
+        let bb = match BoundingBox::of(solid.iter().flatten().copied()) {
+            Some(bb) => bb,
+            None => return Vec::new(),
+        };
+        let resolution = raster::resolution(&bb, resolution);
+        let bb = BoundingBox {
+            min: [bb.min[0] - resolution, bb.min[1] - resolution],
+            max: [bb.max[0] + resolution, bb.max[1] + resolution],
+        };
+        let grid = Grid::covering(&bb, resolution);
+        let mut mask = raster::fill(&grid, &solid);
+        thin(&grid, &mut mask);
+
+        let id = self
+            .loops
+            .first()
+            .map(|l| l.id)
+            .unwrap_or(T::meta_from_i64(0));
+        trace(&grid, &mask)
+            .into_iter()
+            .map(|cells| {
+                let points = cells
+                    .iter()
+                    .flat_map(|&(x, y)| {
+                        [
+                            grid.origin[0] + (x as f64 + 0.5) * resolution,
+                            grid.origin[1] + (y as f64 + 0.5) * resolution,
+                        ]
+                    })
+                    .map(T::coord_from_f64)
+                    .collect();
+                Loop {
+                    id,
+                    dir: T::meta_from_i64(2),
+                    points: Cow::Owned(points),
+                    params: VectorParams::default(),
+                }
+            })
+            .collect()
+    }
+}
+
+fn neighbours(grid: &Grid, mask: &[bool], x: usize, y: usize) -> [bool; 8] {
+    NEIGHBOURS.map(|(dx, dy)| {
+        let (nx, ny) = (x as isize + dx, y as isize + dy);
+        nx >= 0
+            && ny >= 0
+            && (nx as usize) < grid.width
+            && (ny as usize) < grid.height
+            && mask[ny as usize * grid.width + nx as usize]
+    })
+}
+
+/// Zhang-Suen thinning in place
+fn thin(grid: &Grid, mask: &mut [bool]) {
+    let mut remove = Vec::new();
+    loop {
+        let mut changed = false;
+        for pass in 0..2 {
+            remove.clear();
+            for y in 0..grid.height {
+                for x in 0..grid.width {
+                    if !mask[y * grid.width + x] {
+                        continue;
+                    }
+                    let p = neighbours(grid, mask, x, y);
+                    let count = p.iter().filter(|&&n| n).count();
+                    let transitions = (0..8).filter(|&i| !p[i] && p[(i + 1) % 8]).count();
+                    // p[0] north, p[2] east, p[4] south, p[6] west
+                    let (a, b) = if pass == 0 {
+                        (p[0] && p[2] && p[4], p[2] && p[4] && p[6])
+                    } else {
+                        (p[0] && p[2] && p[6], p[0] && p[4] && p[6])
+                    };
+                    if (2..=6).contains(&count) && transitions == 1 && !a && !b {
+                        remove.push(y * grid.width + x);
+                    }
+                }
+            }
+            for &i in &remove {
+                mask[i] = false;
+            }
+            changed |= !remove.is_empty();
+        }
+        if !changed {
+            break;
+        }
+    }
+}
+
+/// Connect the cells of a thinned mask into polylines
+fn trace(grid: &Grid, mask: &[bool]) -> Vec<Vec<(usize, usize)>> {
+    let adjacent = |(x, y): (usize, usize)| -> Vec<(usize, usize)> {
+        let p = neighbours(grid, mask, x, y);
+        NEIGHBOURS
+            .iter()
+            .zip(p.iter())
+            .filter(|(_, &set)| set)
+            .map(|(&(dx, dy), _)| ((x as isize + dx) as usize, (y as isize + dy) as usize))
+            .collect()
+    };
+    let cells: Vec<(usize, usize)> = (0..grid.len())
+        .filter(|&i| mask[i])
+        .map(|i| (i % grid.width, i / grid.width))
+        .collect();
+    let is_node = |c: (usize, usize)| adjacent(c).len() != 2;
+
+    let mut visited: HashSet<((usize, usize), (usize, usize))> = HashSet::new();
+    let mut visit = |a: (usize, usize), b: (usize, usize)| visited.insert((a.min(b), a.max(b)));
+    let mut lines = Vec::new();
+    let mut walk =
+        |start: (usize, usize), first: (usize, usize), visit: &mut dyn FnMut(_, _) -> bool| {
+            if !visit(start, first) {
+                return;
+            }
+            let mut line = vec![start, first];
+            let mut current = first;
+            while !is_node(current) {
+                let next = adjacent(current).into_iter().find(|&n| visit(current, n));
+                match next {
+                    Some(n) => {
+                        line.push(n);
+                        current = n;
+                    }
+                    None => break,
+                }
+            }
+            lines.push(line);
+        };
+
+    for &c in cells.iter().filter(|&&c| is_node(c)) {
+        for n in adjacent(c) {
+            walk(c, n, &mut visit);
+        }
+    }
+    // Rings without any junction or end point
+    for &c in &cells {
+        if let Some(n) = adjacent(c).into_iter().next() {
+            walk(c, n, &mut visit);
+        }
+    }
+    // Isolated cells
+    for &c in cells.iter().filter(|&&c| adjacent(c).is_empty()) {
+        lines.push(vec![c]);
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::clitype::LongCLI;
+    use crate::{Layer, Loop, LoopKind};
+    use std::borrow::Cow;
+
+    #[test]
+    fn bar() {
+        let layer = Layer::<LongCLI> {
+            loops: vec![Loop {
+                id: 5,
+                dir: 1,
+                points: Cow::Owned(vec![0.0, 0.0, 20.0, 0.0, 20.0, 2.0, 0.0, 2.0, 0.0, 0.0]),
+                params: Default::default(),
+            }],
+            ..Layer::empty(1.0)
+        };
+        let skeleton = layer.skeleton(0.25);
+        assert_eq!(skeleton.len(), 1);
+        let line = &skeleton[0];
+        assert_eq!(line.kind(), LoopKind::Open);
+        assert_eq!(line.id(), 5);
+        let length = line.as_open().unwrap().length();
+        assert!(length > 17.0 && length < 20.0, "{}", length);
+        let ys = line.points().chunks(2).map(|p| p[1]);
+        assert!(ys.into_iter().all(|y| (y - 1.0).abs() <= 0.25));
+    }
+
+    #[test]
+    fn ring() {
+        let mut points = vec![];
+        for (x, y) in [
+            (0.0, 0.0),
+            (10.0, 0.0),
+            (10.0, 10.0),
+            (0.0, 10.0),
+            (0.0, 0.0),
+        ] {
+            points.extend([x, y]);
+        }
+        let mut hole = vec![];
+        for (x, y) in [(2.0, 2.0), (2.0, 8.0), (8.0, 8.0), (8.0, 2.0), (2.0, 2.0)] {
+            hole.extend([x, y]);
+        }
+        let layer = Layer::<LongCLI> {
+            loops: vec![
+                Loop {
+                    id: 0,
+                    dir: 1,
+                    points: Cow::Owned(points),
+                    params: Default::default(),
+                },
+                Loop {
+                    id: 0,
+                    dir: 0,
+                    points: Cow::Owned(hole),
+                    params: Default::default(),
+                },
+            ],
+            ..Layer::empty(1.0)
+        };
+        let skeleton = layer.skeleton(0.5);
+        let total: f64 = skeleton.iter().map(|l| l.as_open().unwrap().length()).sum();
+        // The middle of the wall is a square of side 8, plus short branches towards the corners
+        assert!(total > 31.0 && total < 44.0, "{}", total);
+    }
+}
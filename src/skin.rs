@@ -0,0 +1,132 @@
+//! Separation of a layer into skin and core
+//!
+//! Process parameters near the surface of a part usually differ from those in its interior. The
+//! solid region of a layer is split into a band of fixed width along its boundary (the skin) and
+//! the remaining interior (the core), each described by its own set of loops, so that the two can
+//! be hatched differently.
+
+use crate::clitype::CLIType;
+use crate::geom::BoundingBox;
+use crate::raster::{self, Grid};
+use crate::{Layer, Loop, VectorParams};
+use std::borrow::Cow;
+
+/// Loops describing the skin and core of a layer, see [`Layer::skin_core`]
+#[derive(Debug, Clone)]
+pub struct SkinCore<T: CLIType> {
+    /// The boundary band: the closed loops of the layer together with the outlines of the core,
+    /// which cut holes into it
+    pub skin: Vec<Loop<'static, T>>,
+    /// The interior region
+    pub core: Vec<Loop<'static, T>>,
+}
+
+impl<'a, T: CLIType> Layer<'a, T> {
+    /// Split the solid region of the layer into a skin of `width` and the core inside of it.
+    ///
+    /// The core is found by rasterizing the layer with square cells of `resolution`, so its
+    /// outlines follow the cell edges. Resolutions finer than a grid of 16 million cells allows,
+    /// or not positive and finite, are coarsened to fit. Both values are in coordinate units. Outer loops of the core
+    /// run counter-clockwise (direction 1) and its holes clockwise (direction 0). Skin loops keep
+    /// the id of the layer loops, core loops take the id of the first of them.
+    ///
+    /// The core is empty when the layer is thinner than twice the skin width everywhere.
+    pub fn skin_core(&self, width: f64, resolution: f64) -> SkinCore<T> {
+        let solid = self.solid(1.0);
+        let mut skin: Vec<Loop<'static, T>> = self
+            .loops
+            .iter()
+            .filter(|l| l.is_solid())
+            .map(|l| Loop {
+                id: l.id,
+                dir: l.dir,
+                points: Cow::Owned(l.points.to_vec()),
+                params: l.params.to_owned_params(),
+            })
+            .collect();
+        let mut core = Vec::new();
+
+        let bb = match BoundingBox::of(solid.iter().flatten().copied()) {
+            Some(bb) => bb,
+            None => return SkinCore { skin, core },
+        };
+        let resolution = raster::resolution(&bb, resolution);
+        // Leave a ring of empty cells so the outside is always part of the grid
+        let bb = BoundingBox {
+            min: [bb.min[0] - resolution, bb.min[1] - resolution],
+            max: [bb.max[0] + resolution, bb.max[1] + resolution],
+        };
+        let grid = Grid::covering(&bb, resolution);
+        let mask = raster::fill(&grid, &solid);
+        let outside: Vec<bool> = mask.iter().map(|&inside| !inside).collect();
+        // Distances are measured between cell centers, half a cell away from the boundary
+        let depth = raster::distance(&grid, &outside);
+        let inner: Vec<bool> = depth
+            .iter()
+            .map(|&d| d - resolution / 2.0 > width)
+            .collect();
+
+        let id = self
+            .loops
+            .first()
+            .map(|l| l.id)
+            .unwrap_or(T::meta_from_i64(0));
+        for outline in raster::contours(&grid, &inner) {
+            let ccw = crate::geom::signed_area(&outline) > 0.0;
+            core.push(Loop {
+                id,
+                dir: T::meta_from_i64(if ccw { 1 } else { 0 }),
+                points: Cow::Owned(to_coords::<T>(outline.iter())),
+                params: VectorParams::default(),
+            });
+            skin.push(Loop {
+                id,
+                dir: T::meta_from_i64(if ccw { 0 } else { 1 }),
+                points: Cow::Owned(to_coords::<T>(outline.iter().rev())),
+                params: VectorParams::default(),
+            });
+        }
+        SkinCore { skin, core }
+    }
+}
+
+fn to_coords<'p, T: CLIType>(
+    points: impl Iterator<Item = &'p [f64; 2]>,
+) -> Vec<<T as CLIType>::Coord> {
+    points
+        .flat_map(|p| [T::coord_from_f64(p[0]), T::coord_from_f64(p[1])])
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::clitype::LongCLI;
+    use crate::geom;
+    use crate::{Layer, Loop};
+    use std::borrow::Cow;
+
+    #[test]
+    fn square() {
+        let layer = Layer::<LongCLI> {
+            loops: vec![Loop {
+                id: 4,
+                dir: 1,
+                points: Cow::Owned(vec![0.0, 0.0, 10.0, 0.0, 10.0, 10.0, 0.0, 10.0, 0.0, 0.0]),
+                params: Default::default(),
+            }],
+            ..Layer::empty(1.0)
+        };
+        let split = layer.skin_core(2.0, 0.1);
+        assert_eq!(split.core.len(), 1);
+        assert_eq!(split.skin.len(), 2);
+
+        let area =
+            |l: &Loop<LongCLI>| geom::signed_area(&geom::to_points(l.points(), |c| c as f64));
+        let core = area(&split.core[0]);
+        assert!((core - 36.0).abs() < 1.0, "{}", core);
+        assert_eq!(split.core[0].id(), 4);
+        assert!((area(&split.skin[1]) + core).abs() < 1e-6);
+
+        assert!(layer.skin_core(6.0, 0.1).core.is_empty());
+    }
+}
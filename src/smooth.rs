@@ -0,0 +1,196 @@
+//! Smoothing of contours
+//!
+//! Slices derived from voxel data have stair-stepped contours. The filters in here remove these
+//! artifacts before the geometry is written again, optionally keeping real corners of the part
+//! sharp.
+
+use crate::clitype::CLIType;
+use crate::geom;
+use crate::{Loop, VectorParams};
+use std::borrow::Cow;
+
+/// Smoothing filter for [`Loop::smoothed`]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Smoothing {
+    /// Chaikin corner cutting, each iteration doubles the number of points
+    Chaikin {
+        /// Number of times the filter is applied
+        iterations: usize,
+    },
+    /// Every point is replaced by the mean of itself and its neighbours
+    MovingAverage {
+        /// Number of neighbours on each side included in the mean
+        radius: usize,
+        /// Number of times the filter is applied
+        iterations: usize,
+    },
+}
+
+impl<'a, T: CLIType> Loop<'a, T> {
+    /// Copy of the loop with a smoothing filter applied.
+    ///
+    /// Closed loops (first point equal to the last) are smoothed all the way around and stay
+    /// closed, open paths keep their end points. Points where the path turns by more than
+    /// `corner_angle` radians are corners and are kept in place, pass `None` to smooth everything.
+    ///
+    /// Process parameters are dropped since the vectors no longer match the original ones.
+    pub fn smoothed(&self, filter: Smoothing, corner_angle: Option<f64>) -> Loop<'static, T> {
+        let mut points = geom::to_points(&self.points, |c| T::coord_to_f64(c));
+        let closed = points.len() > 3 && points.first() == points.last();
+        if closed {
+            points.pop();
+        }
+
+        if points.len() >= 3 {
+            match filter {
+                Smoothing::Chaikin { iterations } => {
+                    for _ in 0..iterations {
+                        points = chaikin(&points, closed, corner_angle);
+                    }
+                }
+                Smoothing::MovingAverage { radius, iterations } => {
+                    for _ in 0..iterations {
+                        points = average(&points, closed, radius, corner_angle);
+                    }
+                }
+            }
+        }
+
+        if closed {
+            points.push(points[0]);
+        }
+        Loop {
+            id: self.id,
+            dir: self.dir,
+            points: Cow::Owned(
+                points
+                    .iter()
+                    .flat_map(|p| [T::coord_from_f64(p[0]), T::coord_from_f64(p[1])])
+                    .collect(),
+            ),
+            params: VectorParams::default(),
+        }
+    }
+}
+
+/// Which points must stay in place: corners and the ends of open paths
+fn fixed(points: &[[f64; 2]], closed: bool, corner_angle: Option<f64>) -> Vec<bool> {
+    let n = points.len();
+    (0..n)
+        .map(|i| {
+            if !closed && (i == 0 || i == n - 1) {
+                return true;
+            }
+            let limit = match corner_angle {
+                Some(limit) => limit,
+                None => return false,
+            };
+            let (a, b, c) = (points[(i + n - 1) % n], points[i], points[(i + 1) % n]);
+            let (u, v) = ([b[0] - a[0], b[1] - a[1]], [c[0] - b[0], c[1] - b[1]]);
+            let turn = (u[0] * v[1] - u[1] * v[0]).atan2(u[0] * v[0] + u[1] * v[1]);
+            turn.abs() > limit
+        })
+        .collect()
+}
+
+fn chaikin(points: &[[f64; 2]], closed: bool, corner_angle: Option<f64>) -> Vec<[f64; 2]> {
+    let fixed = fixed(points, closed, corner_angle);
+    let n = points.len();
+    let edges = if closed { n } else { n - 1 };
+    let mut out = Vec::with_capacity(2 * n + 1);
+    for i in 0..edges {
+        let (p, q) = (points[i], points[(i + 1) % n]);
+        if fixed[i] {
+            out.push(p);
+        }
+        out.push([0.75 * p[0] + 0.25 * q[0], 0.75 * p[1] + 0.25 * q[1]]);
+        out.push([0.25 * p[0] + 0.75 * q[0], 0.25 * p[1] + 0.75 * q[1]]);
+    }
+    if !closed {
+        out.push(points[n - 1]);
+    }
+    out
+}
+
+fn average(
+    points: &[[f64; 2]],
+    closed: bool,
+    radius: usize,
+    corner_angle: Option<f64>,
+) -> Vec<[f64; 2]> {
+    let fixed = fixed(points, closed, corner_angle);
+    let n = points.len() as isize;
+    let r = radius as isize;
+    (0..n)
+        .map(|i| {
+            if fixed[i as usize] {
+                return points[i as usize];
+            }
+            let mut sum = [0.0, 0.0];
+            let mut count = 0.0;
+            for k in i - r..=i + r {
+                let k = if closed {
+                    k.rem_euclid(n)
+                } else if (0..n).contains(&k) {
+                    k
+                } else {
+                    continue;
+                };
+                let p = points[k as usize];
+                sum = [sum[0] + p[0], sum[1] + p[1]];
+                count += 1.0;
+            }
+            [sum[0] / count, sum[1] / count]
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clitype::LongCLI;
+
+    fn staircase() -> Loop<'static, LongCLI> {
+        // Right triangle with a stair-stepped hypotenuse
+        Loop {
+            id: 0,
+            dir: 1,
+            points: Cow::Owned(vec![
+                0.0, 0.0, 4.0, 0.0, 4.0, 1.0, 3.0, 1.0, 3.0, 2.0, 2.0, 2.0, 2.0, 3.0, 1.0, 3.0,
+                1.0, 4.0, 0.0, 4.0, 0.0, 0.0,
+            ]),
+            params: Default::default(),
+        }
+    }
+
+    #[test]
+    fn chaikin() {
+        let l = staircase().smoothed(Smoothing::Chaikin { iterations: 2 }, None);
+        // 10 distinct points, doubled twice, closed again
+        assert_eq!(l.points().len(), 2 * 41);
+        assert_eq!(l.points()[..2], l.points()[80..]);
+    }
+
+    #[test]
+    fn corners() {
+        let filter = Smoothing::MovingAverage {
+            radius: 1,
+            iterations: 3,
+        };
+        let smooth = staircase().smoothed(filter, None);
+        assert_ne!(smooth.points()[..2], [0.0, 0.0]);
+
+        let sharp = staircase().smoothed(filter, Some(std::f64::consts::FRAC_PI_2 * 0.9));
+        // Every step of the staircase is a right angle and stays in place
+        assert_eq!(sharp.points(), staircase().points());
+
+        let open = Loop::<LongCLI> {
+            dir: 2,
+            points: Cow::Owned(vec![0.0, 0.0, 1.0, 1.0, 2.0, 0.0, 3.0, 1.0]),
+            ..staircase()
+        }
+        .smoothed(filter, None);
+        assert_eq!(open.points()[..2], [0.0, 0.0]);
+        assert_eq!(open.points()[6..], [3.0, 1.0]);
+    }
+}
@@ -0,0 +1,478 @@
+//! Spatial index over the segments of a layer
+//!
+//! Picking geometry in a viewer or correlating a defect with nearby scan vectors requires finding
+//! segments by position. [`Layer::spatial_index`] bulk loads an R-tree over every loop segment and
+//! hatch vector of a layer, which answers nearest-segment and range queries in logarithmic time.
+//!
+//! ```
+//! # use colain::{CLI, clitype::LongCLI};
+//! # let bytes = colain::BuildPlate::<LongCLI>::new(1.0).to_bytes();
+//! let model = CLI::<LongCLI>::new(&bytes).unwrap();
+//! for layer in model.iter() {
+//!     let index = layer.spatial_index();
+//!     if let Some(hit) = index.nearest([10.0, 20.0]) {
+//!         println!("{:?} is {} away", hit.segment, hit.distance);
+//!     }
+//! }
+//! ```
+
+use crate::clitype::CLIType;
+use crate::geom::{self, BoundingBox};
+use crate::Layer;
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use std::ops::Range;
+
+/// Maximum number of children of a node
+const NODE_SIZE: usize = 16;
+
+/// Identifies a segment of a layer
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SegmentRef {
+    /// Segment `segment` of the loop with index `index`, running from point `segment` to `segment + 1`
+    Loop {
+        /// Index of the loop in the layer
+        index: usize,
+        /// Index of the segment within the loop
+        segment: usize,
+    },
+    /// A hatch vector
+    Hatch {
+        /// Index of the hatch block in the layer
+        block: usize,
+        /// Index of the vector within the block
+        index: usize,
+    },
+}
+
+/// Result of a query against a [`SpatialIndex`]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SegmentHit {
+    /// Which segment was found
+    pub segment: SegmentRef,
+    /// End points of the segment
+    pub points: [[f64; 2]; 2],
+    /// Point of the segment closest to the query point
+    pub closest: [f64; 2],
+    /// Distance from the query point
+    pub distance: f64,
+}
+
+/// Closest contour to a point, see [`Layer::nearest_loop`]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct NearestLoop {
+    /// Index of the loop in the layer
+    pub index: usize,
+    /// Index of the closest segment within the loop
+    pub segment: usize,
+    /// Point on the loop closest to the query point
+    pub point: [f64; 2],
+    /// Distance from the query point
+    pub distance: f64,
+}
+
+#[derive(Debug, Clone)]
+struct Node {
+    bb: BoundingBox,
+    /// Indices into the next lower level, or into the segments for leaves
+    children: Range<usize>,
+}
+
+/// R-tree over the segments of a layer, in coordinate units. See [`Layer::spatial_index`].
+#[derive(Debug, Clone)]
+pub struct SpatialIndex {
+    segments: Vec<([[f64; 2]; 2], SegmentRef)>,
+    /// Levels of the tree from the leaves up, the last one holds the root
+    levels: Vec<Vec<Node>>,
+}
+
+/// Queue entry of the nearest neighbour search
+struct Candidate {
+    distance: f64,
+    level: usize,
+    index: usize,
+}
+
+impl PartialEq for Candidate {
+    fn eq(&self, other: &Self) -> bool {
+        self.distance == other.distance
+    }
+}
+impl Eq for Candidate {}
+impl PartialOrd for Candidate {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+impl Ord for Candidate {
+    fn cmp(&self, other: &Self) -> Ordering {
+        // reversed for a min-heap
+        other.distance.total_cmp(&self.distance)
+    }
+}
+
+impl SpatialIndex {
+    fn build(mut segments: Vec<([[f64; 2]; 2], SegmentRef)>) -> Self {
+        let bounds = |s: &[[f64; 2]; 2]| BoundingBox::of(s.iter().copied()).unwrap();
+        let center = |bb: &BoundingBox, axis: usize| (bb.min[axis] + bb.max[axis]) / 2.0;
+
+        // Sort-tile-recursive packing: strips along x, sorted along y within each strip
+        let pack = |boxes: &mut Vec<(BoundingBox, usize)>| -> Vec<Range<usize>> {
+            let n = boxes.len();
+            let leaves = n.div_ceil(NODE_SIZE);
+            let strips = (leaves as f64).sqrt().ceil() as usize;
+            let per_strip = strips * NODE_SIZE;
+            boxes.sort_by(|a, b| center(&a.0, 0).total_cmp(&center(&b.0, 0)));
+            for strip in boxes.chunks_mut(per_strip.max(1)) {
+                strip.sort_by(|a, b| center(&a.0, 1).total_cmp(&center(&b.0, 1)));
+            }
+            (0..leaves)
+                .map(|i| i * NODE_SIZE..((i + 1) * NODE_SIZE).min(n))
+                .collect()
+        };
+
+        let mut levels: Vec<Vec<Node>> = Vec::new();
+        if segments.is_empty() {
+            return SpatialIndex { segments, levels };
+        }
+
+        let mut boxes: Vec<(BoundingBox, usize)> = segments
+            .iter()
+            .enumerate()
+            .map(|(i, s)| (bounds(&s.0), i))
+            .collect();
+        let ranges = pack(&mut boxes);
+        segments = boxes.iter().map(|&(_, i)| segments[i]).collect();
+        let mut level: Vec<Node> = ranges
+            .into_iter()
+            .map(|r| Node {
+                bb: union(boxes[r.clone()].iter().map(|b| b.0)),
+                children: r,
+            })
+            .collect();
+
+        while level.len() > 1 {
+            let mut boxes: Vec<(BoundingBox, usize)> =
+                level.iter().enumerate().map(|(i, n)| (n.bb, i)).collect();
+            let ranges = pack(&mut boxes);
+            level = boxes.iter().map(|&(_, i)| level[i].clone()).collect();
+            let parents = ranges
+                .into_iter()
+                .map(|r| Node {
+                    bb: union(level[r.clone()].iter().map(|n| n.bb)),
+                    children: r,
+                })
+                .collect();
+            levels.push(level);
+            level = parents;
+        }
+        levels.push(level);
+        SpatialIndex { segments, levels }
+    }
+
+    /// Number of segments in the index
+    pub fn len(&self) -> usize {
+        self.segments.len()
+    }
+
+    /// True if the layer has no segments
+    pub fn is_empty(&self) -> bool {
+        self.segments.is_empty()
+    }
+
+    /// The segment closest to a point
+    pub fn nearest(&self, p: [f64; 2]) -> Option<SegmentHit> {
+        self.nearest_where(p, |_| true)
+    }
+
+    /// The segment closest to a point among those accepted by `filter`
+    pub fn nearest_where(
+        &self,
+        p: [f64; 2],
+        filter: impl Fn(SegmentRef) -> bool,
+    ) -> Option<SegmentHit> {
+        let top = self.levels.len().checked_sub(1)?;
+        let mut queue = BinaryHeap::new();
+        for (index, node) in self.levels[top].iter().enumerate() {
+            queue.push(Candidate {
+                distance: geom::box_distance(p, &node.bb),
+                level: top + 1,
+                index,
+            });
+        }
+        while let Some(c) = queue.pop() {
+            if c.level == 0 {
+                return Some(self.hit(c.index, p));
+            }
+            let node = &self.levels[c.level - 1][c.index];
+            for child in node.children.clone() {
+                let distance = if c.level == 1 {
+                    if !filter(self.segments[child].1) {
+                        continue;
+                    }
+                    let [a, b] = self.segments[child].0;
+                    geom::point_segment_distance(p, a, b)
+                } else {
+                    geom::box_distance(p, &self.levels[c.level - 2][child].bb)
+                };
+                queue.push(Candidate {
+                    distance,
+                    level: c.level - 1,
+                    index: child,
+                });
+            }
+        }
+        None
+    }
+
+    /// The loop closest to a point, hatches are ignored
+    pub fn nearest_loop(&self, p: [f64; 2]) -> Option<NearestLoop> {
+        let hit = self.nearest_where(p, |s| matches!(s, SegmentRef::Loop { .. }))?;
+        match hit.segment {
+            SegmentRef::Loop { index, segment } => Some(NearestLoop {
+                index,
+                segment,
+                point: hit.closest,
+                distance: hit.distance,
+            }),
+            SegmentRef::Hatch { .. } => None,
+        }
+    }
+
+    /// All segments passing through the rectangle from `min` to `max`
+    pub fn within(&self, min: [f64; 2], max: [f64; 2]) -> Vec<SegmentRef> {
+        let range = BoundingBox { min, max };
+        let mut found = Vec::new();
+        let top = match self.levels.len().checked_sub(1) {
+            Some(top) => top,
+            None => return found,
+        };
+        let mut stack: Vec<(usize, usize)> =
+            (0..self.levels[top].len()).map(|i| (top + 1, i)).collect();
+        while let Some((level, index)) = stack.pop() {
+            let node = &self.levels[level - 1][index];
+            if node.bb.intersection(&range).is_none() {
+                continue;
+            }
+            for child in node.children.clone() {
+                if level == 1 {
+                    let [a, b] = self.segments[child].0;
+                    if geom::clip_segment(a, b, &range).is_some() {
+                        found.push(self.segments[child].1);
+                    }
+                } else {
+                    stack.push((level - 1, child));
+                }
+            }
+        }
+        found
+    }
+
+    /// All segments within `radius` of a point, closest first
+    pub fn within_distance(&self, p: [f64; 2], radius: f64) -> Vec<SegmentHit> {
+        let mut hits: Vec<SegmentHit> = Vec::new();
+        let top = match self.levels.len().checked_sub(1) {
+            Some(top) => top,
+            None => return hits,
+        };
+        let mut stack: Vec<(usize, usize)> =
+            (0..self.levels[top].len()).map(|i| (top + 1, i)).collect();
+        while let Some((level, index)) = stack.pop() {
+            let node = &self.levels[level - 1][index];
+            if geom::box_distance(p, &node.bb) > radius {
+                continue;
+            }
+            for child in node.children.clone() {
+                if level == 1 {
+                    let hit = self.hit(child, p);
+                    if hit.distance <= radius {
+                        hits.push(hit);
+                    }
+                } else {
+                    stack.push((level - 1, child));
+                }
+            }
+        }
+        hits.sort_by(|a, b| a.distance.total_cmp(&b.distance));
+        hits
+    }
+
+    fn hit(&self, index: usize, p: [f64; 2]) -> SegmentHit {
+        let ([a, b], segment) = self.segments[index];
+        let closest = geom::closest_point(p, a, b);
+        SegmentHit {
+            segment,
+            points: [a, b],
+            closest,
+            distance: (p[0] - closest[0]).hypot(p[1] - closest[1]),
+        }
+    }
+}
+
+fn union(mut boxes: impl Iterator<Item = BoundingBox>) -> BoundingBox {
+    let mut bb = boxes.next().unwrap();
+    for b in boxes {
+        bb.add(b.min);
+        bb.add(b.max);
+    }
+    bb
+}
+
+impl<'a, T: CLIType> Layer<'a, T> {
+    /// Build a [`SpatialIndex`] over every loop segment and hatch vector of the layer
+    pub fn spatial_index(&self) -> SpatialIndex {
+        let f = |c| T::coord_to_f64(c);
+        let mut segments = Vec::new();
+        for (index, l) in self.loops.iter().enumerate() {
+            let points = geom::to_points(&l.points, f);
+            for (segment, w) in points.windows(2).enumerate() {
+                segments.push(([w[0], w[1]], SegmentRef::Loop { index, segment }));
+            }
+        }
+        for (block, h) in self.hatches.iter().enumerate() {
+            for (index, v) in h.iter().enumerate() {
+                segments.push((
+                    [[f(v[0]), f(v[1])], [f(v[2]), f(v[3])]],
+                    SegmentRef::Hatch { block, index },
+                ));
+            }
+        }
+        SpatialIndex::build(segments)
+    }
+
+    /// The contour closest to a point, in coordinate units.
+    ///
+    /// This builds a [`SpatialIndex`] on every call, when mapping many points to the same layer
+    /// build it once with [`Layer::spatial_index`] and use [`SpatialIndex::nearest_loop`] instead.
+    pub fn nearest_loop(&self, p: [f64; 2]) -> Option<NearestLoop> {
+        self.spatial_index().nearest_loop(p)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clitype::LongCLI;
+    use crate::{Hatches, Loop};
+    use std::borrow::Cow;
+
+    fn grid_layer() -> Layer<'static, LongCLI> {
+        // 40 x 40 short horizontal vectors, enough for a tree of several levels
+        let mut points = Vec::new();
+        for y in 0..40 {
+            for x in 0..40 {
+                let (x, y) = (x as f32 * 2.0, y as f32 * 2.0);
+                points.extend([x, y, x + 1.0, y]);
+            }
+        }
+        Layer {
+            loops: vec![Loop {
+                id: 0,
+                dir: 1,
+                points: Cow::Owned(vec![
+                    -5.0, -5.0, 100.0, -5.0, 100.0, 100.0, -5.0, 100.0, -5.0, -5.0,
+                ]),
+                params: Default::default(),
+            }],
+            hatches: vec![Hatches {
+                id: 0,
+                points: Cow::Owned(points),
+                params: Default::default(),
+            }],
+            ..Layer::empty(1.0)
+        }
+    }
+
+    #[test]
+    fn queries() {
+        let layer = grid_layer();
+        let index = layer.spatial_index();
+        assert_eq!(index.len(), 1604);
+
+        let hit = index.nearest([10.5, 20.4]).unwrap();
+        assert_eq!(
+            hit.segment,
+            SegmentRef::Hatch {
+                block: 0,
+                index: 10 * 40 + 5
+            }
+        );
+        assert!((hit.distance - 0.4).abs() < 1e-9);
+        assert_eq!(hit.closest, [10.5, 20.0]);
+
+        let hit = index.nearest([99.0, 50.0]).unwrap();
+        assert_eq!(
+            hit.segment,
+            SegmentRef::Loop {
+                index: 0,
+                segment: 1
+            }
+        );
+
+        let mut found = index.within([2.5, 3.5], [6.5, 4.5]);
+        found.sort_by_key(|s| match s {
+            SegmentRef::Hatch { index, .. } => *index,
+            _ => usize::MAX,
+        });
+        assert_eq!(
+            found,
+            vec![
+                SegmentRef::Hatch {
+                    block: 0,
+                    index: 81
+                },
+                SegmentRef::Hatch {
+                    block: 0,
+                    index: 82
+                },
+                SegmentRef::Hatch {
+                    block: 0,
+                    index: 83
+                }
+            ]
+        );
+
+        let near = index.within_distance([0.5, 1.0], 1.0);
+        assert_eq!(near.len(), 2);
+        assert!(near[0].distance <= near[1].distance);
+
+        // brute force agrees with the tree
+        for p in [[13.3, 7.9], [-20.0, 3.0], [55.5, 61.2]] {
+            let best = (0..1600)
+                .map(|i| {
+                    let (x, y) = ((i % 40) as f64 * 2.0, (i / 40) as f64 * 2.0);
+                    geom::point_segment_distance(p, [x, y], [x + 1.0, y])
+                })
+                .fold(f64::INFINITY, f64::min);
+            let hit = index
+                .nearest_where(p, |s| matches!(s, SegmentRef::Hatch { .. }))
+                .unwrap();
+            assert!((hit.distance - best).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn nearest_loop() {
+        let layer = grid_layer();
+        let nearest = layer.nearest_loop([10.5, 20.4]).unwrap();
+        assert_eq!(nearest.index, 0);
+        assert_eq!(nearest.segment, 3);
+        assert_eq!(nearest.point[0], -5.0);
+        assert!((nearest.point[1] - 20.4).abs() < 1e-9);
+        assert!((nearest.distance - 15.5).abs() < 1e-9);
+
+        let index = layer.spatial_index();
+        let nearest = index.nearest_loop([120.0, 110.0]).unwrap();
+        assert_eq!(nearest.point, [100.0, 100.0]);
+    }
+
+    #[test]
+    fn empty() {
+        let layer = Layer::<LongCLI>::empty(1.0);
+        let index = layer.spatial_index();
+        assert!(index.is_empty());
+        assert!(index.nearest([0.0, 0.0]).is_none());
+        assert!(layer.nearest_loop([0.0, 0.0]).is_none());
+        assert!(index.within([0.0, 0.0], [1.0, 1.0]).is_empty());
+    }
+}
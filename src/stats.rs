@@ -0,0 +1,144 @@
+//! Per-layer statistics for quality assurance
+//!
+//! Build quality dashboards track a handful of metrics per layer. [`CLI::layer_stats`] computes
+//! them and [`CLI::write_stats_csv`] writes them in a form any spreadsheet or dashboard can ingest.
+//!
+//! ```no_run
+//! use colain::{CLI, clitype::LongCLI};
+//! # let bytes = Vec::new();
+//!
+//! let model = CLI::<LongCLI>::new(&bytes).unwrap();
+//! let file = std::fs::File::create("stats.csv").unwrap();
+//! model.write_stats_csv(std::io::BufWriter::new(file)).unwrap();
+//! ```
+
+use crate::clitype::CLIType;
+use crate::geom::BoundingBox;
+use crate::CLI;
+use std::io::{self, Write};
+
+/// Metrics of a single layer, lengths in millimeters
+#[derive(Debug, Clone, PartialEq)]
+pub struct LayerStats {
+    /// Index of the layer
+    pub layer: usize,
+    /// Height of the layer
+    pub z: f64,
+    /// Distance to the previous layer, or to the build plate for the first layer
+    pub thickness: f64,
+    /// Number of loops
+    pub loops: usize,
+    /// Total length of all hatch vectors
+    pub hatch_length: f64,
+    /// Solid area enclosed by the closed loops, in square millimeters
+    pub solid_area: f64,
+    /// Bounding box of all geometry as (min, max), `None` for empty layers
+    pub bounds: Option<([f64; 2], [f64; 2])>,
+}
+
+impl<'a, T: CLIType> CLI<'a, T> {
+    /// Compute the [`LayerStats`] of every layer
+    pub fn layer_stats(&self) -> Vec<LayerStats> {
+        let units = self.header.units;
+        let f = |c| T::coord_to_f64(c) * units;
+        let mut previous = 0.0;
+        self.layers
+            .iter()
+            .enumerate()
+            .map(|(index, layer)| {
+                let z = f(layer.height);
+                let thickness = z - previous;
+                previous = z;
+
+                let hatch_length = layer
+                    .hatches
+                    .iter()
+                    .flat_map(|h| h.iter())
+                    .map(|h| (f(h[2]) - f(h[0])).hypot(f(h[3]) - f(h[1])))
+                    .fold(0.0, |a, b| a + b);
+                let solid_area =
+                    layer.islands().iter().fold(0.0, |a, i| a + i.area) * units * units;
+                let points = layer
+                    .loops
+                    .iter()
+                    .flat_map(|l| l.points.chunks_exact(2))
+                    .chain(layer.hatches.iter().flat_map(|h| h.points.chunks_exact(2)))
+                    .map(|p| [f(p[0]), f(p[1])]);
+                LayerStats {
+                    layer: index,
+                    z,
+                    thickness,
+                    loops: layer.loops.len(),
+                    hatch_length,
+                    solid_area,
+                    bounds: BoundingBox::of(points).map(|bb| (bb.min, bb.max)),
+                }
+            })
+            .collect()
+    }
+
+    /// Write the [`LayerStats`] of every layer as CSV with a header row.
+    ///
+    /// The bounding box columns are left empty for layers without geometry.
+    pub fn write_stats_csv<W: Write>(&self, mut out: W) -> io::Result<()> {
+        writeln!(
+            out,
+            "layer,z,thickness,loops,hatch_length,solid_area,min_x,min_y,max_x,max_y"
+        )?;
+        for s in self.layer_stats() {
+            write!(
+                out,
+                "{},{},{},{},{},{},",
+                s.layer, s.z, s.thickness, s.loops, s.hatch_length, s.solid_area
+            )?;
+            match s.bounds {
+                Some((min, max)) => writeln!(out, "{},{},{},{}", min[0], min[1], max[0], max[1])?,
+                None => writeln!(out, ",,,")?,
+            }
+        }
+        out.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::clitype::ShortCLI;
+    use crate::{Hatches, Header, Layer, Loop, CLI};
+    use std::borrow::Cow;
+
+    #[test]
+    fn csv() {
+        let model = CLI::<ShortCLI> {
+            header: Header {
+                units: 0.5,
+                ..Header::default()
+            },
+            layers: vec![
+                Layer {
+                    loops: vec![Loop {
+                        id: 0,
+                        dir: 1,
+                        points: Cow::Owned(vec![0, 0, 4, 0, 4, 4, 0, 4, 0, 0]),
+                        params: Default::default(),
+                    }],
+                    hatches: vec![Hatches {
+                        id: 0,
+                        points: Cow::Owned(vec![1, 1, 3, 1, 1, 2, 3, 2]),
+                        params: Default::default(),
+                    }],
+                    ..Layer::empty(1)
+                },
+                Layer::empty(3),
+            ],
+            verbatim: Default::default(),
+        };
+        let mut out = Vec::new();
+        model.write_stats_csv(&mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "layer,z,thickness,loops,hatch_length,solid_area,min_x,min_y,max_x,max_y\n\
+             0,0.5,0.5,1,2,4,0,0,2,2\n\
+             1,1.5,1,0,0,0,,,,\n"
+        );
+    }
+}
@@ -0,0 +1,263 @@
+//! Heuristic separation of support structures
+//!
+//! Build files often contain parts and their supports merged into one model, with nothing but
+//! conventions telling them apart. [`Layer::classify_supports`] applies the common ones to flag
+//! geometry blocks that are probably supports, and [`CLI::split_supports`] separates a model
+//! accordingly.
+//!
+//! ```
+//! use colain::{CLI, SupportHeuristics, clitype::LongCLI};
+//! # let bytes = colain::BuildPlate::<LongCLI>::new(1.0).to_bytes();
+//!
+//! let model = CLI::<LongCLI>::new(&bytes).unwrap();
+//! let heuristics = SupportHeuristics {
+//!     support_ids: Some(1000..=1999),
+//!     ..Default::default()
+//! };
+//! let (parts, supports) = model.split_supports(&heuristics);
+//! ```
+
+use crate::clitype::CLIType;
+use crate::geom;
+use crate::{Hatches, Header, Layer, Loop, LoopKind, CLI};
+use std::borrow::Cow;
+use std::ops::RangeInclusive;
+
+/// Which conventions identify support geometry
+#[derive(Debug, Clone, PartialEq)]
+pub struct SupportHeuristics {
+    /// Loop and hatch ids reserved for supports by the toolchain that wrote the file
+    pub support_ids: Option<RangeInclusive<i64>>,
+    /// Open polylines are single track walls, which are almost exclusively used for supports
+    pub open_polylines: bool,
+    /// Hatches outside of every closed loop of their layer have no contour, like block supports
+    pub unbounded_hatches: bool,
+}
+
+impl Default for SupportHeuristics {
+    fn default() -> Self {
+        SupportHeuristics {
+            support_ids: None,
+            open_polylines: true,
+            unbounded_hatches: true,
+        }
+    }
+}
+
+/// Why a block was classified as support
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SupportReason {
+    /// The id is in [`SupportHeuristics::support_ids`]
+    Id,
+    /// The loop is an open polyline
+    OpenPolyline,
+    /// The hatches are not enclosed by any closed loop
+    UnboundedHatches,
+}
+
+/// Result of [`Layer::classify_supports`], one entry per loop and hatch block of the layer
+///
+/// `None` marks geometry that is considered part of a part.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SupportClassification {
+    /// Classification of each loop
+    pub loops: Vec<Option<SupportReason>>,
+    /// Classification of each hatch block
+    pub hatches: Vec<Option<SupportReason>>,
+}
+
+impl<'a, T: CLIType> Layer<'a, T> {
+    /// Flag the loops and hatch blocks of the layer that are probably supports.
+    ///
+    /// The heuristics are checked in the order of [`SupportReason`] and the first that applies is
+    /// reported. Hatches count as unbounded when the midpoint of their first vector lies outside of
+    /// every closed loop that is not itself classified as support.
+    pub fn classify_supports(&self, heuristics: &SupportHeuristics) -> SupportClassification {
+        let by_id = |id: <T as CLIType>::Meta| {
+            heuristics
+                .support_ids
+                .as_ref()
+                .is_some_and(|r| r.contains(&T::meta_to_i64(id)))
+        };
+
+        let loops: Vec<Option<SupportReason>> = self
+            .loops
+            .iter()
+            .map(|l| {
+                if by_id(l.id) {
+                    Some(SupportReason::Id)
+                } else if heuristics.open_polylines && l.kind() == LoopKind::Open {
+                    Some(SupportReason::OpenPolyline)
+                } else {
+                    None
+                }
+            })
+            .collect();
+
+        let contours: Vec<Vec<[f64; 2]>> = self
+            .loops
+            .iter()
+            .zip(loops.iter())
+            .filter(|(l, class)| class.is_none() && l.is_solid())
+            .map(|(l, _)| geom::to_points(&l.points, |c| T::coord_to_f64(c)))
+            .collect();
+        let hatches = self
+            .hatches
+            .iter()
+            .map(|h| {
+                if by_id(h.id) {
+                    return Some(SupportReason::Id);
+                }
+                let first = h.iter().next()?;
+                let f = |c| T::coord_to_f64(c);
+                let mid = [
+                    (f(first[0]) + f(first[2])) / 2.0,
+                    (f(first[1]) + f(first[3])) / 2.0,
+                ];
+                let inside = contours
+                    .iter()
+                    .filter(|c| geom::winding_number(c, mid) != 0)
+                    .count();
+                if heuristics.unbounded_hatches && inside % 2 != 1 {
+                    Some(SupportReason::UnboundedHatches)
+                } else {
+                    None
+                }
+            })
+            .collect();
+
+        SupportClassification { loops, hatches }
+    }
+}
+
+impl<'a, T: CLIType> CLI<'a, T> {
+    /// Separate a model into parts and supports according to [`Layer::classify_supports`].
+    ///
+    /// Both models have every layer of the original, possibly empty. User data stays with the parts.
+    pub fn split_supports(
+        &self,
+        heuristics: &SupportHeuristics,
+    ) -> (CLI<'static, T>, CLI<'static, T>) {
+        let mut parts = Vec::with_capacity(self.layers.len());
+        let mut supports = Vec::with_capacity(self.layers.len());
+        for layer in self.layers.iter() {
+            let class = layer.classify_supports(heuristics);
+            let empty = || Layer::empty(layer.height);
+            let (mut part, mut support) = (empty(), empty());
+            for (l, c) in layer.loops.iter().zip(class.loops.iter()) {
+                let target = if c.is_some() { &mut support } else { &mut part };
+                target.loops.push(Loop {
+                    id: l.id,
+                    dir: l.dir,
+                    points: Cow::Owned(l.points.to_vec()),
+                    params: l.params.to_owned_params(),
+                });
+            }
+            for (h, c) in layer.hatches.iter().zip(class.hatches.iter()) {
+                let target = if c.is_some() { &mut support } else { &mut part };
+                target.hatches.push(Hatches {
+                    id: h.id,
+                    points: Cow::Owned(h.points.to_vec()),
+                    params: h.params.to_owned_params(),
+                });
+            }
+            let end = part.loops.len() + part.hatches.len();
+            part.user_data = layer
+                .user_data
+                .iter()
+                .map(|u| crate::UserData {
+                    position: u.position.min(end),
+                    ..u.to_owned_data()
+                })
+                .collect();
+            parts.push(part);
+            supports.push(support);
+        }
+
+        let header = Header {
+            layers: Some(self.layers.len()),
+            ..self.header.clone()
+        };
+        (
+            CLI {
+                header: header.clone(),
+                layers: parts,
+                verbatim: Default::default(),
+            },
+            CLI {
+                header,
+                layers: supports,
+                verbatim: Default::default(),
+            },
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clitype::LongCLI;
+
+    fn lp(id: i32, dir: i32, points: Vec<f32>) -> Loop<'static, LongCLI> {
+        Loop {
+            id,
+            dir,
+            points: Cow::Owned(points),
+            params: Default::default(),
+        }
+    }
+
+    fn hatch(id: i32, x: f32) -> Hatches<'static, LongCLI> {
+        Hatches {
+            id,
+            points: Cow::Owned(vec![x, 1.0, x + 2.0, 1.0]),
+            params: Default::default(),
+        }
+    }
+
+    #[test]
+    fn classify() {
+        let square = vec![0.0, 0.0, 10.0, 0.0, 10.0, 10.0, 0.0, 10.0, 0.0, 0.0];
+        let layer = Layer::<LongCLI> {
+            loops: vec![
+                lp(1, 1, square.clone()),
+                lp(1, 2, vec![20.0, 0.0, 20.0, 5.0]),
+                lp(1500, 1, square.iter().map(|c| c + 30.0).collect()),
+            ],
+            hatches: vec![hatch(1, 1.0), hatch(1, 15.0), hatch(1, 31.0)],
+            ..Layer::empty(1.0)
+        };
+        let heuristics = SupportHeuristics {
+            support_ids: Some(1000..=1999),
+            ..Default::default()
+        };
+        let class = layer.classify_supports(&heuristics);
+        assert_eq!(
+            class.loops,
+            vec![
+                None,
+                Some(SupportReason::OpenPolyline),
+                Some(SupportReason::Id)
+            ]
+        );
+        assert_eq!(
+            class.hatches,
+            vec![
+                None,
+                Some(SupportReason::UnboundedHatches),
+                Some(SupportReason::UnboundedHatches)
+            ]
+        );
+
+        let model = CLI {
+            header: Header::default(),
+            layers: vec![layer],
+            verbatim: Default::default(),
+        };
+        let (parts, supports) = model.split_supports(&heuristics);
+        assert_eq!(parts.layers[0].loops.len(), 1);
+        assert_eq!(parts.layers[0].hatches.len(), 1);
+        assert_eq!(supports.layers[0].loops.len(), 2);
+        assert_eq!(supports.layers[0].hatches.len(), 2);
+    }
+}
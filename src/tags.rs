@@ -0,0 +1,229 @@
+//! String tags on layers and loops, stored in user data records
+//!
+//! Annotation workflows mark parts of a build, e.g. witness coupons or regions of special interest
+//! for QA, and need these marks to travel with the file. Tags are key/value strings attached to a
+//! [`Layer`] or one of its loops. They are stored as [user data](crate::extension) records with the
+//! id [`TAG_ID`], so they survive writing and parsing with [`Parser::extensions`].
+//!
+//! The user data record is defined by this crate and not by the spec. Machines and other readers
+//! reject a file holding tags as having an invalid command, so remove the tags with
+//! [`Layer::remove_tag`] before writing a file for them.
+//!
+//! The payload of a tag record is `tag\0<target>\0<key>\0<value>` in UTF-8, where the target is
+//! `layer` or the decimal index of the loop. Loop tags are written right after the loop they belong to.
+//! Keys therefore cannot contain NUL characters, values can.
+//!
+//! ```
+//! use colain::{CLI, Parser, TagTarget, clitype::LongCLI};
+//! # let bytes = colain::CLIBuilder::new().layer(1.0).to_bytes().unwrap();
+//!
+//! let mut model = CLI::<LongCLI>::new(&bytes).unwrap();
+//! for layer in model.iter_mut() {
+//!     layer.tag("qa", "witness coupon").unwrap();
+//! }
+//! let bytes = model.to_bytes();
+//!
+//! let model = Parser::new().extensions().parse(&bytes).unwrap();
+//! for layer in model.iter() {
+//!     assert_eq!(layer.tags().next().unwrap().value, "witness coupon");
+//! }
+//! ```
+//!
+//! [`Parser::extensions`]: crate::Parser::extensions
+
+use crate::clitype::CLIType;
+use crate::{Block, Error, Layer, UserData};
+use std::borrow::Cow;
+
+/// Id of the user data records holding tags
+pub const TAG_ID: i64 = 0x7461;
+
+const MAGIC: &str = "tag";
+
+/// What a [`Tag`] is attached to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TagTarget {
+    /// The layer as a whole
+    Layer,
+    /// The loop with this index within the layer
+    Loop(usize),
+}
+
+/// A key/value annotation, see the [module documentation](self)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Tag {
+    /// What the tag is attached to
+    pub target: TagTarget,
+    /// Name of the tag
+    pub key: String,
+    /// Value of the tag, may be empty
+    pub value: String,
+}
+
+impl Tag {
+    fn encode(&self) -> Vec<u8> {
+        let target = match self.target {
+            TagTarget::Layer => "layer".to_string(),
+            TagTarget::Loop(i) => i.to_string(),
+        };
+        [MAGIC, &target, &self.key, &self.value]
+            .join("\0")
+            .into_bytes()
+    }
+
+    fn decode(data: &[u8]) -> Option<Tag> {
+        let text = std::str::from_utf8(data).ok()?;
+        let mut parts = text.splitn(4, '\0');
+        if parts.next()? != MAGIC {
+            return None;
+        }
+        let target = match parts.next()? {
+            "layer" => TagTarget::Layer,
+            i => TagTarget::Loop(i.parse().ok()?),
+        };
+        Some(Tag {
+            target,
+            key: parts.next()?.to_string(),
+            value: parts.next()?.to_string(),
+        })
+    }
+}
+
+impl<'a, T: CLIType> Layer<'a, T> {
+    /// All tags of the layer and its loops
+    pub fn tags(&self) -> impl Iterator<Item = Tag> + '_ {
+        self.user_data
+            .iter()
+            .filter(|u| T::meta_to_i64(u.id) == TAG_ID)
+            .filter_map(|u| Tag::decode(&u.data))
+    }
+
+    /// Value of a tag of the layer itself
+    pub fn tag_value(&self, key: &str) -> Option<String> {
+        self.tags()
+            .find(|t| t.target == TagTarget::Layer && t.key == key)
+            .map(|t| t.value)
+    }
+
+    /// Value of a tag of a loop
+    pub fn loop_tag_value(&self, index: usize, key: &str) -> Option<String> {
+        self.tags()
+            .find(|t| t.target == TagTarget::Loop(index) && t.key == key)
+            .map(|t| t.value)
+    }
+
+    /// Tag the layer, replacing the value of an existing tag with the same key.
+    ///
+    /// Fails with [`Error::InvalidTagKey`] if the key contains a NUL character.
+    pub fn tag(&mut self, key: &str, value: &str) -> Result<(), Error> {
+        self.set_tag(Tag {
+            target: TagTarget::Layer,
+            key: key.to_string(),
+            value: value.to_string(),
+        })
+    }
+
+    /// Tag a loop of the layer, replacing the value of an existing tag with the same key.
+    ///
+    /// Tags refer to loops by index, so they have to be updated if loops are added or removed
+    /// before the tagged loop. Fails with [`Error::InvalidTagKey`] if the key contains a NUL
+    /// character.
+    pub fn tag_loop(&mut self, index: usize, key: &str, value: &str) -> Result<(), Error> {
+        self.set_tag(Tag {
+            target: TagTarget::Loop(index),
+            key: key.to_string(),
+            value: value.to_string(),
+        })
+    }
+
+    /// Remove tags matching a target and key, returns the number of removed tags
+    pub fn remove_tag(&mut self, target: TagTarget, key: &str) -> usize {
+        self.source = None;
+        let before = self.user_data.len();
+        self.user_data.retain(|u| {
+            let tag = Tag::decode(&u.data);
+            T::meta_to_i64(u.id) != TAG_ID
+                || !matches!(tag, Some(t) if t.target == target && t.key == key)
+        });
+        before - self.user_data.len()
+    }
+
+    fn set_tag(&mut self, tag: Tag) -> Result<(), Error> {
+        if tag.key.contains('\0') {
+            return Err(Error::InvalidTagKey);
+        }
+        self.remove_tag(tag.target, &tag.key);
+        let position = match tag.target {
+            TagTarget::Layer => 0,
+            TagTarget::Loop(i) => {
+                let blocks = self.blocks();
+                let block = blocks.iter().position(|&b| b == (Block::Loop, i));
+                block.map_or(blocks.len(), |b| b + 1)
+            }
+        };
+        self.user_data.push(UserData {
+            id: T::meta_from_i64(TAG_ID),
+            position,
+            data: Cow::Owned(tag.encode()),
+        });
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clitype::ShortCLI;
+    use crate::{Error, Header, Loop, Parser, CLI};
+
+    #[test]
+    fn round_trip() -> Result<(), Error> {
+        let square = || Loop {
+            id: 1,
+            dir: 1,
+            points: Cow::Owned(vec![0, 0, 1, 0, 1, 1, 0, 0]),
+            params: Default::default(),
+        };
+        let mut layer = Layer::<ShortCLI> {
+            loops: vec![square(), square()],
+            user_data: vec![UserData {
+                id: 7,
+                position: 0,
+                data: Cow::Borrowed(b"vendor"),
+            }],
+            ..Layer::empty(10)
+        };
+        layer.tag("region", "coupon")?;
+        layer.tag("region", "qa")?;
+        layer.tag_loop(1, "name", "tensile bar\0 2")?;
+        assert!(matches!(
+            layer.tag_loop(0, "name\0layer", "x"),
+            Err(Error::InvalidTagKey)
+        ));
+        assert_eq!(layer.tags().count(), 2);
+
+        let bytes = CLI {
+            header: Header {
+                aligned: true,
+                ..Header::default()
+            },
+            layers: vec![layer],
+            verbatim: Default::default(),
+        }
+        .to_bytes();
+        let mut model = Parser::new().short().extensions().parse(&bytes)?;
+        let layer = model.iter_mut().next().unwrap();
+        assert_eq!(layer.tag_value("region").as_deref(), Some("qa"));
+        assert_eq!(
+            layer.loop_tag_value(1, "name").as_deref(),
+            Some("tensile bar\0 2")
+        );
+        assert_eq!(layer.loop_tag_value(0, "name"), None);
+        assert_eq!(layer.user_data()[2].position(), 2);
+
+        assert_eq!(layer.remove_tag(TagTarget::Layer, "region"), 1);
+        assert_eq!(layer.tags().count(), 1);
+        assert_eq!(layer.user_data().len(), 2);
+        Ok(())
+    }
+}
@@ -0,0 +1,201 @@
+//! Distribution of layer thicknesses
+//!
+//! Adaptive slicers vary the layer thickness over the height of a part: thin layers where the
+//! surface is shallow or detailed, thick ones elsewhere. Process planners choose recoater
+//! parameters per band of constant thickness, [`CLI::thickness_bands`] finds these bands and
+//! [`CLI::thickness_histogram`] summarizes how much of the build uses each thickness.
+//!
+//! Thicknesses are in millimeters, each layer's thickness is the distance to the previous layer,
+//! or to the build plate for the first layer, as in [`LayerStats`](crate::LayerStats).
+//!
+//! ```
+//! use colain::{CLI, clitype::LongCLI};
+//! # let buf = colain::BuildPlate::<LongCLI>::new(1.0).to_bytes();
+//! let model = CLI::<LongCLI>::new(&buf).unwrap();
+//!
+//! for band in model.thickness_bands(0.001) {
+//!     println!("{:?} {} mm from {} to {} mm", band.kind, band.thickness, band.z[0], band.z[1]);
+//! }
+//! ```
+
+use crate::clitype::CLIType;
+use crate::CLI;
+use std::ops::Range;
+
+/// Number of layers with about the same thickness
+#[derive(Debug, Clone, PartialEq)]
+pub struct ThicknessBin {
+    /// Mean thickness of the layers in the bin
+    pub thickness: f64,
+    /// Number of layers
+    pub layers: usize,
+    /// Sum of the thicknesses, the part of the build height made of these layers
+    pub height: f64,
+}
+
+/// How the thickness of a band compares to the rest of the build
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BandKind {
+    /// The thickness used for most of the layers
+    Nominal,
+    /// Thinner than nominal
+    Fine,
+    /// Thicker than nominal
+    Coarse,
+}
+
+/// Consecutive layers with about the same thickness
+#[derive(Debug, Clone, PartialEq)]
+pub struct ThicknessBand {
+    /// Indices of the layers in the band
+    pub layers: Range<usize>,
+    /// Height below the first layer and height of the last layer, i.e. the slab of material
+    /// built by the band
+    pub z: [f64; 2],
+    /// Mean thickness of the layers
+    pub thickness: f64,
+    /// Comparison with the nominal thickness
+    pub kind: BandKind,
+}
+
+impl<'a, T: CLIType> CLI<'a, T> {
+    /// Thickness of every layer
+    fn thicknesses(&self) -> Vec<f64> {
+        let mut previous = 0.0;
+        self.layers
+            .iter()
+            .map(|l| {
+                let z = T::coord_to_f64(l.height) * self.header.units;
+                let thickness = z - previous;
+                previous = z;
+                thickness
+            })
+            .collect()
+    }
+
+    /// Group the layers by thickness, sorted from thin to thick.
+    ///
+    /// Layers whose thickness differs by at most `tolerance` from the thinnest layer of a bin are
+    /// counted in the same bin, which absorbs rounding of the heights to the file units.
+    pub fn thickness_histogram(&self, tolerance: f64) -> Vec<ThicknessBin> {
+        let mut thicknesses = self.thicknesses();
+        thicknesses.sort_by(f64::total_cmp);
+        let mut bins: Vec<(f64, Vec<f64>)> = Vec::new();
+        for t in thicknesses {
+            match bins.last_mut() {
+                Some((first, members)) if t - *first <= tolerance => members.push(t),
+                _ => bins.push((t, vec![t])),
+            }
+        }
+        bins.into_iter()
+            .map(|(_, members)| {
+                let height: f64 = members.iter().sum();
+                ThicknessBin {
+                    thickness: height / members.len() as f64,
+                    layers: members.len(),
+                    height,
+                }
+            })
+            .collect()
+    }
+
+    /// Split the layers into bands of about constant thickness, in build order.
+    ///
+    /// A layer joins the current band if its thickness is within `tolerance` of the first layer
+    /// of the band. The nominal thickness is that of the [histogram](CLI::thickness_histogram)
+    /// bin with the most layers, bands within `tolerance` of it are [`BandKind::Nominal`]. A model
+    /// sliced with a constant thickness gives a single band.
+    pub fn thickness_bands(&self, tolerance: f64) -> Vec<ThicknessBand> {
+        let nominal = self
+            .thickness_histogram(tolerance)
+            .into_iter()
+            .max_by_key(|b| b.layers)
+            .map_or(0.0, |b| b.thickness);
+        let kind = |thickness: f64| {
+            if (thickness - nominal).abs() <= tolerance {
+                BandKind::Nominal
+            } else if thickness < nominal {
+                BandKind::Fine
+            } else {
+                BandKind::Coarse
+            }
+        };
+
+        let thicknesses = self.thicknesses();
+        let mut bands: Vec<ThicknessBand> = Vec::new();
+        let mut bottom = 0.0;
+        let mut start = 0;
+        for (i, &t) in thicknesses.iter().enumerate() {
+            let top = bottom + t;
+            if i == start || (t - thicknesses[start]).abs() > tolerance {
+                start = i;
+                bands.push(ThicknessBand {
+                    layers: i..i + 1,
+                    z: [bottom, top],
+                    thickness: t,
+                    kind: kind(t),
+                });
+            } else {
+                let band = bands.last_mut().unwrap();
+                band.layers.end = i + 1;
+                band.z[1] = top;
+                band.thickness = (band.z[1] - band.z[0]) / band.layers.len() as f64;
+                band.kind = kind(band.thickness);
+            }
+            bottom = top;
+        }
+        bands
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clitype::LongCLI;
+    use crate::{Header, Layer};
+
+    fn model(heights: &[f32]) -> CLI<'static, LongCLI> {
+        CLI {
+            header: Header {
+                units: 0.001,
+                ..Header::default()
+            },
+            layers: heights.iter().map(|&height| Layer::empty(height)).collect(),
+            verbatim: Default::default(),
+        }
+    }
+
+    #[test]
+    fn adaptive() {
+        // 50 µm layers with a band of 25 µm layers and one of 100 µm layers
+        let model = model(&[
+            50.0, 100.0, 150.0, 175.0, 200.0, 225.0, 250.0, 300.0, 350.0, 450.0, 550.0,
+        ]);
+        let bins = model.thickness_histogram(0.001);
+        assert_eq!(bins.len(), 3);
+        assert_eq!((bins[0].layers, bins[1].layers, bins[2].layers), (4, 5, 2));
+        assert!((bins[0].thickness - 0.025).abs() < 1e-9);
+        assert!((bins[2].height - 0.2).abs() < 1e-9);
+
+        let bands = model.thickness_bands(0.001);
+        let summary: Vec<_> = bands.iter().map(|b| (b.layers.clone(), b.kind)).collect();
+        assert_eq!(
+            summary,
+            vec![
+                (0..3, BandKind::Nominal),
+                (3..7, BandKind::Fine),
+                (7..9, BandKind::Nominal),
+                (9..11, BandKind::Coarse),
+            ]
+        );
+        assert!((bands[1].z[0] - 0.15).abs() < 1e-9 && (bands[1].z[1] - 0.25).abs() < 1e-9);
+    }
+
+    #[test]
+    fn constant() {
+        let bands = model(&[30.0, 60.0, 90.0]).thickness_bands(0.001);
+        assert_eq!(bands.len(), 1);
+        assert_eq!(bands[0].kind, BandKind::Nominal);
+        assert!(model(&[]).thickness_bands(0.001).is_empty());
+    }
+}
@@ -0,0 +1,277 @@
+//! Splitting the binary geometry section into records
+//!
+//! Each record consists of a command word followed by its parameters. The tokenizer only
+//! determines where records start and end; interpreting the parameters is left to the caller.
+//!
+//! The parser is built on top of [`Tokens`], which is public for inspecting files record by
+//! record, for example when reverse engineering a vendor dialect the parser rejects. Iteration
+//! stops at the first record whose length cannot be determined, [`Tokens::position`] tells
+//! where that is.
+//!
+//! ```
+//! use colain::{CLI, ParserOptions, clitype::LongCLI};
+//! # let buf = colain::BuildPlate::<LongCLI>::new(1.0).to_bytes();
+//!
+//! let mut tokens = CLI::<LongCLI>::tokens(&buf, &ParserOptions::default()).unwrap();
+//! while let Some(token) = tokens.next() {
+//!     match token {
+//!         Ok(t) => println!("{:6} command {} with {} bytes", t.offset, t.command, t.payload.len()),
+//!         Err(e) => println!("stopped at {}: {}", tokens.position(), e),
+//!     }
+//! }
+//! ```
+
+use crate::any::is_command;
+use crate::clitype::{CLIType, LongCLI, ShortCLI};
+use crate::{Dialect, Error, ParserOptions, CLI};
+use bytes::Buf;
+use std::marker::PhantomData;
+use std::mem::size_of;
+
+/// A single record of the geometry section
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Token<'a> {
+    /// Offset of the command word from the start of the geometry section
+    pub offset: usize,
+    /// Command word, translated to the standard command by the dialect
+    pub command: u16,
+    /// Parameters of the command
+    pub payload: &'a [u8],
+    /// Offset of the first byte after the record
+    pub end: usize,
+}
+
+/// Iterator over the records of a geometry section
+///
+/// Iteration stops after the first error.
+#[derive(Debug, Clone)]
+pub struct Tokens<'a, T: CLIType> {
+    geom: &'a [u8],
+    pos: usize,
+    failed: Option<usize>,
+    aligned: bool,
+    dialect: Dialect,
+    extensions: bool,
+    _type: PhantomData<T>,
+}
+
+impl<'a, T: CLIType> Tokens<'a, T> {
+    /// Iterate over the records of a geometry section, use [`CLI::tokens`](crate::CLI::tokens)
+    /// to start after the header of a file.
+    ///
+    /// `aligned` tells whether the file declares `$$ALIGN`. The records of the
+    /// [`extension`](crate::extension) module are unknown, see [`Tokens::extensions`].
+    pub fn new(geom: &'a [u8], aligned: bool, dialect: Dialect) -> Self {
+        Tokens {
+            geom,
+            pos: 0,
+            failed: None,
+            aligned,
+            dialect,
+            extensions: false,
+            _type: PhantomData,
+        }
+    }
+
+    /// Also read the records of the [`extension`](crate::extension) module, see
+    /// [`Parser::extensions`](crate::Parser::extensions)
+    pub fn extensions(mut self, enabled: bool) -> Self {
+        self.extensions = enabled;
+        self
+    }
+
+    /// Iterate with the dialect and extensions of `options`
+    pub(crate) fn with_options(geom: &'a [u8], aligned: bool, options: &ParserOptions) -> Self {
+        Tokens::new(geom, aligned, options.dialect).extensions(options.extensions)
+    }
+
+    /// Offset of the next record from the start of the geometry section, or of the record that
+    /// could not be read after an error
+    pub fn position(&self) -> usize {
+        self.failed.unwrap_or(self.pos)
+    }
+
+    /// Continue with the record at `pos`, also after an error
+    pub(crate) fn resume(&mut self, pos: usize) {
+        self.pos = pos;
+        self.failed = None;
+    }
+
+    fn next_token(&mut self) -> Result<Option<Token<'a>>, Error> {
+        let mut rest = &self.geom[self.pos..];
+        if self.dialect.zero_padding() {
+            while rest.len() >= 2 && rest[0] == 0 && rest[1] == 0 {
+                rest.advance(2);
+            }
+            if rest.iter().all(|&b| b == 0) {
+                rest = &[];
+            }
+        }
+        if rest.is_empty() {
+            self.pos = self.geom.len();
+            return Ok(None);
+        }
+
+        // Implementation notes:
+        // the CLI spec does not actually make clear what should happen to the last element in a 32bit aligned
+        // data section. You could technically leave the last two empty bytes off of the end of the file and still have valid data.
+        // Its also unlikely that this would ever matter since the last element of a data section is likely to be a hatches or polyline
+        // command which would not end with a half word element. The aggressive EOF check makes sure the parameters can always be read.
+        let offset = self.geom.len() - rest.len();
+        self.pos = offset;
+        let word = stride(2, self.aligned);
+        expect(rest, word)?;
+        let command = self.dialect.command(rest.get_u16_le());
+        rest = &self.geom[offset + word..];
+
+        let len = payload_len::<T>(command, rest, self.aligned, self.extensions)?;
+        self.pos = offset + word + len;
+        Ok(Some(Token {
+            offset,
+            command,
+            payload: &rest[..len],
+            end: self.pos,
+        }))
+    }
+}
+
+impl<'a, T: CLIType> CLI<'a, T> {
+    /// Parse the header of a file and iterate over the records of its geometry section, see the
+    /// [module documentation](crate::token)
+    pub fn tokens(raw: &'a [u8], options: &ParserOptions) -> Result<Tokens<'a, T>, Error> {
+        let (header, _, geom) = CLI::<T>::split(raw, options)?;
+        Ok(Tokens::with_options(geom, header.aligned, options))
+    }
+}
+
+impl<'a, T: CLIType> Iterator for Tokens<'a, T> {
+    type Item = Result<Token<'a>, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let token = self.next_token();
+        if token.is_err() {
+            self.failed = Some(self.pos);
+            self.pos = self.geom.len();
+        }
+        token.transpose()
+    }
+}
+
+/// Size of a value in the file, everything occupies at least 4 bytes when aligned
+pub(crate) fn stride(size: usize, aligned: bool) -> usize {
+    if aligned {
+        size.next_multiple_of(4)
+    } else {
+        size
+    }
+}
+
+fn expect(buf: &[u8], req_bytes: usize) -> Result<(), Error> {
+    if buf.len() < req_bytes {
+        Err(Error::UnexpectedEOF)
+    } else {
+        Ok(())
+    }
+}
+
+/// Length of the parameters of a command, `extensions` telling whether the records of the
+/// [`extension`](crate::extension) module are known
+pub(crate) fn payload_len<T: CLIType>(
+    command: u16,
+    rest: &[u8],
+    aligned: bool,
+    extensions: bool,
+) -> Result<usize, Error> {
+    let meta = stride(size_of::<<T as CLIType>::Meta>(), aligned);
+    let coord = size_of::<<T as CLIType>::Coord>();
+
+    // Fixed parameters followed by a count of variable sized items
+    let counted = |fixed: usize, item: usize| -> Result<usize, Error> {
+        expect(rest, fixed * meta)?;
+        let mut count = &rest[(fixed - 1) * meta..];
+        let n = T::get_usize(&mut count, aligned);
+        let len = n
+            .checked_mul(item)
+            .and_then(|len| len.checked_add(fixed * meta))
+            .ok_or(Error::UnexpectedEOF)?;
+        expect(rest, len)?;
+        Ok(len)
+    };
+
+    let len = match command {
+        c if c == T::CMD_LAYER => {
+            let len = stride(coord, aligned);
+            expect(rest, len)?;
+            len
+        }
+        // $$ALIGN not a factor for the points since the spec says they should be tightly packed
+        c if c == T::CMD_PLINE => counted(3, 2 * coord)?,
+        c if c == T::CMD_HATCH => counted(2, 4 * coord)?,
+        c if extensions && (c == T::CMD_POWER || c == T::CMD_SPEED) => counted(1, coord)?,
+        c if extensions && c == T::CMD_USERDATA => {
+            let len = counted(2, 1)?;
+            let padded = 2 * meta + stride(len - 2 * meta, aligned);
+            expect(rest, padded)?;
+            padded
+        }
+        // Not a command of `T`, so a command of the other type
+        c if is_command::<ShortCLI>(c, extensions) || is_command::<LongCLI>(c, extensions) => {
+            return Err(Error::TypeMismatch)
+        }
+        _ => return Err(Error::InvalidGeometryCommand(command)),
+    };
+    Ok(len)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clitype::ShortCLI;
+
+    #[test]
+    fn records() -> Result<(), Error> {
+        let mut buf = b"$$HEADERSTART\n$$BINARY\n$$UNITS/1\n$$VERSION/200\n$$HEADEREND".to_vec();
+        let geometry = buf.len();
+        // layer at 5, hatches with one vector, then an unknown command
+        for word in [128u16, 5, 131, 1, 1, 0, 0, 2, 2, 0x77, 1] {
+            buf.extend(word.to_le_bytes());
+        }
+
+        let mut tokens = CLI::<ShortCLI>::tokens(&buf, &ParserOptions::default())?;
+        let layer = tokens.next().unwrap()?;
+        assert_eq!(
+            (layer.offset, layer.command, layer.payload),
+            (0, 128, &[5, 0][..])
+        );
+        let hatches = tokens.next().unwrap()?;
+        assert_eq!(
+            (hatches.offset, hatches.command, hatches.payload.len()),
+            (4, 131, 12)
+        );
+        assert_eq!(hatches.end, tokens.position());
+        assert!(matches!(
+            tokens.next(),
+            Some(Err(Error::InvalidGeometryCommand(0x77)))
+        ));
+        assert_eq!(tokens.position(), 18);
+        assert_eq!(&buf[geometry + tokens.position()..][..2], &[0x77, 0]);
+        assert!(tokens.next().is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn commands() {
+        let len = |command, extensions| payload_len::<ShortCLI>(command, &[], false, extensions);
+        // Long polyline in a short file
+        assert!(matches!(len(130, false), Err(Error::TypeMismatch)));
+        // Long power in a short file, only a command if extensions are enabled
+        assert!(matches!(len(134, true), Err(Error::TypeMismatch)));
+        for command in [126, 133, 134, 137, 139] {
+            assert!(matches!(
+                len(command, false),
+                Err(Error::InvalidGeometryCommand(c)) if c == command
+            ));
+        }
+        assert!(matches!(len(133, true), Err(Error::UnexpectedEOF)));
+    }
+}
@@ -0,0 +1,212 @@
+//! Optional instrumentation with `tracing`
+//!
+//! With the `tracing` feature enabled, parsing reports what it is doing through the
+//! [`tracing`](https://docs.rs/tracing) crate, so applications can profile and log the loading of
+//! files with whatever subscriber they already use:
+//!
+//! - a `parse` span covers [`CLI::new_with_options`](crate::CLI::new_with_options), with the size
+//!   of the file,
+//! - a `header` span covers parsing and validating the header. A debug event reports the units,
+//!   version, alignment, declared layer count and the time taken, a warning the reason a header is
+//!   rejected,
+//! - a `geometry` span covers decoding the geometry section. A trace event is emitted for every
+//!   decoded layer with its loop and hatch counts, and a debug event at the end reports the
+//!   number of layers, loops, hatches and records along with the time taken. Decoding errors are
+//!   reported as warnings with the number of records read successfully,
+//! - an `index` span covers indexing a file for [`CLI::decode_nearest`](crate::CLI::decode_nearest)
+//!   and a `layer` span decoding each of its layers.
+//!
+//! Without the feature, all of this compiles to nothing.
+
+use crate::clitype::CLIType;
+use crate::{Error, Header, Layer};
+
+/// Guard keeping a span entered until it is dropped
+#[cfg(feature = "tracing")]
+pub(crate) type Entered = tracing::span::EnteredSpan;
+#[cfg(not(feature = "tracing"))]
+pub(crate) struct Entered;
+
+/// Measures the duration of a phase, only when tracing is enabled
+pub(crate) struct Timer {
+    #[cfg(feature = "tracing")]
+    start: std::time::Instant,
+}
+
+impl Timer {
+    pub(crate) fn start() -> Self {
+        Timer {
+            #[cfg(feature = "tracing")]
+            start: std::time::Instant::now(),
+        }
+    }
+
+    #[cfg(feature = "tracing")]
+    fn micros(&self) -> u64 {
+        self.start.elapsed().as_micros() as u64
+    }
+}
+
+#[cfg_attr(not(feature = "tracing"), allow(unused_variables))]
+pub(crate) fn parse_span(bytes: usize) -> Entered {
+    #[cfg(feature = "tracing")]
+    return tracing::debug_span!("parse", bytes).entered();
+    #[cfg(not(feature = "tracing"))]
+    Entered
+}
+
+pub(crate) fn header_span() -> Entered {
+    #[cfg(feature = "tracing")]
+    return tracing::debug_span!("header").entered();
+    #[cfg(not(feature = "tracing"))]
+    Entered
+}
+
+#[cfg_attr(not(feature = "tracing"), allow(unused_variables))]
+pub(crate) fn geometry_span(bytes: usize) -> Entered {
+    #[cfg(feature = "tracing")]
+    return tracing::debug_span!("geometry", bytes).entered();
+    #[cfg(not(feature = "tracing"))]
+    Entered
+}
+
+#[cfg_attr(not(feature = "tracing"), allow(unused_variables))]
+pub(crate) fn index_span(bytes: usize) -> Entered {
+    #[cfg(feature = "tracing")]
+    return tracing::debug_span!("index", bytes).entered();
+    #[cfg(not(feature = "tracing"))]
+    Entered
+}
+
+#[cfg_attr(not(feature = "tracing"), allow(unused_variables))]
+pub(crate) fn layer_span(bytes: usize) -> Entered {
+    #[cfg(feature = "tracing")]
+    return tracing::trace_span!("layer", bytes).entered();
+    #[cfg(not(feature = "tracing"))]
+    Entered
+}
+
+#[cfg_attr(not(feature = "tracing"), allow(unused_variables))]
+pub(crate) fn header_parsed(header: &Header, bytes: usize, timer: &Timer) {
+    #[cfg(feature = "tracing")]
+    tracing::debug!(
+        units = header.units,
+        version = header.version,
+        aligned = header.aligned,
+        layers = ?header.layers,
+        bytes,
+        micros = timer.micros(),
+        "parsed header"
+    );
+}
+
+#[cfg_attr(not(feature = "tracing"), allow(unused_variables))]
+pub(crate) fn header_rejected(error: &Error) {
+    #[cfg(feature = "tracing")]
+    tracing::warn!(?error, "invalid header");
+}
+
+#[cfg_attr(not(feature = "tracing"), allow(unused_variables))]
+pub(crate) fn layer_decoded<T: CLIType>(index: usize, layer: &Layer<'_, T>) {
+    #[cfg(feature = "tracing")]
+    tracing::trace!(
+        layer = index,
+        loops = layer.loops.len(),
+        hatches = layer.hatches.len(),
+        "decoded layer"
+    );
+}
+
+#[cfg_attr(not(feature = "tracing"), allow(unused_variables))]
+pub(crate) fn geometry_decoded<T: CLIType>(layers: &[Layer<'_, T>], records: usize, timer: &Timer) {
+    #[cfg(feature = "tracing")]
+    tracing::debug!(
+        layers = layers.len(),
+        loops = layers.iter().map(|l| l.loops.len()).sum::<usize>(),
+        hatches = layers.iter().map(|l| l.hatches.len()).sum::<usize>(),
+        records,
+        micros = timer.micros(),
+        "decoded geometry"
+    );
+}
+
+#[cfg_attr(not(feature = "tracing"), allow(unused_variables))]
+pub(crate) fn geometry_rejected(error: &Error, records: usize) {
+    #[cfg(feature = "tracing")]
+    tracing::warn!(?error, records, "invalid geometry");
+}
+
+#[cfg(all(test, feature = "tracing"))]
+mod tests {
+    use crate::clitype::LongCLI;
+    use crate::{Header, Layer, CLI};
+    use std::sync::{Arc, Mutex};
+    use tracing::field::{Field, Visit};
+    use tracing::span::{Attributes, Id, Record};
+    use tracing::{Event, Metadata, Subscriber};
+
+    /// Collects span names and event messages
+    #[derive(Clone, Default)]
+    struct Collect(Arc<Mutex<Vec<String>>>);
+
+    struct Message<'a>(&'a mut String);
+
+    impl Visit for Message<'_> {
+        fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+            if field.name() == "message" {
+                *self.0 = format!("{:?}", value);
+            }
+        }
+    }
+
+    impl Subscriber for Collect {
+        fn enabled(&self, _: &Metadata<'_>) -> bool {
+            true
+        }
+        fn new_span(&self, span: &Attributes<'_>) -> Id {
+            let mut names = self.0.lock().unwrap();
+            names.push(span.metadata().name().to_string());
+            Id::from_u64(names.len() as u64)
+        }
+        fn record(&self, _: &Id, _: &Record<'_>) {}
+        fn record_follows_from(&self, _: &Id, _: &Id) {}
+        fn event(&self, event: &Event<'_>) {
+            let mut message = String::new();
+            event.record(&mut Message(&mut message));
+            self.0.lock().unwrap().push(message);
+        }
+        fn enter(&self, _: &Id) {}
+        fn exit(&self, _: &Id) {}
+    }
+
+    #[test]
+    fn phases() {
+        let buf = CLI::<LongCLI> {
+            header: Header::default(),
+            layers: vec![Layer::empty(0.5)],
+            verbatim: Default::default(),
+        }
+        .to_bytes();
+
+        let collect = Collect::default();
+        tracing::subscriber::with_default(collect.clone(), || {
+            CLI::<LongCLI>::new(&buf).unwrap();
+            assert!(CLI::<LongCLI>::new(b"$$HEADERSTART $$HEADEREND").is_err());
+        });
+        let seen = collect.0.lock().unwrap();
+        assert_eq!(
+            *seen,
+            [
+                "parse",
+                "header",
+                "parsed header",
+                "geometry",
+                "decoded layer",
+                "decoded geometry",
+                "parse",
+                "header",
+                "invalid header"
+            ]
+        );
+    }
+}
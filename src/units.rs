@@ -0,0 +1,390 @@
+//! Length units and conversion of coordinates
+//!
+//! Coordinates in a CLI file are integers or floats in model units, the header states how many
+//! millimeters one unit is. [`UnitScale`] turns coordinates into lengths in a chosen [`Unit`] and
+//! back, and can be passed to the `_scaled` accessors of loops, hatches and layers.
+//!
+//! ```
+//! use colain::units::{Unit, UnitScale};
+//! use colain::{CLI, clitype::LongCLI};
+//! # let buf = colain::BuildPlate::<LongCLI>::new(1.0).to_bytes();
+//! let model = CLI::<LongCLI>::new(&buf).unwrap();
+//!
+//! let scale = model.unit_scale().with_unit(Unit::Micrometer);
+//! for layer in model.iter() {
+//!     let z = layer.height_scaled(scale);
+//!     for contour in layer.iter_loops() {
+//!         for [x, y] in contour.iter_scaled(scale) {}
+//!     }
+//! }
+//!
+//! // store the same geometry with a 1 µm resolution
+//! let fine = model.with_units(0.001);
+//! ```
+//!
+//! [`CLI::scaled`] wraps a model so that the scale does not have to be passed around:
+//!
+//! ```
+//! # use colain::{CLI, clitype::LongCLI};
+//! # let buf = colain::BuildPlate::<LongCLI>::new(1.0).to_bytes();
+//! # let model = CLI::<LongCLI>::new(&buf).unwrap();
+//! for layer in model.scaled().iter() {
+//!     let z = layer.height();
+//!     for contour in layer.loops() {
+//!         for [x, y] in contour.iter() {}
+//!     }
+//! }
+//! ```
+
+use crate::clitype::CLIType;
+use crate::{Hatches, Layer, Loop, CLI};
+use std::borrow::Cow;
+
+/// Unit of length
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Unit {
+    /// Millimeters, the unit of the `$$UNITS` header value
+    #[default]
+    Millimeter,
+    /// Micrometers
+    Micrometer,
+    /// Inches
+    Inch,
+}
+
+impl Unit {
+    /// Length of one of this unit in millimeters
+    pub fn millimeters(self) -> f64 {
+        match self {
+            Unit::Millimeter => 1.0,
+            Unit::Micrometer => 0.001,
+            Unit::Inch => 25.4,
+        }
+    }
+
+    /// Convert a length in this unit to `unit`
+    pub fn convert(self, value: f64, unit: Unit) -> f64 {
+        if self == unit {
+            value
+        } else {
+            value * self.millimeters() / unit.millimeters()
+        }
+    }
+}
+
+/// Conversion between coordinates of a model and lengths in a [`Unit`]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct UnitScale {
+    /// Millimeters per model unit
+    units: f64,
+    /// Unit of the lengths
+    unit: Unit,
+}
+
+impl UnitScale {
+    /// Scale for models where one unit is `units` millimeters, giving lengths in millimeters
+    pub fn new(units: f64) -> Self {
+        UnitScale {
+            units,
+            unit: Unit::Millimeter,
+        }
+    }
+
+    /// Give lengths in `unit` instead
+    pub fn with_unit(self, unit: Unit) -> Self {
+        UnitScale { unit, ..self }
+    }
+
+    /// Unit of the lengths
+    pub fn unit(&self) -> Unit {
+        self.unit
+    }
+
+    /// Length of one model unit
+    pub fn factor(&self) -> f64 {
+        Unit::Millimeter.convert(self.units, self.unit)
+    }
+
+    /// Length of a coordinate
+    pub fn to_length<T: CLIType>(&self, coord: <T as CLIType>::Coord) -> f64 {
+        T::coord_to_f64(coord) * self.factor()
+    }
+
+    /// Coordinate of a length, rounded to the nearest representable value
+    pub fn to_coord<T: CLIType>(&self, length: f64) -> <T as CLIType>::Coord {
+        T::coord_from_f64(length / self.factor())
+    }
+}
+
+impl<'a, T: CLIType> Loop<'a, T> {
+    /// Iterator over the points of the loop as lengths, see [`UnitScale`]
+    pub fn iter_scaled(&self, scale: UnitScale) -> impl Iterator<Item = [f64; 2]> + '_ {
+        self.iter()
+            .map(move |[x, y]| [scale.to_length::<T>(x), scale.to_length::<T>(y)])
+    }
+}
+
+impl<'a, T: CLIType> Hatches<'a, T> {
+    /// Iterator over the hatches as `[x0, y0, x1, y1]` lengths, see [`UnitScale`]
+    pub fn iter_scaled(&self, scale: UnitScale) -> impl Iterator<Item = [f64; 4]> + '_ {
+        self.iter().map(move |h| h.map(|c| scale.to_length::<T>(c)))
+    }
+}
+
+impl<'a, T: CLIType> Layer<'a, T> {
+    /// Height of the layer as a length, see [`UnitScale`]
+    pub fn height_scaled(&self, scale: UnitScale) -> f64 {
+        scale.to_length::<T>(self.height)
+    }
+}
+
+/// A model giving lengths instead of coordinates, see [`CLI::scaled`]
+#[derive(Clone, Copy)]
+pub struct Scaled<'m, 'a, T: CLIType> {
+    model: &'m CLI<'a, T>,
+    scale: UnitScale,
+}
+
+impl<'m, 'a, T: CLIType> Scaled<'m, 'a, T> {
+    /// Give lengths in `unit` instead of millimeters
+    pub fn with_unit(self, unit: Unit) -> Self {
+        Scaled {
+            scale: self.scale.with_unit(unit),
+            ..self
+        }
+    }
+
+    /// The scale applied to every coordinate
+    pub fn scale(&self) -> UnitScale {
+        self.scale
+    }
+
+    /// Iterate over the layers
+    pub fn iter(&self) -> impl Iterator<Item = ScaledLayer<'m, 'a, T>> + 'm {
+        let scale = self.scale;
+        self.model
+            .layers
+            .iter()
+            .map(move |layer| ScaledLayer { layer, scale })
+    }
+}
+
+/// A layer giving lengths instead of coordinates, see [`CLI::scaled`]
+#[derive(Debug, Clone, Copy)]
+pub struct ScaledLayer<'m, 'a, T: CLIType> {
+    layer: &'m Layer<'a, T>,
+    scale: UnitScale,
+}
+
+impl<'m, 'a, T: CLIType> ScaledLayer<'m, 'a, T> {
+    /// The layer in coordinates
+    pub fn inner(&self) -> &'m Layer<'a, T> {
+        self.layer
+    }
+
+    /// Height of the layer
+    pub fn height(&self) -> f64 {
+        self.layer.height_scaled(self.scale)
+    }
+
+    /// Iterate over the loops of the layer
+    pub fn loops(&self) -> impl Iterator<Item = ScaledLoop<'m, 'a, T>> + 'm {
+        let scale = self.scale;
+        self.layer
+            .loops
+            .iter()
+            .map(move |inner| ScaledLoop { inner, scale })
+    }
+
+    /// Iterate over the sets of hatches of the layer
+    pub fn hatches(&self) -> impl Iterator<Item = ScaledHatches<'m, 'a, T>> + 'm {
+        let scale = self.scale;
+        self.layer
+            .hatches
+            .iter()
+            .map(move |inner| ScaledHatches { inner, scale })
+    }
+}
+
+/// A loop giving lengths instead of coordinates, see [`CLI::scaled`]
+#[derive(Debug, Clone, Copy)]
+pub struct ScaledLoop<'m, 'a, T: CLIType> {
+    inner: &'m Loop<'a, T>,
+    scale: UnitScale,
+}
+
+impl<'m, 'a, T: CLIType> ScaledLoop<'m, 'a, T> {
+    /// The loop in coordinates, e.g. for its id and direction
+    pub fn inner(&self) -> &'m Loop<'a, T> {
+        self.inner
+    }
+
+    /// Iterate over the points as `[x, y]`
+    pub fn iter(&self) -> impl Iterator<Item = [f64; 2]> + 'm {
+        self.inner.iter_scaled(self.scale)
+    }
+}
+
+/// Hatches giving lengths instead of coordinates, see [`CLI::scaled`]
+#[derive(Debug, Clone, Copy)]
+pub struct ScaledHatches<'m, 'a, T: CLIType> {
+    inner: &'m Hatches<'a, T>,
+    scale: UnitScale,
+}
+
+impl<'m, 'a, T: CLIType> ScaledHatches<'m, 'a, T> {
+    /// The hatches in coordinates, e.g. for their id
+    pub fn inner(&self) -> &'m Hatches<'a, T> {
+        self.inner
+    }
+
+    /// Iterate over the hatches as `[x0, y0, x1, y1]`
+    pub fn iter(&self) -> impl Iterator<Item = [f64; 4]> + 'm {
+        self.inner.iter_scaled(self.scale)
+    }
+}
+
+impl<'a, T: CLIType> CLI<'a, T> {
+    /// Scale converting coordinates of this model to millimeters
+    pub fn unit_scale(&self) -> UnitScale {
+        UnitScale::new(self.header.units)
+    }
+
+    /// View of the model with every coordinate and height multiplied by `$$UNITS`, giving
+    /// millimeters, or another unit with [`Scaled::with_unit`]
+    pub fn scaled(&self) -> Scaled<'_, 'a, T> {
+        Scaled {
+            model: self,
+            scale: self.unit_scale(),
+        }
+    }
+
+    /// Copy of the model with `$$UNITS` set to `units` millimeters and all coordinates rescaled
+    /// so the geometry keeps its size.
+    ///
+    /// Coordinates are rounded to the new units, so going to coarser units loses resolution, and
+    /// integer coordinates of [`ShortCLI`](crate::clitype::ShortCLI) models saturate if they no
+    /// longer fit.
+    pub fn with_units(&self, units: f64) -> CLI<'static, T> {
+        let factor = self.header.units / units;
+        let rescale = |coords: &[<T as CLIType>::Coord]| -> Vec<<T as CLIType>::Coord> {
+            coords
+                .iter()
+                .map(|&c| T::coord_from_f64(T::coord_to_f64(c) * factor))
+                .collect()
+        };
+        let layers = self
+            .layers
+            .iter()
+            .map(|layer| Layer {
+                loops: layer
+                    .loops
+                    .iter()
+                    .map(|l| Loop {
+                        id: l.id,
+                        dir: l.dir,
+                        points: Cow::Owned(rescale(&l.points)),
+                        params: l.params.to_owned_params(),
+                    })
+                    .collect(),
+                hatches: layer
+                    .hatches
+                    .iter()
+                    .map(|h| Hatches {
+                        id: h.id,
+                        points: Cow::Owned(rescale(&h.points)),
+                        params: h.params.to_owned_params(),
+                    })
+                    .collect(),
+                user_data: layer.user_data.iter().map(|u| u.to_owned_data()).collect(),
+                order: layer.order.clone(),
+                ..Layer::empty(T::coord_from_f64(T::coord_to_f64(layer.height) * factor))
+            })
+            .collect();
+
+        let mut header = self.header.clone();
+        header.units = units;
+        CLI {
+            header,
+            layers,
+            verbatim: Default::default(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clitype::ShortCLI;
+    use crate::Header;
+
+    #[test]
+    fn conversions() {
+        assert_eq!(Unit::Inch.convert(1.0, Unit::Millimeter), 25.4);
+        assert_eq!(Unit::Millimeter.convert(2.5, Unit::Micrometer), 2500.0);
+        assert!((Unit::Micrometer.convert(25400.0, Unit::Inch) - 1.0).abs() < 1e-12);
+
+        let scale = UnitScale::new(0.005).with_unit(Unit::Micrometer);
+        assert_eq!(scale.to_length::<ShortCLI>(200), 1000.0);
+        assert_eq!(scale.to_coord::<ShortCLI>(1000.0), 200);
+    }
+
+    #[test]
+    fn rescale() {
+        let model = CLI::<ShortCLI> {
+            header: Header {
+                units: 0.01,
+                ..Header::default()
+            },
+            layers: vec![Layer {
+                loops: vec![Loop {
+                    id: 1,
+                    dir: 1,
+                    points: Cow::Owned(vec![0, 0, 100, 0, 100, 50]),
+                    params: Default::default(),
+                }],
+                hatches: vec![Hatches {
+                    id: 1,
+                    points: Cow::Owned(vec![0, 0, 10, 20]),
+                    params: Default::default(),
+                }],
+                ..Layer::empty(5)
+            }],
+            verbatim: Default::default(),
+        };
+        let scale = model.unit_scale();
+        let layer = model.iter().next().unwrap();
+        assert_eq!(layer.height_scaled(scale), 0.05);
+        let hatch: Vec<_> = layer
+            .iter_hatches()
+            .next()
+            .unwrap()
+            .iter_scaled(scale)
+            .collect();
+        assert_eq!(hatch, vec![[0.0, 0.0, 0.1, 0.2]]);
+
+        let fine = model.with_units(0.001);
+        assert_eq!(fine.header().units, 0.001);
+        let layer = fine.iter().next().unwrap();
+        assert_eq!(layer.height(), 50);
+        let points: Vec<_> = layer
+            .iter_loops()
+            .next()
+            .unwrap()
+            .iter_scaled(fine.unit_scale())
+            .collect();
+        assert_eq!(points[2], [1.0, 0.5]);
+
+        let scaled = model.scaled();
+        let layer = scaled.iter().next().unwrap();
+        assert_eq!(layer.height(), 0.05);
+        assert_eq!(layer.inner().height(), 5);
+        let contour = layer.loops().next().unwrap();
+        assert_eq!(contour.inner().id(), 1);
+        assert_eq!(contour.iter().nth(2), Some([1.0, 0.5]));
+        let hatch = layer.hatches().next().unwrap().iter().collect::<Vec<_>>();
+        assert_eq!(hatch, vec![[0.0, 0.0, 0.1, 0.2]]);
+        let microns = scaled.with_unit(Unit::Micrometer);
+        assert_eq!(microns.iter().next().unwrap().height(), 50.0);
+    }
+}
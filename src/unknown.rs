@@ -0,0 +1,225 @@
+//! Skipping and reporting unknown geometry commands
+//!
+//! Machines and slicers add proprietary records to the geometry section. Their length is not
+//! known, so by default the parser stops at them. With [`Parser::skip_unknown`] it guesses the
+//! length of each unknown record instead, by looking for the next position at which a run of
+//! known records starts, and continues after it. Every skipped record is kept as an
+//! [`UnknownCommand`], [`CLI::unknown_command_report`] summarizes them per command, which helps
+//! to characterize a dialect and to report it.
+//!
+//! ```
+//! use colain::{clitype::LongCLI, Parser};
+//! # let buf = colain::BuildPlate::<LongCLI>::new(1.0).to_bytes();
+//!
+//! let model = Parser::new().skip_unknown().parse(&buf).unwrap();
+//! for summary in model.unknown_command_report() {
+//!     println!("{}", summary);
+//! }
+//! ```
+//!
+//! Guesses can be wrong, for example if the payload of an unknown record happens to look like
+//! known records. If the layout of the records is known, [`Parser::vendor_commands`] reads them
+//! exactly instead. Skipped records are written back by [`CLI::to_bytes`] where they were, between
+//! the same geometry blocks of the same layer.
+//!
+//! [`Parser::skip_unknown`]: crate::Parser::skip_unknown
+//! [`Parser::vendor_commands`]: crate::Parser::vendor_commands
+
+use crate::clitype::CLIType;
+use crate::token::{stride, Tokens};
+use crate::{Error, ParserOptions, CLI};
+use std::fmt;
+
+/// Longest payload considered when guessing the length of an unknown record
+const MAX_GUESS: usize = 1 << 16;
+
+/// Number of known records that must follow a guess, unless the file ends before
+const CONFIRM: usize = 3;
+
+/// An unknown record skipped by the parser
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UnknownCommand {
+    /// The command word
+    pub command: u16,
+    /// Offset of the command word from the start of the geometry section
+    pub offset: usize,
+    /// Guessed length of the parameters following the command word, in bytes
+    pub length: usize,
+}
+
+/// All skipped records with the same command, see [`CLI::unknown_command_report`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnknownSummary {
+    /// The command word
+    pub command: u16,
+    /// Number of records
+    pub count: usize,
+    /// Offset of the first record
+    pub first_offset: usize,
+    /// Distinct guessed parameter lengths, ascending
+    pub lengths: Vec<usize>,
+}
+
+impl fmt::Display for UnknownSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let lengths: Vec<String> = self.lengths.iter().map(|l| l.to_string()).collect();
+        write!(
+            f,
+            "command {} (0x{:04x}): {} times from offset {}, parameters of {} bytes",
+            self.command,
+            self.command,
+            self.count,
+            self.first_offset,
+            lengths.join(", ")
+        )
+    }
+}
+
+/// Guess the parameter length of the unknown record at `offset`.
+///
+/// The shortest length wins after which [`CONFIRM`] known records follow, or fewer known records
+/// up to the end of the section or the next unknown command.
+pub(crate) fn guess_length<T: CLIType>(
+    geom: &[u8],
+    offset: usize,
+    aligned: bool,
+    options: &ParserOptions,
+) -> Option<usize> {
+    let word = stride(2, aligned);
+    let start = offset + word;
+    let longest = geom.len().checked_sub(start)?.min(MAX_GUESS);
+    (0..=longest)
+        .step_by(word)
+        .find(|&length| known_records::<T>(&geom[start + length..], aligned, options))
+}
+
+/// First offset from `from` on at which known records continue, in the same way as
+/// [`guess_length`] but without a limit. The end of the section if there is none.
+pub(crate) fn resync<T: CLIType>(
+    geom: &[u8],
+    from: usize,
+    aligned: bool,
+    options: &ParserOptions,
+) -> usize {
+    (from.min(geom.len())..geom.len())
+        .step_by(stride(2, aligned))
+        .find(|&offset| known_records::<T>(&geom[offset..], aligned, options))
+        .unwrap_or(geom.len())
+}
+
+/// True if `rest` is empty or starts with known records
+fn known_records<T: CLIType>(rest: &[u8], aligned: bool, options: &ParserOptions) -> bool {
+    if rest.is_empty() {
+        return true;
+    }
+    let mut known = 0;
+    for token in Tokens::<T>::with_options(rest, aligned, options).take(CONFIRM) {
+        match token {
+            Ok(_) => known += 1,
+            // the next unknown record
+            Err(Error::InvalidGeometryCommand(_)) => break,
+            Err(_) => return false,
+        }
+    }
+    known > 0
+}
+
+impl<'a, T: CLIType> CLI<'a, T> {
+    /// Unknown records skipped while parsing, in file order, see the
+    /// [module documentation](self)
+    pub fn skipped_commands(&self) -> &[UnknownCommand] {
+        &self.verbatim.skipped
+    }
+
+    /// Summary of the [skipped records](CLI::skipped_commands) per command, ordered by command
+    pub fn unknown_command_report(&self) -> Vec<UnknownSummary> {
+        let mut report: Vec<UnknownSummary> = Vec::new();
+        for skipped in self.skipped_commands() {
+            match report.iter_mut().find(|s| s.command == skipped.command) {
+                Some(summary) => {
+                    summary.count += 1;
+                    if !summary.lengths.contains(&skipped.length) {
+                        summary.lengths.push(skipped.length);
+                    }
+                }
+                None => report.push(UnknownSummary {
+                    command: skipped.command,
+                    count: 1,
+                    first_offset: skipped.offset,
+                    lengths: vec![skipped.length],
+                }),
+            }
+        }
+        for summary in report.iter_mut() {
+            summary.lengths.sort_unstable();
+        }
+        report.sort_by_key(|s| s.command);
+        report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::clitype::ShortCLI;
+    use crate::{Error, Parser, CLI};
+
+    fn file() -> Vec<u8> {
+        let mut buf = b"$$HEADERSTART\n$$BINARY\n$$UNITS/1\n$$VERSION/200\n$$HEADEREND".to_vec();
+        // layer 1, vendor record 0x300 with 4 bytes, polyline, vendor record 0x301 with
+        // 2 bytes, layer 2, vendor record 0x300 with 6 bytes, polyline
+        let words: [u16; 29] = [
+            128, 1, 0x300, 7, 7, 129, 1, 0, 2, 0, 0, 5, 5, 0x301, 9, 128, 2, 0x300, 7, 7, 7, 129,
+            1, 0, 2, 0, 0, 6, 6,
+        ];
+        for word in words {
+            buf.extend(word.to_le_bytes());
+        }
+        buf
+    }
+
+    #[test]
+    fn skip() -> Result<(), Error> {
+        let buf = file();
+        assert!(matches!(
+            CLI::<ShortCLI>::new(&buf).map_err(Error::into_inner),
+            Err(Error::InvalidGeometryCommand(0x300))
+        ));
+        let model = Parser::new().short().skip_unknown().parse(&buf)?;
+        assert_eq!(model.iter().count(), 2);
+        assert!(model.iter().all(|l| l.iter_loops().count() == 1));
+
+        let skipped: Vec<_> = model
+            .skipped_commands()
+            .iter()
+            .map(|s| (s.command, s.offset, s.length))
+            .collect();
+        assert_eq!(skipped, vec![(0x300, 4, 4), (0x301, 26, 2), (0x300, 34, 6)]);
+
+        let report = model.unknown_command_report();
+        assert_eq!(report.len(), 2);
+        assert_eq!((report[0].count, &report[0].lengths[..]), (2, &[4, 6][..]));
+        assert_eq!(
+            report[1].to_string(),
+            "command 769 (0x0301): 1 times from offset 26, parameters of 2 bytes"
+        );
+
+        // Written back in place, also when the layers are encoded again
+        let geometry = &buf[buf.len() - 58..];
+        assert!(model.to_bytes().ends_with(geometry));
+        assert!(model.to_owned_model().to_bytes().ends_with(geometry));
+        Ok(())
+    }
+
+    #[test]
+    fn no_guess() -> Result<(), Error> {
+        let mut buf = file();
+        // garbage that never turns into known records
+        buf.truncate(buf.len() - 20);
+        buf.extend([0xff; 9]);
+        assert!(Parser::new().short().skip_unknown().parse(&buf).is_err());
+        let model = Parser::new().short().skip_unknown().lenient().parse(&buf)?;
+        assert_eq!(model.skipped_commands().len(), 2);
+        assert!(!model.unknown_records().is_empty());
+        Ok(())
+    }
+}
@@ -1,5 +1,9 @@
 //! Produces copies of N elements from the array
 //! Yields a ~10% performance bump over returning pointers
+
+#[cfg(feature = "safe")]
+use std::convert::TryInto;
+
 pub struct ArrayChunksCopy<'a, T: 'a + Copy, const N: usize> {
     v: &'a [T],
     rem: &'a [T],
@@ -42,7 +46,10 @@ impl<'a, T: Copy, const N: usize> Iterator for ArrayChunksCopy<'a, T, N> {
         } else {
             let (fst, snd) = self.v.split_at(N);
             self.v = snd;
-            Some(*unsafe { &*(fst as *const [T] as *const [T; N]) })
+            #[cfg(not(feature = "safe"))]
+            return Some(*unsafe { &*(fst as *const [T] as *const [T; N]) });
+            #[cfg(feature = "safe")]
+            return Some(fst.try_into().unwrap());
         }
     }
 }
@@ -90,7 +97,10 @@ impl<'a, T, const N: usize> Iterator for ArrayChunks<'a, T, N> {
         } else {
             let (fst, snd) = self.v.split_at(N);
             self.v = snd;
-            Some(unsafe { &*(fst as *const [T] as *const [T; N]) })
+            #[cfg(not(feature = "safe"))]
+            return Some(unsafe { &*(fst as *const [T] as *const [T; N]) });
+            #[cfg(feature = "safe")]
+            return Some(fst.try_into().unwrap());
         }
     }
 }
@@ -0,0 +1,242 @@
+//! Parts of a file the model does not interpret
+//!
+//! Vendors put metadata into header lines and records this crate does not know about. A parsed
+//! model keeps the text of the header, the unknown records the parser skipped and the records
+//! following an unknown command it stopped at, so tools reading and rewriting files pass them on
+//! instead of destroying them.
+
+use crate::clitype::CLIType;
+use crate::unknown::UnknownCommand;
+use crate::{writer, Header, CLI};
+use std::borrow::Cow;
+
+/// Content of the file kept to write it back unchanged
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Verbatim<'a> {
+    /// Text of the header section up to and including `$$HEADEREND`
+    pub header: Option<Cow<'a, str>>,
+    /// Geometry section from the first unknown command to the end
+    pub unknown: Cow<'a, [u8]>,
+    /// Unknown commands skipped while parsing
+    pub skipped: Vec<UnknownCommand>,
+    /// Skipped records before the first layer
+    pub leading: Vec<RawRecord<'a>>,
+}
+
+/// Records a layer was decoded from.
+///
+/// Writing a layer copies these instead of encoding it again, as long as it has not been modified
+/// and the file layout is the same. Methods modifying a layer drop its source.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct LayerSource<'a> {
+    /// From the layer command to the end of the last record decoded into the layer
+    pub bytes: &'a [u8],
+    /// Whether the records are laid out with `$$ALIGN`
+    pub aligned: bool,
+}
+
+/// A record skipped by the parser, kept to be written back
+#[derive(Debug, Clone)]
+pub(crate) struct RawRecord<'a> {
+    /// Number of geometry blocks of the layer preceding the record
+    pub position: usize,
+    /// The command word and its parameters
+    pub bytes: Cow<'a, [u8]>,
+    /// Whether the record is laid out with `$$ALIGN`
+    pub aligned: bool,
+}
+
+impl<'a> RawRecord<'a> {
+    /// Copy borrowed data so it no longer references the file
+    pub(crate) fn to_owned_record(&self) -> RawRecord<'static> {
+        RawRecord {
+            bytes: Cow::Owned(self.bytes.to_vec()),
+            ..*self
+        }
+    }
+}
+
+impl<'a> Verbatim<'a> {
+    /// Copy borrowed data so it no longer references the file
+    pub(crate) fn to_owned_verbatim(&self) -> Verbatim<'static> {
+        Verbatim {
+            header: self.header.as_ref().map(|h| Cow::Owned(h.to_string())),
+            unknown: Cow::Owned(self.unknown.to_vec()),
+            skipped: self.skipped.clone(),
+            leading: self.leading.iter().map(|r| r.to_owned_record()).collect(),
+        }
+    }
+}
+
+impl<'a, T: CLIType> CLI<'a, T> {
+    /// Original text of the header section up to and including `$$HEADEREND`, `None` if the
+    /// model was not parsed from a file or if [user data](crate::HeaderUserData) in the header is
+    /// not valid UTF-8.
+    ///
+    /// [`CLI::to_bytes`] writes this text back, only rewriting the lines for the header values
+    /// that changed since parsing, such as `$$LAYERS`. Comments and commands unknown to this
+    /// crate are kept.
+    pub fn raw_header(&self) -> Option<&str> {
+        self.verbatim.header.as_deref()
+    }
+
+    /// The geometry section from the first unknown command to the end of the file.
+    ///
+    /// Records after an unknown command cannot be decoded since their length is not known. A
+    /// [lenient](crate::Parser::lenient) parser stops there and keeps the remainder here, to be
+    /// written back after the last layer by [`CLI::to_bytes`]. Empty if all records were known.
+    pub fn unknown_records(&self) -> &[u8] {
+        &self.verbatim.unknown
+    }
+
+    /// Write the skipped records that came before the first layer, if the file layout is the same
+    pub(crate) fn write_leading(&self, out: &mut Vec<u8>) {
+        for record in self.verbatim.leading.iter() {
+            if record.aligned == self.header.aligned {
+                out.extend_from_slice(&record.bytes);
+            }
+        }
+    }
+
+    /// Write the header section declaring `layers` layers, based on the original one if there
+    /// is one.
+    ///
+    /// The geometry is always written in binary, so the header of an ASCII file is changed to
+    /// declare `$$BINARY`.
+    pub(crate) fn write_header(&self, out: &mut Vec<u8>, layers: usize) {
+        let layers = Some(layers);
+        let header = Header {
+            binary: true,
+            ..self.header.clone()
+        };
+        match self.raw_header() {
+            Some(raw) => writer::write_header_from(out, raw, &header, layers),
+            None => writer::write_header(out, &header, layers),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::clitype::ShortCLI;
+    use crate::{Error, Parser, CLI};
+
+    const HEADER: &str = "$$HEADERSTART\n// written by a slicer\n$$BINARY\n$$UNITS/0.500000\n\
+        $$VERSION/200\n$$LABEL/1,part\n$$VENDOR/x=1\n$$LAYERS/1\n$$HEADEREND";
+
+    fn file(aligned: bool, tail: &[u8]) -> Vec<u8> {
+        let mut buf = HEADER.replace(
+            "$$VERSION",
+            if aligned {
+                "$$ALIGN\n$$VERSION"
+            } else {
+                "$$VERSION"
+            },
+        );
+        if aligned {
+            while buf.len() % 4 != 0 {
+                buf.push('\0');
+            }
+        }
+        let mut buf = buf.into_bytes();
+        let word = |v: u16| {
+            let mut w = v.to_le_bytes().to_vec();
+            if aligned {
+                w.extend([0, 0]);
+            }
+            w
+        };
+        // layer at 2, a polyline with one point
+        for v in [128, 2, 129, 1, 0, 1] {
+            buf.extend(word(v));
+        }
+        buf.extend([3, 0, 4, 0]);
+        buf.extend(tail);
+        buf
+    }
+
+    #[test]
+    fn round_trip() -> Result<(), Error> {
+        for aligned in [false, true] {
+            let buf = file(aligned, &[]);
+            let model = CLI::<ShortCLI>::new(&buf)?;
+            assert!(model.raw_header().unwrap().contains("$$VENDOR/x=1"));
+            assert_eq!(model.to_bytes(), buf);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn changed_header() -> Result<(), Error> {
+        let buf = file(false, &[]);
+        let mut model = CLI::<ShortCLI>::new(&buf)?;
+        model.header.units = 0.25;
+        model.header.aligned = true;
+        model.header.date = Some(crate::Date::new("010124"));
+        model.header.dimension = Some([0.0, 0.0, 0.0, 1.5, 2.0, 3.0]);
+        model.header.labels.insert(2, "lid".into());
+        model.header.extra[0].1 = "x=2".into();
+        model.header.extra.push(("PREVIEW".into(), String::new()));
+        model.layers.push(model.layers[0].clone());
+        let written = model.to_bytes();
+        let expected =
+            "$$HEADERSTART\n// written by a slicer\n$$BINARY\n$$UNITS/0.25\n$$VERSION/200\n\
+             $$LABEL/1,part\n$$VENDOR/x=2\n$$LAYERS/2\n$$ALIGN\n$$LABEL/2,lid\n$$DATE/010124\n\
+             $$DIMENSION/0,0,0,1.5,2,3\n$$PREVIEW\n$$HEADEREND";
+        assert!(written.starts_with(expected.as_bytes()));
+        let reparsed = CLI::<ShortCLI>::new(&written)?;
+        assert_eq!(reparsed.header().units, 0.25);
+        assert_eq!(reparsed.header().date, model.header.date);
+        assert_eq!(reparsed.header().dimension, model.header.dimension);
+        assert_eq!(reparsed.header().labels, model.header.labels);
+        assert_eq!(reparsed.header().extra, model.header.extra);
+        assert_eq!(reparsed.iter().count(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn user_data() -> Result<(), Error> {
+        let text = HEADER.replace("$$VENDOR/x=1", "$$USERDATA/acme,4,a\nb\n // note");
+        let buf = file(false, &[]);
+        let buf = [text.as_bytes(), &buf[HEADER.len()..]].concat();
+        let mut model = CLI::<ShortCLI>::new(&buf)?;
+        assert_eq!(model.header().user_data[0].uid, "acme");
+        assert_eq!(model.header().user_data[0].data, b"a\nb\n");
+        assert_eq!(model.to_bytes(), buf);
+
+        model.header.user_data[0].data = b"\xff".to_vec();
+        model.header.user_data.push(crate::HeaderUserData {
+            uid: "other".into(),
+            data: vec![],
+        });
+        let written = model.to_bytes();
+        let expected = b"$$LABEL/1,part\n$$USERDATA/acme,1,\xff\n$$LAYERS/1\n\
+            $$USERDATA/other,0,\n$$HEADEREND";
+        let start = written.windows(6).position(|w| w == b"$$LABE").unwrap();
+        assert!(written[start..].starts_with(expected));
+
+        // Binary payloads leave no header text to preserve
+        let reparsed = CLI::<ShortCLI>::new(&written)?;
+        assert!(reparsed.raw_header().is_none());
+        assert_eq!(reparsed.header().user_data, model.header.user_data);
+        let buf = reparsed.to_bytes();
+        let rewritten = CLI::<ShortCLI>::new(&buf)?;
+        assert_eq!(rewritten.header().user_data, model.header.user_data);
+        Ok(())
+    }
+
+    #[test]
+    fn unknown_records() -> Result<(), Error> {
+        let tail = [0x34, 0x12, 1, 2, 3];
+        let buf = file(false, &tail);
+        assert!(matches!(
+            CLI::<ShortCLI>::new(&buf).map_err(Error::into_inner),
+            Err(Error::InvalidGeometryCommand(0x1234))
+        ));
+        let model = Parser::new().short().lenient().parse(&buf)?;
+        assert_eq!(model.unknown_records(), &tail);
+        assert_eq!(model.iter().next().unwrap().iter_loops().count(), 1);
+        assert_eq!(model.to_bytes(), buf);
+        Ok(())
+    }
+}
@@ -0,0 +1,207 @@
+//! Suspicious content found while parsing
+//!
+//! With the default [`ParserOptions`] any deviation from the spec fails parsing. Files written by
+//! real machines are often only slightly off, and the options let such files be read anyway.
+//! [`CLI::new_with`] and [`Parser::parse_with_warnings`] return a [`Warning`] for everything that
+//! was tolerated, so that an application can still tell a clean file from a repaired one.
+//!
+//! ```
+//! use colain::{clitype::LongCLI, ParserOptions, CLI};
+//! # let buf = colain::BuildPlate::<LongCLI>::new(1.0).to_bytes();
+//!
+//! let options = ParserOptions {
+//!     lenient: true,
+//!     skip_unknown: true,
+//!     ..Default::default()
+//! };
+//! let (model, warnings) = CLI::<LongCLI>::new_with(&buf, &options).unwrap();
+//! for warning in warnings {
+//!     eprintln!("{}", warning);
+//! }
+//! ```
+//!
+//! [`Parser::parse_with_warnings`]: crate::Parser::parse_with_warnings
+
+use crate::clitype::CLIType;
+use crate::{Layer, Parser, ParserOptions, UnknownCommand, CLI};
+use std::fmt;
+use std::ops::Range;
+
+/// Something suspicious about a parsed file, see the [module documentation](self)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Warning {
+    /// An unknown record of a binary geometry section was skipped
+    SkippedCommand(UnknownCommand),
+    /// A command of an ASCII geometry section that is not understood was skipped
+    SkippedAsciiCommand {
+        /// Line of the file the command starts on
+        line: usize,
+        /// The command as written, e.g. `$$LABEL`
+        command: String,
+    },
+    /// Invalid records of a binary geometry section were skipped, in this range of offsets from
+    /// the start of the geometry section, see [`Parser::recover`](crate::Parser::recover)
+    Resynchronized(Range<usize>),
+    /// Parsing stopped at an invalid record of a binary geometry section, everything from this
+    /// offset from the start of the geometry section on was dropped
+    Stopped {
+        /// Offset of the invalid record
+        offset: usize,
+    },
+    /// Parsing stopped at an invalid command of an ASCII geometry section, everything from this
+    /// line of the file on was dropped
+    StoppedAtLine {
+        /// Line of the invalid command
+        line: usize,
+    },
+    /// The layer with this index is below the one before it
+    UnorderedLayer {
+        /// Index of the layer
+        index: usize,
+    },
+    /// The number of layers declared by `$$LAYERS` differs from the number of layers read
+    LayerCount {
+        /// Number of layers in the header
+        declared: usize,
+        /// Number of layers in the geometry section
+        found: usize,
+    },
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Warning::SkippedCommand(c) => write!(
+                f,
+                "skipped unknown command {} at offset {} with {} bytes of parameters",
+                c.command, c.offset, c.length
+            ),
+            Warning::SkippedAsciiCommand { line, command } => {
+                write!(f, "skipped unknown command {} on line {}", command, line)
+            }
+            Warning::Resynchronized(range) => write!(
+                f,
+                "skipped {} bytes of invalid records from offset {}",
+                range.len(),
+                range.start
+            ),
+            Warning::Stopped { offset } => {
+                write!(f, "stopped at an invalid record at offset {}", offset)
+            }
+            Warning::StoppedAtLine { line } => {
+                write!(f, "stopped at an invalid command on line {}", line)
+            }
+            Warning::UnorderedLayer { index } => {
+                write!(f, "layer {} is below the layer before it", index)
+            }
+            Warning::LayerCount { declared, found } => write!(
+                f,
+                "header declares {} layers but {} were found",
+                declared, found
+            ),
+        }
+    }
+}
+
+/// Index of every layer that is below the one before it
+pub(crate) fn unordered_layers<T: CLIType>(layers: &[Layer<'_, T>]) -> Vec<usize> {
+    layers
+        .windows(2)
+        .enumerate()
+        .filter(|(_, pair)| T::coord_to_f64(pair[1].height) < T::coord_to_f64(pair[0].height))
+        .map(|(n, _)| n + 1)
+        .collect()
+}
+
+impl<'a, T: CLIType> CLI<'a, T> {
+    /// Same as [`CLI::new_with_options`] but also returns a [`Warning`] for everything that was
+    /// tolerated or looks suspicious, see the [module documentation](self)
+    pub fn new_with(
+        raw: &'a [u8],
+        options: &ParserOptions,
+    ) -> Result<(Self, Vec<Warning>), crate::Error> {
+        Parser::default().options(options).parse_with_warnings(raw)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clitype::ShortCLI;
+    use crate::{CLIBuilder, Error};
+
+    fn file(heights: &[u16]) -> Vec<u8> {
+        let mut builder = CLIBuilder::new().short();
+        for z in 0..heights.len() {
+            builder = builder
+                .layer(z as u16)
+                .polyline(1, 1, vec![0, 0, 2, 0, 2, 2, 0, 0]);
+        }
+        let mut model = builder.build().unwrap();
+        // The builder keeps layers in order
+        for (layer, &z) in model.iter_mut().zip(heights) {
+            layer.height = z;
+        }
+        model.to_bytes()
+    }
+
+    #[test]
+    fn clean() -> Result<(), Error> {
+        let buf = file(&[1, 2, 3]);
+        let (model, warnings) = CLI::<ShortCLI>::new_with(&buf, &ParserOptions::default())?;
+        assert_eq!(model.iter().count(), 3);
+        assert!(warnings.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn layer_order() -> Result<(), Error> {
+        let buf = file(&[1, 3, 2]);
+        let (model, warnings) = CLI::<ShortCLI>::new_with(&buf, &ParserOptions::default())?;
+        assert_eq!(model.iter().count(), 3);
+        assert_eq!(warnings, [Warning::UnorderedLayer { index: 2 }]);
+        let options = ParserOptions {
+            require_ordered_layers: true,
+            ..Default::default()
+        };
+        assert!(matches!(
+            CLI::<ShortCLI>::new_with(&buf, &options),
+            Err(Error::LayerOrder)
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn stopped() -> Result<(), Error> {
+        let buf = file(&[1, 2]);
+        let truncated = &buf[..buf.len() - 3];
+        let options = ParserOptions {
+            lenient: true,
+            ..Default::default()
+        };
+        assert!(CLI::<ShortCLI>::new_with(truncated, &ParserOptions::default()).is_err());
+        let (model, warnings) = CLI::<ShortCLI>::new_with(truncated, &options)?;
+        assert_eq!(model.iter().count(), 2);
+        // Two layer commands and the polyline of the first layer before the truncated polyline
+        assert_eq!(warnings, [Warning::Stopped { offset: 4 + 24 + 4 }]);
+        Ok(())
+    }
+
+    #[test]
+    fn layer_count() -> Result<(), Error> {
+        let buf = file(&[1, 2]);
+        let (_, start) = crate::parse_header(&buf)?;
+        let header = std::str::from_utf8(&buf[..start]).unwrap();
+        let header = header.replace("$$LAYERS/2", "$$LAYERS/3");
+        let buf = [header.as_bytes(), &buf[start..]].concat();
+        let (_, warnings) = CLI::<ShortCLI>::new_with(&buf, &ParserOptions::default())?;
+        assert_eq!(
+            warnings,
+            [Warning::LayerCount {
+                declared: 3,
+                found: 2
+            }]
+        );
+        Ok(())
+    }
+}
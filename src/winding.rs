@@ -0,0 +1,212 @@
+//! Winding order of loops
+//!
+//! The spec ties the direction of a closed loop to its winding: internal loops (direction 0) run
+//! clockwise and external loops (direction 1) counter-clockwise. Slicers frequently get this
+//! wrong, [`Loop::winding_matches_dir`] and [`CLI::winding_mismatches`] find the loops whose
+//! points disagree with their direction, and [`Layer::normalize_winding`] corrects them based on
+//! how the loops are nested.
+//!
+//! ```
+//! use colain::CLIBuilder;
+//!
+//! let model = CLIBuilder::new()
+//!     .layer(1.0)
+//!     // declared external but clockwise
+//!     .polyline(1, 1, vec![0.0, 0.0, 0.0, 5.0, 5.0, 5.0, 5.0, 0.0, 0.0, 0.0])
+//!     .build()
+//!     .unwrap();
+//! assert_eq!(model.winding_mismatches(), [(0, 0)]);
+//! ```
+
+use crate::clitype::CLIType;
+use crate::geom;
+use crate::{Direction, Layer, Loop, CLI};
+
+/// Winding order given to loops by [`Layer::normalize_winding`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum WindingConvention {
+    /// Outer boundaries counter-clockwise and holes clockwise, as the spec prescribes
+    #[default]
+    Spec,
+    /// Outer boundaries clockwise and holes counter-clockwise
+    Reversed,
+}
+
+impl<'a, T: CLIType> Loop<'a, T> {
+    /// Area enclosed by the loop in square coordinate units, positive if its points run
+    /// counter-clockwise and negative if they run clockwise.
+    ///
+    /// The loop is treated as closed whether or not its last point repeats the first one.
+    pub fn signed_area(&self) -> f64 {
+        geom::signed_area(&geom::to_points(&self.points, |c| T::coord_to_f64(c)))
+    }
+
+    /// True if the points of the loop run clockwise, see [`Loop::signed_area`]
+    pub fn is_clockwise(&self) -> bool {
+        self.signed_area() < 0.0
+    }
+
+    /// Whether the winding of the loop agrees with its direction: clockwise for direction 0 and
+    /// counter-clockwise for direction 1.
+    ///
+    /// `None` for loops that are not closed according to their direction and for loops without
+    /// area, whose winding is undefined.
+    pub fn winding_matches_dir(&self) -> Option<bool> {
+        let area = self.signed_area();
+        if area == 0.0 {
+            return None;
+        }
+        match self.direction() {
+            Direction::Clockwise => Some(area < 0.0),
+            Direction::CounterClockwise => Some(area > 0.0),
+            _ => None,
+        }
+    }
+
+    /// Reverse the order of the points, and of the per-vector parameters along with them
+    pub fn reverse(&mut self) {
+        let points = self.points.to_mut();
+        points.reverse();
+        for point in points.chunks_exact_mut(2) {
+            point.swap(0, 1);
+        }
+        if let Some(power) = &mut self.params.power {
+            power.to_mut().reverse();
+        }
+        if let Some(speed) = &mut self.params.speed {
+            speed.to_mut().reverse();
+        }
+    }
+}
+
+impl<'a, T: CLIType> Layer<'a, T> {
+    /// Reverse loops so that outer boundaries and holes run as `convention` prescribes, returns
+    /// the number of reversed loops.
+    ///
+    /// Which loops are holes is decided by their nesting, see [`Layer::islands`], not by their
+    /// direction. Loops with direction 0 or 1 get the direction of their role: 1 for outer
+    /// boundaries and 0 for holes. Open polylines and loops without area are left as they are.
+    pub fn normalize_winding(&mut self, convention: WindingConvention) -> usize {
+        let mut roles: Vec<(usize, bool)> = Vec::new();
+        for island in self.islands() {
+            roles.push((island.outer, true));
+            roles.extend(island.holes.iter().map(|&h| (h, false)));
+        }
+        let mut reversed = 0;
+        for (index, outer) in roles {
+            let l = &mut self.loops[index];
+            let ccw = outer == (convention == WindingConvention::Spec);
+            if l.is_clockwise() == ccw {
+                l.reverse();
+                reversed += 1;
+            }
+            if matches!(T::meta_to_i64(l.dir), 0 | 1) {
+                l.dir = T::meta_from_i64(outer as i64);
+            }
+        }
+        self.source = None;
+        reversed
+    }
+}
+
+impl<'a, T: CLIType> CLI<'a, T> {
+    /// Layer and loop indices of every loop whose winding contradicts its direction, see
+    /// [`Loop::winding_matches_dir`]
+    pub fn winding_mismatches(&self) -> Vec<(usize, usize)> {
+        self.layers
+            .iter()
+            .enumerate()
+            .flat_map(|(n, layer)| {
+                layer
+                    .loops
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, l)| l.winding_matches_dir() == Some(false))
+                    .map(move |(i, _)| (n, i))
+            })
+            .collect()
+    }
+
+    /// Normalize the winding of every layer, see [`Layer::normalize_winding`]. Returns the
+    /// number of reversed loops.
+    pub fn normalize_winding(&mut self, convention: WindingConvention) -> usize {
+        self.layers
+            .iter_mut()
+            .map(|layer| layer.normalize_winding(convention))
+            .sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clitype::ShortCLI;
+    use crate::CLIBuilder;
+
+    #[test]
+    fn winding() {
+        let ccw = vec![0, 0, 4, 0, 4, 3, 0, 3, 0, 0];
+        let cw = vec![0, 0, 0, 3, 4, 3, 4, 0];
+        let model: CLI<'_, ShortCLI> = CLIBuilder::new()
+            .short()
+            .layer(1)
+            .polyline(1, 1, ccw.clone())
+            .polyline(1, 0, cw.clone())
+            .layer(2)
+            .polyline(1, 0, ccw)
+            .polyline(1, 2, cw)
+            .polyline(1, 1, vec![0, 0, 5, 5, 0, 0])
+            .build()
+            .unwrap();
+        let layers: Vec<_> = model.iter().collect();
+        let loops: Vec<_> = layers[0]
+            .iter_loops()
+            .chain(layers[1].iter_loops())
+            .collect();
+        assert_eq!(loops[0].signed_area(), 12.0);
+        assert_eq!(loops[1].signed_area(), -12.0);
+        assert!(!loops[0].is_clockwise() && loops[1].is_clockwise());
+        let matches: Vec<_> = loops.iter().map(|l| l.winding_matches_dir()).collect();
+        assert_eq!(matches, [Some(true), Some(true), Some(false), None, None]);
+        assert_eq!(model.winding_mismatches(), [(1, 0)]);
+    }
+
+    #[test]
+    fn normalize() {
+        let ccw = |x: u16, size: u16| vec![x, x, x + size, x, x + size, x + size, x, x + size];
+        let cw = |x: u16, size: u16| {
+            let mut l = ccw(x, size);
+            l[2..].reverse();
+            l[2..].chunks_exact_mut(2).for_each(|p| p.swap(0, 1));
+            l
+        };
+        // A clockwise outer loop around a counter-clockwise hole, both declared external
+        let mut model: CLI<'_, ShortCLI> = CLIBuilder::new()
+            .short()
+            .layer(1)
+            .polyline(1, 1, cw(0, 10))
+            .polyline(1, 1, ccw(2, 2))
+            .polyline(1, 2, cw(20, 1))
+            .build()
+            .unwrap();
+        assert_eq!(model.normalize_winding(WindingConvention::Spec), 2);
+        assert!(model.winding_mismatches().is_empty());
+        let layer = model.iter().next().unwrap();
+        let loops: Vec<_> = layer.iter_loops().collect();
+        assert_eq!((loops[0].dir(), loops[1].dir(), loops[2].dir()), (1, 0, 2));
+        assert!(!loops[0].is_clockwise() && loops[1].is_clockwise());
+        assert_eq!(loops[0].points()[..4], [10, 0, 10, 10]);
+        assert_eq!(loops[2].points(), cw(20, 1));
+
+        assert_eq!(model.normalize_winding(WindingConvention::Spec), 0);
+        assert_eq!(model.normalize_winding(WindingConvention::Reversed), 2);
+        assert!(model
+            .iter()
+            .next()
+            .unwrap()
+            .iter_loops()
+            .next()
+            .unwrap()
+            .is_clockwise());
+    }
+}
@@ -0,0 +1,517 @@
+//! Conversion to and from Well-Known Text
+//!
+//! WKT is understood by PostGIS, Shapely, GEOS, JTS and most other geometry libraries, so it is a
+//! low friction way to move slice geometry into an analysis pipeline and back. Coordinates are
+//! written in coordinate units, as stored in the file.
+//!
+//! A layer maps to a `GEOMETRYCOLLECTION`:
+//!
+//! - the closed loops become a `MULTIPOLYGON`, one polygon per island (see
+//!   [`Layer::islands`](crate::Layer::islands)) with its holes,
+//! - each open polyline becomes a `LINESTRING`,
+//! - each hatch block becomes a `MULTILINESTRING` of two point lines.
+//!
+//! Reading follows the same rules, so layers survive a round trip apart from loop and hatch ids,
+//! which WKT has no place for. Polygon rings are reoriented to the loop directions of the spec,
+//! outer boundaries counterclockwise and holes clockwise.
+//!
+//! ```
+//! use colain::{Layer, clitype::LongCLI};
+//!
+//! let layer = Layer::<LongCLI>::from_wkt("POLYGON ((0 0, 10 0, 10 10, 0 10, 0 0))", 1.0).unwrap();
+//! assert_eq!(layer.iter_loops().count(), 1);
+//! assert!(layer.to_wkt().starts_with("GEOMETRYCOLLECTION (MULTIPOLYGON"));
+//! ```
+
+use crate::clitype::CLIType;
+use crate::geom;
+use crate::{Hatches, Layer, Loop, LoopKind};
+use std::borrow::Cow;
+use std::fmt::Write;
+
+/// Errors encountered when reading WKT
+#[derive(Debug, Clone, PartialEq)]
+pub enum WktError {
+    /// The text ended in the middle of a geometry
+    UnexpectedEnd,
+    /// Unexpected character at this byte offset
+    Unexpected(usize),
+    /// A geometry type that has no equivalent in a layer, e.g. `POINT`
+    UnsupportedType(String),
+}
+
+impl std::fmt::Display for WktError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+impl std::error::Error for WktError {}
+
+impl<'a, T: CLIType> Loop<'a, T> {
+    /// The loop as a WKT `POLYGON`, or a `LINESTRING` if it is an open polyline
+    pub fn to_wkt(&self) -> String {
+        let points = geom::to_points(&self.points, |c| T::coord_to_f64(c));
+        let mut out = String::new();
+        if self.kind() == LoopKind::Open {
+            out.push_str("LINESTRING ");
+            write_points(&mut out, &points);
+        } else {
+            out.push_str("POLYGON (");
+            write_points(&mut out, &closed(points));
+            out.push(')');
+        }
+        out
+    }
+
+    /// Read a loop from a WKT `POLYGON` without holes or a `LINESTRING`.
+    ///
+    /// The polygon becomes a closed loop with its direction taken from the orientation of the ring
+    /// and the line string an open polyline. The id is 0.
+    pub fn from_wkt(wkt: &str) -> Result<Loop<'static, T>, WktError> {
+        let mut parser = Parser { text: wkt, pos: 0 };
+        let geometry = parser.geometry()?;
+        parser.end()?;
+        match geometry {
+            Geometry::Polygon(rings) if rings.len() == 1 => {
+                let ccw = geom::signed_area(&rings[0]) >= 0.0;
+                Ok(make_loop(&rings[0], if ccw { 1 } else { 0 }))
+            }
+            Geometry::LineString(points) => Ok(make_loop(&points, 2)),
+            Geometry::Polygon(_) => Err(WktError::UnsupportedType("POLYGON with holes".into())),
+            other => Err(WktError::UnsupportedType(other.name().into())),
+        }
+    }
+}
+
+impl<'a, T: CLIType> Layer<'a, T> {
+    /// The geometry of the layer as a WKT `GEOMETRYCOLLECTION`, see the
+    /// [module documentation](self)
+    pub fn to_wkt(&self) -> String {
+        let f = |c| T::coord_to_f64(c);
+        let mut members = Vec::new();
+
+        let islands = self.islands();
+        if !islands.is_empty() {
+            let mut out = String::from("MULTIPOLYGON (");
+            for (i, island) in islands.iter().enumerate() {
+                if i > 0 {
+                    out.push_str(", ");
+                }
+                out.push('(');
+                for (j, &l) in std::iter::once(&island.outer)
+                    .chain(&island.holes)
+                    .enumerate()
+                {
+                    if j > 0 {
+                        out.push_str(", ");
+                    }
+                    write_points(&mut out, &closed(geom::to_points(&self.loops[l].points, f)));
+                }
+                out.push(')');
+            }
+            out.push(')');
+            members.push(out);
+        }
+        for l in self.loops.iter().filter(|l| l.kind() == LoopKind::Open) {
+            members.push(l.to_wkt());
+        }
+        for h in &self.hatches {
+            let mut out = String::from("MULTILINESTRING (");
+            for (i, v) in h.iter().enumerate() {
+                if i > 0 {
+                    out.push_str(", ");
+                }
+                write_points(&mut out, &[[f(v[0]), f(v[1])], [f(v[2]), f(v[3])]]);
+            }
+            out.push(')');
+            members.push(out);
+        }
+
+        if members.is_empty() {
+            "GEOMETRYCOLLECTION EMPTY".into()
+        } else {
+            format!("GEOMETRYCOLLECTION ({})", members.join(", "))
+        }
+    }
+
+    /// Read a layer at the given height from WKT, see the [module documentation](self).
+    ///
+    /// Any of `POLYGON`, `MULTIPOLYGON`, `LINESTRING`, `MULTILINESTRING` and a
+    /// `GEOMETRYCOLLECTION` of them is accepted. A `MULTILINESTRING` becomes a hatch block if all
+    /// of its lines have exactly two points, otherwise each line becomes an open polyline. Z and M
+    /// values are dropped.
+    pub fn from_wkt(
+        wkt: &str,
+        height: <T as CLIType>::Coord,
+    ) -> Result<Layer<'static, T>, WktError> {
+        let mut parser = Parser { text: wkt, pos: 0 };
+        let geometry = parser.geometry()?;
+        parser.end()?;
+        let mut layer = Layer::empty(height);
+        add(&mut layer, geometry)?;
+        Ok(layer)
+    }
+}
+
+fn add<T: CLIType>(layer: &mut Layer<'static, T>, geometry: Geometry) -> Result<(), WktError> {
+    match geometry {
+        Geometry::Polygon(rings) => {
+            for (i, mut ring) in rings.into_iter().enumerate() {
+                let outer = i == 0;
+                if (geom::signed_area(&ring) < 0.0) == outer {
+                    ring.reverse();
+                }
+                layer
+                    .loops
+                    .push(make_loop(&ring, if outer { 1 } else { 0 }));
+            }
+        }
+        Geometry::LineString(points) => layer.loops.push(make_loop(&points, 2)),
+        Geometry::MultiLineString(lines) => {
+            if lines.iter().all(|l| l.len() == 2) {
+                layer.hatches.push(Hatches {
+                    id: T::meta_from_i64(0),
+                    points: Cow::Owned(
+                        lines
+                            .iter()
+                            .flatten()
+                            .flat_map(|p| coords::<T>(*p))
+                            .collect(),
+                    ),
+                    params: Default::default(),
+                });
+            } else {
+                for l in lines {
+                    layer.loops.push(make_loop(&l, 2));
+                }
+            }
+        }
+        Geometry::Multi(members) | Geometry::Collection(members) => {
+            for g in members {
+                add(layer, g)?;
+            }
+        }
+        Geometry::Point => return Err(WktError::UnsupportedType("POINT".into())),
+    }
+    Ok(())
+}
+
+fn make_loop<T: CLIType>(points: &[[f64; 2]], dir: i64) -> Loop<'static, T> {
+    Loop {
+        id: T::meta_from_i64(0),
+        dir: T::meta_from_i64(dir),
+        points: Cow::Owned(points.iter().flat_map(|p| coords::<T>(*p)).collect()),
+        params: Default::default(),
+    }
+}
+
+fn coords<T: CLIType>(p: [f64; 2]) -> [<T as CLIType>::Coord; 2] {
+    [T::coord_from_f64(p[0]), T::coord_from_f64(p[1])]
+}
+
+/// Repeat the first point at the end, as WKT requires for rings
+fn closed(mut points: Vec<[f64; 2]>) -> Vec<[f64; 2]> {
+    if let (Some(&first), Some(last)) = (points.first(), points.last()) {
+        if first != *last {
+            points.push(first);
+        }
+    }
+    points
+}
+
+fn write_points(out: &mut String, points: &[[f64; 2]]) {
+    if points.is_empty() {
+        out.push_str("EMPTY");
+        return;
+    }
+    out.push('(');
+    for (i, p) in points.iter().enumerate() {
+        if i > 0 {
+            out.push_str(", ");
+        }
+        let _ = write!(out, "{} {}", p[0], p[1]);
+    }
+    out.push(')');
+}
+
+/// Parsed WKT geometry, only what is needed to build a layer
+#[derive(Debug)]
+enum Geometry {
+    Point,
+    LineString(Vec<[f64; 2]>),
+    Polygon(Vec<Vec<[f64; 2]>>),
+    MultiLineString(Vec<Vec<[f64; 2]>>),
+    /// `MULTIPOLYGON` or `MULTIPOINT`
+    Multi(Vec<Geometry>),
+    Collection(Vec<Geometry>),
+}
+
+impl Geometry {
+    fn name(&self) -> &'static str {
+        match self {
+            Geometry::Point => "POINT",
+            Geometry::LineString(_) => "LINESTRING",
+            Geometry::Polygon(_) => "POLYGON",
+            Geometry::MultiLineString(_) => "MULTILINESTRING",
+            Geometry::Multi(_) => "MULTIPOLYGON",
+            Geometry::Collection(_) => "GEOMETRYCOLLECTION",
+        }
+    }
+}
+
+struct Parser<'t> {
+    text: &'t str,
+    pos: usize,
+}
+
+impl<'t> Parser<'t> {
+    fn skip_whitespace(&mut self) {
+        let rest = &self.text[self.pos..];
+        self.pos += rest.len() - rest.trim_start().len();
+    }
+
+    fn peek(&mut self) -> Option<char> {
+        self.skip_whitespace();
+        self.text[self.pos..].chars().next()
+    }
+
+    fn expect(&mut self, c: char) -> Result<(), WktError> {
+        match self.peek() {
+            Some(p) if p == c => {
+                self.pos += 1;
+                Ok(())
+            }
+            Some(_) => Err(WktError::Unexpected(self.pos)),
+            None => Err(WktError::UnexpectedEnd),
+        }
+    }
+
+    fn end(&mut self) -> Result<(), WktError> {
+        match self.peek() {
+            None => Ok(()),
+            Some(_) => Err(WktError::Unexpected(self.pos)),
+        }
+    }
+
+    fn word(&mut self) -> Result<String, WktError> {
+        self.skip_whitespace();
+        let rest = &self.text[self.pos..];
+        let len = rest
+            .find(|c: char| !c.is_ascii_alphabetic())
+            .unwrap_or(rest.len());
+        if len == 0 {
+            return match self.peek() {
+                Some(_) => Err(WktError::Unexpected(self.pos)),
+                None => Err(WktError::UnexpectedEnd),
+            };
+        }
+        self.pos += len;
+        Ok(rest[..len].to_ascii_uppercase())
+    }
+
+    /// Consume `EMPTY` if it comes next, otherwise an opening parenthesis
+    fn open(&mut self) -> Result<bool, WktError> {
+        if matches!(self.peek(), Some(c) if c.is_ascii_alphabetic()) {
+            let start = self.pos;
+            if self.word()? == "EMPTY" {
+                return Ok(false);
+            }
+            return Err(WktError::Unexpected(start));
+        }
+        self.expect('(')?;
+        Ok(true)
+    }
+
+    /// Parenthesized, comma separated list of items
+    fn list<I>(
+        &mut self,
+        mut item: impl FnMut(&mut Self) -> Result<I, WktError>,
+    ) -> Result<Vec<I>, WktError> {
+        let mut items = Vec::new();
+        if !self.open()? {
+            return Ok(items);
+        }
+        loop {
+            items.push(item(self)?);
+            match self.peek() {
+                Some(',') => self.pos += 1,
+                Some(')') => {
+                    self.pos += 1;
+                    return Ok(items);
+                }
+                Some(_) => return Err(WktError::Unexpected(self.pos)),
+                None => return Err(WktError::UnexpectedEnd),
+            }
+        }
+    }
+
+    fn number(&mut self) -> Result<f64, WktError> {
+        self.skip_whitespace();
+        let rest = &self.text[self.pos..];
+        let len = rest
+            .find(|c: char| !(c.is_ascii_digit() || "+-.eE".contains(c)))
+            .unwrap_or(rest.len());
+        let value = rest[..len].parse().map_err(|_| {
+            if rest.is_empty() {
+                WktError::UnexpectedEnd
+            } else {
+                WktError::Unexpected(self.pos)
+            }
+        })?;
+        self.pos += len;
+        Ok(value)
+    }
+
+    /// A point with two or more ordinates, only x and y are kept
+    fn point(&mut self) -> Result<[f64; 2], WktError> {
+        let p = [self.number()?, self.number()?];
+        while matches!(self.peek(), Some(c) if c != ',' && c != ')') {
+            self.number()?;
+        }
+        Ok(p)
+    }
+
+    fn points(&mut self) -> Result<Vec<[f64; 2]>, WktError> {
+        self.list(Self::point)
+    }
+
+    fn rings(&mut self) -> Result<Vec<Vec<[f64; 2]>>, WktError> {
+        self.list(Self::points)
+    }
+
+    fn geometry(&mut self) -> Result<Geometry, WktError> {
+        let start = self.pos;
+        let name = self.word()?;
+        // Optional dimension marker, e.g. POLYGON Z
+        if matches!(self.peek(), Some(c) if c.is_ascii_alphabetic()) {
+            let before = self.pos;
+            match self.word()?.as_str() {
+                "Z" | "M" | "ZM" => {}
+                _ => self.pos = before,
+            }
+        }
+        Ok(match name.as_str() {
+            "POINT" => {
+                self.list(Self::point)?;
+                Geometry::Point
+            }
+            "LINESTRING" => Geometry::LineString(self.points()?),
+            "POLYGON" => Geometry::Polygon(self.rings()?),
+            "MULTILINESTRING" => Geometry::MultiLineString(self.rings()?),
+            "MULTIPOLYGON" => Geometry::Multi(
+                self.list(Self::rings)?
+                    .into_iter()
+                    .map(Geometry::Polygon)
+                    .collect(),
+            ),
+            "MULTIPOINT" => {
+                self.list(|p| {
+                    // Both MULTIPOINT (1 2, 3 4) and MULTIPOINT ((1 2), (3 4)) are in use
+                    if p.peek() == Some('(') {
+                        p.points().map(|_| ())
+                    } else {
+                        p.point().map(|_| ())
+                    }
+                })?;
+                Geometry::Point
+            }
+            "GEOMETRYCOLLECTION" => Geometry::Collection(self.list(Self::geometry)?),
+            _ => return Err(WktError::Unexpected(start)),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clitype::LongCLI;
+
+    fn layer() -> Layer<'static, LongCLI> {
+        Layer {
+            loops: vec![
+                make_loop(
+                    &[[0.0, 0.0], [8.0, 0.0], [8.0, 8.0], [0.0, 8.0], [0.0, 0.0]],
+                    1,
+                ),
+                make_loop(
+                    &[[2.0, 2.0], [2.0, 4.0], [4.0, 4.0], [4.0, 2.0], [2.0, 2.0]],
+                    0,
+                ),
+                make_loop(&[[10.0, 0.0], [12.0, 2.5]], 2),
+            ],
+            hatches: vec![Hatches {
+                id: 0,
+                points: Cow::Owned(vec![1.0, 1.0, 3.0, 1.0, 1.0, 2.0, 3.0, 2.0]),
+                params: Default::default(),
+            }],
+            ..Layer::empty(1.0)
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let layer = layer();
+        let wkt = layer.to_wkt();
+        assert_eq!(
+            wkt,
+            "GEOMETRYCOLLECTION (MULTIPOLYGON (((0 0, 8 0, 8 8, 0 8, 0 0), (2 2, 2 4, 4 4, 4 2, 2 2))), \
+             LINESTRING (10 0, 12 2.5), MULTILINESTRING ((1 1, 3 1), (1 2, 3 2)))"
+        );
+        let back = Layer::<LongCLI>::from_wkt(&wkt, 1.0).unwrap();
+        assert_eq!(back.to_wkt(), wkt);
+        for (a, b) in back.loops.iter().zip(&layer.loops) {
+            assert_eq!((a.dir, a.points()), (b.dir, b.points()));
+        }
+        assert_eq!(back.hatches[0].points(), layer.hatches[0].points());
+    }
+
+    #[test]
+    fn loops() {
+        let l = Loop::<LongCLI>::from_wkt("polygon z ((0 0 1, 0 4 1, 4 4 1, 0 0 1))").unwrap();
+        assert_eq!(l.dir, 0);
+        assert_eq!(l.points(), &[0.0, 0.0, 0.0, 4.0, 4.0, 4.0, 0.0, 0.0]);
+        assert_eq!(l.to_wkt(), "POLYGON ((0 0, 0 4, 4 4, 0 0))");
+
+        let open = Loop::<LongCLI>::from_wkt("LINESTRING(1.5 2,3 -4e1)").unwrap();
+        assert_eq!(open.dir, 2);
+        assert_eq!(open.points(), &[1.5, 2.0, 3.0, -40.0]);
+    }
+
+    #[test]
+    fn reading() {
+        // Holes are reoriented clockwise
+        let layer = Layer::<LongCLI>::from_wkt(
+            "MULTIPOLYGON (((0 0, 0 8, 8 8, 8 0, 0 0), (2 2, 4 2, 4 4, 2 4, 2 2)), EMPTY)",
+            0.5,
+        )
+        .unwrap();
+        assert_eq!(layer.loops.len(), 2);
+        assert_eq!(layer.loops[0].points()[..4], [0.0, 0.0, 8.0, 0.0]);
+        assert_eq!(layer.loops[1].points()[..4], [2.0, 2.0, 2.0, 4.0]);
+
+        let open = Layer::<LongCLI>::from_wkt("MULTILINESTRING ((0 0, 1 1, 2 0))", 0.5).unwrap();
+        assert_eq!(open.loops.len(), 1);
+        assert!(open.hatches.is_empty());
+
+        assert_eq!(
+            Layer::<LongCLI>::from_wkt("GEOMETRYCOLLECTION EMPTY", 0.5)
+                .unwrap()
+                .loops
+                .len(),
+            0
+        );
+        assert_eq!(
+            Layer::<LongCLI>::from_wkt("POLYGON ((0 0, 1 0", 0.5).err(),
+            Some(WktError::UnexpectedEnd)
+        );
+        assert_eq!(
+            Layer::<LongCLI>::from_wkt("POLYGON ((0 0, 1 0)) x", 0.5).err(),
+            Some(WktError::Unexpected(21))
+        );
+        assert_eq!(
+            Layer::<LongCLI>::from_wkt("POINT (1 2)", 0.5).err(),
+            Some(WktError::UnsupportedType("POINT".into()))
+        );
+    }
+}
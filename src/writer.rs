@@ -0,0 +1,337 @@
+//! Binary encoder for the CLI format
+//!
+//! Everything that emits .cli data goes through these functions so that the header
+//! layout and `$$ALIGN` padding are handled in one place.
+
+use crate::clitype::CLIType;
+use crate::header::{self, extra_command, parse_dimension, parse_label, Line};
+use crate::{Block, Header, Layer, VectorParams};
+use bytes::BufMut;
+
+/// Header commands written by this crate, in the order they are written
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Command {
+    Format,
+    Units,
+    Version,
+    Align,
+    /// One `$$LABEL` line per labelled id
+    Label(i64),
+    Date,
+    Dimension,
+    Layers,
+    /// The `$$USERDATA` entry at an index of [`Header::user_data`]
+    UserData(usize),
+    /// The command at an index of [`Header::extra`]
+    Extra(usize),
+}
+
+/// The commands to write for `header`, in order
+fn commands(header: &Header) -> impl Iterator<Item = Command> + '_ {
+    let before = [
+        Command::Format,
+        Command::Units,
+        Command::Version,
+        Command::Align,
+    ];
+    let after = [Command::Date, Command::Dimension, Command::Layers];
+    IntoIterator::into_iter(before)
+        .chain(header.labels.keys().map(|&id| Command::Label(id)))
+        .chain(after)
+        .chain((0..header.user_data.len()).map(Command::UserData))
+        .chain((0..header.extra.len()).map(Command::Extra))
+}
+
+/// The header line for a command, `None` if it is omitted
+fn header_line(command: Command, header: &Header, layers: Option<usize>) -> Option<Vec<u8>> {
+    let line: Option<String> = match command {
+        Command::Format if header.binary => Some("$$BINARY".into()),
+        Command::Format => Some("$$ASCII".into()),
+        Command::Units => Some(format!("$$UNITS/{}", header.units)),
+        Command::Version => Some(format!(
+            "$$VERSION/{}",
+            (header.version * 100.0).round() as u32
+        )),
+        Command::Align => header.aligned.then(|| "$$ALIGN".into()),
+        Command::Label(id) => header
+            .label(id)
+            .map(|text| format!("$$LABEL/{},{}", id, text)),
+        Command::Date => header.date.as_ref().map(|d| format!("$$DATE/{}", d.raw)),
+        Command::Dimension => header.dimension.map(|d| {
+            let values: Vec<_> = d.iter().map(|v| v.to_string()).collect();
+            format!("$$DIMENSION/{}", values.join(","))
+        }),
+        Command::Layers => layers.map(|layers| format!("$$LAYERS/{}", layers)),
+        Command::Extra(i) => header.extra.get(i).map(|(name, value)| match &**value {
+            "" => format!("$${}", name),
+            value => format!("$${}/{}", name, value),
+        }),
+        Command::UserData(i) => return header.user_data.get(i).map(|u| u.to_line()),
+    };
+    line.map(String::into_bytes)
+}
+
+/// The command of an existing header line, if it is one written by this crate, and whether the
+/// line already states what `header` and `layers` say. `extra` counts the unknown commands seen
+/// so far.
+fn classify(
+    line: &str,
+    header: &Header,
+    layers: Option<usize>,
+    extra: &mut usize,
+) -> Option<(Command, bool)> {
+    let mut cleaned = line.trim();
+    if let Some(comment) = cleaned.find("//") {
+        cleaned = cleaned[..comment].trim();
+    }
+    let (command, value) =
+        cleaned.split_at(cleaned.find('/').map(|x| x + 1).unwrap_or(cleaned.len()));
+    let value = value.trim();
+    let command: String = command
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .collect::<String>()
+        .to_ascii_uppercase();
+    Some(match &*command {
+        "$$BINARY" => (Command::Format, header.binary),
+        "$$ASCII" => (Command::Format, !header.binary),
+        "$$UNITS/" => (Command::Units, value.parse() == Ok(header.units)),
+        "$$VERSION/" => (
+            Command::Version,
+            value.parse::<f32>().map(|v| v / 100.0) == Ok(header.version),
+        ),
+        "$$ALIGN" => (Command::Align, header.aligned),
+        "$$LABEL/" => {
+            let (id, text) = parse_label(value)?;
+            (Command::Label(id), header.label(id) == Some(text))
+        }
+        "$$DATE/" => (
+            Command::Date,
+            header.date.as_ref().map(|d| d.raw.as_str()) == Some(value),
+        ),
+        "$$DIMENSION/" => (
+            Command::Dimension,
+            header.dimension.is_some() && parse_dimension(value) == header.dimension,
+        ),
+        "$$LAYERS/" => (
+            Command::Layers,
+            layers.is_some() && value.parse().ok() == layers,
+        ),
+        command => {
+            let name = extra_command(command)?;
+            let current = header
+                .extra
+                .get(*extra)
+                .is_some_and(|(n, v)| n == name && v == value);
+            *extra += 1;
+            (Command::Extra(*extra - 1), current)
+        }
+    })
+}
+
+/// Write the header section.
+///
+/// The declared layer count is taken from `layers` rather than from the header so that
+/// the two can never disagree, it is omitted when unknown. When the header is aligned the
+/// section is padded with zeros so the geometry starts on a 4 byte boundary.
+pub(crate) fn write_header(out: &mut Vec<u8>, header: &Header, layers: Option<usize>) {
+    out.put_slice(b"$$HEADERSTART\n");
+    for command in commands(header) {
+        if let Some(line) = header_line(command, header, layers) {
+            out.put_slice(&line);
+            out.put_u8(b'\n');
+        }
+    }
+    out.put_slice(b"$$HEADEREND");
+    pad_header(out, header.aligned);
+}
+
+/// Write the header section based on the original text of the header.
+///
+/// Lines are copied verbatim, except for the commands written by [`write_header`] that no longer
+/// match `header` or `layers`. These are rewritten in place, dropped if they should be omitted
+/// and added before `$$HEADEREND` if they are missing.
+pub(crate) fn write_header_from(
+    out: &mut Vec<u8>,
+    raw: &str,
+    header: &Header,
+    layers: Option<usize>,
+) {
+    let end = raw.rfind("$$HEADEREND").unwrap_or(raw.len());
+    let mut written = Vec::new();
+    let (mut pos, mut user_data, mut extra) = (0, 0, 0);
+    for line in header::lines(&raw.as_bytes()[..end]) {
+        // The header was read with the same rules, copy anything unreadable as is
+        let Ok(line) = line else {
+            out.put_slice(&raw.as_bytes()[pos..end]);
+            break;
+        };
+        // What follows the value of the line, its line break is kept when it is rewritten
+        let (text, tail, classified) = match line {
+            Line::Text(text) => (
+                text.as_bytes(),
+                text.as_bytes(),
+                classify(text, header, layers, &mut extra),
+            ),
+            Line::UserData { line, uid, data } => {
+                let command = Command::UserData(user_data);
+                let current = header
+                    .user_data
+                    .get(user_data)
+                    .is_some_and(|u| u.uid == uid && u.data == data);
+                user_data += 1;
+                let value = data.as_ptr() as usize - line.as_ptr() as usize + data.len();
+                (line, &line[value..], Some((command, current)))
+            }
+        };
+        pos += text.len();
+        let (command, current) = match classified {
+            Some(classified) => classified,
+            None => {
+                out.put_slice(text);
+                continue;
+            }
+        };
+        if written.contains(&command) {
+            continue;
+        }
+        written.push(command);
+        if current {
+            out.put_slice(text);
+        } else if let Some(new) = header_line(command, header, layers) {
+            let content = tail
+                .iter()
+                .rposition(|&b| b != b'\r' && b != b'\n')
+                .map_or(0, |n| n + 1);
+            out.put_slice(&new);
+            out.put_slice(&tail[content..]);
+        }
+    }
+    for command in commands(header) {
+        if let (false, Some(line)) = (
+            written.contains(&command),
+            header_line(command, header, layers),
+        ) {
+            out.put_slice(&line);
+            out.put_u8(b'\n');
+        }
+    }
+    out.put_slice(&raw.as_bytes()[end..]);
+    pad_header(out, header.aligned);
+}
+
+/// Pad the header so the geometry starts on a 4 byte boundary when aligned
+fn pad_header(out: &mut Vec<u8>, aligned: bool) {
+    if aligned {
+        let padded = out.len().div_ceil(4) * 4;
+        out.resize(padded, 0);
+    }
+}
+
+/// Write a single command word
+fn put_command(out: &mut Vec<u8>, cmd: u16, aligned: bool) {
+    out.put_u16_le(cmd);
+    if aligned {
+        out.put_u16_le(0);
+    }
+}
+
+/// Write a layer and all of its geometry
+///
+/// Geometry blocks are written in the order they were read, user data records and records skipped
+/// by the parser in between them at their recorded position. Skipped records are dropped if the
+/// file layout is not the same.
+/// Layers decoded from a file with the same layout and not modified since are copied from it.
+pub(crate) fn write_layer<T: CLIType>(out: &mut Vec<u8>, layer: &Layer<'_, T>, aligned: bool) {
+    if let Some(source) = layer.source.filter(|s| s.aligned == aligned) {
+        out.put_slice(source.bytes);
+        return;
+    }
+    put_command(out, T::CMD_LAYER, aligned);
+    T::put_coord(out, layer.height, aligned);
+
+    let mut user_data: Vec<_> = layer.user_data.iter().collect();
+    user_data.sort_by_key(|u| u.position);
+    let mut user_data = user_data.into_iter().peekable();
+    let mut raw: Vec<_> = layer.raw.iter().filter(|r| r.aligned == aligned).collect();
+    raw.sort_by_key(|r| r.position);
+    let mut raw = raw.into_iter().peekable();
+    let mut write_records = |out: &mut Vec<u8>, block: usize| {
+        while let Some(u) = user_data.next_if(|u| u.position <= block) {
+            write_user_data::<T>(out, u.id, &u.data, aligned);
+        }
+        while let Some(r) = raw.next_if(|r| r.position <= block) {
+            out.put_slice(&r.bytes);
+        }
+    };
+
+    for (block, (kind, i)) in layer.blocks().into_iter().enumerate() {
+        write_records(out, block);
+        match kind {
+            Block::Loop => {
+                let l = &layer.loops[i];
+                put_command(out, T::CMD_PLINE, aligned);
+                T::put_meta(out, l.id, aligned);
+                T::put_meta(out, l.dir, aligned);
+                T::put_usize(out, l.points.len() / 2, aligned);
+                // $$ALIGN not a factor here since the spec says should be tightly packed
+                for c in l.points.iter() {
+                    T::put_coord(out, *c, false);
+                }
+                write_params(out, &l.params, aligned);
+            }
+            Block::Hatches => {
+                let h = &layer.hatches[i];
+                put_command(out, T::CMD_HATCH, aligned);
+                T::put_meta(out, h.id, aligned);
+                T::put_usize(out, h.points.len() / 4, aligned);
+                for c in h.points.iter() {
+                    T::put_coord(out, *c, false);
+                }
+                write_params(out, &h.params, aligned);
+            }
+        }
+    }
+    write_records(out, usize::MAX);
+}
+
+/// Write a user data record
+pub(crate) fn write_user_data<T: CLIType>(
+    out: &mut Vec<u8>,
+    id: <T as CLIType>::Meta,
+    data: &[u8],
+    aligned: bool,
+) {
+    put_command(out, T::CMD_USERDATA, aligned);
+    T::put_meta(out, id, aligned);
+    T::put_usize(out, data.len(), aligned);
+    out.put_slice(data);
+    if aligned {
+        let padding = data.len().next_multiple_of(4) - data.len();
+        out.resize(out.len() + padding, 0);
+    }
+}
+
+/// Write the extension records following a geometry block
+fn write_params<T: CLIType>(out: &mut Vec<u8>, params: &VectorParams<'_, T>, aligned: bool) {
+    for (cmd, values) in [(T::CMD_POWER, &params.power), (T::CMD_SPEED, &params.speed)] {
+        if let Some(values) = values {
+            put_command(out, cmd, aligned);
+            T::put_usize(out, values.len(), aligned);
+            for v in values.iter() {
+                T::put_coord(out, *v, false);
+            }
+        }
+    }
+}
+
+/// Encode a complete binary file with a generated header
+#[cfg(test)]
+pub(crate) fn encode<T: CLIType>(header: &Header, layers: &[Layer<'_, T>]) -> Vec<u8> {
+    let mut out = Vec::new();
+    write_header(&mut out, header, Some(layers.len()));
+    for layer in layers {
+        write_layer(&mut out, layer, header.aligned);
+    }
+    out
+}